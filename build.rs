@@ -0,0 +1,5 @@
+fn main() {
+    // The Node.js addon needs linker flags to leave Node's symbols for it to provide at load time
+    #[cfg(feature = "node")]
+    napi_build::setup();
+}
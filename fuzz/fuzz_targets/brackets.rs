@@ -0,0 +1,13 @@
+//! Bracket matching and compiling never panic, even with unmatched brackets
+#![no_main]
+
+use bf_rs::{Instruction, Program, find_matching_bracket};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|instructions: Vec<Instruction>| {
+    let _ = find_matching_bracket(&instructions, true);
+    let _ = find_matching_bracket(&instructions, false);
+    for optimize in [false, true] {
+        let _ = Program::compile(&instructions, optimize);
+    }
+});
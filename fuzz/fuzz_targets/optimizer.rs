@@ -0,0 +1,20 @@
+//! Optimized and unoptimized programs do the same as the reference interpreter, when it finishes in time
+#![no_main]
+
+use bf_rs::{Program, fuzz::BalancedProgram, reference};
+use libfuzzer_sys::fuzz_target;
+
+const MAX_STEPS: usize = 10_000;
+
+fuzz_target!(|input: (BalancedProgram, Vec<u8>)| {
+    let (BalancedProgram(instructions), input) = input;
+    let Some(expected) = reference::run(&instructions, &input, MAX_STEPS).unwrap() else {
+        return;
+    };
+    for optimize in [false, true] {
+        let program = Program::compile(&instructions, optimize).unwrap();
+        // Folding a loop can add an op when it doesn't run, so the vm gets some leeway
+        let actual = reference::run_vm(program, &input, MAX_STEPS * 2);
+        assert_eq!(actual.as_ref(), Some(&expected), "{:?}, optimized: {}", instructions, optimize);
+    }
+});
@@ -0,0 +1,11 @@
+//! Parsing, macro expansion and formatting never panic, whatever the source
+#![no_main]
+
+use bf_rs::{ParseOptions, format, fuzz::ProgramText, parse_string_macros};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|text: ProgramText| {
+    let options = ParseOptions { macros: true, ..ParseOptions::default() };
+    let _ = parse_string_macros(&text.0, &options);
+    let _ = format::format_source(&text.0, &options);
+});
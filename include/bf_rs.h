@@ -0,0 +1,60 @@
+/* C interface to bf-rs, build with `cargo build --release --features capi` and link to
+ * target/release/libbf_rs.so (or .dylib / .dll).
+ *
+ * Functions return BF_OK or a negative error code, bf_last_error() has the message. */
+
+#ifndef BF_RS_H
+#define BF_RS_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define BF_OK 0
+/* bf_vm_step ran the last op */
+#define BF_FINISHED 1
+#define BF_ERROR_NULL -1
+#define BF_ERROR_UTF8 -2
+#define BF_ERROR_PARSE -3
+#define BF_ERROR_MACRO -4
+#define BF_ERROR_RUNTIME -5
+#define BF_ERROR_IO -6
+
+/* Flags for bf_vm_new */
+#define BF_MACROS 1
+#define BF_BREAKPOINTS 2
+/* Keep one op per instruction, for stepping through a program */
+#define BF_NO_OPTIMIZE 4
+
+typedef struct BfVm BfVm;
+
+/* Parse and compile a program, storing the vm in *out (NULL on errors) */
+int bf_vm_new(const char *code, int flags, BfVm **out);
+void bf_vm_free(BfVm *vm);
+
+/* Run one op: BF_OK if there are more, BF_FINISHED after the last one, or an error */
+int bf_vm_step(BfVm *vm);
+/* Run until the program ends: BF_FINISHED or an error */
+int bf_vm_run(BfVm *vm);
+
+/* Add to the input that `,` reads, the end of input reads as 0 */
+int bf_vm_push_input(BfVm *vm, const uint8_t *data, size_t length);
+/* Move up to capacity bytes of output into buffer, returning how many were moved */
+size_t bf_vm_take_output(BfVm *vm, uint8_t *buffer, size_t capacity);
+
+/* The cells of the tape, valid until the next step */
+const uint8_t *bf_vm_tape_ptr(const BfVm *vm, size_t *length);
+/* Index of the current cell */
+size_t bf_vm_pointer(const BfVm *vm);
+
+/* Message of the last error on this thread, or NULL, valid until the next error */
+const char *bf_last_error(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,497 @@
+//! Finding likely mistakes in a program without running it: loops that never run, loops that never end, loops that
+//! move the pointer further every time round, cells whose values are never used, and moves off the end of a compiled
+//! program's tape.
+//!
+//! Findings are only hints, unlike the errors from `check`, so a program with them still runs.
+
+use std::{collections::{BTreeMap, HashMap}, fmt, ops::Range};
+use serde_json::json;
+use crate::{Instruction, Op, Program, bounds, emit::{TAPE_SIZE, source_map::LineIndex}, ir};
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Probably a mistake
+    Warning,
+    /// Often on purpose, but worth knowing
+    Info,
+}
+
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Info => write!(f, "info"),
+        }
+    }
+}
+
+
+/// Something about a program found by looking at it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub severity: Severity,
+    /// What kind of finding it is, like `unreachable`, for tools to match on
+    pub code: &'static str,
+    pub message: String,
+    /// Byte range in the source
+    pub span: Range<usize>,
+}
+
+
+impl Finding {
+    /// `path:line:column: severity: message [code]`, with lines and columns counting from 1
+    pub fn render(&self, path: &str, source: &str) -> String {
+        let (line, column) = LineIndex::new(source).line_column(source, self.span.start);
+        format!("{}:{}:{}: {}: {} [{}]", path, line + 1, column + 1, self.severity, self.message, self.code)
+    }
+
+    /// The same object as `check --format json` gives for errors, with this finding's severity and whole span
+    pub fn json(&self, path: &str, source: &str) -> serde_json::Value {
+        let index = LineIndex::new(source);
+        let (line, column) = index.line_column(source, self.span.start);
+        let (end_line, end_column) = index.line_column(source, self.span.end);
+        json!({
+            "severity": self.severity.to_string(),
+            "code": self.code,
+            "message": self.message,
+            "file": path,
+            "range": {
+                "start": { "line": line + 1, "column": column + 1 },
+                "end": { "line": end_line + 1, "column": end_column + 1 },
+            },
+        })
+    }
+}
+
+
+/// Everything found in parsed instructions, whose brackets must match, in source order
+pub fn analyze(instructions: &[(Instruction, usize)]) -> Vec<Finding> {
+    let loops = loops(instructions);
+    let mut findings = unreachable_loops(instructions, &loops);
+    let never_run: Vec<Range<usize>> = findings.iter().map(|finding| finding.span.clone()).collect();
+    findings.extend(endless_loops(instructions, &loops).into_iter()
+        .filter(|finding| !never_run.iter().any(|span| span.contains(&finding.span.start))));
+    findings.extend(drifting_loops(instructions, &loops));
+    findings.extend(unused_cells(instructions, &loops));
+    findings.extend(escapes(instructions));
+    findings.sort_by_key(|finding| (finding.span.start, finding.severity));
+    findings
+}
+
+
+/// What a loop does, as far as the optimizer is concerned
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LoopKind {
+    /// `[-]` or `[+]`, which becomes setting the cell to 0
+    Clear,
+    /// A loop like `[->++<]` that takes 1 from its cell and adds to others, which becomes multiplying
+    Multiply,
+    /// Only moves the pointer, like `[>]`, to find a cell that is 0
+    Scan,
+    /// Ends on the cell it started on, but does more than the optimizer can fold
+    Balanced,
+    /// Can end on a different cell each time round
+    Unbalanced,
+}
+
+
+impl LoopKind {
+    pub const ALL: [LoopKind; 5] = [LoopKind::Clear, LoopKind::Multiply, LoopKind::Scan, LoopKind::Balanced, LoopKind::Unbalanced];
+
+    /// Whether the optimizer turns loops like this into straight-line ops
+    pub fn is_optimized(self) -> bool {
+        matches!(self, LoopKind::Clear | LoopKind::Multiply)
+    }
+}
+
+
+impl fmt::Display for LoopKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Padded, so reports can line them up
+        f.pad(match self {
+            LoopKind::Clear => "clear",
+            LoopKind::Multiply => "copy/multiply",
+            LoopKind::Scan => "scan",
+            LoopKind::Balanced => "balanced-complex",
+            LoopKind::Unbalanced => "unbalanced",
+        })
+    }
+}
+
+
+/// What each loop in parsed instructions, whose brackets must match, does, with its span in the source
+pub fn classify_loops(instructions: &[(Instruction, usize)]) -> Vec<(LoopKind, Range<usize>)> {
+    let loops = loops(instructions);
+    let plain: Vec<Instruction> = instructions.iter().map(|&(instruction, _)| instruction).collect();
+    loops.iter().map(|(&open, &close)| {
+        let body = &plain[open + 1..close];
+        let kind = match ir::fold_loop(&plain[open..]) {
+            Some((ops, _)) if ops == [Op::Clear] => LoopKind::Clear,
+            Some(_) => LoopKind::Multiply,
+            None if !body.is_empty() && (body.iter().all(|&i| i == Instruction::Right) || body.iter().all(|&i| i == Instruction::Left)) => {
+                LoopKind::Scan
+            },
+            None if drift(instructions, &loops, open + 1, close) == Some(0) => LoopKind::Balanced,
+            None => LoopKind::Unbalanced,
+        };
+        (kind, span(instructions, open, close))
+    }).collect()
+}
+
+
+/// The index of each `[`'s matching `]`
+pub(crate) fn loops(instructions: &[(Instruction, usize)]) -> BTreeMap<usize, usize> {
+    let mut loops = BTreeMap::new();
+    let mut open = Vec::new();
+    for (i, (instruction, _)) in instructions.iter().enumerate() {
+        match instruction {
+            Instruction::Open => open.push(i),
+            Instruction::Close => {
+                if let Some(start) = open.pop() {
+                    loops.insert(start, i);
+                }
+            },
+            _ => (),
+        }
+    }
+    loops
+}
+
+
+/// The source from a loop's `[` to its `]`
+fn span(instructions: &[(Instruction, usize)], open: usize, close: usize) -> Range<usize> {
+    instructions[open].1..instructions[close].1 + 1
+}
+
+
+/// Loops reached when the cell can only be 0: at the start of the program, or straight after another loop
+fn unreachable_loops(instructions: &[(Instruction, usize)], loops: &BTreeMap<usize, usize>) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    // Whether the current cell is known to be 0
+    let mut zero = true;
+    let mut i = 0;
+    while i < instructions.len() {
+        match instructions[i].0 {
+            Instruction::Open if zero => {
+                let close = loops[&i];
+                let message = match i {
+                    0 => "this loop never runs, the first cell starts at 0 (fine for a comment loop)",
+                    _ => "this loop never runs, the loop before it leaves the cell at 0",
+                };
+                let severity = if i == 0 { Severity::Info } else { Severity::Warning };
+                findings.push(Finding { severity, code: "unreachable", message: message.to_string(), span: span(instructions, i, close) });
+                // Nothing in the loop runs, so the cell is still 0 after it
+                i = close + 1;
+                continue;
+            },
+            Instruction::Close => zero = true,
+            Instruction::Output | Instruction::Break | Instruction::Push => (),
+            _ => zero = false,
+        }
+        i += 1;
+    }
+    findings
+}
+
+
+/// How far one pass through the instructions from `start` to `end` moves the pointer,
+/// `None` if it depends on the tape, like with loops that drift themselves
+fn drift(instructions: &[(Instruction, usize)], loops: &BTreeMap<usize, usize>, start: usize, end: usize) -> Option<i64> {
+    let mut total = 0;
+    let mut i = start;
+    while i < end {
+        match instructions[i].0 {
+            Instruction::Right => total += 1,
+            Instruction::Left => total -= 1,
+            Instruction::Open => {
+                let close = loops[&i];
+                if drift(instructions, loops, i + 1, close)? != 0 {
+                    return None;
+                }
+                i = close;
+            },
+            // Procedures and other tapes could move any pointer
+            Instruction::Call | Instruction::NextTape | Instruction::ProcedureOpen | Instruction::ProcedureClose => return None,
+            _ => (),
+        }
+        i += 1;
+    }
+    Some(total)
+}
+
+
+/// Loops that end further along the tape than they start, so they can move the pointer any distance
+fn drifting_loops(instructions: &[(Instruction, usize)], loops: &BTreeMap<usize, usize>) -> Vec<Finding> {
+    loops.iter().filter_map(|(&open, &close)| {
+        let cells = drift(instructions, loops, open + 1, close).filter(|&cells| cells != 0)?;
+        let direction = if cells > 0 { "right" } else { "left" };
+        let plural = if cells.abs() == 1 { "" } else { "s" };
+        Some(Finding {
+            severity: Severity::Info,
+            code: "pointer-drift",
+            message: format!(
+                "this loop moves the pointer {} cell{} {} each time round, so how far it goes depends on the tape",
+                cells.abs(), plural, direction,
+            ),
+            span: span(instructions, open, close),
+        })
+    }).collect()
+}
+
+
+/// How much one time round a loop changes the cells it uses, by offset from the cell it tests, for loops with only
+/// `+-<>.` and `,` on other cells in them that end on the cell they started on. Cells wrap at 128, so changes are too.
+fn simple_body(instructions: &[(Instruction, usize)], open: usize, close: usize) -> Option<BTreeMap<i64, u8>> {
+    let mut changes = BTreeMap::new();
+    let mut pointer = 0;
+    for &(instruction, _) in &instructions[open + 1..close] {
+        match instruction {
+            Instruction::Increment => *changes.entry(pointer).or_insert(0) += 1,
+            Instruction::Decrement => *changes.entry(pointer).or_insert(0) += 127,
+            Instruction::Right => pointer += 1,
+            Instruction::Left => pointer -= 1,
+            // Nothing can be known about a cell read into, so only other cells can be
+            Instruction::Input if pointer != 0 => (),
+            Instruction::Output | Instruction::Break => (),
+            _ => return None,
+        }
+        if let Some(change) = changes.get_mut(&pointer) {
+            *change %= 128;
+        }
+    }
+    (pointer == 0).then_some(changes)
+}
+
+
+/// The value of the cell each loop tests when it's first reached, by the index of its `[`, for the loops that can be
+/// worked out by running the program from the start until the first loop that isn't simple or reads input
+fn entry_values(instructions: &[(Instruction, usize)], loops: &BTreeMap<usize, usize>) -> HashMap<usize, u8> {
+    let mut values = HashMap::new();
+    let mut cells: BTreeMap<usize, u8> = BTreeMap::new();
+    let mut pointer: usize = 0;
+    let mut i = 0;
+    while i < instructions.len() {
+        match instructions[i].0 {
+            Instruction::Increment => {
+                let cell = cells.entry(pointer).or_insert(0);
+                *cell = (*cell + 1) % 128;
+            },
+            Instruction::Decrement => {
+                let cell = cells.entry(pointer).or_insert(0);
+                *cell = (*cell + 127) % 128;
+            },
+            Instruction::Right => pointer += 1,
+            // Only followed while the pointer stays off the start of the tape
+            Instruction::Left if pointer > 0 => pointer -= 1,
+            Instruction::Output | Instruction::Break => (),
+            Instruction::Open => {
+                let close = loops[&i];
+                let value = cells.get(&pointer).copied().unwrap_or(0);
+                values.insert(i, value);
+                if value != 0 {
+                    let Some(changes) = simple_body(instructions, i, close) else {
+                        break;
+                    };
+                    let Some(times) = times_round(value, changes.get(&0).copied().unwrap_or(0)) else {
+                        break;
+                    };
+                    for (&offset, &change) in &changes {
+                        let Some(cell) = pointer.checked_add_signed(offset as isize) else {
+                            return values;
+                        };
+                        let value = cells.entry(cell).or_insert(0);
+                        *value = ((*value as usize + change as usize * times) % 128) as u8;
+                    }
+                }
+                i = close;
+            },
+            _ => break,
+        }
+        i += 1;
+    }
+    values
+}
+
+
+/// How many times round a loop goes before the cell it tests, starting at `value` and changing by `change` each time,
+/// gets to 0, if it ever does
+fn times_round(value: u8, change: u8) -> Option<usize> {
+    (0..128).find(|&times| (value as usize + change as usize * times).is_multiple_of(128))
+}
+
+
+/// A change to a cell as a number from -64 to 63, since adding 127 is taking away 1
+fn signed(change: u8) -> i64 {
+    if change >= 64 { change as i64 - 128 } else { change as i64 }
+}
+
+
+/// Loops that can never end, and loops that only end for some values of the cell they test.
+/// Only loops with simple bodies are looked at, and a pointer stopping at the start of the tape is not counted.
+fn endless_loops(instructions: &[(Instruction, usize)], loops: &BTreeMap<usize, usize>) -> Vec<Finding> {
+    let entries = entry_values(instructions, loops);
+    loops.iter().filter_map(|(&open, &close)| {
+        let change = simple_body(instructions, open, close)?.get(&0).copied().unwrap_or(0);
+        let span = span(instructions, open, close);
+        if change == 0 {
+            let message = "this loop never ends once it starts, nothing in it changes the cell it tests".to_string();
+            return Some(Finding { severity: Severity::Warning, code: "endless-loop", message, span });
+        }
+        // Going round enough times gets any value to 0 when the change is odd, but only multiples of this when it isn't
+        let multiple = 1 << change.trailing_zeros();
+        if multiple == 1 {
+            return None;
+        }
+        match entries.get(&open) {
+            Some(&value) if times_round(value, change).is_none() => Some(Finding {
+                severity: Severity::Warning,
+                code: "endless-loop",
+                message: format!(
+                    "this loop never ends, the cell starts at {} and changes by {} each time round, so it never gets to 0",
+                    value, signed(change),
+                ),
+                span,
+            }),
+            Some(_) => None,
+            None => Some(Finding {
+                severity: Severity::Info,
+                code: "may-not-end",
+                message: format!(
+                    "this loop only ends if the cell starts at a multiple of {}, since it changes by {} each time round",
+                    multiple, signed(change),
+                ),
+                span,
+            }),
+        }
+    }).collect()
+}
+
+
+/// Cells that are changed but never printed or tested by a loop. This needs to know where the pointer is everywhere,
+/// so it finds nothing in programs with loops that drift, or that use pbrain or the extensions.
+fn unused_cells(instructions: &[(Instruction, usize)], loops: &BTreeMap<usize, usize>) -> Vec<Finding> {
+    if drift(instructions, loops, 0, instructions.len()).is_none() {
+        return Vec::new();
+    }
+    // The offset of the first write to each cell, and whether the cell is read anywhere
+    let mut cells: BTreeMap<usize, (Option<usize>, bool)> = BTreeMap::new();
+    let mut pointer: usize = 0;
+    for &(instruction, offset) in instructions {
+        let cell = cells.entry(pointer).or_default();
+        match instruction {
+            Instruction::Right => pointer += 1,
+            Instruction::Left => {
+                // The pointer stopping at 0 would make loops land on different cells each time round
+                let Some(left) = pointer.checked_sub(1) else {
+                    return Vec::new();
+                };
+                pointer = left;
+            },
+            Instruction::Increment | Instruction::Decrement | Instruction::Input => {
+                cell.0.get_or_insert(offset);
+            },
+            Instruction::Output | Instruction::Open | Instruction::Close => cell.1 = true,
+            Instruction::Break => (),
+            _ => return Vec::new(),
+        }
+    }
+
+    cells.into_iter().filter_map(|(cell, (written, read))| {
+        let offset = written.filter(|_| !read)?;
+        Some(Finding {
+            severity: Severity::Info,
+            code: "unused-cell",
+            message: format!("cell {} is changed here, but its value is never printed or tested by a loop", cell),
+            span: offset..offset + 1,
+        })
+    }).collect()
+}
+
+
+/// Places the pointer is sure to go past the end of a compiled program's tape, if they run
+fn escapes(instructions: &[(Instruction, usize)]) -> Vec<Finding> {
+    let Ok(program) = Program::compile_with_offsets(instructions, true) else {
+        return Vec::new();
+    };
+    let states = bounds::states(&program);
+    bounds::escapes(&program, &states, TAPE_SIZE).into_iter().filter_map(|op| {
+        let offset = program.offset(op)?;
+        Some(Finding {
+            severity: Severity::Warning,
+            code: "pointer-escape",
+            message: format!("the pointer always goes past the end of the tape here when compiled, which has {} cells", TAPE_SIZE),
+            span: offset..offset + 1,
+        })
+    }).collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ParseOptions, parse_with_offsets};
+
+    fn codes(code: &str) -> Vec<(&'static str, &str)> {
+        let instructions = parse_with_offsets(code, &ParseOptions::default()).unwrap();
+        analyze(&instructions).into_iter().map(|finding| (finding.code, &code[finding.span])).collect()
+    }
+
+    #[test]
+    fn unreachable_after_loops() {
+        assert_eq!(codes("[comment]+[-].[never]"), vec![
+            ("unreachable", "[comment]"),
+            ("unreachable", "[never]"),
+        ]);
+    }
+
+    #[test]
+    fn drifting_loops() {
+        assert_eq!(codes("+[>+]"), vec![("pointer-drift", "[>+]")]);
+        // The outer loop's drift depends on how far the inner one goes
+        assert_eq!(codes("+[[<]+>>]"), vec![("pointer-drift", "[<]")]);
+    }
+
+    #[test]
+    fn endless_loops() {
+        let endless = |code| codes(code).into_iter().filter(|(code, _)| code.contains("end")).collect::<Vec<_>>();
+        assert_eq!(endless("+[>+<]"), vec![("endless-loop", "[>+<]")]);
+        // Steps of -2 from 9 only ever reach odd numbers
+        assert_eq!(endless("+++++++++[--]"), vec![("endless-loop", "[--]")]);
+        assert!(endless("++++++++[--]+[---]").is_empty());
+        // The first loop ends after 4 times round, leaving the next cell at 12
+        assert!(endless("++++[->+++<]>[----]").is_empty());
+        assert_eq!(endless(",[--]"), vec![("may-not-end", "[--]")]);
+    }
+
+    #[test]
+    fn loop_kinds() {
+        let code = "+[-]>[+]+[->++>+<<]>[>]<[<<]+[[-]>+<]>[>+]";
+        let instructions = parse_with_offsets(code, &ParseOptions::default()).unwrap();
+        let kinds: Vec<(LoopKind, &str)> = classify_loops(&instructions).into_iter().map(|(kind, span)| (kind, &code[span])).collect();
+        assert_eq!(kinds, vec![
+            (LoopKind::Clear, "[-]"),
+            (LoopKind::Clear, "[+]"),
+            (LoopKind::Multiply, "[->++>+<<]"),
+            (LoopKind::Scan, "[>]"),
+            (LoopKind::Scan, "[<<]"),
+            (LoopKind::Balanced, "[[-]>+<]"),
+            (LoopKind::Clear, "[-]"),
+            (LoopKind::Unbalanced, "[>+]"),
+        ]);
+    }
+
+    #[test]
+    fn escapes_compiled_tape() {
+        let code = format!("+[-]{}", ">".repeat(super::TAPE_SIZE));
+        assert_eq!(codes(&code), vec![("pointer-escape", ">")]);
+    }
+
+    #[test]
+    fn unused_cells() {
+        assert_eq!(codes("+>++<[->+<]>>+++."), vec![("unused-cell", "+")]);
+        // Where `<++` lands depends on the loop
+        assert!(!codes("+[>+]<++").iter().any(|(code, _)| *code == "unused-cell"));
+    }
+}
@@ -0,0 +1,149 @@
+//! Playing a program's output as music: each byte `.` writes is a note, saved as a WAV file.
+//! Bytes are MIDI note numbers, 60 being middle C and 69 the A at 440 Hz, and 0 is a rest.
+
+use std::{f64::consts::TAU, fs, io::{self, Write}, time::Duration};
+
+
+pub const SAMPLE_RATE: u32 = 22_050;
+
+/// How loud the notes are, out of 1
+const VOLUME: f64 = 0.3;
+
+/// Seconds each note takes to fade in and out, so they don't click
+const FADE: f64 = 0.005;
+
+
+/// Output that becomes notes, written to `path` all at once by `finish`, or when dropped without it
+pub struct Tones {
+    path: String,
+    /// Samples in each note
+    note_samples: usize,
+    samples: Vec<i16>,
+    finished: bool,
+}
+
+
+impl Tones {
+    pub fn new(path: &str, note: Duration) -> Tones {
+        let note_samples = (note.as_secs_f64() * SAMPLE_RATE as f64).round() as usize;
+        Tones { path: path.to_string(), note_samples, samples: Vec::new(), finished: false }
+    }
+
+    /// Write the WAV file of everything played
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.finished = true;
+        fs::write(&self.path, wav(&self.samples))
+    }
+
+    fn play(&mut self, byte: u8) {
+        let Some(frequency) = frequency(byte) else {
+            self.samples.extend(std::iter::repeat_n(0, self.note_samples));
+            return;
+        };
+        let fade = (FADE * SAMPLE_RATE as f64).min(self.note_samples as f64 / 2.0).max(1.0);
+        for i in 0..self.note_samples {
+            let time = i as f64 / SAMPLE_RATE as f64;
+            let envelope = (i as f64 / fade).min((self.note_samples - i) as f64 / fade).min(1.0);
+            let sample = (TAU * frequency * time).sin() * envelope * VOLUME;
+            self.samples.push((sample * i16::MAX as f64) as i16);
+        }
+    }
+}
+
+
+impl Write for Tones {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        for &byte in bytes {
+            self.play(byte);
+        }
+        Ok(bytes.len())
+    }
+
+    /// Nothing, since the vm flushes before every `,` and writing the whole file each time would get slower and
+    /// slower
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+
+impl Drop for Tones {
+    /// Keep what was played before an error stopped the program
+    fn drop(&mut self) {
+        if !self.finished {
+            if let Err(error) = self.finish() {
+                log::error!("couldn't write {}: {}", self.path, error);
+            }
+        }
+    }
+}
+
+
+/// The frequency in Hz of a MIDI note, or none for a rest
+pub fn frequency(note: u8) -> Option<f64> {
+    match note {
+        0 => None,
+        note => Some(440.0 * 2f64.powf((note as f64 - 69.0) / 12.0)),
+    }
+}
+
+
+/// A mono 16 bit WAV file of `samples`
+pub fn wav(samples: &[i16]) -> Vec<u8> {
+    let data_length = samples.len() as u32 * 2;
+    let mut wav = Vec::with_capacity(44 + data_length as usize);
+    wav.extend(b"RIFF");
+    wav.extend((36 + data_length).to_le_bytes());
+    wav.extend(b"WAVEfmt ");
+    // 16 bytes of PCM format: 1 channel, the sample rate, bytes per second, bytes per sample, bits per sample
+    wav.extend(16u32.to_le_bytes());
+    wav.extend(1u16.to_le_bytes());
+    wav.extend(1u16.to_le_bytes());
+    wav.extend(SAMPLE_RATE.to_le_bytes());
+    wav.extend((SAMPLE_RATE * 2).to_le_bytes());
+    wav.extend(2u16.to_le_bytes());
+    wav.extend(16u16.to_le_bytes());
+    wav.extend(b"data");
+    wav.extend(data_length.to_le_bytes());
+    for sample in samples {
+        wav.extend(sample.to_le_bytes());
+    }
+    wav
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn notes() {
+        assert_eq!(frequency(69), Some(440.0));
+        assert_eq!(frequency(81), Some(880.0));
+        assert!((frequency(60).unwrap() - 261.63).abs() < 0.01);
+        assert_eq!(frequency(0), None);
+    }
+
+    #[test]
+    fn notes_become_samples() {
+        let path = std::env::temp_dir().join(format!("bf-rs-tune-{}.wav", std::process::id()));
+        let mut tones = Tones::new(path.to_str().unwrap(), Duration::from_millis(10));
+        tones.write_all(&[69, 0]).unwrap();
+        // Only written at the end
+        tones.flush().unwrap();
+        assert!(!path.exists());
+        assert_eq!(tones.samples.len(), 442);
+        // Fading in from silence, then a rest
+        assert_eq!(tones.samples[0], 0);
+        assert!(tones.samples[..221].iter().any(|&sample| sample > 5000));
+        assert!(tones.samples[221..].iter().all(|&sample| sample == 0));
+
+        let wav = wav(&tones.samples);
+        assert_eq!(wav.len(), 44 + 442 * 2);
+        assert_eq!(&wav[..4], b"RIFF");
+        assert_eq!(&wav[36..44], b"data\x74\x03\x00\x00");
+        drop(tones);
+        assert_eq!(fs::read(&path).unwrap(), wav);
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,129 @@
+//! Running one program on many inputs at once, for the `batch` subcommand.
+//! The compiled program is shared between threads, and each input gets its own vm.
+
+use std::{fs, io::{BufReader, BufWriter, Write}, path::{Path, PathBuf}, time::{Duration, Instant}};
+use rayon::prelude::*;
+use crate::{Program, Vm, VmOptions};
+
+
+/// How the program did on one input
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Run {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    /// Bytes written, up to any error
+    pub written: u64,
+    pub duration: Duration,
+    pub error: Option<String>,
+}
+
+
+/// Where the output for `input` goes: `input` with an `.out` extension, in `directory` if there is one
+pub fn output_path(input: &Path, directory: Option<&Path>) -> PathBuf {
+    let output = input.with_extension("out");
+    match (directory, output.file_name()) {
+        (Some(directory), Some(name)) => directory.join(name),
+        _ => output,
+    }
+}
+
+
+/// Run the program on every input in parallel, writing each one's output to its own file, on up to `jobs` threads
+/// (0 for one a core). The runs are in the order of `inputs`.
+pub fn run(program: &Program, vm_options: &VmOptions, inputs: &[PathBuf], directory: Option<&Path>, jobs: usize) -> Result<Vec<Run>, rayon::ThreadPoolBuildError> {
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(jobs).build()?;
+    Ok(pool.install(|| inputs.par_iter().map(|input| run_one(program, vm_options, input, output_path(input, directory))).collect()))
+}
+
+
+fn run_one(program: &Program, vm_options: &VmOptions, input: &Path, output: PathBuf) -> Run {
+    let start = Instant::now();
+    let mut written = 0;
+    let result = (|| {
+        let mut reader = BufReader::new(fs::File::open(input)?);
+        let mut writer = Counted { inner: BufWriter::new(fs::File::create(&output)?), written: &mut written };
+        let result = Vm::new(program.clone(), vm_options).run(&mut reader, &mut writer);
+        // Keep what was written before an error
+        writer.flush()?;
+        result
+    })();
+    Run { input: input.to_path_buf(), output, written, duration: start.elapsed(), error: result.err().map(|error| error.to_string()) }
+}
+
+
+/// Counts the bytes written through it
+struct Counted<'a, W> {
+    inner: W,
+    written: &'a mut u64,
+}
+
+
+impl<W: Write> Write for Counted<'_, W> {
+    fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+        let count = self.inner.write(bytes)?;
+        *self.written += count as u64;
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+
+/// A table of the runs, a row each, and a line counting them
+pub fn summary(runs: &[Run]) -> String {
+    let rows: Vec<[String; 4]> = runs.iter().map(|run| [
+        run.input.display().to_string(),
+        run.error.as_ref().map_or("ok".to_string(), |error| format!("error: {}", error)),
+        run.written.to_string(),
+        format!("{:.3}s", run.duration.as_secs_f64()),
+    ]).collect();
+    let headings = ["input", "result", "bytes", "time"].map(str::to_string);
+    let widths: Vec<usize> = (0..4).map(|column| {
+        rows.iter().chain([&headings]).map(|row| row[column].chars().count()).max().unwrap_or(0)
+    }).collect();
+
+    let mut table = String::new();
+    for row in [&headings].into_iter().chain(&rows) {
+        let line = format!("{:<a$}  {:<b$}  {:>c$}  {:>d$}", row[0], row[1], row[2], row[3], a = widths[0], b = widths[1], c = widths[2], d = widths[3]);
+        table += line.trim_end();
+        table += "\n";
+    }
+    let failed = runs.iter().filter(|run| run.error.is_some()).count();
+    table + &format!("{} ran, {} failed\n", runs.len(), failed)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ParseOptions, parse_with_offsets};
+
+    #[test]
+    fn runs_every_input() {
+        let directory = std::env::temp_dir().join(format!("bf-rs-batch-{}", std::process::id()));
+        fs::create_dir_all(directory.join("out")).unwrap();
+        let inputs: Vec<PathBuf> = (0..8).map(|i| directory.join(format!("{}.txt", i))).collect();
+        for (i, input) in inputs.iter().enumerate() {
+            fs::write(input, "x".repeat(i)).unwrap();
+        }
+        let missing = directory.join("missing.txt");
+
+        let program = Program::compile_with_offsets(&parse_with_offsets(",[.,]", &ParseOptions::default()).unwrap(), true).unwrap();
+        let all: Vec<PathBuf> = inputs.iter().cloned().chain([missing.clone()]).collect();
+        let runs = run(&program, &VmOptions::default(), &all, Some(&directory.join("out")), 3).unwrap();
+        for (i, run) in runs[..8].iter().enumerate() {
+            assert_eq!((&run.input, run.written, &run.error), (&inputs[i], i as u64, &None));
+            assert_eq!(fs::read_to_string(directory.join(format!("out/{}.out", i))).unwrap(), "x".repeat(i));
+        }
+        assert_eq!(runs[8].input, missing);
+        assert!(runs[8].error.is_some());
+
+        let summary = summary(&runs);
+        assert!(summary.lines().next().unwrap().starts_with("input"));
+        assert!(summary.ends_with("9 ran, 1 failed\n"));
+        assert_eq!(output_path(Path::new("a/b.txt"), None), Path::new("a/b.out"));
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
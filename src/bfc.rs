@@ -0,0 +1,654 @@
+//! A small structured language that compiles to brainfuck, for programs too fiddly to write by hand.
+//!
+//! ```text
+//! var n = 5;              // a named cell, 0 unless given a value
+//! var total;
+//! while n {               // loops while n isn't 0
+//!     total += n * 2;
+//!     n -= 1;
+//! }
+//! if total == 30 { print "yes\n"; } else { print "no\n"; }
+//! var c;
+//! read c;                 // a byte of input
+//! print c + 1;            // a byte of output
+//! ```
+//!
+//! Values are cells, so they go from 0 to 127 and wrap. Expressions have `+`, `-`, `*`, `==`, `!=`, `!` and
+//! brackets. Variables live in the first cells, one each, and the cells after them hold values being worked out.
+
+use std::{iter::Peekable, path::Path, str::CharIndices};
+use crate::error::ParseError;
+
+
+/// Whether a file is in this language, going by its `.bfs` extension (`.bfc` being bytecode)
+pub fn is_bfc(path: impl AsRef<Path>) -> bool {
+    path.as_ref().extension().is_some_and(|extension| extension == "bfs")
+}
+
+
+/// Compile a program to brainfuck, a line for each statement
+pub fn compile(source: &str) -> Result<String, ParseError> {
+    let tokens = lex(source)?;
+    let mut parser = Parser { tokens: &tokens, position: 0, variables: Vec::new(), source };
+    let mut statements = Vec::new();
+    while parser.peek() != &Token::End {
+        statements.push(parser.statement()?);
+    }
+    let mut generator = Generator { code: String::new(), pointer: 0, next_free: parser.variables.len(), depth: 0 };
+    for statement in &statements {
+        generator.statement(statement);
+    }
+    Ok(generator.code)
+}
+
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+    Name(String),
+    Number(u8),
+    Text(Vec<u8>),
+    /// Punctuation and operators
+    Symbol(&'static str),
+    End,
+}
+
+
+const SYMBOLS: [&str; 14] = ["+=", "-=", "==", "!=", "=", "+", "-", "*", "!", "(", ")", "{", "}", ";"];
+
+
+/// The tokens of the source, each with its offset
+fn lex(source: &str) -> Result<Vec<(Token, usize)>, ParseError> {
+    let mut tokens = Vec::new();
+    let mut chars = source.char_indices().peekable();
+    while let Some(&(offset, c)) = chars.peek() {
+        let rest = &source[offset..];
+        if c.is_whitespace() {
+            chars.next();
+        } else if rest.starts_with("//") {
+            while chars.next_if(|&(_, c)| c != '\n').is_some() {}
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let mut name = String::new();
+            while let Some((_, c)) = chars.next_if(|&(_, c)| c.is_ascii_alphanumeric() || c == '_') {
+                name.push(c);
+            }
+            tokens.push((Token::Name(name), offset));
+        } else if c.is_ascii_digit() {
+            let mut digits = String::new();
+            while let Some((_, c)) = chars.next_if(|&(_, c)| c.is_ascii_digit()) {
+                digits.push(c);
+            }
+            let number = digits.parse().ok().filter(|&n: &u8| n < 128)
+                .ok_or_else(|| error(source, offset, "numbers go up to 127"))?;
+            tokens.push((Token::Number(number), offset));
+        } else if c == '\'' || c == '"' {
+            chars.next();
+            let text = quoted(source, offset, c, &mut chars)?;
+            match (c, &text[..]) {
+                ('\'', &[byte]) => tokens.push((Token::Number(byte), offset)),
+                ('\'', _) => return Err(error(source, offset, "a character in '' has to be exactly one")),
+                _ => tokens.push((Token::Text(text), offset)),
+            }
+        } else if let Some(symbol) = SYMBOLS.iter().find(|symbol| rest.starts_with(*symbol)) {
+            for _ in 0..symbol.len() {
+                chars.next();
+            }
+            tokens.push((Token::Symbol(symbol), offset));
+        } else {
+            return Err(error(source, offset, &format!("unexpected {:?}", c)));
+        }
+    }
+    tokens.push((Token::End, source.len()));
+    Ok(tokens)
+}
+
+
+/// The bytes of a string or character up to the closing `quote`, with `\n`, `\t`, `\\` and escaped quotes
+fn quoted(source: &str, start: usize, quote: char, chars: &mut Peekable<CharIndices>) -> Result<Vec<u8>, ParseError> {
+    let mut bytes = Vec::new();
+    loop {
+        let c = match chars.next() {
+            Some((_, c)) if c == quote => return Ok(bytes),
+            Some((_, '\\')) => match chars.next() {
+                Some((_, 'n')) => '\n',
+                Some((_, 't')) => '\t',
+                Some((_, c)) => c,
+                None => break,
+            },
+            Some((_, c)) => c,
+            None => break,
+        };
+        if !c.is_ascii() {
+            return Err(error(source, start, "only ASCII fits in a cell"));
+        }
+        bytes.push(c as u8);
+    }
+    Err(error(source, start, &format!("missing closing {}", quote)))
+}
+
+
+/// An error at `offset`, as `line:column: message`
+fn error(source: &str, offset: usize, message: &str) -> ParseError {
+    let before = &source[..offset];
+    let line = before.matches('\n').count() + 1;
+    let column = before.rsplit('\n').next().unwrap_or("").chars().count() + 1;
+    ParseError(format!("{}:{}: {}", line, column, message))
+}
+
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Expression {
+    Number(u8),
+    /// A variable, by its cell
+    Variable(usize),
+    Add(Box<Expression>, Box<Expression>),
+    Subtract(Box<Expression>, Box<Expression>),
+    Multiply(Box<Expression>, Box<Expression>),
+    Equal(Box<Expression>, Box<Expression>),
+    NotEqual(Box<Expression>, Box<Expression>),
+    Not(Box<Expression>),
+}
+
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Statement {
+    Assign(usize, Expression),
+    /// `+=` and `-=`, with whether it adds
+    Change(usize, Expression, bool),
+    While(Expression, Vec<Statement>),
+    If(Expression, Vec<Statement>, Vec<Statement>),
+    Print(Expression),
+    PrintText(Vec<u8>),
+    Read(usize),
+}
+
+
+struct Parser<'a> {
+    tokens: &'a [(Token, usize)],
+    position: usize,
+    /// Names of the variables, in the order of their cells
+    variables: Vec<String>,
+    source: &'a str,
+}
+
+
+impl Parser<'_> {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.position].0
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.tokens[self.position].0.clone();
+        if token != Token::End {
+            self.position += 1;
+        }
+        token
+    }
+
+    fn error(&self, message: &str) -> ParseError {
+        error(self.source, self.tokens[self.position].1, message)
+    }
+
+    fn eat(&mut self, symbol: &str) -> bool {
+        let found = self.peek() == &Token::Symbol(SYMBOLS.iter().find(|&&s| s == symbol).unwrap());
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), ParseError> {
+        match self.eat(symbol) {
+            true => Ok(()),
+            false => Err(self.error(&format!("expected {}", symbol))),
+        }
+    }
+
+    fn name(&mut self) -> Result<String, ParseError> {
+        match self.peek().clone() {
+            Token::Name(name) if !KEYWORDS.contains(&name.as_str()) => {
+                self.position += 1;
+                Ok(name)
+            },
+            _ => Err(self.error("expected a name")),
+        }
+    }
+
+    /// The cell of a declared variable
+    fn variable(&mut self) -> Result<usize, ParseError> {
+        let name = self.name()?;
+        self.variables.iter().position(|variable| *variable == name).ok_or_else(|| {
+            self.position -= 1;
+            self.error(&format!("{} isn't declared, with `var {};`", name, name))
+        })
+    }
+
+    fn block(&mut self) -> Result<Vec<Statement>, ParseError> {
+        self.expect("{")?;
+        let mut statements = Vec::new();
+        while !self.eat("}") {
+            if self.peek() == &Token::End {
+                return Err(self.error("expected }"));
+            }
+            statements.push(self.statement()?);
+        }
+        Ok(statements)
+    }
+
+    fn statement(&mut self) -> Result<Statement, ParseError> {
+        let statement = match self.peek() {
+            Token::Name(keyword) if keyword == "var" => {
+                self.next();
+                let name = self.name()?;
+                if self.variables.contains(&name) {
+                    self.position -= 1;
+                    return Err(self.error(&format!("{} is already declared", name)));
+                }
+                self.variables.push(name);
+                let cell = self.variables.len() - 1;
+                match self.eat("=") {
+                    true => Statement::Assign(cell, self.expression()?),
+                    false => Statement::Assign(cell, Expression::Number(0)),
+                }
+            },
+            Token::Name(keyword) if keyword == "while" => {
+                self.next();
+                let condition = self.expression()?;
+                return Ok(Statement::While(condition, self.block()?));
+            },
+            Token::Name(keyword) if keyword == "if" => {
+                self.next();
+                let condition = self.expression()?;
+                let then = self.block()?;
+                let otherwise = match self.peek() {
+                    Token::Name(keyword) if keyword == "else" => {
+                        self.next();
+                        self.block()?
+                    },
+                    _ => Vec::new(),
+                };
+                return Ok(Statement::If(condition, then, otherwise));
+            },
+            Token::Name(keyword) if keyword == "print" => {
+                self.next();
+                match self.peek().clone() {
+                    Token::Text(text) => {
+                        self.next();
+                        Statement::PrintText(text)
+                    },
+                    _ => Statement::Print(self.expression()?),
+                }
+            },
+            Token::Name(keyword) if keyword == "read" => {
+                self.next();
+                Statement::Read(self.variable()?)
+            },
+            _ => {
+                let cell = self.variable()?;
+                match self.next() {
+                    Token::Symbol("=") => Statement::Assign(cell, self.expression()?),
+                    Token::Symbol("+=") => Statement::Change(cell, self.expression()?, true),
+                    Token::Symbol("-=") => Statement::Change(cell, self.expression()?, false),
+                    _ => {
+                        self.position -= 1;
+                        return Err(self.error("expected =, += or -="));
+                    },
+                }
+            },
+        };
+        self.expect(";")?;
+        Ok(statement)
+    }
+
+    fn expression(&mut self) -> Result<Expression, ParseError> {
+        let left = self.sum()?;
+        if self.eat("==") {
+            Ok(Expression::Equal(Box::new(left), Box::new(self.sum()?)))
+        } else if self.eat("!=") {
+            Ok(Expression::NotEqual(Box::new(left), Box::new(self.sum()?)))
+        } else {
+            Ok(left)
+        }
+    }
+
+    fn sum(&mut self) -> Result<Expression, ParseError> {
+        let mut sum = self.product()?;
+        loop {
+            if self.eat("+") {
+                sum = Expression::Add(Box::new(sum), Box::new(self.product()?));
+            } else if self.eat("-") {
+                sum = Expression::Subtract(Box::new(sum), Box::new(self.product()?));
+            } else {
+                return Ok(sum);
+            }
+        }
+    }
+
+    fn product(&mut self) -> Result<Expression, ParseError> {
+        let mut product = self.unary()?;
+        while self.eat("*") {
+            product = Expression::Multiply(Box::new(product), Box::new(self.unary()?));
+        }
+        Ok(product)
+    }
+
+    fn unary(&mut self) -> Result<Expression, ParseError> {
+        if self.eat("!") {
+            return Ok(Expression::Not(Box::new(self.unary()?)));
+        }
+        if self.eat("(") {
+            let expression = self.expression()?;
+            self.expect(")")?;
+            return Ok(expression);
+        }
+        match self.peek().clone() {
+            Token::Number(number) => {
+                self.next();
+                Ok(Expression::Number(number))
+            },
+            Token::Name(_) => Ok(Expression::Variable(self.variable()?)),
+            _ => Err(self.error("expected a value")),
+        }
+    }
+}
+
+
+const KEYWORDS: [&str; 6] = ["var", "while", "if", "else", "print", "read"];
+
+
+/// Writes the brainfuck, knowing where the pointer is at every point.
+/// Cells from `next_free` on are always 0, and are taken and given back like a stack.
+struct Generator {
+    code: String,
+    pointer: usize,
+    next_free: usize,
+    /// How far in the current statement is nested, for indenting
+    depth: usize,
+}
+
+
+impl Generator {
+    fn go(&mut self, cell: usize) {
+        let step = if cell > self.pointer { '>' } else { '<' };
+        self.code.extend(std::iter::repeat_n(step, cell.abs_diff(self.pointer)));
+        self.pointer = cell;
+    }
+
+    /// Add `amount` to the current cell, going whichever way round is shorter
+    fn add(&mut self, amount: u8) {
+        let amount = amount % 128;
+        match amount <= 64 {
+            true => self.code.extend(std::iter::repeat_n('+', amount as usize)),
+            false => self.code.extend(std::iter::repeat_n('-', 128 - amount as usize)),
+        }
+    }
+
+    fn take(&mut self) -> usize {
+        self.next_free += 1;
+        self.next_free - 1
+    }
+
+    fn give_back(&mut self, cell: usize) {
+        debug_assert_eq!(cell + 1, self.next_free);
+        self.next_free = cell;
+    }
+
+    fn clear(&mut self, cell: usize) {
+        self.go(cell);
+        self.code += "[-]";
+    }
+
+    /// Move `from` onto each cell of `to`, adding or subtracting, leaving `from` at 0
+    fn move_onto(&mut self, from: usize, to: &[(usize, bool)]) {
+        self.go(from);
+        self.code += "[-";
+        for &(cell, adds) in to {
+            self.go(cell);
+            self.code.push(if adds { '+' } else { '-' });
+        }
+        self.go(from);
+        self.code.push(']');
+    }
+
+    /// Add `from` to `to`, keeping `from`
+    fn copy(&mut self, from: usize, to: usize) {
+        let spare = self.take();
+        self.move_onto(from, &[(to, true), (spare, true)]);
+        self.move_onto(spare, &[(from, true)]);
+        self.give_back(spare);
+    }
+
+    /// Work out `expression` into `target`, which is 0
+    fn expression(&mut self, expression: &Expression, target: usize) {
+        match expression {
+            Expression::Number(number) => {
+                self.go(target);
+                self.add(*number);
+            },
+            &Expression::Variable(cell) => self.copy(cell, target),
+            Expression::Add(left, right) | Expression::Subtract(left, right) => {
+                self.expression(left, target);
+                let value = self.take();
+                self.expression(right, value);
+                self.move_onto(value, &[(target, matches!(expression, Expression::Add(..)))]);
+                self.give_back(value);
+            },
+            Expression::Multiply(left, right) => {
+                let (times, value) = (self.take(), self.take());
+                self.expression(left, times);
+                self.expression(right, value);
+                self.go(times);
+                self.code.push('[');
+                self.copy(value, target);
+                self.go(times);
+                self.code += "-]";
+                self.clear(value);
+                self.give_back(value);
+                self.give_back(times);
+            },
+            Expression::Equal(left, right) | Expression::NotEqual(left, right) => {
+                let difference = self.take();
+                self.expression(&Expression::Subtract(left.clone(), right.clone()), difference);
+                self.truth(difference, target, matches!(expression, Expression::NotEqual(..)));
+                self.give_back(difference);
+            },
+            Expression::Not(operand) => {
+                let value = self.take();
+                self.expression(operand, value);
+                self.truth(value, target, false);
+                self.give_back(value);
+            },
+        }
+    }
+
+    /// Set `target` to 1 if `value` is non zero (or is zero, without `non_zero`), leaving `value` at 0
+    fn truth(&mut self, value: usize, target: usize, non_zero: bool) {
+        if !non_zero {
+            self.go(target);
+            self.code.push('+');
+        }
+        self.go(value);
+        self.code.push('[');
+        self.go(target);
+        self.code.push(if non_zero { '+' } else { '-' });
+        self.clear(value);
+        self.code.push(']');
+    }
+
+    /// Start a statement's line
+    fn line(&mut self) {
+        self.code += &"  ".repeat(self.depth);
+    }
+
+    fn end_line(&mut self) {
+        self.code.push('\n');
+    }
+
+    fn block(&mut self, statements: &[Statement]) {
+        self.depth += 1;
+        for statement in statements {
+            self.statement(statement);
+        }
+        self.depth -= 1;
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        self.line();
+        match statement {
+            &Statement::Assign(cell, Expression::Number(0)) => self.clear(cell),
+            &Statement::Assign(cell, ref value) => {
+                let result = self.take();
+                self.expression(value, result);
+                self.clear(cell);
+                self.move_onto(result, &[(cell, true)]);
+                self.give_back(result);
+            },
+            &Statement::Change(cell, ref value, adds) => {
+                let amount = self.take();
+                self.expression(value, amount);
+                self.move_onto(amount, &[(cell, adds)]);
+                self.give_back(amount);
+            },
+            Statement::While(condition, body) => {
+                let test = self.take();
+                self.expression(condition, test);
+                self.go(test);
+                self.code.push('[');
+                self.clear(test);
+                self.end_line();
+                self.block(body);
+                self.line();
+                self.expression(condition, test);
+                self.go(test);
+                self.code.push(']');
+                self.give_back(test);
+            },
+            Statement::If(condition, then, otherwise) => {
+                let (test, other) = (self.take(), self.take());
+                self.expression(condition, test);
+                if !otherwise.is_empty() {
+                    self.go(other);
+                    self.code.push('+');
+                }
+                self.go(test);
+                self.code.push('[');
+                self.clear(test);
+                if !otherwise.is_empty() {
+                    self.go(other);
+                    self.code.push('-');
+                }
+                self.end_line();
+                self.block(then);
+                self.line();
+                self.go(test);
+                self.code.push(']');
+                if !otherwise.is_empty() {
+                    self.go(other);
+                    self.code += "[-";
+                    self.end_line();
+                    self.block(otherwise);
+                    self.line();
+                    self.go(other);
+                    self.code.push(']');
+                }
+                self.give_back(other);
+                self.give_back(test);
+            },
+            &Statement::Print(Expression::Variable(cell)) => {
+                self.go(cell);
+                self.code.push('.');
+            },
+            Statement::Print(value) => {
+                let result = self.take();
+                self.expression(value, result);
+                self.go(result);
+                self.code.push('.');
+                self.clear(result);
+                self.give_back(result);
+            },
+            Statement::PrintText(text) => {
+                let cell = self.take();
+                self.go(cell);
+                let mut last = 0u8;
+                for &byte in text {
+                    self.add((byte + 128 - last) % 128);
+                    self.code.push('.');
+                    last = byte;
+                }
+                self.clear(cell);
+                self.give_back(cell);
+            },
+            &Statement::Read(cell) => {
+                self.go(cell);
+                self.code.push(',');
+            },
+        }
+        self.end_line();
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ParseOptions, Program, Vm, VmOptions, parse};
+
+    fn run(source: &str, input: &[u8]) -> Vec<u8> {
+        let code = compile(source).unwrap();
+        let program = Program::compile(&parse(&code, &ParseOptions::default()).unwrap(), true).unwrap();
+        let mut output = Vec::new();
+        Vm::new(program, &VmOptions::default()).run(&mut &input[..], &mut output).unwrap();
+        output
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(run("var a = 6; var b = a * 7 + 2 - (3 - 1); print b;", b""), [42]);
+        assert_eq!(run("var a = 100; a += 30; print a; a -= 5; print a; print 'A' + 1;", b""), [2, 125, b'B']);
+        assert_eq!(run("var a = 3; a = a * a; print a;", b""), [9]);
+    }
+
+    #[test]
+    fn control_flow() {
+        let source = "
+            var n = 5;
+            var total;
+            while n {
+                total += n * 2;
+                n -= 1;
+            }
+            if total == 30 { print \"yes\\n\"; } else { print \"no\\n\"; }
+            if total != 30 { print 'x'; }
+            if !n { print 'z'; }
+        ";
+        assert_eq!(run(source, b""), b"yes\nz");
+    }
+
+    #[test]
+    fn input() {
+        assert_eq!(run("var c; read c; while c != 10 { print c - 32; read c; }", b"shout\n"), b"SHOUT");
+    }
+
+    #[test]
+    fn lines() {
+        assert_eq!(compile("var a = 2;\nwhile a {\n  a -= 1;\n}\n").unwrap(), [
+            ">++<[-]>[-<+>]",
+            "<[->+>+<<]>>[-<<+>>]<[[-]",
+            "  >+[-<<->>]",
+            "<<[->+>+<<]>>[-<<+>>]<]",
+            "",
+        ].join("\n"));
+    }
+
+    #[test]
+    fn errors() {
+        let error = |source| compile(source).unwrap_err().0;
+        assert_eq!(error("var a;\nb = 1;"), "2:1: b isn't declared, with `var b;`");
+        assert_eq!(error("var a = 200;"), "1:9: numbers go up to 127");
+        assert_eq!(error("var a; var a;"), "1:12: a is already declared");
+        assert_eq!(error("var a\nprint a;"), "2:1: expected ;");
+        assert_eq!(error("print \"hi"), "1:7: missing closing \"");
+        assert_eq!(error("while 1 { print 1;"), "1:19: expected }");
+        assert!(is_bfc("count.bfs"));
+        assert!(!is_bfc("count.bfc"));
+    }
+}
@@ -0,0 +1,279 @@
+//! Abstract interpretation of a compiled program: the range of cells the pointer can be on before each op,
+//! and whether the current cell is known to be 0 there.
+//!
+//! Everything is conservative: a range can be wider than what really happens, never narrower, so ops the
+//! ranges show can't run off the end of a tape can skip checking.
+
+use std::collections::HashMap;
+use crate::{Op, Program};
+
+
+/// Times round a loop before its ranges are widened to whatever the pointer could reach, so analysis ends
+const WIDEN_AFTER: usize = 3;
+
+
+/// What is known about the current cell
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Cell {
+    Zero,
+    NonZero,
+    Unknown,
+}
+
+
+impl Cell {
+    fn join(self, other: Cell) -> Cell {
+        if self == other { self } else { Cell::Unknown }
+    }
+}
+
+
+/// What is known before an op runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct State {
+    /// The lowest and highest cells the pointer can be on, `high` being `None` if there is no limit
+    pub low: usize,
+    pub high: Option<usize>,
+    pub cell: Cell,
+}
+
+
+impl State {
+    /// The start of a program
+    const START: State = State { low: 0, high: Some(0), cell: Cell::Zero };
+    /// Nothing known at all
+    const ANY: State = State { low: 0, high: None, cell: Cell::Unknown };
+
+    fn join(self, other: State) -> State {
+        State {
+            low: self.low.min(other.low),
+            high: self.high.zip(other.high).map(|(a, b)| a.max(b)),
+            cell: self.cell.join(other.cell),
+        }
+    }
+
+    /// Give up on limits that are still moving, so a loop's ranges stop changing
+    fn widen(self, next: State) -> State {
+        State {
+            low: if next.low < self.low { 0 } else { self.low },
+            high: if next.high > self.high || next.high.is_none() { None } else { self.high },
+            cell: self.cell.join(next.cell),
+        }
+    }
+
+    fn with_cell(self, cell: Cell) -> State {
+        State { cell, ..self }
+    }
+
+    /// After moving `n` cells right (or left if negative), onto a cell nothing is known about
+    fn moved(self, n: isize) -> State {
+        let shift = |p: usize| p.saturating_add_signed(n);
+        State { low: shift(self.low), high: self.high.map(shift), cell: Cell::Unknown }
+    }
+}
+
+
+fn join(a: Option<State>, b: Option<State>) -> Option<State> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.join(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+
+/// The state before each op, `None` for ops that can never run.
+/// The pointers of programs with pbrain procedures or more than one tape aren't followed, so they get no limits.
+pub fn states(program: &Program) -> Vec<Option<State>> {
+    let untracked = program.ops.iter().any(|op| matches!(op, Op::ProcedureOpen(_) | Op::Call | Op::NextTape | Op::CopyToNextTape));
+    if untracked {
+        return vec![Some(State::ANY); program.ops.len()];
+    }
+    let mut analysis = Analysis { ops: &program.ops, states: vec![None; program.ops.len()], blocks: HashMap::new() };
+    analysis.block(0, program.ops.len(), Some(State::START));
+    analysis.states
+}
+
+
+struct Analysis<'a> {
+    ops: &'a [Op],
+    states: Vec<Option<State>>,
+    /// The state after each block already run from a state, since nested loops run their bodies many times
+    blocks: HashMap<(usize, State), Option<State>>,
+}
+
+
+impl Analysis<'_> {
+    /// Run ops from `start` up to `end` from `state`, returning the state after them
+    fn block(&mut self, start: usize, end: usize, state: Option<State>) -> Option<State> {
+        let state = state?;
+        if let Some(&after) = self.blocks.get(&(start, state)) {
+            return after;
+        }
+        let mut current = Some(state);
+        let mut i = start;
+        while i < end {
+            let Some(before) = current else {
+                break;
+            };
+            self.states[i] = join(self.states[i], current);
+            current = match self.ops[i] {
+                Op::JumpIfZero(close) => {
+                    let after = self.run_loop(i, close, before);
+                    i = close;
+                    after
+                },
+                op => Some(step(op, before)),
+            };
+            i += 1;
+        }
+        self.blocks.insert((start, state), current);
+        current
+    }
+
+    /// Go round the loop from `open` to `close` until its states stop changing, returning the state after it
+    fn run_loop(&mut self, open: usize, close: usize, entry: State) -> Option<State> {
+        let enters = |state: State| (state.cell != Cell::Zero).then(|| state.with_cell(Cell::NonZero));
+        let leaves = |state: State| (state.cell != Cell::NonZero).then(|| state.with_cell(Cell::Zero));
+
+        let mut body = enters(entry);
+        let mut exit = leaves(entry);
+        for round in 0.. {
+            let end = self.block(open + 1, close, body);
+            self.states[close] = join(self.states[close], end);
+            exit = join(exit, end.and_then(leaves));
+
+            let next = join(body, end.and_then(enters));
+            let next = match (body, next) {
+                (Some(body), Some(next)) if round >= WIDEN_AFTER => Some(body.widen(next)),
+                _ => next,
+            };
+            if next == body {
+                break;
+            }
+            body = next;
+        }
+        exit
+    }
+}
+
+
+/// The state after an op that isn't a loop
+fn step(op: Op, state: State) -> State {
+    match op {
+        // 128 `+` fold to `Add(0)`, which leaves the cell as it was
+        Op::Add(n) if n % 128 == 0 => state,
+        Op::Add(_) if state.cell == Cell::Zero => state.with_cell(Cell::NonZero),
+        Op::Add(_) | Op::Input | Op::Pop => state.with_cell(Cell::Unknown),
+        Op::Right(n) => state.moved(n as isize),
+        Op::Left(n) => state.moved(-(n as isize)),
+        Op::Clear => state.with_cell(Cell::Zero),
+        Op::MulAdd { .. } | Op::Output | Op::Break | Op::Push | Op::ProcedureClose => state,
+        Op::JumpIfZero(_) | Op::JumpIfNonZero(_) | Op::ProcedureOpen(_) | Op::Call | Op::NextTape | Op::CopyToNextTape => State::ANY,
+    }
+}
+
+
+/// How far right of the pointer an op reaches, for the ops that can go past the end of the tape
+fn reach(op: Op) -> Option<usize> {
+    match op {
+        Op::Right(n) => Some(n),
+        Op::MulAdd { offset, .. } => Some(offset),
+        _ => None,
+    }
+}
+
+
+/// Whether each op is sure to stay on a tape of `size` cells, so it needs no check.
+/// Ops the analysis says can't run keep their checks, in case it's wrong.
+pub fn in_bounds(program: &Program, states: &[Option<State>], size: usize) -> Vec<bool> {
+    program.ops.iter().zip(states).map(|(&op, state)| match (reach(op), state) {
+        (Some(reach), Some(state)) => state.high.is_some_and(|high| high + reach < size),
+        (Some(_), None) => false,
+        (None, _) => true,
+    }).collect()
+}
+
+
+/// The ops that always take the pointer past the end of a tape of `size` cells, whenever they run
+pub fn escapes(program: &Program, states: &[Option<State>], size: usize) -> Vec<usize> {
+    program.ops.iter().zip(states).enumerate().filter_map(|(i, (&op, state))| {
+        let reach = reach(op)?;
+        (state.as_ref()?.low + reach >= size).then_some(i)
+    }).collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ParseOptions, parse};
+
+    fn program(code: &str) -> Program {
+        Program::compile(&parse(code, &ParseOptions::default()).unwrap(), true).unwrap()
+    }
+
+    #[test]
+    fn balanced_loops_keep_their_range() {
+        let program = program("+[->>+<<]>>.");
+        let states = states(&program);
+        // The loop becomes a multiply, which reaches the same cell as the `>>` after it
+        assert_eq!(program.ops[1], Op::MulAdd { offset: 2, factor: 1 });
+        assert_eq!(states[1], Some(State { low: 0, high: Some(0), cell: Cell::NonZero }));
+        assert_eq!(states.last().unwrap().unwrap().high, Some(2));
+        assert!(in_bounds(&program, &states, 3).iter().all(|&safe| safe));
+        assert!(!in_bounds(&program, &states, 2).iter().all(|&safe| safe));
+    }
+
+    #[test]
+    fn drifting_loops_lose_their_limit() {
+        let program = program("+[>+]<.");
+        let states = states(&program);
+        assert_eq!(states.last().unwrap().unwrap().high, None);
+        assert!(!in_bounds(&program, &states, 65536)[2]);
+    }
+
+    #[test]
+    fn runs_stay_in_range() {
+        use crate::{Vm, VmOptions};
+        let programs = [
+            "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.",
+            "+++[>+++[>++<-]<<<-]>>[<+>-]>>>+[<<[->+<]>]",
+            ",[>,]<[.<]",
+        ];
+        for code in programs {
+            let program = program(code);
+            let states = states(&program);
+            let mut vm = Vm::new(program, &VmOptions::default());
+            let mut output = Vec::new();
+            for _ in 0..100_000 {
+                let Some(state) = vm.current_op().map(|_| states[vm.ip()]) else {
+                    break;
+                };
+                let state = state.expect("an op the analysis said can't run ran");
+                let pointer = vm.tape().pointer;
+                assert!(state.low <= pointer && state.high.is_none_or(|high| pointer <= high), "{} at op {}", code, vm.ip());
+                vm.step(&mut &b"abc"[..], &mut output).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn loops_on_zero_never_run() {
+        let program = program("[>>]+[-][>]>");
+        let states = states(&program);
+        assert_eq!(program.ops[4], Op::Clear);
+        assert_eq!((states[1], states[6]), (None, None));
+        assert_eq!(escapes(&program, &states, 1), vec![8]);
+        assert!(!in_bounds(&program, &states, 65536)[1]);
+    }
+
+    #[test]
+    fn wrapping_adds_keep_the_cell() {
+        // 128 `+` wrap back to 0, so the loop after them never runs and the code after it does
+        let program = program(&("+".repeat(128) + "[.]>+."));
+        assert_eq!(program.ops[0], Op::Add(0));
+        let states = states(&program);
+        assert_eq!(states[1].unwrap().cell, Cell::Zero);
+        assert_eq!(states[4], Some(State { low: 0, high: Some(0), cell: Cell::Zero }));
+    }
+}
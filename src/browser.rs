@@ -0,0 +1,163 @@
+//! wasm-bindgen bindings, for running programs in a browser playground.
+//! Errors become JavaScript exceptions.
+
+use std::{collections::VecDeque, error::Error};
+use wasm_bindgen::prelude::*;
+use crate::{Op, ParseOptions, Program, Vm, VmOptions, instruction_to_char, parse_with_offsets};
+
+
+fn options(macros: bool) -> ParseOptions {
+    ParseOptions { macros, ..ParseOptions::default() }
+}
+
+
+fn compile(code: &str, macros: bool, optimize: bool) -> Result<Program, Box<dyn Error>> {
+    Ok(Program::compile_with_offsets(&parse_with_offsets(code, &options(macros))?, optimize)?)
+}
+
+
+fn js_error(error: Box<dyn Error>) -> JsError {
+    JsError::new(&error.to_string())
+}
+
+
+/// The program's instructions with everything else removed, with macros expanded if enabled
+#[wasm_bindgen]
+pub fn parse(code: &str, macros: bool) -> Result<String, JsError> {
+    let instructions = parse_with_offsets(code, &options(macros)).map_err(js_error)?;
+    Ok(instructions.iter().map(|(instruction, _)| instruction_to_char(instruction)).collect())
+}
+
+
+fn run_bytes(code: &str, input: &str, macros: bool) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut output = Vec::new();
+    Vm::new(compile(code, macros, true)?, &VmOptions::default()).run(&mut input.as_bytes(), &mut output)?;
+    Ok(output)
+}
+
+
+/// Run a program to the end with `input`, returning its output
+#[wasm_bindgen]
+pub fn run_to_string(code: &str, input: &str, macros: bool) -> Result<String, JsError> {
+    Ok(String::from_utf8_lossy(&run_bytes(code, input, macros).map_err(js_error)?).into_owned())
+}
+
+
+/// A program that runs one op at a time, for showing its memory as it goes.
+/// Ops aren't optimized, so each step is one instruction.
+#[wasm_bindgen]
+pub struct WebVm {
+    vm: Vm,
+    input: VecDeque<u8>,
+    output: Vec<u8>,
+    on_output: Option<js_sys::Function>,
+}
+
+
+impl WebVm {
+    fn compile(code: &str, macros: bool) -> Result<WebVm, Box<dyn Error>> {
+        Ok(WebVm {
+            vm: Vm::new(compile(code, macros, false)?, &VmOptions::default()),
+            input: VecDeque::new(),
+            output: Vec::new(),
+            on_output: None,
+        })
+    }
+
+    fn run_op(&mut self) -> Result<bool, Box<dyn Error>> {
+        let Some(op) = self.vm.current_op() else {
+            return Ok(false);
+        };
+        let written = self.output.len();
+        self.vm.step(&mut self.input, &mut self.output)?;
+
+        if let (Op::Output, Some(callback)) = (op, &self.on_output) {
+            let text = String::from_utf8_lossy(&self.output[written..]).into_owned();
+            self.output.truncate(written);
+            callback.call1(&JsValue::NULL, &JsValue::from_str(&text)).map_err(|_| "the output callback threw")?;
+        }
+        Ok(!self.vm.is_finished())
+    }
+}
+
+
+#[wasm_bindgen]
+impl WebVm {
+    #[wasm_bindgen(constructor)]
+    pub fn new(code: &str, macros: bool) -> Result<WebVm, JsError> {
+        WebVm::compile(code, macros).map_err(js_error)
+    }
+
+    /// Add to the input that `,` reads, the end of it reads as 0
+    pub fn push_input(&mut self, input: &str) {
+        self.input.extend(input.bytes());
+    }
+
+    /// Call `callback` with each character the program outputs, instead of keeping it for `take_output`
+    pub fn set_output_callback(&mut self, callback: js_sys::Function) {
+        self.on_output = Some(callback);
+    }
+
+    /// Run one op, returning whether there are more
+    pub fn step(&mut self) -> Result<bool, JsError> {
+        self.run_op().map_err(js_error)
+    }
+
+    /// Run up to `max_steps` ops, so a long program doesn't block the page, returning whether there are more
+    pub fn run(&mut self, max_steps: usize) -> Result<bool, JsError> {
+        for _ in 0..max_steps {
+            if !self.run_op().map_err(js_error)? {
+                return Ok(false);
+            }
+        }
+        Ok(!self.vm.is_finished())
+    }
+
+    /// The output since the last call, empty with an output callback
+    pub fn take_output(&mut self) -> String {
+        let output = String::from_utf8_lossy(&self.output).into_owned();
+        self.output.clear();
+        output
+    }
+
+    /// The cells of the tape
+    pub fn memory(&self) -> Vec<u8> {
+        self.vm.tape().data.clone()
+    }
+
+    pub fn pointer(&self) -> usize {
+        self.vm.tape().pointer
+    }
+
+    /// Index of the next instruction
+    pub fn ip(&self) -> usize {
+        self.vm.ip()
+    }
+
+    /// Where the next instruction is in the source, in bytes
+    pub fn source_offset(&self) -> Option<usize> {
+        self.vm.program().offset(self.vm.ip())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn run_with_input() {
+        assert_eq!(run_bytes(",[.,]", "hi", false).unwrap(), b"hi");
+        assert_eq!(run_bytes("p { +. } @p@@p@", "", true).unwrap(), [1, 2]);
+    }
+
+    #[test]
+    fn step_through() {
+        let mut vm = WebVm::compile("++>+.", false).unwrap();
+        assert!(vm.run_op().unwrap());
+        assert_eq!((vm.memory(), vm.ip(), vm.source_offset()), (vec![1], 1, Some(1)));
+        while vm.run_op().unwrap() {}
+        assert_eq!((vm.memory(), vm.pointer()), (vec![2, 1], 1));
+        assert_eq!(vm.take_output(), "\u{1}");
+    }
+}
@@ -0,0 +1,350 @@
+//! The `.bfc` bytecode format, for saving compiled programs.
+//!
+//! All numbers are little endian.
+//!
+//! | Size      | Contents                                              |
+//! |-----------|-------------------------------------------------------|
+//! | 4         | Magic bytes `BFC\0`                                   |
+//! | 2         | Format version, currently 1                           |
+//! | 2         | Flags, which extensions the program uses and more     |
+//! | 4         | Number of ops                                         |
+//! | ...       | Ops, an opcode byte followed by its operands          |
+//! | 4         | Number of jump table entries                          |
+//! | 8 each    | Jump table, the index of each jump op and its target  |
+//! | 4 each    | Source offset of each op, only with `FLAG_OFFSETS`    |
+//!
+//! Jump ops (`[`, `]`, `(`) have no operands in the instruction stream, their targets
+//! are all in the jump table.
+
+use std::error::Error;
+use crate::ir::{Op, Program};
+
+
+pub const MAGIC: &[u8; 4] = b"BFC\0";
+pub const VERSION: u16 = 1;
+
+pub const FLAG_PROCEDURES: u16 = 1 << 0;
+pub const FLAG_TAPES: u16 = 1 << 1;
+pub const FLAG_STACK: u16 = 1 << 2;
+/// The file ends with source offsets for every op
+pub const FLAG_OFFSETS: u16 = 1 << 3;
+const KNOWN_FLAGS: u16 = FLAG_PROCEDURES | FLAG_TAPES | FLAG_STACK | FLAG_OFFSETS;
+
+const OP_ADD: u8 = 0x01;
+const OP_RIGHT: u8 = 0x02;
+const OP_LEFT: u8 = 0x03;
+const OP_CLEAR: u8 = 0x04;
+const OP_MUL_ADD: u8 = 0x05;
+const OP_JUMP_IF_ZERO: u8 = 0x06;
+const OP_JUMP_IF_NON_ZERO: u8 = 0x07;
+const OP_INPUT: u8 = 0x08;
+const OP_OUTPUT: u8 = 0x09;
+const OP_BREAK: u8 = 0x0a;
+const OP_PROCEDURE_OPEN: u8 = 0x0b;
+const OP_PROCEDURE_CLOSE: u8 = 0x0c;
+const OP_CALL: u8 = 0x0d;
+const OP_NEXT_TAPE: u8 = 0x0e;
+const OP_COPY_TO_NEXT_TAPE: u8 = 0x0f;
+const OP_PUSH: u8 = 0x10;
+const OP_POP: u8 = 0x11;
+
+
+/// Whether a file looks like bytecode rather than source
+pub fn is_bytecode(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+
+/// Flags describing which extensions a program needs
+pub fn flags(program: &Program) -> u16 {
+    program.ops.iter().fold(0, |flags, op| flags | match op {
+        Op::ProcedureOpen(_) | Op::ProcedureClose | Op::Call => FLAG_PROCEDURES,
+        Op::NextTape | Op::CopyToNextTape => FLAG_TAPES,
+        Op::Push | Op::Pop => FLAG_STACK,
+        _ => 0,
+    })
+}
+
+
+pub fn save(program: &Program) -> Vec<u8> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend(VERSION.to_le_bytes());
+    let has_offsets = program.offsets.len() == program.ops.len() && !program.ops.is_empty();
+    let flags = flags(program) | if has_offsets { FLAG_OFFSETS } else { 0 };
+    bytes.extend(flags.to_le_bytes());
+    bytes.extend((program.ops.len() as u32).to_le_bytes());
+
+    let mut jumps: Vec<(usize, usize)> = Vec::new();
+    for (i, op) in program.ops.iter().enumerate() {
+        match *op {
+            Op::Add(n) => bytes.extend([OP_ADD, n]),
+            Op::Right(n) => {
+                bytes.push(OP_RIGHT);
+                bytes.extend((n as u32).to_le_bytes());
+            },
+            Op::Left(n) => {
+                bytes.push(OP_LEFT);
+                bytes.extend((n as u32).to_le_bytes());
+            },
+            Op::Clear => bytes.push(OP_CLEAR),
+            Op::MulAdd { offset, factor } => {
+                bytes.push(OP_MUL_ADD);
+                bytes.extend((offset as u32).to_le_bytes());
+                bytes.push(factor);
+            },
+            Op::JumpIfZero(target) => {
+                bytes.push(OP_JUMP_IF_ZERO);
+                jumps.push((i, target));
+            },
+            Op::JumpIfNonZero(target) => {
+                bytes.push(OP_JUMP_IF_NON_ZERO);
+                jumps.push((i, target));
+            },
+            Op::Input => bytes.push(OP_INPUT),
+            Op::Output => bytes.push(OP_OUTPUT),
+            Op::Break => bytes.push(OP_BREAK),
+            Op::ProcedureOpen(target) => {
+                bytes.push(OP_PROCEDURE_OPEN);
+                jumps.push((i, target));
+            },
+            Op::ProcedureClose => bytes.push(OP_PROCEDURE_CLOSE),
+            Op::Call => bytes.push(OP_CALL),
+            Op::NextTape => bytes.push(OP_NEXT_TAPE),
+            Op::CopyToNextTape => bytes.push(OP_COPY_TO_NEXT_TAPE),
+            Op::Push => bytes.push(OP_PUSH),
+            Op::Pop => bytes.push(OP_POP),
+        }
+    }
+
+    bytes.extend((jumps.len() as u32).to_le_bytes());
+    for (from, to) in jumps {
+        bytes.extend((from as u32).to_le_bytes());
+        bytes.extend((to as u32).to_le_bytes());
+    }
+
+    if has_offsets {
+        for offset in &program.offsets {
+            bytes.extend((*offset as u32).to_le_bytes());
+        }
+    }
+    bytes
+}
+
+
+pub fn load(bytes: &[u8]) -> Result<Program, Box<dyn Error>> {
+    let mut reader = Reader { bytes, position: 0 };
+
+    if reader.take(4)? != MAGIC {
+        return Err("not a bytecode file".into());
+    }
+    let version = reader.u16()?;
+    if version != VERSION {
+        return Err(format!("unsupported bytecode version {}", version).into());
+    }
+    let flags = reader.u16()?;
+    if flags & !KNOWN_FLAGS != 0 {
+        return Err("bytecode uses unknown flags".into());
+    }
+
+    let op_count = reader.u32()? as usize;
+    let mut ops: Vec<Op> = Vec::new();
+    for _ in 0..op_count {
+        let op = match reader.u8()? {
+            OP_ADD => Op::Add(reader.u8()? % 128),
+            OP_RIGHT => Op::Right(reader.u32()? as usize),
+            OP_LEFT => Op::Left(reader.u32()? as usize),
+            OP_CLEAR => Op::Clear,
+            OP_MUL_ADD => Op::MulAdd { offset: reader.u32()? as usize, factor: reader.u8()? % 128 },
+            // Targets come from the jump table
+            OP_JUMP_IF_ZERO => Op::JumpIfZero(usize::MAX),
+            OP_JUMP_IF_NON_ZERO => Op::JumpIfNonZero(usize::MAX),
+            OP_INPUT => Op::Input,
+            OP_OUTPUT => Op::Output,
+            OP_BREAK => Op::Break,
+            OP_PROCEDURE_OPEN => Op::ProcedureOpen(usize::MAX),
+            OP_PROCEDURE_CLOSE => Op::ProcedureClose,
+            OP_CALL => Op::Call,
+            OP_NEXT_TAPE => Op::NextTape,
+            OP_COPY_TO_NEXT_TAPE => Op::CopyToNextTape,
+            OP_PUSH => Op::Push,
+            OP_POP => Op::Pop,
+            opcode => return Err(format!("unknown opcode {:#04x}", opcode).into()),
+        };
+        ops.push(op);
+    }
+
+    let jump_count = reader.u32()? as usize;
+    for _ in 0..jump_count {
+        let from = reader.u32()? as usize;
+        let to = reader.u32()? as usize;
+        let target_op = ops.get(to).copied();
+
+        // Check the target is the right kind of op, so the VM can trust it
+        let op = match (ops.get(from), target_op) {
+            (Some(Op::JumpIfZero(usize::MAX)), Some(Op::JumpIfNonZero(_))) => Op::JumpIfZero(to),
+            (Some(Op::JumpIfNonZero(usize::MAX)), Some(Op::JumpIfZero(_))) => Op::JumpIfNonZero(to),
+            (Some(Op::ProcedureOpen(usize::MAX)), Some(Op::ProcedureClose)) => Op::ProcedureOpen(to),
+            _ => return Err(format!("invalid jump table entry {} -> {}", from, to).into()),
+        };
+        ops[from] = op;
+    }
+
+    let mut offsets: Vec<usize> = Vec::new();
+    if flags & FLAG_OFFSETS != 0 {
+        for _ in 0..ops.len() {
+            offsets.push(reader.u32()? as usize);
+        }
+    }
+
+    if reader.position != bytes.len() {
+        return Err("unexpected data at the end of the bytecode".into());
+    }
+    if ops.iter().any(|op| matches!(op, Op::JumpIfZero(usize::MAX) | Op::JumpIfNonZero(usize::MAX) | Op::ProcedureOpen(usize::MAX))) {
+        return Err("jump table is missing entries".into());
+    }
+    check_jumps(&ops)?;
+
+    Ok(Program { ops, offsets })
+}
+
+
+/// Check every jump goes to the op it pairs with, the way `Program::compile` links them: brackets in nested pairs,
+/// and each procedure to the close that ends it
+fn check_jumps(ops: &[Op]) -> Result<(), String> {
+    let mut open_brackets: Vec<(usize, usize)> = Vec::new();
+    let mut open_procedures: Vec<(usize, usize)> = Vec::new();
+    for (i, &op) in ops.iter().enumerate() {
+        match op {
+            Op::JumpIfZero(close) => open_brackets.push((i, close)),
+            Op::JumpIfNonZero(open) => match open_brackets.pop() {
+                Some((expected, close)) if expected == open && close == i => (),
+                _ => return Err(format!("the jump at op {} doesn't pair with the one at op {}", i, open)),
+            },
+            Op::ProcedureOpen(close) => open_procedures.push((i, close)),
+            Op::ProcedureClose => match open_procedures.pop() {
+                Some((_, close)) if close != i => return Err(format!("the procedure ending at op {} is closed at op {}", close, i)),
+                _ => (),
+            },
+            _ => (),
+        }
+    }
+    match (open_brackets.first(), open_procedures.first()) {
+        (Some((open, close)), _) | (_, Some((open, close))) => Err(format!("the jump at op {} doesn't pair with the one at op {}", open, close)),
+        (None, None) => Ok(()),
+    }
+}
+
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8], &'static str> {
+        let Some(bytes) = self.bytes.get(self.position..(self.position + length)) else {
+            return Err("bytecode ended unexpectedly");
+        };
+        self.position += length;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, &'static str> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, &'static str> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, &'static str> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse, ParseOptions, Dialect};
+
+    fn compile(code: &str, options: &ParseOptions) -> Program {
+        Program::compile(&parse(code, options).unwrap(), true).unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let program = compile("+++[->>++<<]>>[.<,]>>>>+<<@", &ParseOptions { breakpoints: true, ..Default::default() });
+        let bytes = save(&program);
+
+        assert!(is_bytecode(&bytes));
+        assert_eq!(load(&bytes).unwrap(), program);
+    }
+
+    #[test]
+    fn round_trip_offsets() {
+        let instructions = crate::parse_with_offsets("+ + [->+<] .", &ParseOptions::default()).unwrap();
+        let program = Program::compile_with_offsets(&instructions, true).unwrap();
+        let bytes = save(&program);
+
+        assert_eq!(u16::from_le_bytes([bytes[6], bytes[7]]), FLAG_OFFSETS);
+        assert_eq!(load(&bytes).unwrap(), program);
+    }
+
+    #[test]
+    fn procedure_flags() {
+        let program = compile("+(-):", &ParseOptions { dialect: Dialect::Pbrain, ..Default::default() });
+        let bytes = save(&program);
+
+        assert_eq!(flags(&program), FLAG_PROCEDURES);
+        assert_eq!(load(&bytes).unwrap(), program);
+    }
+
+    #[test]
+    fn reject_bad_jumps() {
+        let mut bytes = save(&compile("[.]", &ParseOptions::default()));
+        // Point the `[` at the `.`
+        let length = bytes.len();
+        bytes[(length - 12)..(length - 8)].copy_from_slice(&1u32.to_le_bytes());
+
+        assert!(load(&bytes).is_err());
+    }
+
+    /// Bytecode for `program` with the jump table entry of the op at `from` going to `to` instead
+    fn retargeted(program: &Program, from: usize, to: usize) -> Vec<u8> {
+        let mut program = program.clone();
+        program.ops[from] = match program.ops[from] {
+            Op::JumpIfZero(_) => Op::JumpIfZero(to),
+            Op::JumpIfNonZero(_) => Op::JumpIfNonZero(to),
+            Op::ProcedureOpen(_) => Op::ProcedureOpen(to),
+            op => op,
+        };
+        save(&program)
+    }
+
+    #[test]
+    fn reject_crossing_and_unpaired_jumps() {
+        let program = compile("[.[.].]", &ParseOptions::default());
+        assert!(load(&save(&program)).is_ok());
+        // The outer `[` jumping to the inner `]`, which still jumps back to the inner `[`
+        assert!(load(&retargeted(&program, 0, 4)).is_err());
+        // Both `]` jumping back to the outer `[`, so the inner `[` has no pair
+        assert!(load(&retargeted(&program, 4, 0)).is_err());
+        // Crossing pairs: `[` 0 with `]` 4, and `[` 2 with `]` 6
+        let crossing = save(&Program { ops: vec![
+            Op::JumpIfZero(4), Op::Output, Op::JumpIfZero(6), Op::Output, Op::JumpIfNonZero(0), Op::Output, Op::JumpIfNonZero(2),
+        ], offsets: Vec::new() });
+        assert!(load(&crossing).is_err());
+
+        let procedures = compile("(+)(-):", &ParseOptions { dialect: Dialect::Pbrain, ..Default::default() });
+        assert!(load(&save(&procedures)).is_ok());
+        assert!(load(&retargeted(&procedures, 0, 5)).is_err());
+    }
+
+    #[test]
+    fn reject_truncated() {
+        let bytes = save(&compile("+[-]>.", &ParseOptions::default()));
+        assert!(load(&bytes[..(bytes.len() - 1)]).is_err());
+        assert!(load(b"BFC").is_err());
+    }
+}
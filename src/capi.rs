@@ -0,0 +1,240 @@
+//! C interface for embedding the interpreter, declared in `include/bf_rs.h`.
+//!
+//! Functions return `BF_OK` or a negative error code, and `bf_last_error` has the message
+//! of the last error on the calling thread.
+
+use std::{cell::RefCell, collections::VecDeque, error::Error, ffi::{c_char, c_int, CStr, CString}, ptr, slice};
+use crate::{ParseOptions, Program, Vm, VmOptions, error, parse_with_offsets};
+
+
+pub const BF_OK: c_int = 0;
+/// `bf_vm_step` ran the last op
+pub const BF_FINISHED: c_int = 1;
+pub const BF_ERROR_NULL: c_int = -1;
+pub const BF_ERROR_UTF8: c_int = -2;
+pub const BF_ERROR_PARSE: c_int = -(error::PARSE as c_int);
+pub const BF_ERROR_MACRO: c_int = -(error::MACRO as c_int);
+pub const BF_ERROR_RUNTIME: c_int = -(error::RUNTIME as c_int);
+pub const BF_ERROR_IO: c_int = -(error::IO as c_int);
+
+/// `bf_vm_new` flag to enable macros
+pub const BF_MACROS: c_int = 1;
+/// `bf_vm_new` flag to enable breakpoints, which do nothing without a debugger
+pub const BF_BREAKPOINTS: c_int = 2;
+/// `bf_vm_new` flag to keep one op per instruction, for stepping through a program
+pub const BF_NO_OPTIMIZE: c_int = 4;
+
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+
+/// Remember the error's message and return its code
+fn fail(code: c_int, error: Box<dyn Error>) -> c_int {
+    // Messages don't have nul bytes, but they can't be allowed to panic either
+    let message = CString::new(error.to_string().replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+    code
+}
+
+
+/// An interpreter with its input and output buffers
+pub struct BfVm {
+    vm: Vm,
+    input: VecDeque<u8>,
+    output: Vec<u8>,
+}
+
+
+fn compile(code: &str, flags: c_int) -> Result<Program, Box<dyn Error>> {
+    let options = ParseOptions {
+        macros: flags & BF_MACROS != 0,
+        breakpoints: flags & BF_BREAKPOINTS != 0,
+        ..ParseOptions::default()
+    };
+    let instructions = parse_with_offsets(code, &options).map_err(error::parse)?;
+    Program::compile_with_offsets(&instructions, flags & BF_NO_OPTIMIZE == 0).map_err(|error| error::parse(error.into()))
+}
+
+
+/// Parse and compile a nul-terminated brainfuck program, storing the vm in `*out` (NULL on errors).
+///
+/// # Safety
+/// `code` must be a valid C string and `out` must be valid to write to.
+#[no_mangle]
+pub unsafe extern "C" fn bf_vm_new(code: *const c_char, flags: c_int, out: *mut *mut BfVm) -> c_int {
+    if code.is_null() || out.is_null() {
+        return fail(BF_ERROR_NULL, "code and out can't be NULL".into());
+    }
+    *out = ptr::null_mut();
+    let Ok(code) = CStr::from_ptr(code).to_str() else {
+        return fail(BF_ERROR_UTF8, "the code isn't valid UTF-8".into());
+    };
+
+    match compile(code, flags) {
+        Ok(program) => {
+            let vm = BfVm { vm: Vm::new(program, &VmOptions::default()), input: VecDeque::new(), output: Vec::new() };
+            *out = Box::into_raw(Box::new(vm));
+            BF_OK
+        },
+        Err(error) => fail(-(error::exit_code(&*error) as c_int), error),
+    }
+}
+
+
+/// Free a vm from `bf_vm_new`, NULL is ignored.
+///
+/// # Safety
+/// `vm` must come from `bf_vm_new` and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn bf_vm_free(vm: *mut BfVm) {
+    if !vm.is_null() {
+        drop(Box::from_raw(vm));
+    }
+}
+
+
+/// Run one op: `BF_OK` if there are more, `BF_FINISHED` after the last one, or an error.
+///
+/// # Safety
+/// `vm` must come from `bf_vm_new`.
+#[no_mangle]
+pub unsafe extern "C" fn bf_vm_step(vm: *mut BfVm) -> c_int {
+    let Some(vm) = vm.as_mut() else {
+        return fail(BF_ERROR_NULL, "vm can't be NULL".into());
+    };
+    if let Err(error) = vm.vm.step(&mut vm.input, &mut vm.output) {
+        let error = error::runtime(error);
+        return fail(-(error::exit_code(&*error) as c_int), error);
+    }
+    if vm.vm.is_finished() { BF_FINISHED } else { BF_OK }
+}
+
+
+/// Run until the program ends, `BF_FINISHED` or an error.
+///
+/// # Safety
+/// `vm` must come from `bf_vm_new`.
+#[no_mangle]
+pub unsafe extern "C" fn bf_vm_run(vm: *mut BfVm) -> c_int {
+    loop {
+        let code = bf_vm_step(vm);
+        if code != BF_OK {
+            return code;
+        }
+    }
+}
+
+
+/// Add `length` bytes to the input that `,` reads. The end of input reads as 0.
+///
+/// # Safety
+/// `vm` must come from `bf_vm_new` and `data` must point to `length` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn bf_vm_push_input(vm: *mut BfVm, data: *const u8, length: usize) -> c_int {
+    let Some(vm) = vm.as_mut() else {
+        return fail(BF_ERROR_NULL, "vm can't be NULL".into());
+    };
+    if length > 0 {
+        if data.is_null() {
+            return fail(BF_ERROR_NULL, "data can't be NULL".into());
+        }
+        vm.input.extend(slice::from_raw_parts(data, length));
+    }
+    BF_OK
+}
+
+
+/// Move up to `capacity` bytes of output into `buffer`, returning how many were moved.
+///
+/// # Safety
+/// `vm` must come from `bf_vm_new` and `buffer` must have room for `capacity` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn bf_vm_take_output(vm: *mut BfVm, buffer: *mut u8, capacity: usize) -> usize {
+    let Some(vm) = vm.as_mut() else {
+        return 0;
+    };
+    let count = capacity.min(vm.output.len());
+    if count > 0 && !buffer.is_null() {
+        ptr::copy_nonoverlapping(vm.output.as_ptr(), buffer, count);
+        vm.output.drain(..count);
+        return count;
+    }
+    0
+}
+
+
+/// The cells of the tape, with their number in `*length`. The tape grows as the program
+/// moves right, so the pointer is only valid until the next step.
+///
+/// # Safety
+/// `vm` must come from `bf_vm_new` and `length` must be valid to write to or NULL.
+#[no_mangle]
+pub unsafe extern "C" fn bf_vm_tape_ptr(vm: *const BfVm, length: *mut usize) -> *const u8 {
+    let Some(vm) = vm.as_ref() else {
+        return ptr::null();
+    };
+    let data = &vm.vm.tape().data;
+    if !length.is_null() {
+        *length = data.len();
+    }
+    data.as_ptr()
+}
+
+
+/// Index of the current cell.
+///
+/// # Safety
+/// `vm` must come from `bf_vm_new`.
+#[no_mangle]
+pub unsafe extern "C" fn bf_vm_pointer(vm: *const BfVm) -> usize {
+    vm.as_ref().map_or(0, |vm| vm.vm.tape().pointer)
+}
+
+
+/// The message of the last error on this thread, or NULL. It stays valid until the next error.
+#[no_mangle]
+pub extern "C" fn bf_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |message| message.as_ptr()))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn run_and_read_output() {
+        unsafe {
+            let mut vm = ptr::null_mut();
+            assert_eq!(bf_vm_new(c",[+.,]".as_ptr(), 0, &mut vm), BF_OK);
+            assert_eq!(bf_vm_push_input(vm, b"ab".as_ptr(), 2), BF_OK);
+            assert_eq!(bf_vm_run(vm), BF_FINISHED);
+
+            let mut buffer = [0u8; 8];
+            assert_eq!(bf_vm_take_output(vm, buffer.as_mut_ptr(), buffer.len()), 2);
+            assert_eq!(&buffer[..2], b"bc");
+
+            let mut length = 0;
+            let tape = bf_vm_tape_ptr(vm, &mut length);
+            assert_eq!(slice::from_raw_parts(tape, length), [0]);
+            bf_vm_free(vm);
+        }
+    }
+
+    #[test]
+    fn error_codes() {
+        unsafe {
+            let mut vm = ptr::null_mut();
+            assert_eq!(bf_vm_new(c"[".as_ptr(), 0, &mut vm), BF_ERROR_PARSE);
+            assert!(vm.is_null());
+            assert_eq!(bf_vm_new(c"a { @a@ } @a@".as_ptr(), BF_MACROS, &mut vm), BF_ERROR_MACRO);
+            assert_eq!(CStr::from_ptr(bf_last_error()).to_str().unwrap(), "recursive macros are not allowed");
+
+            assert_eq!(bf_vm_new(c"+".as_ptr(), BF_NO_OPTIMIZE, &mut vm), BF_OK);
+            assert_eq!(bf_vm_step(vm), BF_FINISHED);
+            bf_vm_free(vm);
+        }
+    }
+}
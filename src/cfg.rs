@@ -0,0 +1,122 @@
+//! A compiled program's control flow graph, as Graphviz DOT
+//!
+//! Each basic block is a run of ops that always run one after the other, ending at a `[` or `]` that picks which
+//! block comes next. Procedure calls aren't drawn, since which procedure `:` calls depends on the tape.
+
+use std::{fmt::Write, ops::Range};
+use crate::{Op, Program, emit::source_map::LineIndex};
+
+
+/// Ops shown in a block's label before the rest are left out
+const SHOWN_OPS: usize = 12;
+
+
+/// The ops of each basic block, in program order
+pub fn blocks(program: &Program) -> Vec<Range<usize>> {
+    let mut blocks = Vec::new();
+    let mut start = 0;
+    for (i, op) in program.ops.iter().enumerate() {
+        if ends_block(op) {
+            blocks.push(start..i + 1);
+            start = i + 1;
+        }
+    }
+    if start < program.ops.len() {
+        blocks.push(start..program.ops.len());
+    }
+    blocks
+}
+
+
+fn ends_block(op: &Op) -> bool {
+    matches!(op, Op::JumpIfZero(_) | Op::JumpIfNonZero(_) | Op::ProcedureOpen(_) | Op::ProcedureClose)
+}
+
+
+/// Where a block can go next, as the op it goes to (past the last one for the end of the program) and the edge's label
+fn successors(program: &Program, block: &Range<usize>) -> Vec<(usize, &'static str)> {
+    let last = block.end - 1;
+    match program.ops[last] {
+        Op::JumpIfZero(close) => vec![(close + 1, "0"), (last + 1, "not 0")],
+        Op::JumpIfNonZero(open) => vec![(open + 1, "not 0"), (last + 1, "0")],
+        Op::ProcedureOpen(close) => vec![(close + 1, "")],
+        // A `)` goes back to the call, or on if it isn't in one
+        _ => vec![(last + 1, "")],
+    }
+}
+
+
+/// The graph as DOT, each block labeled with its ops and where it starts in `source` if there is one.
+/// With `counts` from `profile::run_profiled`, blocks also show how many times they ran.
+pub fn dot(program: &Program, source: Option<&str>, counts: Option<&[u64]>) -> String {
+    let blocks = blocks(program);
+    let index = source.map(LineIndex::new);
+    let block_of = |op: usize| blocks.iter().position(|block| block.start == op);
+
+    let mut dot = String::from("digraph program {\n    node [shape=box, fontname=monospace];\n");
+    dot += "    start [shape=point];\n    end [shape=doublecircle, label=\"end\"];\n";
+    for (i, block) in blocks.iter().enumerate() {
+        let mut label = format!("ops {}-{}", block.start, block.end - 1);
+        if let (Some(source), Some(index), Some(offset)) = (source, &index, program.offset(block.start)) {
+            let (line, column) = index.line_column(source, offset);
+            let _ = write!(label, " at {}:{}", line + 1, column + 1);
+        }
+        let ops: Vec<String> = program.ops[block.clone()].iter().take(SHOWN_OPS).map(|op| op.to_string()).collect();
+        let _ = write!(label, "\\n{}", escape(&ops.join(" ")));
+        if block.len() > SHOWN_OPS {
+            let _ = write!(label, " ... ({} ops)", block.len());
+        }
+        if let Some(counts) = counts {
+            let _ = write!(label, "\\nran {} times", counts[block.start]);
+        }
+        let _ = writeln!(dot, "    b{} [label=\"{}\"];", i, label);
+    }
+
+    let node = |op: usize| block_of(op).map_or("end".to_string(), |block| format!("b{}", block));
+    let _ = writeln!(dot, "    start -> {};", node(0));
+    for (i, block) in blocks.iter().enumerate() {
+        for (to, label) in successors(program, block) {
+            match label {
+                "" => { let _ = writeln!(dot, "    b{} -> {};", i, node(to)); },
+                _ => { let _ = writeln!(dot, "    b{} -> {} [label=\"{}\"];", i, node(to), label); },
+            }
+        }
+    }
+    dot += "}\n";
+    dot
+}
+
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ParseOptions, VmOptions, parse_with_offsets, profile};
+
+    fn program(code: &str) -> Program {
+        Program::compile_with_offsets(&parse_with_offsets(code, &ParseOptions::default()).unwrap(), true).unwrap()
+    }
+
+    #[test]
+    fn blocks_end_at_brackets() {
+        let program = program("++[>+.<-]>.");
+        assert_eq!(blocks(&program), vec![0..2, 2..8, 8..10]);
+        let dot = dot(&program, None, None);
+        assert!(dot.contains("    b0 -> b2 [label=\"0\"];\n    b0 -> b1 [label=\"not 0\"];\n"));
+        assert!(dot.contains("    b1 -> b1 [label=\"not 0\"];\n    b1 -> b2 [label=\"0\"];\n    b2 -> end;\n"));
+    }
+
+    #[test]
+    fn labels() {
+        let code = "+++\n[-.]";
+        let program = program(code);
+        let (_, counts) = profile::run_profiled(program.clone(), &VmOptions::default(), &mut std::io::empty(), &mut std::io::sink()).unwrap();
+        let dot = dot(&program, Some(code), Some(&counts));
+        assert!(dot.contains("    b0 [label=\"ops 0-1 at 1:1\\n+3 [\\nran 1 times\"];\n"), "{}", dot);
+        assert!(dot.contains("    b1 [label=\"ops 2-4 at 2:2\\n- . ]\\nran 3 times\"];\n"), "{}", dot);
+    }
+}
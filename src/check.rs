@@ -0,0 +1,167 @@
+use std::fmt;
+use serde_json::json;
+use crate::{Instruction, ParseOptions, parse_with_offsets, parse_with_libraries, Dialect, error::{MacroError, ParseError}};
+use crate::emit::source_map::LineIndex;
+
+
+/// A problem found in a program without running it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// Byte offset in the source, if the problem has one place
+    pub offset: Option<usize>,
+    /// What kind of problem it is, like `unmatched-bracket`, for tools to match on
+    pub code: &'static str,
+    pub message: String,
+}
+
+
+impl Diagnostic {
+    /// `path:line:column: message`, with lines and columns counting from 1
+    pub fn render(&self, path: &str, source: &str) -> String {
+        match self.offset {
+            Some(offset) => {
+                let (line, column) = LineIndex::new(source).line_column(source, offset);
+                format!("{}:{}:{}: {}", path, line + 1, column + 1, self.message)
+            },
+            None => format!("{}: {}", path, self.message),
+        }
+    }
+
+    /// An object with the file, the place as lines and columns counting from 1 (the end is exclusive)
+    /// and the problem, for editors and CI to annotate the source with
+    pub fn json(&self, path: &str, source: &str) -> serde_json::Value {
+        let range = self.offset.map(|offset| {
+            let index = LineIndex::new(source);
+            let (line, column) = index.line_column(source, offset);
+            let (end_line, end_column) = index.line_column(source, offset + 1);
+            json!({
+                "start": { "line": line + 1, "column": column + 1 },
+                "end": { "line": end_line + 1, "column": end_column + 1 },
+            })
+        });
+        json!({
+            "severity": "error",
+            "code": self.code,
+            "message": self.message,
+            "file": path,
+            "range": range,
+        })
+    }
+}
+
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.offset {
+            Some(offset) => write!(f, "offset {}: {}", offset, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+
+/// Parse a program, expanding macros, and find every unmatched bracket and procedure.
+/// Macro errors stop parsing, so there is at most one of them.
+pub fn check(libraries: &[&str], code: &str, options: &ParseOptions) -> Vec<Diagnostic> {
+    let parsed = if libraries.is_empty() {
+        parse_with_offsets(code, options)
+    } else if options.dialect == Dialect::Ook {
+        Err("Ook! doesn't have macros, so it can't use libraries".into())
+    } else {
+        parse_with_libraries(libraries, code, options)
+    };
+    match parsed {
+        Ok(instructions) => brackets(&instructions),
+        Err(error) => {
+            let code = if error.is::<MacroError>() { "macro" } else { "parse" };
+            vec![Diagnostic { offset: None, code, message: error.to_string() }]
+        },
+    }
+}
+
+
+/// Every unmatched bracket and procedure in parsed instructions, in source order
+pub fn brackets(instructions: &[(Instruction, usize)]) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let mut open_brackets: Vec<usize> = Vec::new();
+    let mut open_procedures: Vec<usize> = Vec::new();
+    for &(instruction, offset) in instructions {
+        let (open, message) = match instruction {
+            Instruction::Open => { open_brackets.push(offset); continue },
+            Instruction::ProcedureOpen => { open_procedures.push(offset); continue },
+            Instruction::Close => (&mut open_brackets, "`]` has no matching `[`"),
+            Instruction::ProcedureClose => (&mut open_procedures, "`)` has no matching `(`"),
+            _ => continue,
+        };
+        if open.pop().is_none() {
+            diagnostics.push(Diagnostic { offset: Some(offset), code: "unmatched-bracket", message: message.to_string() });
+        }
+    }
+
+    diagnostics.extend(open_brackets.into_iter()
+        .map(|offset| Diagnostic { offset: Some(offset), code: "unclosed-bracket", message: "`[` is never closed".to_string() }));
+    diagnostics.extend(open_procedures.into_iter()
+        .map(|offset| Diagnostic { offset: Some(offset), code: "unclosed-bracket", message: "`(` is never closed".to_string() }));
+    diagnostics.sort_by_key(|diagnostic| diagnostic.offset);
+    diagnostics
+}
+
+
+/// Fail with every unmatched bracket and where it is, so nothing runs before they are fixed
+pub fn validate(instructions: &[(Instruction, usize)], path: &str, source: &str) -> Result<(), ParseError> {
+    let diagnostics = brackets(instructions);
+    if diagnostics.is_empty() {
+        return Ok(());
+    }
+    let mut message = format!("{} unmatched bracket{}", diagnostics.len(), if diagnostics.len() == 1 { "" } else { "s" });
+    for diagnostic in &diagnostics {
+        message += &format!("\n  {}", diagnostic.render(path, source));
+    }
+    Err(ParseError(message))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_unmatched_bracket() {
+        let code = "]+[\n[-]]]\n[";
+        let diagnostics = check(&[], code, &ParseOptions::default());
+        let rendered: Vec<String> = diagnostics.iter().map(|diagnostic| diagnostic.render("a.b", code)).collect();
+        assert_eq!(rendered, vec![
+            "a.b:1:1: `]` has no matching `[`",
+            "a.b:2:5: `]` has no matching `[`",
+            "a.b:3:1: `[` is never closed",
+        ]);
+    }
+
+    #[test]
+    fn valid_program() {
+        let options = ParseOptions { macros: true, ..ParseOptions::default() };
+        assert!(check(&[], "loop { [-] } +@loop@", &options).is_empty());
+        assert_eq!(check(&[], "a { @a@ } @a@", &options).len(), 1);
+    }
+
+    #[test]
+    fn validate_lists_every_bracket() {
+        let code = "[\n]]";
+        let instructions = parse_with_offsets(code, &ParseOptions::default()).unwrap();
+        assert_eq!(validate(&instructions, "a.b", code).unwrap_err().0, "1 unmatched bracket\n  a.b:2:2: `]` has no matching `[`");
+        assert!(validate(&instructions[..2], "a.b", code).is_ok());
+    }
+
+    #[test]
+    fn json_ranges() {
+        let code = "+\n+]";
+        let diagnostics = check(&[], code, &ParseOptions::default());
+        assert_eq!(diagnostics[0].json("a.b", code), json!({
+            "severity": "error",
+            "code": "unmatched-bracket",
+            "message": "`]` has no matching `[`",
+            "file": "a.b",
+            "range": { "start": { "line": 2, "column": 2 }, "end": { "line": 2, "column": 3 } },
+        }));
+    }
+}
@@ -0,0 +1,1004 @@
+//! Command line arguments for the `bf-rs` binary
+
+use std::{io::{self, IsTerminal}, str::FromStr};
+use clap::{ArgAction, Parser, Subcommand, ValueEnum};
+use log::LevelFilter;
+use crate::{DebugFormat, Dialect, Eof, Extension, ParseOptions, Vm, config::Config, dashboard::Dashboard, progress::Progress, hang::Warnings as HangWarnings, detect_dialect, framebuffer::{Framebuffer, Size}, emit, image::{Palette, Recording, TapeImage}, trace::{Trace, TraceFormat}};
+
+
+/// A brainfuck interpreter, with debugging, transpiling and compiling
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Print more about what bf-rs is doing to stderr, repeat for even more
+    #[arg(short, long, action = ArgAction::Count, global = true)]
+    pub verbose: u8,
+
+    /// Only print errors
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Without a subcommand, `bf-rs <FILEPATH>` is the same as `bf-rs run <FILEPATH>`
+    #[command(flatten)]
+    pub run: RunArgs,
+}
+
+
+impl Args {
+    /// Warnings by default, fewer or more with `-q` and `-v`
+    pub fn log_level(&self) -> LevelFilter {
+        match (self.quiet, self.verbose) {
+            (true, _) => LevelFilter::Error,
+            (false, 0) => LevelFilter::Warn,
+            (false, 1) => LevelFilter::Info,
+            (false, 2) => LevelFilter::Debug,
+            (false, _) => LevelFilter::Trace,
+        }
+    }
+
+    /// The subcommand to run, which is `run` if none was given
+    pub fn into_command(self) -> Command {
+        self.command.unwrap_or(Command::Run(self.run))
+    }
+}
+
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Run a program (the default)
+    Run(RunArgs),
+
+    /// Run a program, printing memory and instructions after every step
+    Debug(DebugArgs),
+
+    /// Check a program for errors without running it
+    Check(CheckArgs),
+
+    /// Point out likely mistakes in a program, like loops that can never run
+    Analyze(AnalyzeArgs),
+
+    /// Re-indent a program by loop nesting, keeping its comments
+    Fmt(FmtArgs),
+
+    /// Compile a program to a native executable
+    Compile(CompileArgs),
+
+    /// Time how long a program takes to run
+    Bench(BenchArgs),
+
+    /// List the ops in a bytecode (.bfc) file
+    Disasm(DisasmArgs),
+
+    /// Write a program's control flow graph as Graphviz DOT
+    Cfg(CfgArgs),
+
+    /// Print a program with everything but its instructions removed
+    Minify(MinifyArgs),
+
+    /// Look for a shorter program that does the same, checked on sample inputs
+    Golf(GolfArgs),
+
+    /// Print a short program that prints the given text
+    GenPrint(GenPrintArgs),
+
+    /// Print short code that sets a cell that's 0 to the given value
+    Const(ConstArgs),
+
+    /// Print random programs with matched brackets, for testing interpreters
+    GenRandom(GenRandomArgs),
+
+    /// Compile a program in bfc, a small language with variables, loops and ifs, to brainfuck
+    Bfc(BfcArgs),
+
+    /// Check two programs write the same output and leave the same tape, for a set of inputs
+    Equiv(EquivArgs),
+
+    /// Experimental: find inputs that reach breakpoints or move the pointer left of cell 0, by trying every path
+    Explore(ExploreArgs),
+
+    /// Run programs against their .in/.out or .tests.toml fixtures
+    Test(TestArgs),
+
+    /// Check how good programs' fixtures are, by making small changes to the programs that the fixtures should catch
+    Mutate(MutateArgs),
+
+    /// Run programs at the same time, each one's output being the next one's input
+    #[cfg(not(target_family = "wasm"))]
+    Pipe(PipeArgs),
+
+    /// Run a program on many input files in parallel, writing an output file for each and a table of how they went
+    #[cfg(not(target_family = "wasm"))]
+    Batch(BatchArgs),
+
+    /// Print a shell completion script
+    Completions(CompletionsArgs),
+
+    /// Run a language server for editors, over stdin and stdout
+    #[cfg(not(target_family = "wasm"))]
+    Lsp,
+
+    /// Serve a playground page for writing and running programs in the browser
+    #[cfg(not(target_family = "wasm"))]
+    Serve(ServeArgs),
+}
+
+
+// Where a program comes from, and how to parse it
+#[derive(clap::Args, Debug)]
+pub struct SourceArgs {
+    /// Program to run, `-` to read it from stdin.
+    /// Files before the last one are libraries, whose macros the program can call.
+    #[arg(required_unless_present = "eval", conflicts_with = "eval")]
+    pub filepaths: Vec<String>,
+
+    /// Run this code instead of a file
+    #[arg(short, long, value_name = "CODE")]
+    pub eval: Option<String>,
+
+    /// In a Markdown program, only use the ```bf blocks with this name, as in ```bf NAME (can be repeated)
+    #[arg(long = "block", value_name = "NAME")]
+    pub blocks: Vec<String>,
+
+    /// Look for `#include <name>` libraries in this directory, before those in BF_PATH (can be repeated)
+    #[arg(short = 'I', long = "include-dir", value_name = "DIR")]
+    pub include_dirs: Vec<String>,
+
+    /// Stop the program at its first `!`, and give what comes after it to the program as the start of its input
+    #[arg(long)]
+    pub bang: bool,
+
+    #[command(flatten)]
+    pub parse: ParseArgs,
+}
+
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct ParseArgs {
+    /// Source dialect (detected from the file extension by default)
+    #[arg(long, value_enum)]
+    pub dialect: Option<Dialect>,
+
+    /// Enable breakpoints
+    #[arg(short='b', long)]
+    pub breakpoints: bool,
+
+    /// Enable macros
+    #[arg(short='m', long)]
+    pub macros: bool,
+
+    /// Enable language extensions (comma separated)
+    #[arg(short='x', long, value_enum, value_delimiter=',')]
+    pub extensions: Vec<Extension>,
+}
+
+
+impl ParseArgs {
+    /// Options for parsing the file at `filepath`, with anything the flags don't set taken from `config`.
+    /// The file extension picks the dialect if neither has one.
+    pub fn options(&self, filepath: &str, config: &Config) -> ParseOptions {
+        ParseOptions {
+            dialect: self.dialect.or(config.dialect).unwrap_or_else(|| detect_dialect(filepath)),
+            breakpoints: self.breakpoints || config.breakpoints.unwrap_or(false),
+            macros: self.macros || config.macros.unwrap_or(false),
+            extensions: match (&self.extensions[..], &config.extensions) {
+                ([], Some(extensions)) => extensions.clone(),
+                (extensions, _) => extensions.to_vec(),
+            },
+        }
+    }
+}
+
+
+// The vm settings every subcommand that runs programs has
+#[derive(clap::Args, Debug, Clone)]
+pub struct VmArgs {
+    /// Number of tapes for the tapes extension [default: 2]
+    #[arg(long)]
+    pub tapes: Option<usize>,
+
+    /// What `,` sets the cell to once input runs out [default: zero]
+    #[arg(long, value_enum)]
+    pub eof: Option<Eof>,
+}
+
+
+// How to run a program
+#[derive(clap::Args, Debug)]
+pub struct ExecArgs {
+    #[command(flatten)]
+    pub vm: VmArgs,
+
+    /// Read the program's input from this file instead of stdin
+    #[arg(long)]
+    pub input: Option<String>,
+
+    /// Once the --input file runs out, read from the terminal, to start a program off with data and then use it
+    #[arg(long, requires = "input")]
+    pub then_terminal: bool,
+
+    /// Give the program these arguments as input, joined by spaces and ending with a newline
+    #[arg(last = true, conflicts_with = "input")]
+    pub args: Vec<String>,
+
+    /// Don't wait for input: `,` reads this value when no byte is ready yet, 0 by default
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "0", value_name = "SENTINEL",
+        value_parser = clap::value_parser!(u8).range(0..128))]
+    pub poll_input: Option<u8>,
+
+    /// Warn and pause when a loop comes back to the same state without any I/O, as it would never end
+    #[arg(long)]
+    pub detect_hangs: bool,
+
+    /// Stop with an error at a loop that would never end, instead of pausing
+    #[arg(long)]
+    pub stop_on_hang: bool,
+
+    /// Stop with an error when the program goes to write more than this many bytes
+    #[arg(long, value_name = "BYTES")]
+    pub max_output: Option<u64>,
+
+    /// At --max-output, cut the output off there and end the program with a warning instead of an error
+    #[arg(long, requires = "max_output")]
+    pub truncate_output: bool,
+
+    /// Print this to stderr before each line of input is read
+    #[arg(long, conflicts_with = "poll_input")]
+    pub prompt: Option<String>,
+
+    /// Copy each byte of input to stderr as it's read, for input that doesn't show itself like files and --raw-input
+    #[arg(long)]
+    pub echo: bool,
+
+    /// Send the program input from a script of `send "text"`, `wait 100ms` and `send-eof` lines
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["input", "args"])]
+    pub input_script: Option<String>,
+
+    /// Read each key as it's pressed, without Enter or echo, when stdin is a terminal
+    #[arg(long, conflicts_with_all = ["input", "args", "poll_input", "input_script"])]
+    pub raw_input: bool,
+
+    /// Exit with the value of a cell when the program ends, the current one by default
+    #[arg(long, value_enum, num_args = 0..=1, require_equals = true, default_missing_value = "current", value_name = "CELL")]
+    pub exit_cell: Option<ExitCell>,
+
+    /// Write every step to this file: the op, where it is in the source, the pointer, the cell it changed and any I/O.
+    /// The program isn't optimized, so there is a step for each instruction.
+    #[arg(long, value_name = "FILE")]
+    pub trace: Option<String>,
+
+    /// How to write `--trace`
+    #[arg(long, value_enum, default_value_t, requires = "trace")]
+    pub trace_format: TraceFormat,
+
+    /// Serve a page showing the program as it runs at this address, like `:8080` for port 8080 on localhost
+    #[arg(long, value_name = "[HOST]:PORT")]
+    pub dashboard: Option<String>,
+
+    /// Keep a line on stderr showing the steps run, how many a second, the time taken and where the program is
+    #[arg(long)]
+    pub progress: bool,
+
+    #[command(flatten)]
+    pub image: TapeImageArgs,
+
+    #[command(flatten)]
+    pub framebuffer: FramebufferArgs,
+}
+
+
+// The tape as a screen in the terminal
+#[derive(clap::Args, Debug)]
+pub struct FramebufferArgs {
+    /// Draw cells of the first tape as a grid of pixels in the terminal, redrawn whenever the sync cell changes
+    #[arg(long, value_name = "WIDTHxHEIGHT")]
+    pub framebuffer: Option<Size>,
+
+    /// The cell of the grid's top left pixel
+    #[arg(long, value_name = "CELL", default_value_t = 0, requires = "framebuffer")]
+    pub fb_start: usize,
+
+    /// The cell the program changes when a frame is ready [default: the one after the grid]
+    #[arg(long, value_name = "CELL", requires = "framebuffer")]
+    pub fb_sync: Option<usize>,
+
+    /// Colors for the pixels
+    #[arg(long, value_enum, default_value_t, requires = "framebuffer")]
+    pub fb_palette: Palette,
+}
+
+
+impl FramebufferArgs {
+    pub fn framebuffer(&self) -> Option<Framebuffer> {
+        let Size { width, height } = self.framebuffer?;
+        Some(Framebuffer {
+            width,
+            height,
+            start: self.fb_start,
+            sync: self.fb_sync.unwrap_or(self.fb_start + width * height),
+            palette: self.fb_palette,
+        })
+    }
+}
+
+
+impl ExecArgs {
+    /// The trace to write, if there is one, finding lines in `source`
+    pub fn trace(&self, source: Option<&str>) -> Option<Trace> {
+        let path = self.trace.clone()?;
+        Some(Trace { path, format: self.trace_format, source: source.map(str::to_string) })
+    }
+
+    /// The dashboard to serve, if there is one, showing positions in `source`
+    pub fn dashboard(&self, source: Option<&str>) -> Option<Dashboard> {
+        let address = self.dashboard.clone()?;
+        Some(Dashboard { address, source: source.map(str::to_string) })
+    }
+
+    /// The progress line to show, if there is one, with positions in `source`
+    pub fn progress(&self, source: Option<&str>) -> Option<Progress> {
+        self.progress.then(|| Progress { source: source.map(str::to_string) })
+    }
+
+    /// Where to say hangs are when warning about them, if they're only warned about
+    pub fn hang_warnings(&self, filepath: &str, source: Option<&str>) -> Option<HangWarnings> {
+        (self.detect_hangs && !self.stop_on_hang).then(|| HangWarnings { path: filepath.to_string(), source: source.map(str::to_string) })
+    }
+}
+
+
+// Pictures of the tape
+#[derive(clap::Args, Debug)]
+pub struct TapeImageArgs {
+    /// Write a picture of the tape to this PNG file when the program ends, a square for each cell
+    #[arg(long, value_name = "FILE")]
+    pub tape_png: Option<String>,
+
+    /// Also write one at every breakpoint, FILE-1.png, FILE-2.png and so on
+    #[arg(long, requires = "tape_png")]
+    pub snapshots: bool,
+
+    /// Record the tape every so many steps into this animated GIF, ending on the finished tape
+    #[arg(long, value_name = "FILE")]
+    pub record_gif: Option<String>,
+
+    /// Steps between the GIF's frames
+    #[arg(long, value_name = "STEPS", default_value_t = 1000, requires = "record_gif")]
+    pub record_every: usize,
+
+    /// Milliseconds each frame of the GIF shows for
+    #[arg(long, value_name = "MS", default_value_t = 100, requires = "record_gif")]
+    pub gif_delay: u64,
+
+    /// Cells in each row of the picture
+    #[arg(long, default_value_t = 64)]
+    pub png_width: usize,
+
+    /// Pixels across each cell
+    #[arg(long, default_value_t = 4)]
+    pub png_scale: usize,
+
+    /// Colors for the cell values
+    #[arg(long, value_enum, default_value_t)]
+    pub png_palette: Palette,
+}
+
+
+impl TapeImageArgs {
+    pub fn image(&self) -> Option<TapeImage> {
+        Some(self.drawn_to(self.tape_png.clone()?))
+    }
+
+    /// The image to write at breakpoints, if there is one
+    pub fn snapshots(&self) -> Option<TapeImage> {
+        self.image().filter(|_| self.snapshots)
+    }
+
+    fn drawn_to(&self, path: String) -> TapeImage {
+        TapeImage { path, width: self.png_width, scale: self.png_scale, palette: self.png_palette }
+    }
+
+    /// The GIF to record, drawn the same way as the PNG
+    pub fn recording(&self) -> Option<Recording> {
+        let path = self.record_gif.clone()?;
+        Some(Recording {
+            image: self.drawn_to(path),
+            every: self.record_every,
+            delay: std::time::Duration::from_millis(self.gif_delay),
+        })
+    }
+}
+
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExitCell {
+    /// The cell under the pointer
+    Current,
+
+    /// The first cell of the first tape
+    First,
+}
+
+
+impl ExitCell {
+    /// The cell's value in a finished vm
+    pub fn value(self, vm: &Vm) -> u8 {
+        match self {
+            ExitCell::Current => vm.tape().current(),
+            ExitCell::First => vm.tapes()[0].data[0],
+        }
+    }
+}
+
+
+#[derive(clap::Args, Debug)]
+pub struct RunArgs {
+    #[command(flatten)]
+    pub source: SourceArgs,
+
+    #[command(flatten)]
+    pub exec: ExecArgs,
+
+    /// Write the program's output to this file instead of stdout
+    #[arg(short, long)]
+    pub output_file: Option<String>,
+
+    /// Play the program's output as notes, writing them to this WAV file instead of printing the bytes.
+    /// Each byte is a MIDI note number, 0 being a rest.
+    #[arg(long, value_name = "FILE", conflicts_with = "output_file")]
+    pub wav: Option<String>,
+
+    /// Milliseconds each note of `--wav` lasts
+    #[arg(long, value_name = "MS", default_value_t = 250, requires = "wav")]
+    pub note_length: u64,
+
+    /// Print the program translated to another language instead of running it
+    #[arg(long, value_enum)]
+    pub emit: Option<emit::Target>,
+
+    /// Write a source map for the emitted code to this path (C, JavaScript and Python only)
+    #[arg(long, requires = "emit")]
+    pub source_map: Option<String>,
+
+    /// Print the optimized program's ops instead of running it
+    #[arg(long)]
+    pub dump_ir: bool,
+
+    /// Write how many ops ran in each loop to this file, as folded stacks for flamegraph tools
+    #[arg(long, value_name = "FILE")]
+    pub profile: Option<String>,
+
+    /// Time every op the program runs and print a table of the time each kind took to stderr at the end, with the
+    /// ops the optimizer makes from several instructions as kinds of their own
+    #[arg(long, conflicts_with_all = ["profile", "coverage"])]
+    pub op_times: bool,
+
+    /// Write which instructions ran to this file: an lcov tracefile if it ends in `.info` or `.lcov`,
+    /// otherwise the source with how many times each line ran
+    #[arg(long, value_name = "FILE")]
+    pub coverage: Option<String>,
+
+    /// Print a newline to stderr when the program ends, so the shell prompt starts on a new line
+    #[arg(long)]
+    pub newline: bool,
+
+    /// Print how long parsing and running took to stderr
+    #[arg(long)]
+    pub time: bool,
+
+    /// Print warnings about suspicious code, like empty loops, to stderr before running
+    #[arg(long)]
+    pub lint: bool,
+
+    /// Run the program again whenever its files change
+    #[arg(short, long, conflicts_with = "exit_cell")]
+    pub watch: bool,
+
+    /// Read input from and write output to a TCP connection, either to a server or from the first client to connect
+    #[arg(long, value_name = "tcp:HOST:PORT|listen:HOST:PORT", conflicts_with_all = ["input", "args", "output_file", "wav", "watch"])]
+    pub io: Option<Io>,
+}
+
+
+/// Where `--io` gets the program's input and sends its output
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Io {
+    /// `tcp:HOST:PORT`, connect to a server
+    Connect(String),
+
+    /// `listen:HOST:PORT`, wait for a client
+    Listen(String),
+}
+
+
+impl FromStr for Io {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Io, String> {
+        let (kind, address) = text.split_once(':').ok_or("expected tcp:HOST:PORT or listen:HOST:PORT")?;
+        if !address.contains(':') {
+            return Err(format!("{} needs a host and a port", address));
+        }
+        match kind {
+            "tcp" => Ok(Io::Connect(address.to_string())),
+            "listen" => Ok(Io::Listen(address.to_string())),
+            _ => Err(format!("unknown I/O mode {}, expected tcp or listen", kind)),
+        }
+    }
+}
+
+
+#[derive(clap::Args, Debug)]
+pub struct DebugArgs {
+    #[command(flatten)]
+    pub source: SourceArgs,
+
+    #[command(flatten)]
+    pub exec: ExecArgs,
+
+    /// Pause at every instruction until enter is pressed
+    #[arg(short, long)]
+    pub step: bool,
+
+    /// When paused, read commands for taking snapshots of the tape and seeing what changed between them
+    #[arg(long)]
+    pub commands: bool,
+
+    /// Show one screen with the ops, the tape and the output, redrawn as the program runs
+    #[arg(long, conflicts_with_all = ["step", "debug_format"])]
+    pub visualize: bool,
+
+    /// Steps between redraws of --visualize
+    #[arg(long, default_value_t = 10, value_name = "STEPS")]
+    pub steps_per_frame: usize,
+
+    /// Milliseconds to wait after each --visualize frame
+    #[arg(long, default_value_t = 30, value_name = "MS")]
+    pub frame_delay: u64,
+
+    /// When to color the debug output
+    #[arg(long, value_enum, default_value_t=ColorChoice::Auto)]
+    pub color: ColorChoice,
+
+    /// How to show each step
+    #[arg(long, value_enum, default_value_t=DebugFormat::Text)]
+    pub debug_format: DebugFormat,
+}
+
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// When stderr is a terminal and NO_COLOR isn't set
+    Auto,
+    Always,
+    Never,
+}
+
+
+impl ColorChoice {
+    pub fn enabled(self) -> bool {
+        match self {
+            ColorChoice::Auto => io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none(),
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        }
+    }
+}
+
+
+#[derive(clap::Args, Debug)]
+pub struct CheckArgs {
+    #[command(flatten)]
+    pub source: SourceArgs,
+
+    /// How to print the problems found
+    #[arg(long, value_enum, default_value_t=CheckFormat::Text)]
+    pub format: CheckFormat,
+}
+
+
+#[derive(clap::Args, Debug)]
+pub struct AnalyzeArgs {
+    #[command(flatten)]
+    pub source: SourceArgs,
+
+    /// How to print what was found
+    #[arg(long, value_enum, default_value_t=CheckFormat::Text)]
+    pub format: CheckFormat,
+
+    /// Instead, count the loops of each kind and list the ones the optimizer can't turn into straight-line ops
+    #[arg(long, conflicts_with = "format")]
+    pub loops: bool,
+}
+
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CheckFormat {
+    /// `file:line:column: message` lines on stderr
+    Text,
+
+    /// A JSON array of diagnostics on stdout, with their severity, code, message, file and range
+    Json,
+}
+
+
+#[derive(clap::Args, Debug)]
+pub struct BenchArgs {
+    #[command(flatten)]
+    pub source: SourceArgs,
+
+    #[command(flatten)]
+    pub vm: VmArgs,
+
+    /// Give the program the contents of this file as input on every run
+    #[arg(long)]
+    pub input: Option<String>,
+
+    /// How many times to run the program
+    #[arg(short='n', long, default_value_t=10)]
+    pub runs: usize,
+}
+
+
+#[derive(clap::Args, Debug)]
+pub struct TestArgs {
+    /// Programs, or directories to search for programs with fixtures
+    #[arg(default_value = ".")]
+    pub paths: Vec<String>,
+
+    #[command(flatten)]
+    pub parse: ParseArgs,
+
+    #[command(flatten)]
+    pub vm: VmArgs,
+
+    /// How to print the results
+    #[arg(long, value_enum, default_value_t = TestFormat::Text)]
+    pub format: TestFormat,
+}
+
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TestFormat {
+    /// A line for each case, with diffs of wrong output
+    Text,
+
+    /// A JUnit XML report, for CI dashboards and IDE test explorers
+    Junit,
+}
+
+
+#[derive(clap::Args, Debug)]
+pub struct MutateArgs {
+    /// Programs, or directories to search for programs with fixtures
+    #[arg(default_value = ".")]
+    pub paths: Vec<String>,
+
+    #[command(flatten)]
+    pub parse: ParseArgs,
+
+    #[command(flatten)]
+    pub vm: VmArgs,
+
+    /// Ops a mutant can run per case before it counts as caught for never finishing
+    #[arg(long, default_value_t = crate::reference::MAX_STEPS)]
+    pub max_steps: usize,
+}
+
+
+#[derive(clap::Args, Debug)]
+pub struct EquivArgs {
+    pub first: String,
+    pub second: String,
+
+    #[command(flatten)]
+    pub parse: ParseArgs,
+
+    #[command(flatten)]
+    pub vm: VmArgs,
+
+    /// Files to use as inputs, one run each, instead of a few built in ones
+    #[arg(long = "input", value_name = "FILE")]
+    pub inputs: Vec<String>,
+
+    /// Ops each program can run per input before it counts as not finishing
+    #[arg(long, default_value_t = crate::reference::MAX_STEPS)]
+    pub max_steps: usize,
+}
+
+
+#[derive(clap::Args, Debug)]
+pub struct ExploreArgs {
+    #[command(flatten)]
+    pub source: SourceArgs,
+
+    /// Input bytes to try, after which `,` acts as at the end of input
+    #[arg(long, default_value_t = crate::symbolic::Limits::default().inputs)]
+    pub max_input: usize,
+
+    /// Ops each path can run before it's cut off
+    #[arg(long, default_value_t = crate::symbolic::Limits::default().steps)]
+    pub max_steps: usize,
+
+    /// Paths to explore before giving up
+    #[arg(long, default_value_t = crate::symbolic::Limits::default().paths)]
+    pub max_paths: usize,
+}
+
+
+#[derive(clap::Args, Debug)]
+pub struct ServeArgs {
+    /// Address to listen on
+    #[arg(default_value = "127.0.0.1:8000")]
+    pub address: String,
+}
+
+
+#[derive(clap::Args, Debug)]
+pub struct PipeArgs {
+    /// Programs in pipeline order, the first reading stdin and the last writing stdout
+    #[arg(required = true)]
+    pub programs: Vec<String>,
+
+    #[command(flatten)]
+    pub parse: ParseArgs,
+
+    #[command(flatten)]
+    pub vm: VmArgs,
+
+    /// Read the first program's input from this file instead of stdin
+    #[arg(long)]
+    pub input: Option<String>,
+}
+
+
+#[derive(clap::Args, Debug)]
+pub struct BatchArgs {
+    pub filepath: String,
+
+    /// Files to give the program as input, one a run
+    #[arg(required = true)]
+    pub inputs: Vec<String>,
+
+    #[command(flatten)]
+    pub parse: ParseArgs,
+
+    #[command(flatten)]
+    pub vm: VmArgs,
+
+    /// Write each output here instead of beside its input, named like the input with an `.out` extension
+    #[arg(long, value_name = "DIR")]
+    pub out_dir: Option<String>,
+
+    /// Most programs to run at once [default: one a core]
+    #[arg(short, long, default_value_t = 0, hide_default_value = true)]
+    pub jobs: usize,
+}
+
+
+#[derive(clap::Args, Debug)]
+pub struct GenPrintArgs {
+    pub text: String,
+}
+
+
+#[derive(clap::Args, Debug)]
+pub struct GolfArgs {
+    pub filepath: String,
+
+    #[command(flatten)]
+    pub parse: ParseArgs,
+
+    /// Files to check the shorter program on, one run each, instead of a few built in ones
+    #[arg(long = "input", value_name = "FILE")]
+    pub inputs: Vec<String>,
+
+    /// Instructions each run can take before it counts as not finishing
+    #[arg(long, default_value_t = crate::reference::MAX_STEPS)]
+    pub max_steps: usize,
+
+    /// Wrap lines after this many characters, 0 for a single line
+    #[arg(long, default_value_t=80)]
+    pub width: usize,
+}
+
+
+#[derive(clap::Args, Debug)]
+pub struct GenRandomArgs {
+    /// Seed for the generator, the same one giving the same programs [default: from the clock]
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// Programs to print, one a line
+    #[arg(short = 'n', long, default_value_t = 1)]
+    pub count: usize,
+
+    /// Most instructions in each program
+    #[arg(long, default_value_t = crate::random::Options::default().max_length)]
+    pub max_length: usize,
+
+    /// How deep loops can nest
+    #[arg(long, default_value_t = crate::random::Options::default().max_depth)]
+    pub max_depth: usize,
+
+    /// How often to pick each instruction, like `loops=5,input=0`, from plus, minus, left, right, loops, input and output
+    #[arg(long, value_name = "NAME=WEIGHT,...", default_value = "")]
+    pub weights: crate::random::Weights,
+}
+
+
+#[derive(clap::Args, Debug)]
+pub struct ConstArgs {
+    #[arg(value_parser = clap::value_parser!(u8).range(0..128))]
+    pub value: u8,
+}
+
+
+#[derive(clap::Args, Debug)]
+pub struct BfcArgs {
+    /// The .bfs program
+    pub filepath: String,
+
+    /// Write the brainfuck to this file instead of stdout
+    #[arg(short, long)]
+    pub output: Option<String>,
+}
+
+
+#[derive(clap::Args, Debug)]
+pub struct CompletionsArgs {
+    #[arg(value_enum)]
+    pub shell: clap_complete::Shell,
+}
+
+
+#[derive(clap::Args, Debug)]
+pub struct MinifyArgs {
+    /// Program to minify, Ook! is minified to brainfuck. With macros enabled they are expanded.
+    pub filepath: String,
+
+    #[command(flatten)]
+    pub parse: ParseArgs,
+
+    /// Wrap lines after this many characters, 0 for a single line
+    #[arg(long, default_value_t=80)]
+    pub width: usize,
+}
+
+
+#[derive(clap::Args, Debug)]
+pub struct FmtArgs {
+    pub filepath: String,
+
+    #[command(flatten)]
+    pub parse: ParseArgs,
+
+    /// Overwrite the file instead of printing the formatted code
+    #[arg(short, long)]
+    pub write: bool,
+}
+
+
+#[derive(clap::Args, Debug)]
+pub struct CfgArgs {
+    #[command(flatten)]
+    pub source: SourceArgs,
+
+    /// Write the graph to this file instead of stdout
+    #[arg(short, long)]
+    pub output: Option<String>,
+
+    /// Run the program first, and show how many times each block ran
+    #[arg(long)]
+    pub counts: bool,
+
+    /// Input for the program when counting
+    #[arg(long, requires = "counts")]
+    pub input: Option<String>,
+
+    #[command(flatten)]
+    pub vm: VmArgs,
+}
+
+
+#[derive(clap::Args, Debug)]
+pub struct DisasmArgs {
+    pub filepath: String,
+}
+
+
+#[derive(clap::Args, Debug)]
+pub struct CompileArgs {
+    pub filepath: String,
+
+    #[command(flatten)]
+    pub parse: ParseArgs,
+
+    /// Path of the executable (defaults to the program's name without its extension)
+    #[arg(short, long)]
+    pub output: Option<String>,
+
+    /// What to compile the program to
+    #[arg(long, value_enum, default_value_t=CompileFormat::Native)]
+    pub emit: CompileFormat,
+
+    /// C compiler to build the executable with
+    #[arg(long, default_value="cc")]
+    pub cc: String,
+
+    /// Add debug info pointing at the brainfuck source, for debuggers and profilers
+    #[arg(short='g', long)]
+    pub debug_info: bool,
+
+    /// What `,` sets the cell to once input runs out [default: zero]
+    #[arg(long, value_enum)]
+    pub eof: Option<Eof>,
+}
+
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompileFormat {
+    /// A native executable, through the C backend
+    Native,
+
+    /// Bytecode (.bfc) that `bf-rs` can run without parsing it again
+    Bfc,
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn valid_args() {
+        Args::command().debug_assert();
+    }
+
+    #[test]
+    fn bare_path_runs() {
+        let Command::Run(args) = Args::parse_from(["bf-rs", "a.b", "-x", "tapes"]).into_command() else {
+            panic!("expected the run command");
+        };
+        assert_eq!(args.source.filepaths, vec!["a.b"]);
+        assert_eq!(args.source.parse.extensions, vec![Extension::Tapes]);
+
+        let Command::Run(args) = Args::parse_from(["bf-rs", "lib.b", "a.b", "--", "hello", "world"]).into_command() else {
+            panic!("expected the run command");
+        };
+        assert_eq!(args.source.filepaths, vec!["lib.b", "a.b"]);
+        assert_eq!(args.exec.args, vec!["hello", "world"]);
+
+        let args = Args::parse_from(["bf-rs", "a.b", "-vv"]);
+        assert_eq!(args.log_level(), LevelFilter::Debug);
+
+        let command = Args::parse_from(["bf-rs", "debug", "--step", "a.b"]).into_command();
+        assert!(matches!(command, Command::Debug(DebugArgs { step: true, .. })));
+    }
+
+    #[test]
+    fn exit_cell_defaults_to_current() {
+        let Command::Run(args) = Args::parse_from(["bf-rs", "--exit-cell", "a.b"]).into_command() else {
+            panic!("expected the run command");
+        };
+        assert_eq!(args.exec.exit_cell, Some(ExitCell::Current));
+        assert_eq!(args.source.filepaths, vec!["a.b"]);
+
+        let Command::Run(args) = Args::parse_from(["bf-rs", "a.b", "--exit-cell=first"]).into_command() else {
+            panic!("expected the run command");
+        };
+        assert_eq!(args.exec.exit_cell, Some(ExitCell::First));
+    }
+
+    #[test]
+    fn io_addresses() {
+        assert_eq!("tcp:localhost:8000".parse(), Ok(Io::Connect("localhost:8000".to_string())));
+        assert_eq!("listen:0.0.0.0:0".parse(), Ok(Io::Listen("0.0.0.0:0".to_string())));
+        assert!("udp:localhost:8000".parse::<Io>().is_err());
+        assert!("tcp:8000".parse::<Io>().is_err());
+    }
+}
@@ -0,0 +1,35 @@
+use std::{error::Error, fs, path::Path, process::Command};
+use crate::{Eof, emit::c, ir::Program};
+
+
+/// Compile a program to a native executable at `output`, through the C backend and a C compiler
+/// With `debug_source` (the source's path and contents), the executable gets debug info
+/// pointing at the brainfuck source. `eof` is what `,` does at the end of input.
+pub fn compile_native(program: &Program, output: &Path, cc: &str, eof: Eof, debug_source: Option<(&str, &str)>) -> Result<(), Box<dyn Error>> {
+    if cfg!(target_family = "wasm") {
+        return Err("WASI can't run a C compiler, compile to bytecode with --emit bfc instead".into());
+    }
+    let (mut code, source_map) = c::emit_c_with_source_map(program, eof)?;
+    if let Some((path, source)) = debug_source {
+        code = c::with_line_directives(&code, &source_map, path, source);
+    }
+
+    // Keep the C file next to nothing else, so parallel compiles don't clash
+    let c_path = std::env::temp_dir().join(format!("bf-rs-{}.c", std::process::id()));
+    fs::write(&c_path, code)?;
+
+    let status = Command::new(cc)
+        .arg("-O2")
+        .args(debug_source.map(|_| "-g"))
+        .arg("-o")
+        .arg(output)
+        .arg(&c_path)
+        .status()
+        .map_err(|e| format!("failed to run C compiler `{}`: {}", cc, e));
+    let _ = fs::remove_file(&c_path);
+
+    if !status?.success() {
+        return Err(format!("C compiler `{}` failed", cc).into());
+    }
+    Ok(())
+}
@@ -0,0 +1,107 @@
+//! Defaults from `bf.toml` files, which command line flags override
+
+use std::{error::Error, fs, path::{Path, PathBuf}};
+use serde::Deserialize;
+use crate::{Dialect, Eof, Extension};
+
+
+pub const FILE_NAME: &str = "bf.toml";
+
+
+/// Settings from a `bf.toml`, each one unset unless the file has it
+#[derive(Deserialize, Default, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize))]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub dialect: Option<Dialect>,
+    pub breakpoints: Option<bool>,
+    pub macros: Option<bool>,
+    pub extensions: Option<Vec<Extension>>,
+    pub tapes: Option<usize>,
+    pub eof: Option<Eof>,
+}
+
+
+impl Config {
+    pub fn parse(text: &str) -> Result<Config, Box<dyn Error>> {
+        Ok(toml::from_str(text)?)
+    }
+
+    /// Settings from `self`, with the ones it doesn't have taken from `fallback`
+    pub fn or(self, fallback: Config) -> Config {
+        Config {
+            dialect: self.dialect.or(fallback.dialect),
+            breakpoints: self.breakpoints.or(fallback.breakpoints),
+            macros: self.macros.or(fallback.macros),
+            extensions: self.extensions.or(fallback.extensions),
+            tapes: self.tapes.or(fallback.tapes),
+            eof: self.eof.or(fallback.eof),
+        }
+    }
+
+    /// The config for the program at `program_path`: its directory's `bf.toml`,
+    /// then the one in the user config directory. Programs from stdin or --eval use the current directory.
+    pub fn load(program_path: &str) -> Result<Config, Box<dyn Error>> {
+        let directory = match Path::new(program_path).parent() {
+            Some(parent) if program_path != "-" && program_path != "<eval>" => parent.to_path_buf(),
+            _ => PathBuf::new(),
+        };
+
+        let mut config = Config::default();
+        for path in [Some(directory.join(FILE_NAME)), user_config_path()].into_iter().flatten() {
+            config = config.or(Config::read(&path)?);
+        }
+        Ok(config)
+    }
+
+    /// The config in the file at `path`, or the default if there is no file
+    fn read(path: &Path) -> Result<Config, Box<dyn Error>> {
+        match fs::read_to_string(path) {
+            Ok(text) => {
+                log::debug!("reading settings from {}", path.display());
+                Config::parse(&text).map_err(|error| format!("{}: {}", path.display(), error).into())
+            },
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(error) => Err(format!("{}: {}", path.display(), error).into()),
+        }
+    }
+}
+
+
+/// `bf-rs/bf.toml` in `$XDG_CONFIG_HOME`, `~/.config` or `%APPDATA%`
+fn user_config_path() -> Option<PathBuf> {
+    let directory = std::env::var_os("XDG_CONFIG_HOME").map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+        .or_else(|| std::env::var_os("APPDATA").map(PathBuf::from))?;
+    Some(directory.join("bf-rs").join(FILE_NAME))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_settings() {
+        let config = Config::parse("dialect = \"pbrain\"\nextensions = [\"tapes\", \"stack\"]\ntapes = 3\n").unwrap();
+        assert_eq!(config, Config {
+            dialect: Some(Dialect::Pbrain),
+            extensions: Some(vec![Extension::Tapes, Extension::Stack]),
+            tapes: Some(3),
+            ..Config::default()
+        });
+        assert!(Config::parse("cell-size = 16\n").is_err());
+    }
+
+    #[test]
+    fn program_config_first() {
+        let program = Config { macros: Some(true), tapes: Some(4), ..Config::default() };
+        let user = Config { macros: Some(false), breakpoints: Some(true), ..Config::default() };
+        assert_eq!(program.or(user), Config {
+            macros: Some(true),
+            breakpoints: Some(true),
+            tapes: Some(4),
+            ..Config::default()
+        });
+    }
+}
@@ -0,0 +1,112 @@
+//! Which instructions of a program ran, as an annotated listing or an lcov tracefile
+
+use std::{collections::BTreeMap, fmt::Write};
+use crate::Program;
+
+
+/// How many times the instruction at each source offset ran, from the op counts of a run of an unoptimized program.
+/// Instructions from a macro share their call's offset, which gets the count of the one that ran most.
+pub fn counts(program: &Program, op_counts: &[u64]) -> BTreeMap<usize, u64> {
+    let mut counts = BTreeMap::new();
+    for (op, &count) in op_counts.iter().enumerate() {
+        if let Some(offset) = program.offset(op) {
+            let total = counts.entry(offset).or_insert(0);
+            *total = count.max(*total);
+        }
+    }
+    counts
+}
+
+
+/// The count of each line with instructions, by line index, being the most any instruction on it ran
+fn line_counts(source: &str, counts: &BTreeMap<usize, u64>) -> BTreeMap<usize, u64> {
+    let mut lines = BTreeMap::new();
+    let mut line_start = 0;
+    for (line, text) in source.split_inclusive('\n').enumerate() {
+        for (_, &count) in counts.range(line_start..line_start + text.len()) {
+            let total = lines.entry(line).or_insert(0);
+            *total = count.max(*total);
+        }
+        line_start += text.len();
+    }
+    lines
+}
+
+
+/// The source with each line's count in front, `#####` for lines where nothing ran and `-` for lines without
+/// instructions. Lines where only some instructions ran get a line of `^` under the ones that didn't.
+pub fn annotate(source: &str, counts: &BTreeMap<usize, u64>) -> String {
+    let lines = line_counts(source, counts);
+    let mut listing = String::new();
+    let mut line_start = 0;
+    for (line, text) in source.split_inclusive('\n').enumerate() {
+        let text = text.trim_end_matches('\n');
+        let count = match lines.get(&line) {
+            Some(0) => "#####".to_string(),
+            Some(count) => count.to_string(),
+            None => "-".to_string(),
+        };
+        let _ = writeln!(listing, "{:>9} | {}", count, text);
+
+        let missed: Vec<usize> = counts.range(line_start..line_start + text.len())
+            .filter(|(_, &count)| count == 0)
+            .map(|(&offset, _)| text[..offset - line_start].chars().count())
+            .collect();
+        if lines.get(&line).is_some_and(|&count| count > 0) && !missed.is_empty() {
+            let mut markers = String::new();
+            for column in missed {
+                markers += &" ".repeat(column - markers.chars().count());
+                markers += "^";
+            }
+            let _ = writeln!(listing, "{:>9} | {}", "", markers);
+        }
+        line_start += text.len() + 1;
+    }
+    listing
+}
+
+
+/// An lcov tracefile, with a `DA` record for each line that has instructions
+pub fn lcov(path: &str, source: &str, counts: &BTreeMap<usize, u64>) -> String {
+    let lines = line_counts(source, counts);
+    let mut tracefile = format!("TN:\nSF:{}\n", path);
+    for (line, count) in &lines {
+        let _ = writeln!(tracefile, "DA:{},{}", line + 1, count);
+    }
+    let hit = lines.values().filter(|&&count| count > 0).count();
+    let _ = write!(tracefile, "LF:{}\nLH:{}\nend_of_record\n", lines.len(), hit);
+    tracefile
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use crate::{ParseOptions, VmOptions, parse_with_offsets, profile};
+
+    fn run(source: &str) -> BTreeMap<usize, u64> {
+        let instructions = parse_with_offsets(source, &ParseOptions::default()).unwrap();
+        let program = Program::compile_with_offsets(&instructions, false).unwrap();
+        let (_, op_counts) = profile::run_profiled(program.clone(), &VmOptions::default(), &mut io::empty(), &mut io::sink()).unwrap();
+        counts(&program, &op_counts)
+    }
+
+    #[test]
+    fn listing_marks_what_never_ran() {
+        let source = "++[-]\nwhy\n[.] +[-]\n";
+        assert_eq!(annotate(source, &run(source)), [
+            "        2 | ++[-]",
+            "        - | why",
+            "        1 | [.] +[-]",
+            "          |  ^^",
+            "",
+        ].join("\n"));
+    }
+
+    #[test]
+    fn lcov_lines() {
+        let source = "+\n\n[-]\n";
+        assert_eq!(lcov("a.b", source, &run(source)), "TN:\nSF:a.b\nDA:1,1\nDA:3,1\nLF:2\nLH:2\nend_of_record\n");
+    }
+}
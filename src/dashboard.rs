@@ -0,0 +1,202 @@
+//! A page showing a running program live, for keeping an eye on long runs from a browser.
+//! The program publishes how far it's got every so often, and each browser gets the latest as a server-sent event.
+
+/// Where to serve the dashboard, and the source to show positions in
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub struct Dashboard {
+    /// `HOST:PORT`, or `:PORT` for localhost
+    pub address: String,
+    pub source: Option<String>,
+}
+
+
+#[cfg(not(target_family = "wasm"))]
+pub use running::Monitor;
+
+
+// Threads and sockets, which WASI and browsers don't have
+#[cfg(not(target_family = "wasm"))]
+mod running {
+    use std::{
+        io, net::{TcpListener, TcpStream}, sync::{Arc, Mutex, atomic::{AtomicUsize, Ordering}}, thread,
+        time::{Duration, Instant},
+    };
+    use serde_json::json;
+    use crate::{Op, Vm, emit::source_map::LineIndex, serve::{receive, respond}};
+    use super::Dashboard;
+
+
+    const PAGE: &str = include_str!("dashboard.html");
+
+    /// How often the program publishes and browsers get an event
+    const INTERVAL: Duration = Duration::from_millis(250);
+
+    /// Steps between looking at the clock
+    const CHECK_EVERY: usize = 4096;
+
+    /// Cells sent for the heatmap
+    const CELLS_SENT: usize = 4096;
+
+
+    /// What's shared between the running program and the server's connections
+    #[derive(Default)]
+    struct Shared {
+        /// The latest event, as JSON
+        latest: Mutex<String>,
+        clients: AtomicUsize,
+    }
+
+
+    /// The running side of a dashboard, which publishes the vm's state
+    pub struct Monitor {
+        shared: Arc<Shared>,
+        source: Option<(String, LineIndex)>,
+        last: Instant,
+        last_steps: usize,
+    }
+
+
+    impl Dashboard {
+        /// Start serving, in the background
+        pub fn start(&self) -> io::Result<Monitor> {
+            let address = match self.address.strip_prefix(':') {
+                Some(port) => format!("127.0.0.1:{}", port),
+                None => self.address.clone(),
+            };
+            let listener = TcpListener::bind(address)?;
+            eprintln!("dashboard at http://{}/", listener.local_addr()?);
+            let shared = Arc::new(Shared::default());
+            let serving = shared.clone();
+            thread::spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    let shared = serving.clone();
+                    thread::spawn(move || {
+                        if let Err(error) = handle(stream, &shared) {
+                            log::debug!("dashboard connection ended: {}", error);
+                        }
+                    });
+                }
+            });
+            let source = self.source.as_ref().map(|source| (source.clone(), LineIndex::new(source)));
+            Ok(Monitor { shared, source, last: Instant::now(), last_steps: 0 })
+        }
+    }
+
+
+    impl Monitor {
+        /// Publish the vm's state if it's been long enough since the last time, after `steps` steps
+        pub fn step(&mut self, vm: &Vm, steps: usize) {
+            if steps.is_multiple_of(CHECK_EVERY) && self.last.elapsed() >= INTERVAL {
+                self.publish(vm, steps, false);
+            }
+        }
+
+        /// Publish the finished vm, giving browsers time to see it
+        pub fn finish(&mut self, vm: &Vm, steps: usize) {
+            self.publish(vm, steps, true);
+            if self.shared.clients.load(Ordering::Relaxed) > 0 {
+                thread::sleep(INTERVAL * 2);
+            }
+        }
+
+        fn publish(&mut self, vm: &Vm, steps: usize, finished: bool) {
+            let rate = (steps - self.last_steps) as f64 / self.last.elapsed().as_secs_f64().max(f64::EPSILON);
+            self.last = Instant::now();
+            self.last_steps = steps;
+
+            let position = match (&self.source, vm.program().offset(vm.ip())) {
+                (Some((source, index)), Some(offset)) => {
+                    let (line, column) = index.line_column(source, offset);
+                    json!({ "line": line + 1, "column": column + 1 })
+                },
+                _ => serde_json::Value::Null,
+            };
+            let tape = vm.tape();
+            let event = json!({
+                "steps": steps,
+                "rate": rate.round() as u64,
+                "ip": vm.ip(),
+                "op": vm.current_op().as_ref().map(Op::to_string),
+                "position": position,
+                "pointer": tape.pointer,
+                "length": tape.data.len(),
+                "cells": &tape.data[..tape.data.len().min(CELLS_SENT)],
+                "finished": finished,
+            });
+            *self.shared.latest.lock().unwrap() = event.to_string();
+        }
+    }
+
+
+    /// Send the page, or the latest event every interval until the program finishes
+    fn handle(mut stream: TcpStream, shared: &Shared) -> io::Result<()> {
+        use std::io::Write;
+
+        let Some(request) = receive(&mut stream)? else {
+            return Ok(());
+        };
+        match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/") => respond(&mut stream, "200 OK", "text/html; charset=utf-8", PAGE.as_bytes()),
+            ("GET", "/events") => {
+                shared.clients.fetch_add(1, Ordering::Relaxed);
+                let result = (|| {
+                    write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\n\r\n")?;
+                    loop {
+                        let latest = shared.latest.lock().unwrap().clone();
+                        if !latest.is_empty() {
+                            write!(stream, "data: {}\n\n", latest)?;
+                            stream.flush()?;
+                        }
+                        if latest.contains("\"finished\":true") {
+                            return Ok(());
+                        }
+                        thread::sleep(INTERVAL);
+                    }
+                })();
+                shared.clients.fetch_sub(1, Ordering::Relaxed);
+                result
+            },
+            _ => respond(&mut stream, "404 Not Found", "text/plain", b"not found"),
+        }
+    }
+
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use std::io::{BufRead, BufReader, Write};
+        use crate::{ParseOptions, Program, VmOptions, parse_with_offsets};
+
+        #[test]
+        fn streams_events() {
+            let source = "+++\n>+";
+            let program = Program::compile_with_offsets(&parse_with_offsets(source, &ParseOptions::default()).unwrap(), false).unwrap();
+            let mut vm = Vm::new(program, &VmOptions::default());
+            let mut monitor = Dashboard { address: "127.0.0.1:0".to_string(), source: Some(source.to_string()) }.start().unwrap();
+            for _ in 0..4 {
+                vm.step(&mut io::empty(), &mut io::sink()).unwrap();
+            }
+            monitor.publish(&vm, 4, false);
+            let event: serde_json::Value = serde_json::from_str(&monitor.shared.latest.lock().unwrap()).unwrap();
+            assert_eq!(event["position"], json!({ "line": 2, "column": 2 }));
+            assert_eq!(event["op"], "+");
+            assert_eq!(event["cells"], json!([3, 0]));
+            assert_eq!(event["finished"], false);
+
+            // Connect to a server of its own, to see an event arrive
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let address = listener.local_addr().unwrap();
+            let shared = monitor.shared.clone();
+            thread::spawn(move || handle(listener.accept().unwrap().0, &shared));
+            let mut client = TcpStream::connect(address).unwrap();
+            client.write_all(b"GET /events HTTP/1.1\r\n\r\n").unwrap();
+            vm.run(&mut io::empty(), &mut io::sink()).unwrap();
+            monitor.publish(&vm, 5, true);
+            let lines: Vec<String> = BufReader::new(client).lines().map(Result::unwrap).collect();
+            assert!(lines[0].starts_with("HTTP/1.1 200 OK"));
+            assert!(lines.last().is_some_and(|line| line.is_empty()));
+            assert!(lines.iter().any(|line| line.starts_with("data: ") && line.contains("\"finished\":true")));
+        }
+    }
+}
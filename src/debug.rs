@@ -0,0 +1,495 @@
+//! The lines the debug modes print after each step, the screen the visualizer redraws,
+//! and the commands that can be typed when paused
+
+use std::{fmt::Write, io, time::Duration};
+use clap::ValueEnum;
+use crate::{Op, Vm, dashboard::Dashboard, progress::Progress, hang::Warnings as HangWarnings, emit::source_map::json_string, framebuffer::Framebuffer, image::{Recording, TapeImage}, snapshot::Snapshots, trace::Trace};
+
+
+const RESET: &str = "\x1b[0m";
+const OP_COLOR: &str = "\x1b[1;36m";
+const POINTER_COLOR: &str = "\x1b[7m";
+const CHANGED_COLOR: &str = "\x1b[32m";
+const CHANGED_POINTER_COLOR: &str = "\x1b[7;32m";
+const BREAK_COLOR: &str = "\x1b[1;31m";
+
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub enum DebugMode {
+    #[default]
+    None,
+
+    /// Print memory and instructions
+    Verbose,
+
+    /// Verbose, but pause at every instruction
+    Step,
+
+    /// Redraw one screen with the ops, the tape and the output every `steps_per_frame` steps,
+    /// waiting `delay` after each frame
+    Visualize { steps_per_frame: usize, delay: Duration },
+}
+
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub enum DebugFormat {
+    /// Memory and the op, for people
+    #[default]
+    Text,
+
+    /// One JSON object per step, for other programs
+    Json,
+}
+
+
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub struct DebugOptions {
+    pub mode: DebugMode,
+    pub format: DebugFormat,
+
+    /// Color the op, the current cell and the cells the last step changed (text only)
+    pub color: bool,
+
+    /// Write a numbered picture of the tape at each breakpoint
+    pub snapshots: Option<TapeImage>,
+
+    /// Record the tape every so many steps into an animated GIF
+    pub recording: Option<Recording>,
+
+    /// Write every step to a file
+    pub trace: Option<Trace>,
+
+    /// Read commands when paused, instead of going on at any key
+    pub commands: bool,
+
+    /// Serve a page showing the program as it runs
+    pub dashboard: Option<Dashboard>,
+
+    /// Keep a line on stderr showing how many steps have run, how fast, and where the program is
+    pub progress: Option<Progress>,
+
+    /// Draw cells of the first tape as a grid in the terminal whenever the program changes the sync cell
+    pub framebuffer: Option<Framebuffer>,
+
+    /// Time each kind of op, and print a table of the times to stderr at the end
+    pub op_times: bool,
+    /// Warn about hangs the vm notices and pause, instead of stopping
+    pub hang_warnings: Option<HangWarnings>,
+}
+
+
+/// Wait for a key before the next step. Keys come from the terminal rather than stdin where there is one,
+/// so pausing never takes bytes meant for `,`. Ctrl-C stops the program.
+#[cfg(not(target_family = "wasm"))]
+pub fn pause() -> io::Result<()> {
+    use crossterm::{event::{self, Event, KeyCode, KeyEventKind, KeyModifiers}, terminal};
+
+    if terminal::enable_raw_mode().is_err() {
+        // No terminal, so all there is to wait on is stdin
+        return io::stdin().read_line(&mut String::new()).map(|_| ());
+    }
+    let key = loop {
+        match event::read() {
+            Ok(Event::Key(key)) if key.kind != KeyEventKind::Release => break Ok(key),
+            Ok(_) => (),
+            Err(error) => break Err(error),
+        }
+    };
+    terminal::disable_raw_mode()?;
+
+    let key = key?;
+    if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+        crate::interrupt::request();
+    }
+    Ok(())
+}
+
+
+/// Wait for Enter before the next step
+#[cfg(target_family = "wasm")]
+pub fn pause() -> io::Result<()> {
+    io::stdin().read_line(&mut String::new()).map(|_| ())
+}
+
+
+/// Read a line typed after `prompt`, from the terminal rather than stdin where there is one, like `pause`.
+/// Ctrl-C stops the program.
+#[cfg(not(target_family = "wasm"))]
+pub fn read_line(prompt: &str) -> io::Result<String> {
+    use std::io::Write;
+    use crossterm::{event::{self, Event, KeyCode, KeyEventKind, KeyModifiers}, terminal};
+
+    eprint!("{}", prompt);
+    if terminal::enable_raw_mode().is_err() {
+        let mut line = String::new();
+        io::stdin().read_line(&mut line)?;
+        return Ok(line.trim_end().to_string());
+    }
+    let mut line = String::new();
+    let result = loop {
+        let key = match event::read() {
+            Ok(Event::Key(key)) if key.kind != KeyEventKind::Release => key,
+            Ok(_) => continue,
+            Err(error) => break Err(error),
+        };
+        match key.code {
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                crate::interrupt::request();
+                break Ok(String::new());
+            },
+            KeyCode::Char(c) => {
+                line.push(c);
+                eprint!("{}", c);
+            },
+            KeyCode::Backspace if line.pop().is_some() => eprint!("\x08 \x08"),
+            KeyCode::Enter => break Ok(line),
+            _ => (),
+        }
+        let _ = io::stderr().flush();
+    };
+    terminal::disable_raw_mode()?;
+    eprintln!();
+    result
+}
+
+
+/// Read a line typed after `prompt` from stdin
+#[cfg(target_family = "wasm")]
+pub fn read_line(prompt: &str) -> io::Result<String> {
+    eprint!("{}", prompt);
+    let mut line = String::new();
+    io::stdin().read_line(&mut line)?;
+    Ok(line.trim_end().to_string())
+}
+
+
+const COMMANDS_HELP: &str = "\
+snap NAME      save the tapes as NAME
+diff A [B]     cells changed from snapshot A to snapshot B, or to now
+snaps          list the snapshots
+state          the ops around this one, the tape and the stack
+Enter, c       go on";
+
+
+/// The commands typed when paused, and the snapshots they took
+#[derive(Debug, Default)]
+pub struct Commands {
+    snapshots: Snapshots,
+}
+
+
+impl Commands {
+    /// Read and run commands until one goes on
+    pub fn prompt(&mut self, vm: &Vm) -> io::Result<()> {
+        loop {
+            let line = read_line("(bf) ")?;
+            match self.run(&line, vm) {
+                Some(shown) => eprintln!("{}", shown),
+                None => return Ok(()),
+            }
+        }
+    }
+
+    /// Run a command, returning what it shows, or none if the program should go on
+    pub fn run(&mut self, line: &str, vm: &Vm) -> Option<String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        Some(match words[..] {
+            [] | ["c" | "continue"] => return None,
+            ["snap", name] => {
+                self.snapshots.take(name, vm);
+                format!("saved {}", name)
+            },
+            ["diff", from] | ["diff", from, _] => match self.snapshots.diff(from, words.get(2).copied(), vm) {
+                Ok(changes) if changes.is_empty() => "no cells changed".to_string(),
+                Ok(changes) => changes.iter().map(|change| change.to_string()).collect::<Vec<_>>().join("\n"),
+                Err(error) => error,
+            },
+            ["state"] => vm.to_string(),
+            ["snaps"] => match self.snapshots.names().collect::<Vec<_>>() {
+                names if names.is_empty() => "no snapshots".to_string(),
+                names => names.join("\n"),
+            },
+            _ => COMMANDS_HELP.to_string(),
+        })
+    }
+}
+
+
+/// Prints the state of a vm after each step, remembering the last tape to show what changed
+pub struct StepPrinter {
+    format: DebugFormat,
+    color: bool,
+    uses_stack: bool,
+    /// The active tape's index and cells after the last step
+    previous: Option<(usize, Vec<u8>)>,
+}
+
+
+impl StepPrinter {
+    pub fn new(options: &DebugOptions, uses_stack: bool) -> StepPrinter {
+        // A new vm has one empty cell
+        StepPrinter { format: options.format, color: options.color, uses_stack, previous: Some((0, vec![0])) }
+    }
+
+    fn paint(&self, text: &str, color: &str) -> String {
+        if self.color {
+            format!("{}{}{}", color, text, RESET)
+        } else {
+            text.to_string()
+        }
+    }
+
+    /// What to show after running `op`, which was at `ip`
+    pub fn step(&mut self, vm: &Vm, ip: usize, op: Op) -> String {
+        let shown = match self.format {
+            DebugFormat::Text => self.text_step(vm, op),
+            DebugFormat::Json => self.json_step(vm, ip, op),
+        };
+        self.previous = Some((vm.active_tape(), vm.tape().data.clone()));
+        shown
+    }
+
+    /// Whether the cell at `index` of the active tape is different from the last step
+    fn changed(&self, vm: &Vm, index: usize) -> bool {
+        match &self.previous {
+            Some((tape, cells)) if *tape == vm.active_tape() => {
+                cells.get(index).copied().unwrap_or(0) != vm.tape().data[index]
+            },
+            _ => false,
+        }
+    }
+
+    /// The op and memory, with a `^` under the current cell on a second line
+    fn text_step(&self, vm: &Vm, op: Op) -> String {
+        let mut line = self.paint(&op.to_string(), OP_COLOR);
+        if vm.tapes().len() > 1 {
+            line += &format!(" (tape {})", vm.active_tape());
+        }
+        line += ":";
+
+        let tape = vm.tape();
+        // Colors take up no space, so the caret goes by the visible width
+        let mut width = line.len() - if self.color { OP_COLOR.len() + RESET.len() } else { 0 };
+        let mut pointer_position: usize = 0;
+        for (i, x) in tape.data.iter().enumerate() {
+            line += " ";
+            width += 1;
+            if i == tape.pointer {
+                pointer_position = width;
+            }
+
+            let cell = x.to_string();
+            width += cell.len();
+            line += &match (i == tape.pointer, self.changed(vm, i)) {
+                (true, true) => self.paint(&cell, CHANGED_POINTER_COLOR),
+                (true, false) => self.paint(&cell, POINTER_COLOR),
+                (false, true) => self.paint(&cell, CHANGED_COLOR),
+                (false, false) => cell,
+            };
+        }
+        if self.uses_stack {
+            line += "  stack:";
+            for x in vm.stack() {
+                line += " ";
+                line += &x.to_string();
+            }
+        }
+
+        format!("{}\n{}^", line, " ".repeat(pointer_position))
+    }
+
+    /// `{"ip":..,"op":..,"tape":..,"pointer":..,"changed":[[index,value],..],"output":..}`,
+    /// where output is the byte `.` wrote or null
+    fn json_step(&self, vm: &Vm, ip: usize, op: Op) -> String {
+        let tape = vm.tape();
+        let mut changed = String::new();
+        for (i, x) in tape.data.iter().enumerate().filter(|&(i, _)| self.changed(vm, i)) {
+            if !changed.is_empty() {
+                changed.push(',');
+            }
+            let _ = write!(changed, "[{},{}]", i, x);
+        }
+        let output = if op == Op::Output { tape.current().to_string() } else { "null".to_string() };
+
+        format!(
+            "{{\"ip\":{},\"op\":{},\"tape\":{},\"pointer\":{},\"changed\":[{}],\"output\":{}}}",
+            ip, json_string(&op.to_string()), vm.active_tape(), tape.pointer, changed, output
+        )
+    }
+
+    /// The line shown when a breakpoint pauses the program
+    pub fn breakpoint(&self, ip: usize) -> String {
+        match self.format {
+            DebugFormat::Text => self.paint(&format!("breakpoint at op {}, press a key to continue", ip), BREAK_COLOR),
+            DebugFormat::Json => format!("{{\"ip\":{},\"breakpoint\":true}}", ip),
+        }
+    }
+}
+
+
+/// Ops either side of the current one in the visualizer
+const OPS_AROUND: usize = 12;
+/// Cells in the visualizer's bar chart
+const CELLS_SHOWN: usize = 64;
+/// Bars for cell values, 0 being blank
+const BARS: [char; 9] = [' ', '▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+
+/// Draws a live view of a running program on stderr, over the same screen each frame
+pub struct Visualizer {
+    steps_per_frame: usize,
+    delay: Duration,
+    steps: usize,
+    /// The end of what the program wrote, for the last line of the screen
+    output: Vec<u8>,
+}
+
+
+impl Visualizer {
+    pub fn new(steps_per_frame: usize, delay: Duration) -> Visualizer {
+        Visualizer { steps_per_frame: steps_per_frame.max(1), delay, steps: 0, output: Vec::new() }
+    }
+
+    /// Count a step that ran `op`, drawing a frame if it's time for one
+    pub fn step(&mut self, vm: &Vm, op: Op) -> io::Result<()> {
+        self.steps += 1;
+        if op == Op::Output {
+            self.output.push(vm.tape().current());
+            if self.output.len() > 256 {
+                self.output.drain(..128);
+            }
+        }
+        if self.steps.is_multiple_of(self.steps_per_frame) {
+            self.draw(vm)?;
+            std::thread::sleep(self.delay);
+        }
+        Ok(())
+    }
+
+    /// Draw the screen now, like when the program ends or pauses
+    pub fn draw(&self, vm: &Vm) -> io::Result<()> {
+        // Going back to the top and clearing each line as it's drawn keeps the screen from flickering
+        let frame: String = self.frame(vm).lines().map(|line| format!("{}\x1b[K\n", line)).collect();
+        io::Write::write_all(&mut io::stderr(), format!("\x1b[H{}\x1b[J", frame).as_bytes())
+    }
+
+    fn frame(&self, vm: &Vm) -> String {
+        let ops = &vm.program().ops;
+        let ip = vm.ip();
+        let mut frame = match vm.current_op() {
+            Some(op) => format!("step {}, op {} of {}: {}\n", self.steps, ip, ops.len(), op),
+            None => format!("step {}, finished\n", self.steps),
+        };
+
+        let (mut line, mut caret) = (String::new(), 0);
+        for (i, op) in ops.iter().enumerate().skip(ip.saturating_sub(OPS_AROUND)).take(OPS_AROUND * 2 + 1) {
+            if i == ip {
+                caret = line.chars().count();
+            }
+            let _ = write!(line, "{} ", op);
+        }
+        if ip >= ops.len() {
+            caret = line.chars().count();
+        }
+        let _ = writeln!(frame, "{}\n{}^", line, " ".repeat(caret));
+
+        let tape = vm.tape();
+        let start = tape.pointer / CELLS_SHOWN * CELLS_SHOWN;
+        let cells = &tape.data[start..tape.data.len().min(start + CELLS_SHOWN)];
+        let _ = writeln!(
+            frame, "cells {}-{}, pointer at {} holding {}",
+            start, start + CELLS_SHOWN - 1, tape.pointer, tape.current(),
+        );
+        let bars: String = cells.iter().map(|&value| BARS[(value as usize * 8).div_ceil(127)]).collect();
+        let _ = writeln!(frame, "{:<width$}|\n{}^", bars, " ".repeat(tape.pointer - start), width = CELLS_SHOWN);
+
+        let output = String::from_utf8_lossy(&self.output);
+        let last_line = output.trim_end_matches('\n').rsplit('\n').next().unwrap_or("");
+        let shown: String = last_line.chars().skip(last_line.chars().count().saturating_sub(60)).collect();
+        let _ = writeln!(frame, "output: {}", shown);
+        frame
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Program, VmOptions, parse};
+
+    /// What the printer shows after the last step of `code`
+    fn last_step(code: &str, format: DebugFormat, color: bool) -> String {
+        let program = Program::compile(&parse(code, &Default::default()).unwrap(), false).unwrap();
+        let mut printer = StepPrinter::new(&DebugOptions { mode: DebugMode::Verbose, format, color, ..DebugOptions::default() }, false);
+        let mut vm = Vm::new(program, &VmOptions::default());
+        let mut shown = String::new();
+        while let Some(op) = vm.current_op() {
+            let ip = vm.ip();
+            vm.step(&mut std::io::empty(), &mut std::io::sink()).unwrap();
+            shown = printer.step(&vm, ip, op);
+        }
+        shown
+    }
+
+    #[test]
+    fn plain_step() {
+        assert_eq!(last_step("++>+", DebugFormat::Text, false), "+: 2 1\n     ^");
+    }
+
+    #[test]
+    fn colored_step() {
+        assert_eq!(last_step("+>+<+", DebugFormat::Text, true), "\x1b[1;36m+\x1b[0m: \x1b[7;32m2\x1b[0m 1\n   ^");
+        assert_eq!(last_step("+>+<", DebugFormat::Text, true), "\x1b[1;36m<\x1b[0m: \x1b[7m1\x1b[0m 1\n   ^");
+    }
+
+    #[test]
+    fn visualizer_frame() {
+        let program = Program::compile(&parse("++++++++++.>+++++++[<+++++++++>-]<+.", &Default::default()).unwrap(), true).unwrap();
+        let mut vm = Vm::new(program, &VmOptions::default());
+        let mut visualizer = Visualizer::new(usize::MAX, Duration::ZERO);
+        while let Some(op) = vm.current_op() {
+            vm.step(&mut std::io::empty(), &mut std::io::sink()).unwrap();
+            visualizer.step(&vm, op).unwrap();
+        }
+        let frame = visualizer.frame(&vm);
+        let lines: Vec<&str> = frame.lines().collect();
+        // Only the ops just before the end are shown
+        assert_eq!(lines[0], "step 43, finished");
+        assert_eq!(lines[1..3], [". > +7 [ < +9 > - ] < + . ", "                          ^"]);
+        assert_eq!(lines[3], "cells 0-63, pointer at 0 holding 74");
+        assert_eq!(lines[4], format!("▅{}|", " ".repeat(63)));
+        // The output's last line, after the newline it starts with
+        assert_eq!(lines[6], "output: J");
+    }
+
+    #[test]
+    fn json_step() {
+        assert_eq!(
+            last_step("+>+", DebugFormat::Json, false),
+            "{\"ip\":2,\"op\":\"+\",\"tape\":0,\"pointer\":1,\"changed\":[[1,1]],\"output\":null}"
+        );
+        assert_eq!(
+            last_step("+.", DebugFormat::Json, false),
+            "{\"ip\":1,\"op\":\".\",\"tape\":0,\"pointer\":0,\"changed\":[],\"output\":1}"
+        );
+    }
+
+    #[test]
+    fn snapshot_commands() {
+        let mut vm = Vm::new(Program::compile(&parse("+>++", &Default::default()).unwrap(), false).unwrap(), &VmOptions::default());
+        let mut commands = Commands::default();
+        assert_eq!(commands.run("snap start", &vm).unwrap(), "saved start");
+        vm.run(&mut std::io::empty(), &mut std::io::sink()).unwrap();
+        assert_eq!(commands.run("diff start", &vm).unwrap(), "cell 0: 0 -> 1\ncell 1: 0 -> 2");
+        assert_eq!(commands.run("snap end", &vm).unwrap(), "saved end");
+        assert_eq!(commands.run("diff end", &vm).unwrap(), "no cells changed");
+        assert_eq!(commands.run("diff end start", &vm).unwrap(), "cell 0: 1 -> 0\ncell 1: 2 -> 0");
+        assert_eq!(commands.run("snaps", &vm).unwrap(), "end\nstart");
+        assert_eq!(commands.run("diff middle", &vm).unwrap(), "there's no snapshot called middle");
+        assert_eq!(commands.run("what", &vm).unwrap(), COMMANDS_HELP);
+        assert_eq!(commands.run("", &vm), None);
+    }
+}
@@ -0,0 +1,103 @@
+use std::{error::Error, path::Path};
+use clap::ValueEnum;
+use serde::Deserialize;
+
+
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize))]
+#[serde(rename_all = "lowercase")]
+pub enum Dialect {
+    /// Plain brainfuck (.b, .bf)
+    #[default]
+    Brainfuck,
+
+    /// Ook! (.ook)
+    Ook,
+
+    /// pbrain, brainfuck with procedures (.pb)
+    Pbrain,
+}
+
+
+impl Dialect {
+    /// Guess the dialect from a file extension, if it is a known one
+    pub fn from_path(path: impl AsRef<Path>) -> Option<Dialect> {
+        let extension = path.as_ref().extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "b" | "bf" => Some(Dialect::Brainfuck),
+            "ook" => Some(Dialect::Ook),
+            "pb" => Some(Dialect::Pbrain),
+            _ => None,
+        }
+    }
+}
+
+
+/// Pick the dialect for a file, falling back to brainfuck for unknown extensions
+pub fn detect_dialect(path: impl AsRef<Path>) -> Dialect {
+    Dialect::from_path(path).unwrap_or_default()
+}
+
+
+/// Convert Ook! source into the equivalent brainfuck source
+pub fn ook_to_brainfuck(code: &str) -> Result<String, Box<dyn Error>> {
+    Ok(translate_ook(code)?.into_iter().map(|(c, _)| c).collect())
+}
+
+
+/// Convert Ook! source into brainfuck characters, with the offset of the first word of each pair
+pub fn translate_ook(code: &str) -> Result<Vec<(char, usize)>, Box<dyn Error>> {
+    // Anything that isn't an Ook word is a comment
+    let words: Vec<(usize, &str)> = code.split_whitespace()
+        .filter(|word| matches!(*word, "Ook." | "Ook?" | "Ook!"))
+        .map(|word| (word.as_ptr() as usize - code.as_ptr() as usize, word))
+        .collect();
+
+    if !words.len().is_multiple_of(2) {
+        return Err("Ook! words must come in pairs".into());
+    }
+
+    words.chunks(2).map(|pair| {
+        let c = match (pair[0].1, pair[1].1) {
+            ("Ook.", "Ook?") => '>',
+            ("Ook?", "Ook.") => '<',
+            ("Ook.", "Ook.") => '+',
+            ("Ook!", "Ook!") => '-',
+            ("Ook!", "Ook.") => '.',
+            ("Ook.", "Ook!") => ',',
+            ("Ook!", "Ook?") => '[',
+            ("Ook?", "Ook!") => ']',
+            _ => return Err("\"Ook? Ook?\" is not a valid instruction".into()),
+        };
+        Ok((c, pair[0].0))
+    }).collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_from_extension() {
+        assert_eq!(detect_dialect("hello.b"), Dialect::Brainfuck);
+        assert_eq!(detect_dialect("dir/hello.BF"), Dialect::Brainfuck);
+        assert_eq!(detect_dialect("hello.ook"), Dialect::Ook);
+        assert_eq!(detect_dialect("hello.pb"), Dialect::Pbrain);
+        assert_eq!(detect_dialect("hello.txt"), Dialect::Brainfuck);
+        assert_eq!(Dialect::from_path("hello"), None);
+    }
+
+    #[test]
+    fn ook_translation() {
+        assert_eq!(
+            ook_to_brainfuck("Ook. Ook. Ook! Ook? comment Ook! Ook! Ook? Ook. Ook? Ook!").unwrap(),
+            "+[-<]"
+        );
+    }
+
+    #[test]
+    fn ook_odd_words() {
+        assert!(ook_to_brainfuck("Ook. Ook. Ook!").is_err());
+    }
+}
@@ -0,0 +1,74 @@
+use std::fmt::Write;
+use crate::ir::{Op, Program};
+
+
+/// List a program's ops, one per line, with their index, source offset and operands
+pub fn disassemble(program: &Program) -> String {
+    let mut output = String::from(" index  offset  op\n");
+
+    for (i, op) in program.ops.iter().enumerate() {
+        let offset = match program.offset(i) {
+            Some(offset) => offset.to_string(),
+            None => "-".to_string(),
+        };
+        let _ = writeln!(output, "{:>6}  {:>6}  {}", i, offset, describe(op));
+    }
+
+    output
+}
+
+
+fn describe(op: &Op) -> String {
+    match *op {
+        // Cells wrap at 128, so big additions are really subtractions
+        Op::Add(n) if n > 64 => format!("sub {}", 128 - n as usize),
+        Op::Add(n) => format!("add {}", n),
+        Op::Right(n) => format!("right {}", n),
+        Op::Left(n) => format!("left {}", n),
+        Op::Clear => "clear".to_string(),
+        Op::MulAdd { offset, factor } => format!("muladd +{} x{}", offset, factor),
+        Op::JumpIfZero(target) => format!("jz {}", target),
+        Op::JumpIfNonZero(target) => format!("jnz {}", target),
+        Op::Input => "in".to_string(),
+        Op::Output => "out".to_string(),
+        Op::Break => "break".to_string(),
+        Op::ProcedureOpen(end) => format!("proc {}", end),
+        Op::ProcedureClose => "ret".to_string(),
+        Op::Call => "call".to_string(),
+        Op::NextTape => "tape".to_string(),
+        Op::CopyToNextTape => "copy".to_string(),
+        Op::Push => "push".to_string(),
+        Op::Pop => "pop".to_string(),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_with_offsets, ParseOptions};
+
+    #[test]
+    fn listing() {
+        let instructions = parse_with_offsets("+++[->++<]>[-.]", &ParseOptions::default()).unwrap();
+        let program = Program::compile_with_offsets(&instructions, true).unwrap();
+
+        assert_eq!(disassemble(&program), [
+            " index  offset  op\n",
+            "     0       0  add 3\n",
+            "     1       3  muladd +1 x2\n",
+            "     2       3  clear\n",
+            "     3      10  right 1\n",
+            "     4      11  jz 7\n",
+            "     5      12  sub 1\n",
+            "     6      13  out\n",
+            "     7      14  jnz 4\n",
+        ].concat());
+    }
+
+    #[test]
+    fn listing_without_offsets() {
+        let program = Program { ops: vec![Op::Input], offsets: Vec::new() };
+        assert_eq!(disassemble(&program), " index  offset  op\n     0       -  in\n");
+    }
+}
@@ -0,0 +1,167 @@
+use std::{error::Error, fmt::Write};
+use crate::ir::{Op, Program};
+use super::{TAPE_SIZE, unsupported};
+
+
+// `cell` is the current cell, `%rbx` is the start of the tape and `%r12` is the pointer
+const CELL: &str = "(%rbx,%r12)";
+
+const HEADER: &str = r#"# Generated by bf-rs, for x86-64 Linux. Assemble and link with `cc program.s`.
+#
+# %rbx  start of the tape
+# %r12  index of the current cell
+# Cells wrap at 128, the end of input reads as 0.
+
+    .bss
+    .lcomm tape, TAPE_SIZE
+
+    .section .rodata
+overflow_message:
+    .string "error: pointer moved past the end of the tape\n"
+input_message:
+    .string "error: input must be an ASCII character\n"
+
+    .text
+
+# Print the message in %rdi to stderr and exit with 1
+fail:
+    # This never returns, so the stack can be aligned for calls by throwing some away
+    andq $-16, %rsp
+    movq %rdi, %rbp
+    xorl %edi, %edi
+    call fflush@PLT
+    movq %rbp, %rdi
+    movq stderr@GOTPCREL(%rip), %rax
+    movq (%rax), %rsi
+    call fputs@PLT
+    movl $1, %edi
+    call exit@PLT
+
+overflow:
+    leaq overflow_message(%rip), %rdi
+    jmp fail
+
+# Read a byte into %eax, 0 at the end of input
+input:
+    subq $8, %rsp
+    xorl %edi, %edi
+    call fflush@PLT
+    call getchar@PLT
+    addq $8, %rsp
+    testl %eax, %eax
+    js .Leof
+    cmpl $127, %eax
+    jg .Lnot_ascii
+    ret
+.Leof:
+    xorl %eax, %eax
+    ret
+.Lnot_ascii:
+    leaq input_message(%rip), %rdi
+    jmp fail
+
+    .globl main
+main:
+    # Three pushes keep the stack 16 byte aligned for calls
+    pushq %rbx
+    pushq %r12
+    pushq %rbp
+    leaq tape(%rip), %rbx
+    xorl %r12d, %r12d
+"#;
+
+const FOOTER: &str = r#"
+    xorl %edi, %edi
+    call fflush@PLT
+    xorl %eax, %eax
+    popq %rbp
+    popq %r12
+    popq %rbx
+    ret
+
+    .section .note.GNU-stack,"",@progbits
+"#;
+
+
+/// Translate a program into x86-64 assembly (GNU syntax) for Linux, using libc for I/O.
+/// Each op is commented with what it came from.
+pub fn emit_asm(program: &Program) -> Result<String, Box<dyn Error>> {
+    let mut code = HEADER.replace("TAPE_SIZE", &TAPE_SIZE.to_string());
+
+    for (i, op) in program.ops.iter().enumerate() {
+        let _ = write!(code, "\n    # {}", op);
+        if let Some(offset) = program.offset(i) {
+            let _ = write!(code, " (offset {})", offset);
+        }
+        code += "\n";
+
+        let lines = match *op {
+            Op::Add(n) => vec![format!("addb ${}, {}", n, CELL), format!("andb $127, {}", CELL)],
+            Op::Right(n) => vec![
+                format!("addq ${}, %r12", n),
+                format!("cmpq ${}, %r12", TAPE_SIZE),
+                "jae overflow".to_string(),
+            ],
+            // Subtracting past 0 sets the carry flag, which picks 0 instead
+            Op::Left(n) => vec![
+                "xorl %eax, %eax".to_string(),
+                format!("subq ${}, %r12", n),
+                "cmovbq %rax, %r12".to_string(),
+            ],
+            Op::Clear => vec![format!("movb $0, {}", CELL)],
+            Op::MulAdd { offset, factor } => vec![
+                format!("leaq {}(%r12), %rcx", offset),
+                format!("cmpq ${}, %rcx", TAPE_SIZE),
+                "jae overflow".to_string(),
+                format!("movzbl {}, %eax", CELL),
+                format!("imull ${}, %eax, %eax", factor),
+                "addb %al, (%rbx,%rcx)".to_string(),
+                "andb $127, (%rbx,%rcx)".to_string(),
+            ],
+            // Labels are named after the op they come after
+            Op::JumpIfZero(close) => vec![
+                format!("cmpb $0, {}", CELL),
+                format!("je .Lop{}", close),
+                format!(".Lop{}:", i),
+            ],
+            Op::JumpIfNonZero(open) => vec![
+                format!("cmpb $0, {}", CELL),
+                format!("jne .Lop{}", open),
+                format!(".Lop{}:", i),
+            ],
+            Op::Input => vec!["call input".to_string(), format!("movb %al, {}", CELL)],
+            Op::Output => vec![format!("movzbl {}, %edi", CELL), "call putchar@PLT".to_string()],
+            Op::Break => Vec::new(),
+            _ => return Err(unsupported(op, "x86-64")),
+        };
+
+        for line in lines {
+            // Labels aren't indented
+            if !line.ends_with(':') {
+                code += "    ";
+            }
+            code += &line;
+            code += "\n";
+        }
+    }
+
+    code += FOOTER;
+    Ok(code)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_with_offsets, ParseOptions};
+
+    #[test]
+    fn annotated_loops() {
+        let instructions = parse_with_offsets("+[.-]", &ParseOptions::default()).unwrap();
+        let code = emit_asm(&Program::compile_with_offsets(&instructions, false).unwrap()).unwrap();
+
+        assert!(code.contains("\n    # [ (offset 1)\n    cmpb $0, (%rbx,%r12)\n    je .Lop4\n.Lop1:\n"));
+        assert!(code.contains("\n    # ] (offset 4)\n    cmpb $0, (%rbx,%r12)\n    jne .Lop1\n.Lop4:\n"));
+        assert!(code.contains(".lcomm tape, 65536\n"));
+    }
+}
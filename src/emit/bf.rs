@@ -0,0 +1,72 @@
+use crate::ir::{Op, Program};
+
+
+/// Translate a program back into plain brainfuck, with its macros and libraries already expanded.
+/// Folded ops are written out again, so the result runs the same in any interpreter.
+pub fn emit_bf(program: &Program) -> String {
+    let mut code = String::new();
+    let repeat = |code: &mut String, c: char, n: usize| code.extend(std::iter::repeat_n(c, n));
+    let add = |code: &mut String, n: u8| match n > 64 {
+        true => repeat(code, '-', 128 - n as usize),
+        false => repeat(code, '+', n as usize),
+    };
+
+    let mut ops = program.ops.iter().peekable();
+    while let Some(op) = ops.next() {
+        match *op {
+            Op::Add(n) => add(&mut code, n),
+            Op::Right(n) => repeat(&mut code, '>', n),
+            Op::Left(n) => repeat(&mut code, '<', n),
+            Op::Clear => code += "[-]",
+            // The loop a run of these and their `Clear` came from
+            Op::MulAdd { offset, factor } => {
+                code += "[-";
+                let (mut target, mut at) = (Some((offset, factor)), 0);
+                while let Some((offset, factor)) = target {
+                    match offset > at {
+                        true => repeat(&mut code, '>', offset - at),
+                        false => repeat(&mut code, '<', at - offset),
+                    }
+                    add(&mut code, factor);
+                    at = offset;
+                    target = match ops.next() {
+                        Some(&Op::MulAdd { offset, factor }) => Some((offset, factor)),
+                        _ => None,
+                    };
+                }
+                repeat(&mut code, '<', at);
+                code.push(']');
+            },
+            Op::JumpIfZero(_) => code.push('['),
+            Op::JumpIfNonZero(_) => code.push(']'),
+            Op::Input => code.push(','),
+            Op::Output => code.push('.'),
+            Op::Break => code.push('@'),
+            Op::ProcedureOpen(_) => code.push('('),
+            Op::ProcedureClose => code.push(')'),
+            Op::Call => code.push(':'),
+            Op::NextTape => code.push('^'),
+            Op::CopyToNextTape => code.push('&'),
+            Op::Push => code.push('$'),
+            Op::Pop => code.push('%'),
+        }
+    }
+    code + "\n"
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ParseOptions, parse};
+
+    #[test]
+    fn round_trip() {
+        let code = "+++++[->++>+++<<]>>-----.[-]<<,[>+<-]";
+        let program = Program::compile(&parse(code, &ParseOptions::default()).unwrap(), true).unwrap();
+        assert_eq!(emit_bf(&program), "+++++[->++>+++<<]>>-----.[-]<<,[->+<]\n");
+        // Going the shorter way round
+        let folded = Program::compile(&parse(&"-".repeat(73), &ParseOptions::default()).unwrap(), true).unwrap();
+        assert_eq!(emit_bf(&folded), format!("{}\n", "+".repeat(55)));
+    }
+}
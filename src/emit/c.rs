@@ -0,0 +1,166 @@
+use std::error::Error;
+use crate::{Eof, bounds, ir::{Op, Program}};
+use super::{structured_lines, unsupported, SourceMap, TAPE_SIZE, source_map::LineIndex};
+
+
+const HEADER: &str = r#"#include <stdio.h>
+#include <stdlib.h>
+
+#define TAPE_SIZE 65536
+
+static unsigned char tape[TAPE_SIZE];
+
+static void fail(const char *message) {
+    fflush(stdout);
+    fprintf(stderr, "error: %s\n", message);
+    exit(1);
+}
+
+/* Index of the cell `offset` to the right of `p`, failing if it is off the end of the tape */
+static inline size_t at(size_t p, size_t offset) {
+    if (p + offset >= TAPE_SIZE) {
+        fail("pointer moved past the end of the tape");
+    }
+    return p + offset;
+}
+
+/* Read a byte for `,` on a cell holding `cell` */
+static inline unsigned char input(unsigned char cell) {
+    fflush(stdout);
+    int c = getchar();
+    if (c == EOF) {
+        return {eof};
+    }
+    if (c > 127) {
+        fail("input must be an ASCII character");
+    }
+    return (unsigned char)c;
+}
+
+int main(void) {
+    size_t p = 0;
+
+"#;
+
+const FOOTER: &str = r#"
+    fflush(stdout);
+    return 0;
+}
+"#;
+
+
+/// Translate a program into C, with cells wrapping at 128 and `,` at the end of input doing what `eof` says,
+/// like the interpreter
+pub fn emit_c(program: &Program, eof: Eof) -> Result<String, Box<dyn Error>> {
+    Ok(emit_c_with_source_map(program, eof)?.0)
+}
+
+
+/// The same as `emit_c`, along with the source offset each line came from
+pub fn emit_c_with_source_map(program: &Program, eof: Eof) -> Result<(String, SourceMap), Box<dyn Error>> {
+    // Moves the analysis shows stay on the tape don't need checking
+    let in_bounds = bounds::in_bounds(program, &bounds::states(program), TAPE_SIZE);
+    let (body, source_map) = structured_lines(program, 1, HEADER.matches('\n').count(), |i, op| Ok(Some(match *op {
+        Op::Add(n) => format!("tape[p] = (tape[p] + {}) & 127;", n),
+        Op::Right(n) if in_bounds[i] => format!("p += {};", n),
+        Op::Right(n) => format!("p = at(p, {});", n),
+        Op::Left(n) => format!("p = p < {0} ? 0 : p - {0};", n),
+        Op::Clear => "tape[p] = 0;".to_string(),
+        Op::MulAdd { offset, factor } if in_bounds[i] => format!(
+            "tape[p + {0}] = (tape[p + {0}] + tape[p] * {1}) & 127;", offset, factor
+        ),
+        Op::MulAdd { offset, factor } => format!(
+            "tape[at(p, {0})] = (tape[at(p, {0})] + tape[p] * {1}) & 127;", offset, factor
+        ),
+        Op::JumpIfZero(_) => "while (tape[p]) {".to_string(),
+        Op::JumpIfNonZero(_) => "}".to_string(),
+        Op::Input => "tape[p] = input(tape[p]);".to_string(),
+        Op::Output => "putchar(tape[p]);".to_string(),
+        Op::Break => return Ok(None),
+        _ => return Err(unsupported(op, "C")),
+    })))?;
+
+    let eof = match eof {
+        Eof::Zero => "0",
+        Eof::Max => "127",
+        Eof::Unchanged => "cell",
+    };
+    Ok((HEADER.replace("{eof}", eof) + &body + FOOTER, source_map))
+}
+
+
+/// Add `#line` directives, so debug info points at the brainfuck source instead of the C
+pub fn with_line_directives(code: &str, source_map: &SourceMap, source_path: &str, source: &str) -> String {
+    let index = LineIndex::new(source);
+    let path = source_path.replace('\\', "\\\\").replace('"', "\\\"");
+    let mut mapped = source_map.lines.iter().peekable();
+    let mut output = String::new();
+
+    for (i, line) in code.lines().enumerate() {
+        if let Some(&&(_, offset)) = mapped.peek().filter(|(mapped_line, _)| *mapped_line == i) {
+            let (source_line, _) = index.line_column(source, offset);
+            output += &format!("#line {} \"{}\"\n", source_line + 1, path);
+            mapped.next();
+        }
+        output += line;
+        output += "\n";
+    }
+    output
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse, ParseOptions};
+
+    #[test]
+    fn idioms_become_arithmetic() {
+        let instructions = parse("++[->+++<]>[.-]", &ParseOptions::default()).unwrap();
+        let code = emit_c(&Program::compile(&instructions, true).unwrap(), Eof::Zero).unwrap();
+
+        assert!(code.contains("    tape[p] = (tape[p] + 2) & 127;\n"));
+        assert!(code.contains("    tape[p + 1] = (tape[p + 1] + tape[p] * 3) & 127;\n    tape[p] = 0;\n"));
+        assert!(code.contains("    while (tape[p]) {\n        putchar(tape[p]);\n"));
+    }
+
+    #[test]
+    fn checks_only_where_needed() {
+        let checked = |code| emit_c(&Program::compile(&parse(code, &ParseOptions::default()).unwrap(), true).unwrap(), Eof::Zero).unwrap();
+        assert!(checked("+[->>+<<]>>.").contains("    tape[p + 2] = (tape[p + 2] + tape[p] * 1) & 127;\n"));
+        assert!(checked("+[->>+<<]>>.").contains("    p += 2;\n"));
+        assert!(checked("+[>+]>").contains("    p = at(p, 1);\n"));
+        // The 128 `+` wrap to 0, so the loop is skipped and the move after it has to be checked
+        let wrapped = checked(&("+".repeat(128) + "[.]" + &">".repeat(70000) + "+."));
+        assert!(wrapped.contains("    p = at(p, 70000);\n"));
+        assert!(!wrapped.contains("p += 70000;"));
+    }
+
+    #[test]
+    fn line_directives() {
+        let source = "+\n[-]\n.";
+        let instructions = crate::parse_with_offsets(source, &ParseOptions::default()).unwrap();
+        let program = Program::compile_with_offsets(&instructions, true).unwrap();
+        let (code, source_map) = emit_c_with_source_map(&program, Eof::Zero).unwrap();
+        let code = with_line_directives(&code, &source_map, "a.b", source);
+
+        assert!(code.contains("#line 1 \"a.b\"\n    tape[p] = (tape[p] + 1) & 127;\n#line 2 \"a.b\"\n    tape[p] = 0;\n#line 3 \"a.b\"\n    putchar"));
+    }
+
+    #[test]
+    fn extensions_are_unsupported() {
+        let options = ParseOptions { extensions: vec![crate::Extension::Stack], ..Default::default() };
+        let instructions = parse("+$", &options).unwrap();
+        assert!(emit_c(&Program::compile(&instructions, true).unwrap(), Eof::Zero).is_err());
+    }
+
+    #[test]
+    fn eof_policies() {
+        let program = Program::compile(&parse("+++,.", &ParseOptions::default()).unwrap(), true).unwrap();
+        for (eof, value) in [(Eof::Zero, "0"), (Eof::Max, "127"), (Eof::Unchanged, "cell")] {
+            let code = emit_c(&program, eof).unwrap();
+            assert!(code.contains(&format!("    if (c == EOF) {{\n        return {};\n", value)));
+            assert!(code.contains("    tape[p] = input(tape[p]);\n"));
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use std::error::Error;
+use crate::ir::{Op, Program};
+use super::{structured_lines, unsupported, SourceMap};
+
+
+const HEADER: &str = r#"const TAPE_SIZE = 65536;
+
+/**
+ * Run the program with `input` as the data for `,`, returning everything it printed.
+ * Cells wrap at 128, and reading past the end of the input gives 0.
+ */
+export default async function run(input = "") {
+    const tape = new Uint8Array(TAPE_SIZE);
+    const inputBytes = new TextEncoder().encode(input);
+    let inputPosition = 0;
+    let output = "";
+    let p = 0;
+
+    // Index of the cell `offset` to the right of `p`, throwing if it is off the end of the tape
+    const at = (offset) => {
+        if (p + offset >= TAPE_SIZE) {
+            throw new RangeError("pointer moved past the end of the tape");
+        }
+        return p + offset;
+    };
+
+    const read = () => {
+        if (inputPosition >= inputBytes.length) {
+            return 0;
+        }
+        const byte = inputBytes[inputPosition++];
+        if (byte > 127) {
+            throw new RangeError("input must be an ASCII character");
+        }
+        return byte;
+    };
+
+"#;
+
+const FOOTER: &str = r#"
+    return output;
+}
+"#;
+
+
+/// Translate a program into a JavaScript module, with an async `run(input)` as the default export
+pub fn emit_js(program: &Program) -> Result<String, Box<dyn Error>> {
+    Ok(emit_js_with_source_map(program)?.0)
+}
+
+
+/// The same as `emit_js`, along with the source offset each line came from
+pub fn emit_js_with_source_map(program: &Program) -> Result<(String, SourceMap), Box<dyn Error>> {
+    let (body, source_map) = structured_lines(program, 1, HEADER.matches('\n').count(), |_, op| Ok(Some(match *op {
+        Op::Add(n) => format!("tape[p] = (tape[p] + {}) & 127;", n),
+        Op::Right(n) => format!("p = at({});", n),
+        Op::Left(n) => format!("p = p < {0} ? 0 : p - {0};", n),
+        Op::Clear => "tape[p] = 0;".to_string(),
+        Op::MulAdd { offset, factor } => format!(
+            "tape[at({0})] = (tape[at({0})] + tape[p] * {1}) & 127;", offset, factor
+        ),
+        Op::JumpIfZero(_) => "while (tape[p]) {".to_string(),
+        Op::JumpIfNonZero(_) => "}".to_string(),
+        Op::Input => "tape[p] = read();".to_string(),
+        Op::Output => "output += String.fromCharCode(tape[p]);".to_string(),
+        Op::Break => return Ok(None),
+        _ => return Err(unsupported(op, "JavaScript")),
+    })))?;
+
+    Ok((HEADER.to_string() + &body + FOOTER, source_map))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse, ParseOptions};
+
+    #[test]
+    fn loops_become_while() {
+        let instructions = parse(",[.,]", &ParseOptions::default()).unwrap();
+        let code = emit_js(&Program::compile(&instructions, true).unwrap()).unwrap();
+
+        assert!(code.contains("    tape[p] = read();\n    while (tape[p]) {\n        output += String.fromCharCode(tape[p]);\n"));
+        assert!(code.ends_with("    }\n\n    return output;\n}\n"));
+    }
+}
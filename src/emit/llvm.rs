@@ -0,0 +1,246 @@
+use std::{error::Error, fmt::Write};
+use crate::ir::{Op, Program};
+use super::{TAPE_SIZE, unsupported};
+
+
+const OVERFLOW_MESSAGE: &str = "error: pointer moved past the end of the tape\n";
+const INPUT_MESSAGE: &str = "error: input must be an ASCII character\n";
+
+
+/// Translate a program into LLVM IR text, with a `main` that uses libc for I/O.
+/// Compile it with `clang program.ll`, or turn it into bitcode with `llvm-as`.
+pub fn emit_llvm(program: &Program) -> Result<String, Box<dyn Error>> {
+    let mut emitter = Emitter { code: String::new(), next_value: 0, next_loop: 0, loops: Vec::new() };
+    emitter.header();
+
+    emitter.line("define i32 @main() {");
+    emitter.line("entry:");
+    emitter.line("  %p = alloca i64");
+    emitter.line("  store i64 0, ptr %p");
+
+    for op in &program.ops {
+        match *op {
+            Op::Add(n) => {
+                let p = emitter.load_pointer();
+                let cell = emitter.cell_pointer(&p);
+                emitter.add_to_cell(&cell, &n.to_string());
+            },
+            Op::Right(n) => {
+                let p = emitter.load_pointer();
+                let moved = emitter.value();
+                emitter.line(&format!("  {} = call i64 @at(i64 {}, i64 {})", moved, p, n));
+                emitter.line(&format!("  store i64 {}, ptr %p", moved));
+            },
+            Op::Left(n) => {
+                let p = emitter.load_pointer();
+                let fits = emitter.value();
+                let moved = emitter.value();
+                let clamped = emitter.value();
+                emitter.line(&format!("  {} = icmp uge i64 {}, {}", fits, p, n));
+                emitter.line(&format!("  {} = sub i64 {}, {}", moved, p, n));
+                emitter.line(&format!("  {} = select i1 {}, i64 {}, i64 0", clamped, fits, moved));
+                emitter.line(&format!("  store i64 {}, ptr %p", clamped));
+            },
+            Op::Clear => {
+                let p = emitter.load_pointer();
+                let cell = emitter.cell_pointer(&p);
+                emitter.line(&format!("  store i8 0, ptr {}", cell));
+            },
+            Op::MulAdd { offset, factor } => {
+                let p = emitter.load_pointer();
+                let cell = emitter.cell_pointer(&p);
+                let value = emitter.value();
+                let product = emitter.value();
+                let index = emitter.value();
+                emitter.line(&format!("  {} = load i8, ptr {}", value, cell));
+                emitter.line(&format!("  {} = mul i8 {}, {}", product, value, factor));
+                emitter.line(&format!("  {} = call i64 @at(i64 {}, i64 {})", index, p, offset));
+                let target = emitter.cell_pointer(&index);
+                emitter.add_to_cell(&target, &product);
+            },
+            Op::JumpIfZero(_) => {
+                let id = emitter.next_loop;
+                emitter.next_loop += 1;
+                emitter.loops.push(id);
+
+                emitter.line(&format!("  br label %loop{}", id));
+                emitter.line(&format!("loop{}:", id));
+                let p = emitter.load_pointer();
+                let cell = emitter.cell_pointer(&p);
+                let value = emitter.value();
+                let is_zero = emitter.value();
+                emitter.line(&format!("  {} = load i8, ptr {}", value, cell));
+                emitter.line(&format!("  {} = icmp eq i8 {}, 0", is_zero, value));
+                emitter.line(&format!("  br i1 {}, label %end{}, label %body{}", is_zero, id, id));
+                emitter.line(&format!("body{}:", id));
+            },
+            Op::JumpIfNonZero(_) => {
+                let id = emitter.loops.pop().ok_or("all brackets must have matching brackets")?;
+                emitter.line(&format!("  br label %loop{}", id));
+                emitter.line(&format!("end{}:", id));
+            },
+            Op::Input => {
+                let p = emitter.load_pointer();
+                let cell = emitter.cell_pointer(&p);
+                let byte = emitter.value();
+                emitter.line(&format!("  {} = call i8 @input()", byte));
+                emitter.line(&format!("  store i8 {}, ptr {}", byte, cell));
+            },
+            Op::Output => {
+                let p = emitter.load_pointer();
+                let cell = emitter.cell_pointer(&p);
+                let value = emitter.value();
+                let extended = emitter.value();
+                emitter.line(&format!("  {} = load i8, ptr {}", value, cell));
+                emitter.line(&format!("  {} = zext i8 {} to i32", extended, value));
+                emitter.line(&format!("  call i32 @putchar(i32 {})", extended));
+            },
+            Op::Break => (),
+            _ => return Err(unsupported(op, "LLVM")),
+        }
+    }
+
+    emitter.line("  call i32 @fflush(ptr null)");
+    emitter.line("  ret i32 0");
+    emitter.line("}");
+    Ok(emitter.code)
+}
+
+
+struct Emitter {
+    code: String,
+    // Counters for unique value and label names
+    next_value: usize,
+    next_loop: usize,
+    // Loops that are currently open
+    loops: Vec<usize>,
+}
+
+impl Emitter {
+    fn line(&mut self, line: &str) {
+        self.code += line;
+        self.code += "\n";
+    }
+
+    fn value(&mut self) -> String {
+        self.next_value += 1;
+        format!("%v{}", self.next_value)
+    }
+
+    fn load_pointer(&mut self) -> String {
+        let p = self.value();
+        self.line(&format!("  {} = load i64, ptr %p", p));
+        p
+    }
+
+    /// Get a pointer to the cell at index `p`
+    fn cell_pointer(&mut self, p: &str) -> String {
+        let cell = self.value();
+        self.line(&format!("  {} = getelementptr inbounds [{} x i8], ptr @tape, i64 0, i64 {}", cell, TAPE_SIZE, p));
+        cell
+    }
+
+    /// Add an i8 to a cell, wrapping at 128
+    fn add_to_cell(&mut self, cell: &str, amount: &str) {
+        let value = self.value();
+        let sum = self.value();
+        let wrapped = self.value();
+        self.line(&format!("  {} = load i8, ptr {}", value, cell));
+        self.line(&format!("  {} = add i8 {}, {}", sum, value, amount));
+        self.line(&format!("  {} = and i8 {}, 127", wrapped, sum));
+        self.line(&format!("  store i8 {}, ptr {}", wrapped, cell));
+    }
+
+    fn header(&mut self) {
+        let mut header = String::new();
+        let _ = writeln!(header, "@tape = internal global [{} x i8] zeroinitializer", TAPE_SIZE);
+        let _ = writeln!(header, "@overflow_message = private constant {}", string_constant(OVERFLOW_MESSAGE));
+        let _ = writeln!(header, "@input_message = private constant {}", string_constant(INPUT_MESSAGE));
+        let _ = write!(header, r#"
+declare i32 @getchar()
+declare i32 @putchar(i32)
+declare i32 @fflush(ptr)
+declare i64 @write(i32, ptr, i64)
+declare void @exit(i32) noreturn
+
+define internal void @fail(ptr %message, i64 %length) noreturn {{
+  call i32 @fflush(ptr null)
+  call i64 @write(i32 2, ptr %message, i64 %length)
+  call void @exit(i32 1)
+  unreachable
+}}
+
+; Index of the cell `offset` to the right of `p`, failing if it is off the end of the tape
+define internal i64 @at(i64 %p, i64 %offset) {{
+  %index = add i64 %p, %offset
+  %in_bounds = icmp ult i64 %index, {tape_size}
+  br i1 %in_bounds, label %ok, label %overflow
+ok:
+  ret i64 %index
+overflow:
+  call void @fail(ptr @overflow_message, i64 {overflow_length})
+  unreachable
+}}
+
+define internal i8 @input() {{
+  call i32 @fflush(ptr null)
+  %c = call i32 @getchar()
+  %eof = icmp slt i32 %c, 0
+  br i1 %eof, label %end, label %check
+end:
+  ret i8 0
+check:
+  %ascii = icmp sle i32 %c, 127
+  br i1 %ascii, label %ok, label %bad
+ok:
+  %byte = trunc i32 %c to i8
+  ret i8 %byte
+bad:
+  call void @fail(ptr @input_message, i64 {input_length})
+  unreachable
+}}
+
+"#,
+            tape_size = TAPE_SIZE,
+            overflow_length = OVERFLOW_MESSAGE.len(),
+            input_length = INPUT_MESSAGE.len(),
+        );
+        self.code += &header;
+    }
+}
+
+
+/// An LLVM `[N x i8] c"..."` constant for a string, without a terminating 0
+fn string_constant(string: &str) -> String {
+    let mut escaped = String::new();
+    for byte in string.bytes() {
+        if byte.is_ascii_graphic() && byte != b'"' && byte != b'\\' || byte == b' ' {
+            escaped.push(byte as char);
+        } else {
+            let _ = write!(escaped, "\\{:02X}", byte);
+        }
+    }
+    format!("[{} x i8] c\"{}\"", string.len(), escaped)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse, ParseOptions};
+
+    #[test]
+    fn escape_strings() {
+        assert_eq!(string_constant("a \"b\"\n"), r#"[6 x i8] c"a \22b\22\0A""#);
+    }
+
+    #[test]
+    fn loops_become_blocks() {
+        let instructions = parse("+[.-]", &ParseOptions::default()).unwrap();
+        let code = emit_llvm(&Program::compile(&instructions, false).unwrap()).unwrap();
+
+        assert!(code.contains("loop0:\n"));
+        assert!(code.contains("label %end0, label %body0\nbody0:\n"));
+        assert!(code.contains("  br label %loop0\nend0:\n"));
+    }
+}
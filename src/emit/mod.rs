@@ -0,0 +1,127 @@
+use std::error::Error;
+use clap::ValueEnum;
+use crate::{Eof, ir::{Op, Program}};
+pub use source_map::SourceMap;
+
+pub mod source_map;
+pub mod c;
+pub mod wasm;
+pub mod js;
+pub mod python;
+pub mod asm;
+pub mod bf;
+#[cfg(feature = "llvm")]
+pub mod llvm;
+
+
+/// Cells on the tape of compiled programs, which unlike the interpreter's can't grow
+pub const TAPE_SIZE: usize = 65536;
+
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Target {
+    /// A self-contained C file
+    C,
+
+    /// A WebAssembly module exporting `run`
+    Wasm,
+
+    /// A JavaScript module exporting an async `run(input)`
+    Js,
+
+    /// A Python 3 script
+    Py,
+
+    /// Annotated x86-64 assembly for Linux
+    Asm,
+
+    /// Plain brainfuck, with macros expanded and libraries linked in
+    Bf,
+
+    /// LLVM IR text
+    #[cfg(feature = "llvm")]
+    Llvm,
+}
+
+
+/// Translate a compiled program into another language, or a binary format, with `,` at the end of input doing
+/// what `eof` says. Only the C backend has policies other than `Eof::Zero`.
+pub fn emit(program: &Program, target: Target, eof: Eof) -> Result<Vec<u8>, Box<dyn Error>> {
+    if eof != Eof::Zero && target != Target::C {
+        return Err(format!("only C output can read the end of input as anything but 0, not {:?}", target).into());
+    }
+    match target {
+        Target::Wasm => wasm::emit_wasm(program),
+        Target::Asm => Ok(asm::emit_asm(program)?.into_bytes()),
+        Target::Bf => Ok(bf::emit_bf(program).into_bytes()),
+        #[cfg(feature = "llvm")]
+        Target::Llvm => Ok(llvm::emit_llvm(program)?.into_bytes()),
+        _ => Ok(emit_with_source_map(program, target, eof)?.0),
+    }
+}
+
+
+/// Translate a program into another language, along with where each line came from.
+/// Only the C, JavaScript and Python backends have source maps.
+pub fn emit_with_source_map(program: &Program, target: Target, eof: Eof) -> Result<(Vec<u8>, SourceMap), Box<dyn Error>> {
+    if eof != Eof::Zero && target != Target::C {
+        return Err(format!("only C output can read the end of input as anything but 0, not {:?}", target).into());
+    }
+    let (code, source_map) = match target {
+        Target::C => c::emit_c_with_source_map(program, eof)?,
+        Target::Js => js::emit_js_with_source_map(program)?,
+        Target::Py => python::emit_python_with_source_map(program)?,
+        _ => return Err("source maps are only supported for C, JavaScript and Python output".into()),
+    };
+    Ok((code.into_bytes(), source_map))
+}
+
+
+/// Write one line per op, indenting loop bodies by four spaces per level on top of `depth`.
+/// `line` gives the code for an op, or `None` to leave it out.
+/// The source map counts lines from `first_line`, where the code will go in the output.
+fn structured_lines(
+    program: &Program,
+    mut depth: usize,
+    first_line: usize,
+    mut line: impl FnMut(usize, &Op) -> Result<Option<String>, Box<dyn Error>>,
+) -> Result<(String, SourceMap), Box<dyn Error>> {
+    let mut code = String::new();
+    let mut source_map = SourceMap::default();
+    let mut line_number = first_line;
+
+    for (i, op) in program.ops.iter().enumerate() {
+        if let Op::JumpIfNonZero(_) = op {
+            depth = depth.saturating_sub(1);
+        }
+
+        if let Some(line) = line(i, op)? {
+            code += &"    ".repeat(depth);
+            code += &line;
+            code += "\n";
+
+            if let Some(offset) = program.offset(i) {
+                source_map.lines.push((line_number, offset));
+            }
+            line_number += line.matches('\n').count() + 1;
+        }
+
+        if let Op::JumpIfZero(_) = op {
+            depth += 1;
+        }
+    }
+
+    Ok((code, source_map))
+}
+
+
+/// Error for the ops that only the interpreter supports
+fn unsupported(op: &Op, target: &str) -> Box<dyn Error> {
+    let feature = match op {
+        Op::ProcedureOpen(_) | Op::ProcedureClose | Op::Call => "pbrain procedures",
+        Op::NextTape | Op::CopyToNextTape => "the tapes extension",
+        Op::Push | Op::Pop => "the stack extension",
+        _ => "this instruction",
+    };
+    format!("{} output does not support {}", target, feature).into()
+}
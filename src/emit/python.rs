@@ -0,0 +1,97 @@
+use std::error::Error;
+use crate::ir::{Op, Program};
+use super::{structured_lines, unsupported, SourceMap};
+
+
+const HEADER: &str = r#"import sys
+from collections import defaultdict
+
+
+def read():
+    """Read one character from stdin, 0 at the end of input"""
+    sys.stdout.flush()
+    byte = sys.stdin.buffer.read(1)
+    if not byte:
+        return 0
+    if byte[0] > 127:
+        sys.exit("error: input must be an ASCII character")
+    return byte[0]
+
+
+def write(value):
+    sys.stdout.write(chr(value))
+
+
+def main():
+    # Cells hold 0 to 127 and wrap around, the tape goes on forever to the right
+    tape = defaultdict(int)
+    p = 0
+
+"#;
+
+const FOOTER: &str = r#"
+    sys.stdout.flush()
+
+
+if __name__ == "__main__":
+    main()
+"#;
+
+
+/// Translate a program into a Python 3 script that uses stdin and stdout
+pub fn emit_python(program: &Program) -> Result<String, Box<dyn Error>> {
+    Ok(emit_python_with_source_map(program)?.0)
+}
+
+
+/// The same as `emit_python`, along with the source offset each line came from
+pub fn emit_python_with_source_map(program: &Program) -> Result<(String, SourceMap), Box<dyn Error>> {
+    // Python needs something in an empty loop body
+    let mut empty_loop = false;
+
+    let (body, source_map) = structured_lines(program, 1, HEADER.matches('\n').count(), |_, op| {
+        let line = match *op {
+            Op::Add(n) if n > 64 => format!("tape[p] = (tape[p] - {}) % 128", 128 - n as usize),
+            Op::Add(n) => format!("tape[p] = (tape[p] + {}) % 128", n),
+            Op::Right(n) => format!("p += {}", n),
+            Op::Left(n) => format!("p = max(p - {}, 0)", n),
+            Op::Clear => "tape[p] = 0".to_string(),
+            Op::MulAdd { offset, factor } => format!(
+                "tape[p + {0}] = (tape[p + {0}] + tape[p] * {1}) % 128", offset, factor
+            ),
+            Op::JumpIfZero(_) => "while tape[p]:".to_string(),
+            Op::JumpIfNonZero(_) if empty_loop => "    pass".to_string(),
+            Op::JumpIfNonZero(_) => return Ok(None),
+            Op::Input => "tape[p] = read()".to_string(),
+            Op::Output => "write(tape[p])".to_string(),
+            Op::Break => return Ok(None),
+            _ => return Err(unsupported(op, "Python")),
+        };
+        empty_loop = matches!(op, Op::JumpIfZero(_));
+        Ok(Some(line))
+    })?;
+
+    Ok((HEADER.to_string() + &body + FOOTER, source_map))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse, ParseOptions};
+
+    fn emit(code: &str) -> String {
+        let instructions = parse(code, &ParseOptions::default()).unwrap();
+        emit_python(&Program::compile(&instructions, true).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn loops_become_while() {
+        assert!(emit("+[>--<-.]").contains("    while tape[p]:\n        p += 1\n        tape[p] = (tape[p] - 2) % 128\n"));
+    }
+
+    #[test]
+    fn empty_loops_pass() {
+        assert!(emit("+[]").contains("    while tape[p]:\n        pass\n"));
+    }
+}
@@ -0,0 +1,136 @@
+use std::fmt::Write;
+
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+
+/// Which part of the source each line of generated code came from
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    /// Generated line (counting from 0) and the source offset of the op on it, in line order
+    pub lines: Vec<(usize, usize)>,
+}
+
+
+impl SourceMap {
+    /// The map as a version 3 source map, for the file at `source_path` with contents `source`
+    pub fn to_json(&self, source_path: &str, source: &str) -> String {
+        let index = LineIndex::new(source);
+        let mut mappings = String::new();
+        let mut generated_line = 0;
+        // Source positions are relative to the last segment
+        let (mut last_line, mut last_column) = (0, 0);
+
+        for &(line, offset) in &self.lines {
+            while generated_line < line {
+                mappings.push(';');
+                generated_line += 1;
+            }
+            if mappings.ends_with(|c| c != ';') {
+                mappings.push(',');
+            }
+
+            let (source_line, source_column) = index.line_column(source, offset);
+            write_vlq(&mut mappings, 0);
+            // Always the first source, so its index doesn't change after the first segment
+            write_vlq(&mut mappings, 0);
+            write_vlq(&mut mappings, source_line as i64 - last_line as i64);
+            write_vlq(&mut mappings, source_column as i64 - last_column as i64);
+            (last_line, last_column) = (source_line, source_column);
+        }
+
+        format!(
+            "{{\"version\":3,\"sources\":[{}],\"sourcesContent\":[{}],\"names\":[],\"mappings\":\"{}\"}}\n",
+            json_string(source_path), json_string(source), mappings
+        )
+    }
+}
+
+
+/// Start offset of each line, to turn offsets into lines and columns
+pub struct LineIndex {
+    starts: Vec<usize>,
+}
+
+impl LineIndex {
+    pub fn new(source: &str) -> LineIndex {
+        let starts = std::iter::once(0).chain(source.match_indices('\n').map(|(i, _)| i + 1)).collect();
+        LineIndex { starts }
+    }
+
+    /// Line and column (in characters) of a byte offset, both counting from 0
+    pub fn line_column(&self, source: &str, offset: usize) -> (usize, usize) {
+        let line = self.starts.partition_point(|&start| start <= offset) - 1;
+        let start = self.starts[line];
+        let column = source.get(start..offset).map_or(0, |text| text.chars().count());
+        (line, column)
+    }
+}
+
+
+/// Base64 VLQ, as used by source maps
+fn write_vlq(output: &mut String, value: i64) {
+    let mut value = if value < 0 { ((-value) << 1) | 1 } else { value << 1 };
+    loop {
+        let mut digit = (value & 0b11111) as usize;
+        value >>= 5;
+        if value > 0 {
+            digit |= 0b100000;
+        }
+        output.push(BASE64[digit] as char);
+        if value == 0 {
+            return;
+        }
+    }
+}
+
+
+pub(crate) fn json_string(string: &str) -> String {
+    let mut escaped = String::from("\"");
+    for c in string.chars() {
+        match c {
+            '"' => escaped += "\\\"",
+            '\\' => escaped += "\\\\",
+            '\n' => escaped += "\\n",
+            '\r' => escaped += "\\r",
+            '\t' => escaped += "\\t",
+            c if (c as u32) < 0x20 => { let _ = write!(escaped, "\\u{:04x}", c as u32); },
+            c => escaped.push(c),
+        }
+    }
+    escaped + "\""
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vlq() {
+        let mut output = String::new();
+        for value in [0, 1, -1, 16, 123] {
+            write_vlq(&mut output, value);
+            output.push(' ');
+        }
+        assert_eq!(output, "A C D gB 2H ");
+    }
+
+    #[test]
+    fn lines_and_columns() {
+        let source = "+\n\nab+\n";
+        let index = LineIndex::new(source);
+        assert_eq!(index.line_column(source, 0), (0, 0));
+        assert_eq!(index.line_column(source, 2), (1, 0));
+        assert_eq!(index.line_column(source, 5), (2, 2));
+    }
+
+    #[test]
+    fn json_mappings() {
+        let map = SourceMap { lines: vec![(1, 0), (1, 1), (3, 3)] };
+        assert_eq!(
+            map.to_json("a\"b.bf", "+-\n+\n"),
+            "{\"version\":3,\"sources\":[\"a\\\"b.bf\"],\"sourcesContent\":[\"+-\\n+\\n\"],\"names\":[],\"mappings\":\";AAAA,AAAC;;AACD\"}\n"
+        );
+    }
+}
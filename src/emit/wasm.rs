@@ -0,0 +1,302 @@
+use std::error::Error;
+use crate::ir::{Op, Program};
+use super::unsupported;
+
+
+// Types
+const TYPE_READ_BYTE: u32 = 0;
+const TYPE_WRITE_BYTE: u32 = 1;
+const TYPE_RUN: u32 = 2;
+
+// Functions, the imports come first
+const FUNC_READ_BYTE: u32 = 0;
+const FUNC_WRITE_BYTE: u32 = 1;
+const FUNC_RUN: u32 = 2;
+
+// Locals of `run`
+const LOCAL_POINTER: u32 = 0;
+const LOCAL_INPUT: u32 = 1;
+
+// Opcodes
+const UNREACHABLE: u8 = 0x00;
+const BLOCK: u8 = 0x02;
+const LOOP: u8 = 0x03;
+const IF: u8 = 0x04;
+const END: u8 = 0x0b;
+const BR: u8 = 0x0c;
+const BR_IF: u8 = 0x0d;
+const CALL: u8 = 0x10;
+const SELECT: u8 = 0x1b;
+const LOCAL_GET: u8 = 0x20;
+const LOCAL_SET: u8 = 0x21;
+const I32_LOAD8_U: u8 = 0x2d;
+const I32_STORE8: u8 = 0x3a;
+const I32_CONST: u8 = 0x41;
+const I32_EQZ: u8 = 0x45;
+const I32_GT_S: u8 = 0x4a;
+const I32_GE_S: u8 = 0x4e;
+const I32_GE_U: u8 = 0x4f;
+const I32_ADD: u8 = 0x6a;
+const I32_SUB: u8 = 0x6b;
+const I32_MUL: u8 = 0x6c;
+const I32_AND: u8 = 0x71;
+
+const EMPTY_BLOCK: u8 = 0x40;
+const I32: u8 = 0x7f;
+
+
+/// Translate a program into a WebAssembly module.
+///
+/// The module imports `env.read_byte: () -> i32` (returning -1 at the end of input) and
+/// `env.write_byte: (i32) -> ()`, and exports `run` and its tape as `memory`.
+/// The tape is one 64KiB page, going past the end of it traps.
+pub fn emit_wasm(program: &Program) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut module = b"\0asm".to_vec();
+    module.extend(1u32.to_le_bytes());
+
+    // Type section
+    let mut types = Vec::new();
+    write_u32(&mut types, 3);
+    types.extend([0x60, 0, 1, I32]);
+    types.extend([0x60, 1, I32, 0]);
+    types.extend([0x60, 0, 0]);
+    write_section(&mut module, 1, &types);
+
+    // Import section
+    let mut imports = Vec::new();
+    write_u32(&mut imports, 2);
+    for (name, type_index) in [("read_byte", TYPE_READ_BYTE), ("write_byte", TYPE_WRITE_BYTE)] {
+        write_name(&mut imports, "env");
+        write_name(&mut imports, name);
+        imports.push(0x00);
+        write_u32(&mut imports, type_index);
+    }
+    write_section(&mut module, 2, &imports);
+
+    // Function section
+    let mut functions = Vec::new();
+    write_u32(&mut functions, 1);
+    write_u32(&mut functions, TYPE_RUN);
+    write_section(&mut module, 3, &functions);
+
+    // Memory section, one page with no maximum
+    write_section(&mut module, 5, &[1, 0x00, 1]);
+
+    // Export section
+    let mut exports = Vec::new();
+    write_u32(&mut exports, 2);
+    write_name(&mut exports, "run");
+    exports.push(0x00);
+    write_u32(&mut exports, FUNC_RUN);
+    write_name(&mut exports, "memory");
+    exports.push(0x02);
+    write_u32(&mut exports, 0);
+    write_section(&mut module, 7, &exports);
+
+    // Code section
+    let body = run_body(program)?;
+    let mut code = Vec::new();
+    write_u32(&mut code, 1);
+    write_u32(&mut code, body.len() as u32);
+    code.extend(body);
+    write_section(&mut module, 10, &code);
+
+    Ok(module)
+}
+
+
+fn run_body(program: &Program) -> Result<Vec<u8>, Box<dyn Error>> {
+    // Two i32 locals, the pointer and the last input byte
+    let mut body = vec![1, 2, I32];
+
+    for op in &program.ops {
+        match *op {
+            Op::Add(n) => {
+                pointer(&mut body);
+                load(&mut body, 0);
+                constant(&mut body, n as i32);
+                body.push(I32_ADD);
+                wrap(&mut body);
+                store(&mut body, 0);
+            },
+            Op::Right(n) => {
+                pointer(&mut body);
+                constant(&mut body, n as i32);
+                body.push(I32_ADD);
+                set_pointer(&mut body);
+            },
+            Op::Left(n) => {
+                // p >= n ? p - n : 0
+                pointer(&mut body);
+                constant(&mut body, n as i32);
+                body.push(I32_SUB);
+                constant(&mut body, 0);
+                pointer(&mut body);
+                constant(&mut body, n as i32);
+                body.push(I32_GE_U);
+                body.push(SELECT);
+                set_pointer(&mut body);
+            },
+            Op::Clear => {
+                pointer(&mut body);
+                constant(&mut body, 0);
+                store(&mut body, 0);
+            },
+            Op::MulAdd { offset, factor } => {
+                let offset = offset as u32;
+                pointer(&mut body);
+                load(&mut body, offset);
+                load(&mut body, 0);
+                constant(&mut body, factor as i32);
+                body.push(I32_MUL);
+                body.push(I32_ADD);
+                wrap(&mut body);
+                store(&mut body, offset);
+            },
+            Op::JumpIfZero(_) => {
+                // block { loop { if cell == 0 break; ... continue } }
+                body.extend([BLOCK, EMPTY_BLOCK, LOOP, EMPTY_BLOCK]);
+                load(&mut body, 0);
+                body.extend([I32_EQZ, BR_IF, 1]);
+            },
+            Op::JumpIfNonZero(_) => {
+                body.extend([BR, 0, END, END]);
+            },
+            Op::Input => {
+                body.push(CALL);
+                write_u32(&mut body, FUNC_READ_BYTE);
+                body.push(LOCAL_SET);
+                write_u32(&mut body, LOCAL_INPUT);
+
+                // Anything past ASCII is an error
+                input(&mut body);
+                constant(&mut body, 127);
+                body.extend([I32_GT_S, IF, EMPTY_BLOCK, UNREACHABLE, END]);
+
+                // End of input reads as 0
+                pointer(&mut body);
+                input(&mut body);
+                constant(&mut body, 0);
+                input(&mut body);
+                constant(&mut body, 0);
+                body.extend([I32_GE_S, SELECT]);
+                store(&mut body, 0);
+            },
+            Op::Output => {
+                load(&mut body, 0);
+                body.push(CALL);
+                write_u32(&mut body, FUNC_WRITE_BYTE);
+            },
+            Op::Break => (),
+            _ => return Err(unsupported(op, "WebAssembly")),
+        }
+    }
+
+    body.push(END);
+    Ok(body)
+}
+
+
+fn pointer(body: &mut Vec<u8>) {
+    body.push(LOCAL_GET);
+    write_u32(body, LOCAL_POINTER);
+}
+
+fn set_pointer(body: &mut Vec<u8>) {
+    body.push(LOCAL_SET);
+    write_u32(body, LOCAL_POINTER);
+}
+
+fn input(body: &mut Vec<u8>) {
+    body.push(LOCAL_GET);
+    write_u32(body, LOCAL_INPUT);
+}
+
+fn constant(body: &mut Vec<u8>, value: i32) {
+    body.push(I32_CONST);
+    write_i32(body, value);
+}
+
+/// Keep the value on the stack below 128
+fn wrap(body: &mut Vec<u8>) {
+    constant(body, 127);
+    body.push(I32_AND);
+}
+
+/// Load the cell `offset` to the right of the pointer
+fn load(body: &mut Vec<u8>, offset: u32) {
+    pointer(body);
+    body.extend([I32_LOAD8_U, 0]);
+    write_u32(body, offset);
+}
+
+fn store(body: &mut Vec<u8>, offset: u32) {
+    body.extend([I32_STORE8, 0]);
+    write_u32(body, offset);
+}
+
+
+fn write_section(module: &mut Vec<u8>, id: u8, contents: &[u8]) {
+    module.push(id);
+    write_u32(module, contents.len() as u32);
+    module.extend(contents);
+}
+
+fn write_name(bytes: &mut Vec<u8>, name: &str) {
+    write_u32(bytes, name.len() as u32);
+    bytes.extend(name.as_bytes());
+}
+
+/// Unsigned LEB128
+fn write_u32(bytes: &mut Vec<u8>, mut value: u32) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(byte);
+            return;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+/// Signed LEB128
+fn write_i32(bytes: &mut Vec<u8>, mut value: i32) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        let sign_bit = byte & 0x40 != 0;
+        if (value == 0 && !sign_bit) || (value == -1 && sign_bit) {
+            bytes.push(byte);
+            return;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn leb128() {
+        let mut bytes = Vec::new();
+        write_u32(&mut bytes, 624485);
+        assert_eq!(bytes, vec![0xe5, 0x8e, 0x26]);
+
+        bytes.clear();
+        write_i32(&mut bytes, -123456);
+        assert_eq!(bytes, vec![0xc0, 0xbb, 0x78]);
+
+        bytes.clear();
+        write_i32(&mut bytes, 64);
+        assert_eq!(bytes, vec![0xc0, 0x00]);
+    }
+
+    #[test]
+    fn module_header() {
+        let module = emit_wasm(&Program::default()).unwrap();
+        assert_eq!(&module[..8], b"\0asm\x01\0\0\0");
+    }
+}
@@ -0,0 +1,93 @@
+//! Checking two programs do the same thing, by running both on the same inputs
+
+use std::fmt;
+use crate::{Program, VmOptions, reference::{self, Outcome}};
+
+
+/// Inputs to try when none are given: nothing, a blank line, some text, and every printable character
+pub fn default_inputs() -> Vec<Vec<u8>> {
+    vec![
+        Vec::new(),
+        b"\n".to_vec(),
+        b"hello, world\n".to_vec(),
+        b"0123456789\n".to_vec(),
+        (b' '..=b'~').chain([b'\n']).collect(),
+    ]
+}
+
+
+/// An input the programs ended differently on. An outcome is `None` if that program didn't finish in time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Difference {
+    pub input: Vec<u8>,
+    pub first: Option<Outcome>,
+    pub second: Option<Outcome>,
+}
+
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "with input {:?}, ", String::from_utf8_lossy(&self.input))?;
+        match (&self.first, &self.second) {
+            (None, _) => write!(f, "the first program didn't finish, but the second did"),
+            (_, None) => write!(f, "the second program didn't finish, but the first did"),
+            (Some(first), Some(second)) if first.error != second.error => {
+                write!(f, "the first program ended with {:?}, the second with {:?}", first.error, second.error)
+            },
+            (Some(first), Some(second)) if first.output != second.output => write!(
+                f, "the first program wrote {:?}, the second {:?}",
+                String::from_utf8_lossy(&first.output), String::from_utf8_lossy(&second.output),
+            ),
+            (Some(first), Some(second)) => write!(
+                f, "the first program ended with tape {:?} at {}, the second with {:?} at {}",
+                first.cells, first.pointer, second.cells, second.pointer,
+            ),
+        }
+    }
+}
+
+
+/// How many inputs both programs finished on, or the first input they ended differently on.
+/// Each program gets `max_steps` ops per input, and inputs neither finishes on count as the same.
+pub fn compare(
+    first: &(Program, VmOptions),
+    second: &(Program, VmOptions),
+    inputs: &[Vec<u8>],
+    max_steps: usize,
+) -> Result<usize, Box<Difference>> {
+    let mut finished = 0;
+    for input in inputs {
+        let run = |(program, options): &(Program, VmOptions)| reference::run_vm_with(program.clone(), options, input, max_steps);
+        let (first, second) = (run(first), run(second));
+        if first != second {
+            return Err(Box::new(Difference { input: input.clone(), first, second }));
+        }
+        finished += first.is_some() as usize;
+    }
+    Ok(finished)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ParseOptions, parse};
+
+    fn program(code: &str) -> (Program, VmOptions) {
+        (Program::compile(&parse(code, &ParseOptions::default()).unwrap(), true).unwrap(), VmOptions::default())
+    }
+
+    #[test]
+    fn rewrites_match() {
+        let inputs = default_inputs();
+        assert_eq!(compare(&program(",[.,]"), &program(",[.,]+-"), &inputs, 10_000), Ok(inputs.len()));
+        assert_eq!(compare(&program("+[]"), &program("+[>]<[]"), &inputs, 1_000), Ok(0));
+    }
+
+    #[test]
+    fn first_difference() {
+        let difference = compare(&program(",."), &program(",+."), &default_inputs(), 100).unwrap_err();
+        assert_eq!(difference.input, b"");
+        assert_eq!(difference.to_string(), "with input \"\", the first program wrote \"\\0\", the second \"\\u{1}\"");
+    }
+}
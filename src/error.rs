@@ -0,0 +1,320 @@
+//! Error kinds that decide the exit code of `bf-rs`
+//!
+//! | Code | Meaning |
+//! |------|---------|
+//! | 1 | Any other error, or failed tests |
+//! | 2 | Invalid command line arguments |
+//! | 3 | The program doesn't parse or compile, or `check` found problems |
+//! | 4 | A macro is invalid |
+//! | 5 | The program failed while running |
+//! | 6 | Reading or writing a file or stream failed |
+//! | 130 | The program was stopped with Ctrl-C |
+
+use std::{error::Error, fmt, io, ops::Range};
+use crate::{Op, Tape};
+
+
+pub const OTHER: u8 = 1;
+pub const PARSE: u8 = 3;
+pub const MACRO: u8 = 4;
+pub const RUNTIME: u8 = 5;
+pub const IO: u8 = 6;
+pub const INTERRUPTED: u8 = 130;
+
+/// Ops kept from before a runtime error, to show what the program was doing
+pub const TRAIL_LENGTH: usize = 10;
+
+
+/// The program isn't valid code
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError(pub String);
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for ParseError {}
+
+
+/// A macro definition or call is invalid
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MacroError(pub String);
+
+impl fmt::Display for MacroError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for MacroError {}
+
+
+/// The program did something it can't while running
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuntimeError(pub String);
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for RuntimeError {}
+
+
+/// An op the program ran, or was running when it failed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ran {
+    pub ip: usize,
+    pub op: Op,
+    /// Where the op came from in the source, if the program knows
+    pub offset: Option<usize>,
+    /// `path:line:column` of the op, filled in by whatever has the source
+    pub location: Option<String>,
+}
+
+impl fmt::Display for Ran {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "op {} `{}`", self.ip, self.op)?;
+        if let Some(location) = &self.location {
+            write!(f, " ({})", location)?;
+        }
+        Ok(())
+    }
+}
+
+
+/// A runtime error, with the op that caused it and the ones run just before, latest first
+#[derive(Debug)]
+pub struct Failed {
+    pub error: Box<dyn Error>,
+    pub at: Ran,
+    pub trail: Vec<Ran>,
+    /// The line of source the op is on, from `excerpt`
+    pub excerpt: Option<String>,
+}
+
+impl fmt::Display for Failed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at {}", self.error, self.at)?;
+        if let Some(excerpt) = &self.excerpt {
+            write!(f, "\n{}", excerpt)?;
+        }
+        if !self.trail.is_empty() {
+            write!(f, "\nafter running, latest first:")?;
+        }
+        for ran in &self.trail {
+            write!(f, "\n    {}", ran)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for Failed {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&*self.error)
+    }
+}
+
+
+/// The program was stopped with Ctrl-C before running the op at `ip`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Interrupted {
+    pub ip: usize,
+    /// Where the op came from in the source, if the program knows
+    pub offset: Option<usize>,
+    /// `path:line:column` of the op, filled in by whatever has the source
+    pub location: Option<String>,
+    pub tape: Tape,
+    /// The line of source the op is on, from `excerpt`
+    pub excerpt: Option<String>,
+}
+
+impl fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "interrupted at op {}", self.ip)?;
+        if let Some(location) = &self.location {
+            write!(f, " ({})", location)?;
+        }
+        write!(f, ", pointer at {}", self.tape)?;
+        if let Some(excerpt) = &self.excerpt {
+            write!(f, "\n{}", excerpt)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for Interrupted {}
+
+
+/// A loop came back round to the same state without reading or writing, so it would never end
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hang {
+    /// The loop's opening and closing ops
+    pub open: usize,
+    pub close: usize,
+    /// Where the loop's brackets are in the source, if the program knows
+    pub offsets: Option<(usize, usize)>,
+    /// `path:line:column` of the loop, filled in by whatever has the source
+    pub location: Option<String>,
+    /// The line of source the loop starts on, from `excerpt`
+    pub excerpt: Option<String>,
+}
+
+impl fmt::Display for Hang {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the loop at ops {}-{}", self.open, self.close)?;
+        if let Some(location) = &self.location {
+            write!(f, " ({})", location)?;
+        }
+        write!(f, " never ends: it comes back to the same state without reading or writing")?;
+        if let Some(excerpt) = &self.excerpt {
+            write!(f, "\n{}", excerpt)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for Hang {}
+
+
+/// The program went to write more than `VmOptions::max_output` bytes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TooMuchOutput {
+    pub limit: u64,
+}
+
+impl fmt::Display for TooMuchOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the program went over its output limit of {} bytes", self.limit)
+    }
+}
+
+impl Error for TooMuchOutput {}
+
+
+/// Mark an error from parsing or compiling, keeping macro and I/O errors as they are
+pub fn parse(error: Box<dyn Error>) -> Box<dyn Error> {
+    if error.is::<MacroError>() || error.is::<io::Error>() {
+        error
+    } else {
+        Box::new(ParseError(error.to_string()))
+    }
+}
+
+
+/// The line of `source` that `span` starts on, numbered, with carets under the span like rustc's errors:
+/// ```text
+///   |
+/// 3 | ++[>+<-]
+///   |   ^^^^^^
+/// ```
+/// A span going past the end of the line is marked to the end of it.
+pub fn excerpt(source: &str, span: Range<usize>) -> String {
+    let start = span.start.min(source.len());
+    let line_start = source[..start].rfind('\n').map_or(0, |newline| newline + 1);
+    let line_end = source[start..].find('\n').map_or(source.len(), |newline| start + newline);
+    let line = source[line_start..line_end].trim_end_matches('\r');
+    let shown: String = line.chars().map(|c| if crate::is_stray_byte(c) { char::REPLACEMENT_CHARACTER } else { c }).collect();
+    let number = (source[..line_start].matches('\n').count() + 1).to_string();
+    let gutter = " ".repeat(number.len());
+    // Tabs stay tabs so the carets line up however wide the terminal shows them
+    let padding: String = source[line_start..start].chars().map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+    let end = span.end.clamp(start, line_start + line.len());
+    let carets = "^".repeat(source[start..end].chars().count().max(1));
+    format!("{} |\n{} | {}\n{} | {}{}", gutter, number, shown, gutter, padding, carets)
+}
+
+
+/// Mark an error from running a program, keeping I/O errors, interruptions, hangs and output limits as they are
+pub fn runtime(error: Box<dyn Error>) -> Box<dyn Error> {
+    let error = match error.downcast::<Failed>() {
+        Ok(mut failed) => {
+            failed.error = runtime(failed.error);
+            return failed;
+        },
+        Err(error) => error,
+    };
+    if error.is::<io::Error>() || error.is::<Interrupted>() || error.is::<Hang>() || error.is::<TooMuchOutput>() {
+        error
+    } else {
+        Box::new(RuntimeError(error.to_string()))
+    }
+}
+
+
+pub fn exit_code(error: &(dyn Error + 'static)) -> u8 {
+    if let Some(failed) = error.downcast_ref::<Failed>() {
+        exit_code(&*failed.error)
+    } else if error.is::<ParseError>() {
+        PARSE
+    } else if error.is::<MacroError>() {
+        MACRO
+    } else if error.is::<RuntimeError>() || error.is::<Hang>() || error.is::<TooMuchOutput>() {
+        RUNTIME
+    } else if error.is::<io::Error>() {
+        IO
+    } else if error.is::<Interrupted>() {
+        INTERRUPTED
+    } else {
+        OTHER
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kinds_keep_their_codes() {
+        let macro_error: Box<dyn Error> = Box::new(MacroError("macros must have a name".to_string()));
+        assert_eq!(exit_code(&*parse(macro_error)), MACRO);
+        assert_eq!(exit_code(&*parse("all brackets must have matching brackets".into())), PARSE);
+        assert_eq!(exit_code(&*runtime("cannot pop from an empty stack".into())), RUNTIME);
+
+        let io_error: Box<dyn Error> = Box::new(io::Error::new(io::ErrorKind::BrokenPipe, "broken pipe"));
+        assert_eq!(exit_code(&*runtime(io_error)), IO);
+        assert_eq!(exit_code(&*crate::include::resolve("missing", &[]).unwrap_err()), MACRO);
+        assert_eq!(exit_code(&*Box::<dyn Error>::from("unknown")), OTHER);
+    }
+
+    #[test]
+    fn excerpts() {
+        let source = "+\n\t++[>+<-]\r\n";
+        assert_eq!(excerpt(source, 5..11), "  |\n2 | \t++[>+<-]\n  | \t  ^^^^^^");
+        assert_eq!(excerpt(source, 0..1), "  |\n1 | +\n  | ^");
+        // Past the end of the line, and a zero-width span
+        assert_eq!(excerpt(source, 5..100), "  |\n2 | \t++[>+<-]\n  | \t  ^^^^^^");
+        assert_eq!(excerpt("+-", 1..1), "  |\n1 | +-\n  |  ^");
+        assert_eq!(excerpt(&"\n".repeat(9), 9..10), "   |\n10 | \n   | ^");
+        let stray = crate::source_text(b"\xff+]".to_vec());
+        assert_eq!(excerpt(&stray, 4..5), "  |\n1 | \u{fffd}+]\n  |   ^");
+    }
+
+    #[test]
+    fn failed_shows_trail() {
+        let ran = |ip, op| Ran { ip, op, offset: Some(ip), location: Some(format!("stack.b:1:{}", ip + 1)) };
+        let failed = Failed { error: "cannot pop from an empty stack".into(), at: ran(2, Op::Pop), trail: vec![ran(1, Op::Pop), ran(0, Op::Push)], excerpt: None };
+        assert_eq!(
+            failed.to_string(),
+            "cannot pop from an empty stack at op 2 `%` (stack.b:1:3)\nafter running, latest first:\n    op 1 `%` (stack.b:1:2)\n    op 0 `$` (stack.b:1:1)",
+        );
+        let error = runtime(Box::new(failed));
+        assert_eq!(exit_code(&*error), RUNTIME);
+        assert!(error.downcast_ref::<Failed>().unwrap().error.is::<RuntimeError>());
+    }
+
+    #[test]
+    fn interrupted_shows_cells_near_pointer() {
+        let tape = Tape { data: (0..20).collect(), pointer: 10 };
+        let interrupted = Interrupted { ip: 4, offset: Some(7), location: Some("loop.b:1:8".to_string()), tape, excerpt: None };
+        assert_eq!(
+            interrupted.to_string(),
+            "interrupted at op 4 (loop.b:1:8), pointer at cell 10 of 20: ... 2 3 4 5 6 7 8 9 [10] 11 12 13 14 15 16 17 18 ...",
+        );
+        assert_eq!(exit_code(&*runtime(Box::new(interrupted))), INTERRUPTED);
+    }
+}
@@ -0,0 +1,135 @@
+//! Where instructions expanded from macros came from. Parsing gives them the offset of the macro call, which is
+//! all most things need, and this finds the instruction in the macro's definition and the calls that led there,
+//! so errors can point into the macro.
+
+use std::{collections::HashMap, error::Error};
+use crate::{Instruction, ParseOptions, Program, blank_shebang, error::MacroError, parse_char, split_macros};
+
+
+/// A call of a macro, `@name@`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Call {
+    pub name: String,
+    /// Where the call is in the source
+    pub offset: usize,
+}
+
+
+/// An instruction from a macro
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expanded {
+    /// Where the instruction is in the macro's definition
+    pub offset: usize,
+    /// The calls that expanded it, innermost first
+    pub calls: Vec<Call>,
+}
+
+
+/// For each instruction `parse_with_offsets` gives for `code` with macros, where it is if it came from a macro
+pub fn expansions(code: &str, options: &ParseOptions) -> Result<Vec<Option<Expanded>>, Box<dyn Error>> {
+    let code = &*blank_shebang(code);
+    let (segments, macros) = split_macros(code, options)?;
+    let bodies = bodies(code, &macros);
+    let mut expansions = Vec::new();
+    for (offset, segment) in &segments {
+        walk(segment, *offset, &bodies, &mut Vec::new(), options, &mut expansions)?;
+    }
+    Ok(expansions)
+}
+
+
+/// For each op of the program compiled from `instructions`, where its first instruction is if that came from a macro
+pub fn by_op(instructions: &[(Instruction, usize)], expansions: &[Option<Expanded>], optimize: bool) -> Result<Vec<Option<Expanded>>, Box<dyn Error>> {
+    // Compiling with instruction indexes as offsets gives the first instruction of each op
+    let indexed: Vec<(Instruction, usize)> = instructions.iter().enumerate().map(|(i, &(instruction, _))| (instruction, i)).collect();
+    let program = Program::compile_with_offsets(&indexed, optimize)?;
+    Ok(program.offsets.iter().map(|&i| expansions.get(i).cloned().flatten()).collect())
+}
+
+
+/// Each macro's body, keyed by `@name@`, with where the body starts in `code`
+fn bodies<'a>(code: &str, macros: &'a HashMap<String, String>) -> HashMap<&'a str, (&'a str, usize)> {
+    let mut bodies = HashMap::new();
+    let mut after_last = 0;
+    for (open, _) in code.match_indices('{') {
+        // `split_macros` already checked there's a name before each body and a `}` after it
+        let name = code[after_last..open].split_whitespace().last().unwrap_or_default();
+        if let Some((call, body)) = macros.get_key_value(&format!("@{}@", name)) {
+            bodies.insert(call.as_str(), (body.as_str(), open + 1));
+        }
+        after_last = code[open..].find('}').map_or(code.len(), |close| open + close + 1);
+    }
+    bodies
+}
+
+
+/// Parse `code` starting at `offset`, going into the macros it calls, the way `expand` does
+fn walk(
+    code: &str,
+    offset: usize,
+    bodies: &HashMap<&str, (&str, usize)>,
+    calls: &mut Vec<Call>,
+    options: &ParseOptions,
+    expansions: &mut Vec<Option<Expanded>>,
+) -> Result<(), Box<dyn Error>> {
+    if calls.len() > bodies.len() {
+        return Err(MacroError("recursive macros are not allowed".to_string()).into());
+    }
+    let mut i = 0;
+    while i < code.len() {
+        let rest = &code[i..];
+        let call = bodies.iter()
+            .filter(|(name, _)| rest.starts_with(**name))
+            .max_by_key(|(name, _)| name.len());
+        if let Some((name, &(body, body_offset))) = call {
+            calls.push(Call { name: name.trim_matches('@').to_string(), offset: offset + i });
+            walk(body, body_offset, bodies, calls, options, expansions)?;
+            calls.pop();
+            i += name.len();
+        } else {
+            let c = rest.chars().next().unwrap();
+            if parse_char(c, options).is_some() {
+                let calls: Vec<Call> = calls.iter().rev().cloned().collect();
+                expansions.push((!calls.is_empty()).then_some(Expanded { offset: offset + i, calls }));
+            }
+            i += c.len_utf8();
+        }
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_with_offsets;
+
+    #[test]
+    fn finds_definitions_and_calls() {
+        let code = "inner { >++< }\nouter { +@inner@ }\n-@outer@.";
+        let options = ParseOptions { macros: true, ..ParseOptions::default() };
+        let expansions = expansions(code, &options).unwrap();
+        let instructions = parse_with_offsets(code, &options).unwrap();
+        assert_eq!(expansions.len(), instructions.len());
+
+        let call = |name: &str, offset| Call { name: name.to_string(), offset };
+        let outer = call("outer", code.find("@outer@").unwrap());
+        let inner = call("inner", code.find("@inner@").unwrap());
+        let both = vec![inner, outer.clone()];
+        let offset_of_plus = code.find("+@").unwrap();
+        assert_eq!(expansions, [
+            None,
+            Some(Expanded { offset: offset_of_plus, calls: vec![outer] }),
+            Some(Expanded { offset: 8, calls: both.clone() }),
+            Some(Expanded { offset: 9, calls: both.clone() }),
+            Some(Expanded { offset: 10, calls: both.clone() }),
+            Some(Expanded { offset: 11, calls: both }),
+            None,
+        ]);
+
+        // The `++` is one op, starting at the first `+`
+        let ops = by_op(&instructions, &expansions, true).unwrap();
+        let offsets: Vec<Option<usize>> = ops.iter().map(|expanded| expanded.as_ref().map(|expanded| expanded.offset)).collect();
+        assert_eq!(offsets, [None, Some(offset_of_plus), Some(8), Some(9), Some(11), None]);
+    }
+}
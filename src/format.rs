@@ -0,0 +1,418 @@
+//! Pretty-printing and minifying source code, for the `fmt` and `minify` subcommands
+
+use std::{error::Error, ops::Range, cmp::Reverse};
+use crate::{Dialect, Instruction, ParseOptions, blank_shebang, parse_char, instruction_to_char};
+
+
+/// Longest line of code the formatter makes, comments can go past it
+const WIDTH: usize = 64;
+/// Runs of one instruction at least this long get a line to themselves
+const LONG_RUN: usize = 8;
+/// Loops up to this long stay on one line, if there are no loops or comments in them
+const SHORT_LOOP: usize = 16;
+
+
+/// Source code split up by loops and macros.
+/// The spans of the nodes cover the whole source, so nothing is lost.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Node {
+    /// Repeats of one instruction, with nothing in between
+    Run { instruction: Instruction, count: usize, span: Range<usize> },
+    /// `[...]`, or a pbrain `(...)`, where `instruction` is the opening one
+    Block { instruction: Instruction, body: Vec<Node>, span: Range<usize> },
+    /// Anything that isn't code, including whitespace
+    Comment(Range<usize>),
+    /// `name { ... }`
+    MacroDefinition { name: Range<usize>, body: Vec<Node>, span: Range<usize> },
+    /// `@name@`
+    MacroCall(Range<usize>),
+}
+
+
+/// Parse source into a tree of nodes. Brackets have to match, including inside macros.
+pub fn parse_tree(code: &str, options: &ParseOptions) -> Result<Vec<Node>, Box<dyn Error>> {
+    if options.dialect == Dialect::Ook {
+        return Err("only brainfuck and pbrain can be formatted".into());
+    }
+
+    // A `#!` line is a comment
+    let blanked = blank_shebang(code);
+    let code = &*blanked;
+    let mut macro_names = if options.macros { macro_names(code) } else { Vec::new() };
+    macro_names.sort_by_key(|name| Reverse(name.len()));
+
+    let mut parser = TreeParser { code, options, macro_names, position: 0 };
+    parser.nodes(None, false)
+}
+
+
+/// Re-indent code by loop nesting, keeping every instruction and comment
+pub fn format_source(code: &str, options: &ParseOptions) -> Result<String, Box<dyn Error>> {
+    let nodes = parse_tree(code, options)?;
+    let mut formatter = Formatter { code, lines: Vec::new(), line: String::new(), depth: 0, block_start: false };
+    formatter.nodes(&nodes);
+    formatter.end_block();
+
+    Ok(formatter.lines.iter().map(|line| line.to_string() + "\n").collect())
+}
+
+
+/// Write instructions with nothing else, breaking lines after `width` characters (never if it's 0)
+pub fn minify(instructions: &[Instruction], width: usize) -> String {
+    let code: String = instructions.iter().map(instruction_to_char).collect();
+    if code.is_empty() {
+        return code;
+    }
+    if width == 0 {
+        return code + "\n";
+    }
+
+    // Instructions are all ASCII, so chunks of bytes are chunks of characters
+    code.as_bytes().chunks(width).map(|line| String::from_utf8_lossy(line) + "\n").collect()
+}
+
+
+/// Names of all the macros defined in `code`, the same way `parse` finds them
+pub(crate) fn macro_names(code: &str) -> Vec<&str> {
+    let mut names: Vec<&str> = Vec::new();
+    let mut segment_start = 0;
+    for (i, c) in code.char_indices() {
+        match c {
+            '{' => {
+                names.extend(code[segment_start..i].split_whitespace().last());
+                segment_start = i + 1;
+            },
+            '}' => segment_start = i + 1,
+            _ => (),
+        }
+    }
+    names
+}
+
+
+struct TreeParser<'a> {
+    code: &'a str,
+    options: &'a ParseOptions,
+    // Longest first, so calls match the same macro as in `parse`
+    macro_names: Vec<&'a str>,
+    position: usize,
+}
+
+impl TreeParser<'_> {
+    /// Parse until the `close` character, which is left for the caller
+    fn nodes(&mut self, close: Option<char>, in_macro: bool) -> Result<Vec<Node>, Box<dyn Error>> {
+        let mut nodes: Vec<Node> = Vec::new();
+        let mut comment_start: Option<usize> = None;
+
+        while let Some(c) = self.code[self.position..].chars().next() {
+            let start = self.position;
+            if Some(c) == close {
+                break;
+            }
+
+            if let Some(length) = self.macro_call() {
+                push_comment(&mut nodes, comment_start.take(), start);
+                nodes.push(Node::MacroCall(start..(start + length)));
+                self.position += length;
+                continue;
+            }
+
+            if self.options.macros && c == '{' {
+                if in_macro {
+                    return Err("macros in macros are not allowed".into());
+                }
+
+                // The name is the last word of the comment before the `{`
+                let text_start = comment_start.take().unwrap_or(start);
+                let text = &self.code[text_start..start];
+                let Some(name) = text.split_whitespace().last() else {
+                    return Err("macros must have a name".into());
+                };
+                let name_start = text_start + text.rfind(name).unwrap();
+                if name_start == text_start && !matches!(nodes.last(), None | Some(Node::Comment(_) | Node::MacroDefinition { .. })) {
+                    return Err("macro name cannot contain instructions".into());
+                }
+                push_comment(&mut nodes, Some(text_start), name_start);
+
+                self.position += 1;
+                let body = self.nodes(Some('}'), true)?;
+                if !self.code[self.position..].starts_with('}') {
+                    return Err("all curly brackets must be matched".into());
+                }
+                self.position += 1;
+
+                nodes.push(Node::MacroDefinition {
+                    name: name_start..(name_start + name.len()),
+                    body,
+                    span: name_start..self.position,
+                });
+                continue;
+            }
+
+            if self.options.macros && c == '}' {
+                return Err(if in_macro {
+                    "brackets in macros must be matched to format them"
+                } else {
+                    "all curly brackets must be matched"
+                }.into());
+            }
+
+            match parse_char(c, self.options) {
+                Some(instruction @ (Instruction::Open | Instruction::ProcedureOpen)) => {
+                    push_comment(&mut nodes, comment_start.take(), start);
+                    let closer = if instruction == Instruction::Open { ']' } else { ')' };
+
+                    self.position += 1;
+                    let body = self.nodes(Some(closer), in_macro)?;
+                    if !self.code[self.position..].starts_with(closer) {
+                        return Err(if instruction == Instruction::Open {
+                            "all brackets must have matching brackets"
+                        } else {
+                            "all procedures must be closed"
+                        }.into());
+                    }
+                    self.position += 1;
+
+                    nodes.push(Node::Block { instruction, body, span: start..self.position });
+                },
+                Some(Instruction::Close) => {
+                    return Err("all brackets must have matching brackets".into());
+                },
+                Some(instruction) => {
+                    push_comment(&mut nodes, comment_start.take(), start);
+                    // Instructions are all ASCII, so one byte each
+                    let count = self.code[start..].bytes().take_while(|&byte| byte as char == c).count();
+                    self.position += count;
+                    nodes.push(Node::Run { instruction, count, span: start..self.position });
+                },
+                None => {
+                    comment_start.get_or_insert(start);
+                    self.position += c.len_utf8();
+                },
+            }
+        }
+
+        push_comment(&mut nodes, comment_start, self.position);
+        Ok(nodes)
+    }
+
+    /// Length of the macro call at the current position, if there is one
+    fn macro_call(&self) -> Option<usize> {
+        let rest = self.code[self.position..].strip_prefix('@')?;
+        self.macro_names.iter()
+            .find(|name| rest.strip_prefix(*name).is_some_and(|rest| rest.starts_with('@')))
+            .map(|name| name.len() + 2)
+    }
+}
+
+
+fn push_comment(nodes: &mut Vec<Node>, start: Option<usize>, end: usize) {
+    if let Some(start) = start {
+        if start < end {
+            nodes.push(Node::Comment(start..end));
+        }
+    }
+}
+
+
+struct Formatter<'a> {
+    code: &'a str,
+    lines: Vec<String>,
+    // Code waiting to be added as a line
+    line: String,
+    depth: usize,
+    // Nothing has been added since the last block opened
+    block_start: bool,
+}
+
+impl Formatter<'_> {
+    fn nodes(&mut self, nodes: &[Node]) {
+        let code = self.code;
+        for node in nodes {
+            match node {
+                Node::Run { instruction, count, .. } => {
+                    let run = instruction_to_char(instruction).to_string().repeat(*count);
+                    if *count >= LONG_RUN {
+                        self.push_line(&run);
+                    } else {
+                        self.push_code(&run);
+                    }
+                },
+                Node::MacroCall(span) => self.push_code(&code[span.clone()]),
+                Node::Block { instruction, body, .. } => {
+                    let (open, close) = if *instruction == Instruction::Open { ("[", "]") } else { ("(", ")") };
+                    match self.inline(body) {
+                        Some(inner) if inner.len() + 2 <= SHORT_LOOP => self.push_code(&(open.to_string() + &inner + close)),
+                        _ => {
+                            self.push_line(open);
+                            self.start_block();
+                            self.nodes(body);
+                            self.end_block();
+                            self.push_line(close);
+                        },
+                    }
+                },
+                Node::MacroDefinition { name, body, .. } => {
+                    self.push_line(&(code[name.clone()].to_string() + " {"));
+                    self.start_block();
+                    self.nodes(body);
+                    self.end_block();
+                    self.push_line("}");
+                },
+                Node::Comment(span) => self.comment(&code[span.clone()]),
+            }
+        }
+    }
+
+    /// The nodes written on one line, unless there is a loop or comment in them
+    fn inline(&self, nodes: &[Node]) -> Option<String> {
+        let mut code = String::new();
+        for node in nodes {
+            match node {
+                Node::Run { instruction, count, .. } => code += &instruction_to_char(instruction).to_string().repeat(*count),
+                Node::MacroCall(span) => code += &self.code[span.clone()],
+                Node::Comment(span) if self.code[span.clone()].trim().is_empty() => (),
+                _ => return None,
+            }
+        }
+        Some(code)
+    }
+
+    fn comment(&mut self, text: &str) {
+        let parts: Vec<&str> = text.split('\n').collect();
+        for (i, part) in parts.iter().enumerate() {
+            let part = part.trim();
+            if !part.is_empty() {
+                if i == 0 && !self.line.is_empty() {
+                    // Keep comments on the same line as the code before them
+                    self.line += " ";
+                    self.line += part;
+                    self.flush();
+                } else {
+                    self.push_line(part);
+                }
+            } else if i > 0 && i < parts.len() - 1 {
+                self.blank_line();
+            }
+        }
+    }
+
+    /// Add code to the current line, starting a new one if it gets too long
+    fn push_code(&mut self, code: &str) {
+        if !self.line.is_empty() && self.depth * 4 + self.line.len() + code.len() > WIDTH {
+            self.flush();
+        }
+        self.line += code;
+    }
+
+    fn push_line(&mut self, line: &str) {
+        self.flush();
+        self.lines.push("    ".repeat(self.depth) + line);
+        self.block_start = false;
+    }
+
+    fn flush(&mut self) {
+        if !self.line.is_empty() {
+            let line = std::mem::take(&mut self.line);
+            self.push_line(&line);
+        }
+    }
+
+    /// Add one blank line, except at the start of a block or after another blank line
+    fn blank_line(&mut self) {
+        self.flush();
+        if !self.block_start && self.lines.last().is_some_and(|line| !line.is_empty()) {
+            self.lines.push(String::new());
+        }
+    }
+
+    fn start_block(&mut self) {
+        self.depth += 1;
+        self.block_start = true;
+    }
+
+    fn end_block(&mut self) {
+        self.flush();
+        while self.lines.last().is_some_and(|line| line.is_empty()) {
+            self.lines.pop();
+        }
+        self.depth = self.depth.saturating_sub(1);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    fn macros() -> ParseOptions {
+        ParseOptions { macros: true, ..Default::default() }
+    }
+
+    #[test]
+    fn indent_loops() {
+        let code = "++[>+++[>++.<-]\n\n<-] done";
+        assert_eq!(
+            format_source(code, &ParseOptions::default()).unwrap(),
+            "++\n[\n    >+++[>++.<-]\n\n    <-\n]\ndone\n"
+        );
+    }
+
+    #[test]
+    fn keep_comments_and_blank_lines() {
+        let code = "+++ set up\n\n\n  print it:\n.[\n, read\n.]";
+        assert_eq!(
+            format_source(code, &ParseOptions::default()).unwrap(),
+            "+++ set up\n\nprint it:\n.\n[\n    , read\n    .\n]\n"
+        );
+    }
+
+    #[test]
+    fn long_runs_get_their_own_line() {
+        assert_eq!(format_source("<++++++++++>.", &ParseOptions::default()).unwrap(), "<\n++++++++++\n>.\n");
+    }
+
+    #[test]
+    fn format_macros() {
+        let code = "@clear@>@clear@ clear {[-]}";
+        assert_eq!(format_source(code, &macros()).unwrap(), "@clear@>@clear@\nclear {\n    [-]\n}\n");
+    }
+
+    #[test]
+    fn spans_cover_source() {
+        let code = "a +[>-.<,] b { ++ } @b@@b@";
+        let nodes = parse_tree(code, &macros()).unwrap();
+        let ends: Vec<(usize, usize)> = nodes.iter().map(|node| match node {
+            Node::Run { span, .. } | Node::Block { span, .. } | Node::MacroDefinition { span, .. } => (span.start, span.end),
+            Node::Comment(span) | Node::MacroCall(span) => (span.start, span.end),
+        }).collect();
+
+        assert_eq!(ends.first().unwrap().0, 0);
+        assert_eq!(ends.last().unwrap().1, code.len());
+        assert!(ends.windows(2).all(|pair| pair[0].1 == pair[1].0));
+    }
+
+    #[test]
+    fn formatting_keeps_program() {
+        let code = "x{+>}y{<-}\n@x@@y@ [ - [ @y@ ] ] ,,,,,,,,,,,.\n\n [comment>";
+        let formatted = format_source(&(code.to_string() + "]"), &macros()).unwrap();
+
+        assert_eq!(parse(&formatted, &macros()).unwrap(), parse(&(code.to_string() + "]"), &macros()).unwrap());
+        assert_eq!(format_source(&formatted, &macros()).unwrap(), formatted);
+    }
+
+    #[test]
+    fn minify_wraps() {
+        let instructions = parse("a {++} [ - ] @a@ .,\n>>", &macros()).unwrap();
+        assert_eq!(minify(&instructions, 4), "[-]+\n+.,>\n>\n");
+        assert_eq!(minify(&instructions, 0), "[-]++.,>>\n");
+    }
+
+    #[test]
+    fn unmatched_brackets() {
+        assert!(format_source("[[]", &ParseOptions::default()).is_err());
+        assert!(format_source("[]]", &ParseOptions::default()).is_err());
+        assert!(format_source("a { [ } b { ] }", &macros()).is_err());
+    }
+}
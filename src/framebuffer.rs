@@ -0,0 +1,101 @@
+//! Cells of the tape as a grid of pixels drawn in the terminal, for programs that do graphics.
+//! The program says when a frame is ready by changing a sync cell, and the grid is redrawn then.
+
+use std::{fmt::Write, io, str::FromStr};
+use crate::{Tape, image::Palette};
+
+
+/// Which cells are the screen, and which one the program changes when it's drawn a frame
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub struct Framebuffer {
+    pub width: usize,
+    pub height: usize,
+    /// The cell of the top left pixel, rows following each other from there
+    pub start: usize,
+    pub sync: usize,
+    pub palette: Palette,
+}
+
+
+/// `WIDTHxHEIGHT`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Size {
+    pub width: usize,
+    pub height: usize,
+}
+
+
+impl FromStr for Size {
+    type Err = String;
+
+    fn from_str(size: &str) -> Result<Size, String> {
+        let parse = |n: &str| n.parse().ok().filter(|&n: &usize| n > 0);
+        match size.split_once('x').map(|(width, height)| (parse(width), parse(height))) {
+            Some((Some(width), Some(height))) => Ok(Size { width, height }),
+            _ => Err(format!("expected WIDTHxHEIGHT, like 32x16, not {}", size)),
+        }
+    }
+}
+
+
+impl Framebuffer {
+    /// The grid, two rows of pixels to a line of half blocks, in 24 bit colour
+    pub fn frame(&self, tape: &Tape) -> String {
+        let pixel = |x: usize, y: usize| {
+            let value = tape.data.get(self.start + y * self.width + x).copied().unwrap_or(0);
+            self.palette.pixel(value)
+        };
+        let mut frame = String::new();
+        for y in (0..self.height).step_by(2) {
+            for x in 0..self.width {
+                let [r, g, b] = pixel(x, y);
+                let _ = write!(frame, "\x1b[38;2;{};{};{}m", r, g, b);
+                if y + 1 < self.height {
+                    let [r, g, b] = pixel(x, y + 1);
+                    let _ = write!(frame, "\x1b[48;2;{};{};{}m", r, g, b);
+                }
+                frame.push('▀');
+            }
+            frame += "\x1b[0m\x1b[K\n";
+        }
+        frame
+    }
+
+    /// Draw the grid at the top of the terminal, on stderr
+    pub fn draw(&self, tape: &Tape) -> io::Result<()> {
+        io::Write::write_all(&mut io::stderr(), format!("\x1b[H{}\x1b[J", self.frame(tape)).as_bytes())
+    }
+
+    /// The sync cell's value
+    pub fn sync_value(&self, tape: &Tape) -> u8 {
+        tape.data.get(self.sync).copied().unwrap_or(0)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes() {
+        assert_eq!("32x16".parse(), Ok(Size { width: 32, height: 16 }));
+        assert!("32".parse::<Size>().is_err());
+        assert!("0x4".parse::<Size>().is_err());
+    }
+
+    #[test]
+    fn half_blocks() {
+        let framebuffer = Framebuffer { width: 2, height: 3, start: 1, sync: 7, palette: Palette::Gray };
+        let tape = Tape { data: vec![9, 127, 0, 0, 127, 64], pointer: 0 };
+        let frame = framebuffer.frame(&tape);
+        let lines: Vec<&str> = frame.lines().collect();
+        assert_eq!(lines, [
+            "\x1b[38;2;254;254;254m\x1b[48;2;0;0;0m▀\x1b[38;2;0;0;0m\x1b[48;2;254;254;254m▀\x1b[0m\x1b[K",
+            // The last row has no row under it, and its second pixel is past the end of the tape
+            "\x1b[38;2;128;128;128m▀\x1b[38;2;0;0;0m▀\x1b[0m\x1b[K",
+        ]);
+        assert_eq!(framebuffer.sync_value(&tape), 0);
+    }
+}
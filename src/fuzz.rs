@@ -0,0 +1,58 @@
+//! Arbitrary programs for the cargo-fuzz targets in `fuzz/`. `Instruction` also implements `Arbitrary`,
+//! for any sequence of instructions, matched or not.
+
+use arbitrary::{Arbitrary, Unstructured};
+use crate::{Instruction, random::{self, Choices}};
+
+
+/// Characters that make up most programs, with macros and something that isn't ASCII
+const PROGRAM_CHARS: [char; 19] = ['+', '-', '<', '>', '[', ']', '.', ',', '{', '}', '@', '(', ')', ' ', '\n', 'a', 'b', '#', 'é'];
+
+
+
+/// Source text, mostly instructions, macro definitions and calls, for parsing
+#[derive(Debug, Clone)]
+pub struct ProgramText(pub String);
+
+
+impl<'a> Arbitrary<'a> for ProgramText {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<ProgramText> {
+        let length = u.arbitrary_len::<u8>()?;
+        (0..length).map(|_| u.choose(&PROGRAM_CHARS).copied()).collect::<arbitrary::Result<String>>().map(ProgramText)
+    }
+}
+
+
+/// The fuzzer's bytes as the random generator's choices, 0 once they run out
+impl Choices for Unstructured<'_> {
+    fn below(&mut self, n: usize) -> usize {
+        self.choose_index(n.max(1)).unwrap_or(0)
+    }
+}
+
+
+/// Plain brainfuck with matched brackets, so it always compiles, from the random generator
+#[derive(Debug, Clone)]
+pub struct BalancedProgram(pub Vec<Instruction>);
+
+
+impl<'a> Arbitrary<'a> for BalancedProgram {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<BalancedProgram> {
+        let options = random::Options { max_length: 256, max_depth: 16, ..random::Options::default() };
+        Ok(BalancedProgram(random::generate(u, &options)))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Program;
+
+    #[test]
+    fn balanced_programs_compile() {
+        let bytes: Vec<u8> = (0..=255).collect();
+        let program = BalancedProgram::arbitrary(&mut Unstructured::new(&bytes)).unwrap();
+        assert!(Program::compile(&program.0, true).is_ok());
+    }
+}
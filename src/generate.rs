@@ -0,0 +1,202 @@
+//! Generating programs, for the `gen-print` and `const` subcommands
+
+use std::sync::OnceLock;
+
+
+/// Largest multiplier tried for setting up cells
+const MAX_FACTOR: usize = 20;
+
+
+/// A short program that prints `text`.
+///
+/// A loop first sets some cells close to the characters in the text, then each character is
+/// printed from whichever cell is cheapest to move to and adjust.
+pub fn print_program(text: &str) -> Result<String, &'static str> {
+    if !text.is_ascii() {
+        return Err("text must be ASCII");
+    }
+    let bytes = text.as_bytes();
+
+    // Factor 0 means no setup loop, everything is printed from one cell
+    Ok((0..=MAX_FACTOR)
+        .filter(|factor| *factor != 1)
+        .map(|factor| with_factor(bytes, factor))
+        .min_by_key(|program| program.len())
+        .unwrap_or_default())
+}
+
+
+/// Set up cells to multiples of `factor` near the text's characters, then print from them
+fn with_factor(text: &[u8], factor: usize) -> String {
+    let mut code = String::new();
+    // Cell 0 is the loop counter, which ends up as 0
+    let mut cells: Vec<u8> = vec![0];
+
+    if factor > 0 {
+        let mut multiples: Vec<usize> = text.iter().map(|&c| (c as usize + factor / 2) / factor).filter(|&k| k > 0).collect();
+        multiples.sort();
+        multiples.dedup();
+
+        if !multiples.is_empty() {
+            code += &"+".repeat(factor);
+            code += "[";
+            for k in &multiples {
+                code += ">";
+                code += &"+".repeat(*k);
+                cells.push(((k * factor) % 128) as u8);
+            }
+            code += &"<".repeat(multiples.len());
+            code += "-]";
+        }
+    }
+
+    let mut pointer: usize = 0;
+    for &c in text {
+        // Cheapest cell to print from, counting both the moves and the adjustment
+        let (cell, _) = cells.iter().enumerate()
+            .map(|(i, _)| (i, pointer.abs_diff(i) + change(&cells, i, c).len()))
+            .min_by_key(|(_, cost)| *cost)
+            .unwrap();
+
+        code += &if cell > pointer { ">".repeat(cell - pointer) } else { "<".repeat(pointer - cell) };
+        code += &change(&cells, cell, c);
+        code += ".";
+        cells[cell] = c;
+        pointer = cell;
+    }
+
+    code
+}
+
+
+/// Code turning `cells[cell]` into `to`, which can use loops for the last cell, the cells after it being 0
+fn change(cells: &[u8], cell: usize, to: u8) -> String {
+    let plain = adjustment(cells[cell], to);
+    if cell + 1 < cells.len() || plain.len() <= 6 {
+        return plain;
+    }
+    shortest_constant(((to as usize + 128 - cells[cell] as usize) % 128) as u8)
+}
+
+
+/// The shortest code found that adds `n` to the current cell, wrapping at 128, which is also how to set a cell
+/// that's 0. It tries a run of `+` or `-`, and loops multiplying one or two counters, like `>+++++++[<--------->-]<`
+/// for 65. The two cells to the right have to be 0, and are left at 0 with the pointer back where it started.
+pub fn shortest_constant(n: u8) -> String {
+    static TABLE: OnceLock<Vec<String>> = OnceLock::new();
+    TABLE.get_or_init(constants)[n as usize % 128].clone()
+}
+
+
+/// `+` or `-` enough times to add `n`, going whichever way round is shorter
+fn run(n: usize) -> String {
+    adjustment(0, (n % 128) as u8)
+}
+
+
+/// The code to set a counter to loop `times` times, and the step that counts it back to 0
+fn counter(times: usize) -> (String, char) {
+    match times <= 64 {
+        true => ("+".repeat(times), '-'),
+        false => ("-".repeat(128 - times), '+'),
+    }
+}
+
+
+/// The shortest code for each value, from every loop tried and the best run after it
+fn constants() -> Vec<String> {
+    // The shortest loop found giving each value, if any is shorter than a run
+    let mut loops: Vec<String> = (0..128).map(run).collect();
+    let mut keep = |value: usize, code: String| {
+        if code.len() < loops[value].len() {
+            loops[value] = code;
+        }
+    };
+    for outer in 1..128 {
+        let (start, step) = counter(outer);
+        for factor in 1..128 {
+            keep(outer * factor % 128, format!(">{}[<{}>{}]<", start, run(factor), step));
+            if outer > 16 || factor > 16 {
+                continue;
+            }
+            for inner in 1..=16 {
+                let (inner_start, inner_step) = counter(inner);
+                keep(
+                    outer * inner * factor % 128,
+                    format!(">>{}[<{}[<{}>{}]>{}]<<", start, inner_start, run(factor), inner_step, step),
+                );
+            }
+        }
+    }
+
+    (0..128)
+        .map(|n| {
+            (0..128)
+                .map(|value| loops[value].clone() + &adjustment(value as u8, n as u8))
+                .min_by_key(String::len)
+                .unwrap()
+        })
+        .collect()
+}
+
+
+/// The shortest `+` or `-` run that turns `from` into `to`, with cells wrapping at 128
+fn adjustment(from: u8, to: u8) -> String {
+    let up = (to as usize + 128 - from as usize) % 128;
+    if up <= 64 {
+        "+".repeat(up)
+    } else {
+        "-".repeat(128 - up)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use crate::{parse, ParseOptions, Program, Vm, VmOptions};
+
+    fn output(code: &str) -> String {
+        let program = Program::compile(&parse(code, &ParseOptions::default()).unwrap(), true).unwrap();
+        let mut vm = Vm::new(program, &VmOptions::default());
+        let mut output = Vec::new();
+        while !vm.is_finished() {
+            vm.step(&mut io::empty(), &mut output).unwrap();
+        }
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn prints_text() {
+        for text in ["Hello, World!\n", "", "a", "\x7f\x00zz~"] {
+            assert_eq!(output(&print_program(text).unwrap()), text);
+        }
+    }
+
+    #[test]
+    fn shorter_than_plain_runs() {
+        let text = "The quick brown fox jumps over the lazy dog";
+        let plain = with_factor(text.as_bytes(), 0);
+        assert!(print_program(text).unwrap().len() < plain.len() / 2);
+    }
+
+    #[test]
+    fn constants() {
+        for n in 0..128 {
+            let code = shortest_constant(n);
+            let program = Program::compile(&parse(&format!("{}.>.>.", code), &ParseOptions::default()).unwrap(), true).unwrap();
+            let mut output = Vec::new();
+            Vm::new(program, &VmOptions::default()).run(&mut io::empty(), &mut output).unwrap();
+            assert_eq!(output, [n, 0, 0], "{} from {}", n, code);
+        }
+        assert_eq!(shortest_constant(0), "");
+        assert_eq!(shortest_constant(127), "-");
+        assert_eq!(shortest_constant(65), ">+++++++[<--------->-]<");
+    }
+
+    #[test]
+    fn reject_non_ascii() {
+        assert!(print_program("é").is_err());
+    }
+}
@@ -0,0 +1,246 @@
+//! Making programs shorter, for the `golf` subcommand.
+//!
+//! Rewrites are tried one at a time on the program's instructions, and one is only kept if the result is shorter
+//! and does exactly the same as the original on every sample input, run by the reference interpreter. That makes
+//! it safe to try rewrites that are only right for some programs, like a loop using a cell that has to be 0.
+
+use std::error::Error;
+use crate::{Instruction, instruction_to_char, parse, ParseOptions, generate::shortest_constant, reference::{self, Outcome}};
+
+
+/// A shorter program, and what was done to get it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Golfed {
+    pub code: String,
+    pub rewrites: Vec<String>,
+}
+
+
+/// Longest repeated piece of code tried for re-rolling into a loop
+const MAX_ROLLED: usize = 12;
+
+
+/// Shorten a plain brainfuck program as far as the rewrites go, checking each one on `inputs`
+pub fn golf(instructions: &[Instruction], inputs: &[Vec<u8>], max_steps: usize) -> Result<Golfed, Box<dyn Error>> {
+    let mut code: String = instructions.iter().filter(|&&instruction| instruction != Instruction::Break).map(instruction_to_char).collect();
+    let expected = outcomes(&code, inputs, max_steps)?;
+    let mut rewrites = Vec::new();
+
+    'shorter: loop {
+        for (rewrite, candidate) in candidates(&code) {
+            if candidate.len() < code.len() && outcomes(&candidate, inputs, max_steps).ok().as_ref() == Some(&expected) {
+                log::debug!("{}, down to {} instructions", rewrite, candidate.len());
+                code = candidate;
+                rewrites.push(rewrite);
+                continue 'shorter;
+            }
+        }
+        return Ok(Golfed { code, rewrites });
+    }
+}
+
+
+/// How the program ends on each input
+fn outcomes(code: &str, inputs: &[Vec<u8>], max_steps: usize) -> Result<Vec<Option<Outcome>>, Box<dyn Error>> {
+    let instructions = parse(code, &ParseOptions::default())?;
+    inputs.iter().map(|input| reference::run(&instructions, input, max_steps)).collect()
+}
+
+
+/// Rewrites of `code` worth trying, cheapest and most likely to work first
+fn candidates(code: &str) -> Vec<(String, String)> {
+    let mut candidates = Vec::new();
+
+    let mut cancelled = code.to_string();
+    while let Some(pair) = ["+-", "-+", "<>", "><"].iter().find(|pair| cancelled.contains(**pair)) {
+        cancelled = cancelled.replacen(pair, "", 1);
+    }
+    candidates.push(("cancelled out pairs like +- and <>".to_string(), cancelled));
+    // `<>` isn't a no-op on cell 0, so as well as all at once, try each move pair on its own
+    for pair in ["<>", "><"] {
+        for (i, _) in code.match_indices(pair) {
+            candidates.push((format!("cancelled out a {}", pair), format!("{}{}", &code[..i], &code[i + 2..])));
+        }
+    }
+
+    // A loop right after another one, or at the start, begins on a 0 and never runs
+    for (open, close) in loops(code) {
+        if open == 0 || code[..open].ends_with(']') {
+            candidates.push((format!("removed a loop that never runs, {}", &code[open..=close]), format!("{}{}", &code[..open], &code[close + 1..])));
+        }
+    }
+
+    for (start, end) in runs(code) {
+        let added = code[start..end].chars().map(|c| if c == '+' { 1 } else { 127 }).sum::<usize>() % 128;
+        let old = match code[start..end].chars().all(|c| c == code[start..].chars().next().unwrap()) {
+            true => format!("{} {}", end - start, &code[start..start + 1]),
+            false => code[start..end].to_string(),
+        };
+        // The shortest constant is a plain run when no loop beats one
+        let mut replacements = vec![shortest_constant(added as u8), run(added)];
+        replacements.dedup();
+        for replacement in replacements {
+            let how = if replacement.contains('[') { "a loop" } else { "a run" };
+            if replacement.len() < end - start {
+                candidates.push((
+                    match replacement.is_empty() {
+                        true => format!("removed {}, which adds up to nothing", old),
+                        false => format!("wrote {} as {}", old, how),
+                    },
+                    format!("{}{}{}", &code[..start], replacement, &code[end..]),
+                ));
+            }
+        }
+    }
+
+    for (start, piece, times) in repeats(code) {
+        let rolled = rolled(piece, times);
+        if rolled.len() < piece.len() * times {
+            candidates.push((
+                format!("re-rolled {} copies of {} into a loop", times, piece),
+                format!("{}{}{}", &code[..start], rolled, &code[start + piece.len() * times..]),
+            ));
+        }
+    }
+    candidates
+}
+
+
+/// Where each loop opens and closes
+fn loops(code: &str) -> Vec<(usize, usize)> {
+    let mut open = Vec::new();
+    let mut loops = Vec::new();
+    for (i, c) in code.char_indices() {
+        match c {
+            '[' => open.push(i),
+            ']' => loops.extend(open.pop().map(|start| (start, i))),
+            _ => (),
+        }
+    }
+    loops
+}
+
+
+/// Where each run of `+` and `-` starts and ends
+fn runs(code: &str) -> Vec<(usize, usize)> {
+    let mut runs = Vec::new();
+    let mut start = None;
+    for (i, c) in code.char_indices().chain([(code.len(), ' ')]) {
+        match (start, c == '+' || c == '-') {
+            (None, true) => start = Some(i),
+            (Some(from), false) => {
+                runs.push((from, i));
+                start = None;
+            },
+            _ => (),
+        }
+    }
+    runs
+}
+
+
+/// `+` or `-` enough times to add `n`
+fn run(n: usize) -> String {
+    match n <= 64 {
+        true => "+".repeat(n),
+        false => "-".repeat(128 - n),
+    }
+}
+
+
+/// Pieces of code repeated at least three times in a row, with brackets that match and the pointer back where it
+/// started, and where the repeats start
+fn repeats(code: &str) -> Vec<(usize, &str, usize)> {
+    let mut repeats = Vec::new();
+    for length in 1..=MAX_ROLLED {
+        let mut start = 0;
+        while start + length <= code.len() {
+            let piece = &code[start..start + length];
+            let times = (1..).take_while(|k| code[start..].get(k * length..(k + 1) * length) == Some(piece)).count() + 1;
+            if times >= 3 && reach(piece).is_some() {
+                repeats.push((start, piece, times));
+                start += length * times;
+            } else {
+                start += 1;
+            }
+        }
+    }
+    repeats
+}
+
+
+/// The furthest right `piece` goes from where it starts, if it has matching brackets and ends where it started
+fn reach(piece: &str) -> Option<usize> {
+    let (mut pointer, mut furthest, mut depth) = (0i64, 0i64, 0i64);
+    for c in piece.chars() {
+        match c {
+            '>' => pointer += 1,
+            '<' => pointer -= 1,
+            '[' => depth += 1,
+            ']' => depth -= 1,
+            _ => (),
+        }
+        furthest = furthest.max(pointer);
+        if depth < 0 {
+            return None;
+        }
+    }
+    (pointer == 0 && depth == 0).then_some(furthest as usize)
+}
+
+
+/// A loop running `piece` `times` times, counting in the first cell past the ones it uses
+fn rolled(piece: &str, times: usize) -> String {
+    let to_counter = ">".repeat(reach(piece).unwrap_or(0) + 1);
+    let back = "<".repeat(to_counter.len());
+    let (start, step) = match times <= 64 {
+        true => ("+".repeat(times), '-'),
+        false => ("-".repeat(128 - times), '+'),
+    };
+    format!("{}{}[{}{}{}{}]{}", to_counter, start, back, piece, to_counter, step, back)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::equiv::default_inputs;
+
+    fn golfed(code: &str) -> Golfed {
+        golf(&parse(code, &ParseOptions::default()).unwrap(), &default_inputs(), 100_000).unwrap()
+    }
+
+    #[test]
+    fn cancels_and_removes_dead_loops() {
+        let golfed = golfed("[->+<]+-+>< comment ,[.,][-]");
+        assert_eq!(golfed.code, "+,[.,]");
+        assert!(golfed.rewrites.contains(&"removed a loop that never runs, [-]".to_string()));
+    }
+
+    #[test]
+    fn shorter_constants() {
+        assert_eq!(golfed(&"+".repeat(100)).code, shortest_constant(100));
+        assert!(shortest_constant(100).len() < 28);
+        let golfed = golfed(&format!("{}.", "+".repeat(60)));
+        assert_eq!(golfed.code, format!("{}.", shortest_constant(60)));
+        // Said to be what it is, a run even when it comes from `shortest_constant`
+        assert!(candidates("+++--.").contains(&("wrote +++-- as a run".to_string(), "+.".to_string())));
+        assert!(!candidates("+++--.").iter().any(|(how, _)| how.contains("as a loop")));
+    }
+
+    #[test]
+    fn rerolls() {
+        let golfed = golfed(&"+.".repeat(20));
+        // The counter's 20 `+` are then shortened too
+        assert_eq!(golfed.code, format!(">{}[<+.>-]<", shortest_constant(20)));
+        assert_eq!(golfed.rewrites, ["re-rolled 20 copies of +. into a loop", "wrote 20 + as a loop"]);
+    }
+
+    #[test]
+    fn keeps_cell_zero_moves() {
+        // `<>` on cell 0 leaves the pointer at cell 1, so it isn't a no-op
+        let golfed = golfed("<>+.");
+        assert_eq!(golfed.code, "<>+.");
+        assert!(golfed.rewrites.is_empty());
+    }
+}
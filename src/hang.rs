@@ -0,0 +1,190 @@
+//! Noticing loops that can never end: ones that come back round to exactly the same state without any I/O.
+//! The vm runs the same way from the same state, so such a loop would go round forever.
+
+use std::collections::HashMap;
+use crate::{Op, emit::source_map::LineIndex, error::{self, Hang}};
+
+
+/// Iterations of a loop before checking whether it changes anything, so loops that end soon cost nothing
+const CHECK_AFTER: u64 = 10_000;
+
+
+#[derive(Debug, Clone, Default)]
+pub struct Detector {
+    /// Loops going round right now, by the index of their closing op
+    loops: HashMap<usize, Iterations>,
+    /// What `read_set` gives for each loop checked so far, by the index of its closing op
+    read_sets: HashMap<usize, Option<Vec<isize>>>,
+}
+
+
+#[derive(Debug, Clone, Default)]
+struct Iterations {
+    count: u64,
+    /// Hash of the state the loop depends on the last time round, if it was checked and there was no I/O since
+    last: Option<u64>,
+}
+
+
+impl Detector {
+    /// The loop from `open` to `close` in `ops` is about to go round again. `state` hashes what the loop depends
+    /// on, given the loop's `read_set` if it has one. Returns whether that's the same as the last time round,
+    /// after which the loop counts from 0 again.
+    pub fn repeat(&mut self, ops: &[Op], open: usize, close: usize, state: impl FnOnce(Option<&[isize]>) -> u64) -> bool {
+        let iterations = self.loops.entry(close).or_default();
+        iterations.count += 1;
+        if iterations.count < CHECK_AFTER {
+            return false;
+        }
+        let read_set = self.read_sets.entry(close).or_insert_with(|| read_set(ops, open, close));
+        let state = state(read_set.as_deref());
+        let repeated = iterations.last.replace(state) == Some(state);
+        if repeated {
+            self.loops.remove(&close);
+        }
+        repeated
+    }
+
+    /// The loop closing at `close` ended, so the next time it runs counts from 0 again
+    pub fn exit(&mut self, close: usize) {
+        self.loops.remove(&close);
+    }
+
+    /// A loop that reads or writes does something each time round, even in the same state
+    pub fn io(&mut self) {
+        for iterations in self.loops.values_mut() {
+            iterations.last = None;
+        }
+    }
+}
+
+
+/// The offsets from the pointer, lowest first, of every cell the loop from `open` to `close` can touch each time
+/// round, including the one it tests and the furthest it moves left. `None` if the pointer doesn't come back to where it started each time, or
+/// the loop uses procedures, other tapes or the stack, so anything could matter.
+pub fn read_set(ops: &[Op], open: usize, close: usize) -> Option<Vec<isize>> {
+    let mut offsets = vec![0];
+    let mut position: isize = 0;
+    let mut loop_starts = Vec::new();
+    for &op in ops.get(open + 1..close)? {
+        match op {
+            Op::Add(_) | Op::Clear | Op::Input | Op::Output => offsets.push(position),
+            Op::Right(n) => position += n as isize,
+            Op::Left(n) => {
+                // Where the pointer gets furthest left has to be on the tape, even if nothing there is touched
+                position -= n as isize;
+                offsets.push(position);
+            },
+            Op::MulAdd { offset, .. } => offsets.extend([position, position + offset as isize]),
+            Op::JumpIfZero(_) => {
+                offsets.push(position);
+                loop_starts.push(position);
+            },
+            // An inner loop has to come back to where it started too
+            Op::JumpIfNonZero(_) if loop_starts.pop() == Some(position) => offsets.push(position),
+            Op::Break => (),
+            _ => return None,
+        }
+    }
+    if position != 0 {
+        return None;
+    }
+    offsets.sort_unstable();
+    offsets.dedup();
+    Some(offsets)
+}
+
+
+/// Where to say a hang is when only warning about it
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub struct Warnings {
+    /// The program's path, and its source if it isn't bytecode
+    pub path: String,
+    pub source: Option<String>,
+}
+
+
+impl Warnings {
+    /// Fill in the hang's location and excerpt from the source
+    pub fn locate(&self, hang: &mut Hang) {
+        let (Some(source), Some((open, close))) = (&self.source, hang.offsets) else {
+            return;
+        };
+        let index = LineIndex::new(source);
+        let position = |offset| {
+            let (line, column) = index.line_column(source, offset);
+            format!("{}:{}", line + 1, column + 1)
+        };
+        hang.location = Some(format!("{}:{} to {}", self.path, position(open), position(close)));
+        hang.excerpt = Some(error::excerpt(source, open..close + 1));
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use crate::{ParseOptions, Program, Vm, VmOptions, parse};
+
+    fn run(code: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let program = Program::compile(&parse(code, &ParseOptions::default()).unwrap(), false).unwrap();
+        let mut vm = Vm::new(program, &VmOptions { detect_hangs: true, stop_on_hang: true, ..VmOptions::default() });
+        vm.run(&mut io::empty(), &mut io::sink())
+    }
+
+    #[test]
+    fn same_state_loops_hang() {
+        let error = run(">+[<+->]").unwrap_err();
+        let hang = error.downcast_ref::<Hang>().unwrap();
+        assert_eq!((hang.open, hang.close), (2, 7));
+        // The cells the loop doesn't touch can change elsewhere without hiding it
+        assert!(run("+[>+[<+->-]<]").unwrap_err().is::<Hang>());
+    }
+
+    #[test]
+    fn changing_loops_and_io_are_fine() {
+        assert!(run("-[>-[-]<-]").is_ok());
+
+        let ops = [Op::JumpIfZero(1), Op::JumpIfNonZero(0)];
+        let mut detector = Detector::default();
+        for i in 0..CHECK_AFTER * 2 {
+            assert!(!detector.repeat(&ops, 0, 1, |_| i));
+        }
+        for _ in 0..CHECK_AFTER * 2 {
+            assert!(!detector.repeat(&ops, 0, 1, |_| 0));
+            detector.io();
+        }
+    }
+
+    #[test]
+    fn warns_and_goes_on() {
+        let program = Program::compile_with_offsets(&crate::parse_with_offsets("+\n[]", &ParseOptions::default()).unwrap(), false).unwrap();
+        let mut vm = Vm::new(program, &VmOptions { detect_hangs: true, ..VmOptions::default() });
+        let mut found = 0;
+        for _ in 0..CHECK_AFTER * 3 {
+            vm.step(&mut io::empty(), &mut io::sink()).unwrap();
+            let Some(mut hang) = vm.take_hang() else {
+                continue;
+            };
+            found += 1;
+            Warnings { path: "a.b".to_string(), source: Some("+\n[]".to_string()) }.locate(&mut hang);
+            assert_eq!(hang.location.as_deref(), Some("a.b:2:1 to 2:2"));
+        }
+        // Checked again from scratch after each warning
+        assert_eq!(found, 2);
+        assert!(!vm.is_finished());
+    }
+
+    #[test]
+    fn read_sets() {
+        let program = |code| Program::compile(&parse(code, &ParseOptions::default()).unwrap(), false).unwrap();
+        let ops = program("[->>+<[-]<]").ops;
+        assert_eq!(read_set(&ops, 0, ops.len() - 1), Some(vec![0, 1, 2]));
+        let ops = program("[<<+>[>]>]").ops;
+        assert_eq!(read_set(&ops, 0, ops.len() - 1), None);
+        let ops = program("[>]").ops;
+        assert_eq!(read_set(&ops, 0, 2), None);
+    }
+}
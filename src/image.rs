@@ -0,0 +1,426 @@
+//! Pictures of the tape as PNG files, one square of pixels per cell, for programs that draw in memory,
+//! and animated GIFs of the tape changing as a program runs
+
+use std::{collections::HashMap, fs, io, path::Path, time::Duration};
+use clap::ValueEnum;
+use crate::Tape;
+
+
+/// How a cell's value becomes a pixel
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub enum Palette {
+    /// Black for 0 up to white for 127
+    #[default]
+    Gray,
+    /// Black for 0, the other values going round the colour wheel
+    Color,
+}
+
+
+impl Palette {
+    pub(crate) fn pixel(self, value: u8) -> [u8; 3] {
+        match (self, value) {
+            (Palette::Gray, value) => [value * 2; 3],
+            (Palette::Color, 0) => [0; 3],
+            (Palette::Color, value) => hue(value as usize * 6 * 256 / 128),
+        }
+    }
+}
+
+
+/// A fully saturated colour, `position` going from 0 to 6 * 256 round the wheel from red
+fn hue(position: usize) -> [u8; 3] {
+    let rising = (position % 256) as u8;
+    let falling = 255 - rising;
+    match position / 256 {
+        0 => [255, rising, 0],
+        1 => [falling, 255, 0],
+        2 => [0, 255, rising],
+        3 => [0, falling, 255],
+        4 => [rising, 0, 255],
+        _ => [255, 0, falling],
+    }
+}
+
+
+/// Where and how to draw the tape
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub struct TapeImage {
+    pub path: String,
+    /// Cells in each row of the picture
+    pub width: usize,
+    /// Width and height in pixels of each cell
+    pub scale: usize,
+    pub palette: Palette,
+}
+
+
+/// Tapes to record every so many steps into an animated GIF
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub struct Recording {
+    /// Where to write the GIF, and how to draw each tape
+    pub image: TapeImage,
+    /// Steps between frames
+    pub every: usize,
+    /// How long each frame shows for
+    pub delay: Duration,
+}
+
+
+impl Recording {
+    /// Write the GIF of the cells of each recorded tape
+    pub fn write(&self, tapes: &[Vec<u8>]) -> io::Result<()> {
+        fs::write(&self.image.path, self.image.gif(tapes, self.delay))
+    }
+}
+
+
+impl TapeImage {
+    /// The cells as rows of palette indices, one per pixel, `rows` cells high
+    fn indices(&self, cells: &[u8], rows: usize) -> Vec<u8> {
+        let (width, scale) = (self.width.max(1), self.scale.max(1));
+        let mut pixels = Vec::with_capacity(width * scale * rows * scale);
+        for row in 0..rows {
+            let mut line = Vec::with_capacity(width * scale);
+            for column in 0..width {
+                let value = cells.get(row * width + column).copied().unwrap_or(0);
+                line.extend(std::iter::repeat_n(value, scale));
+            }
+            for _ in 0..scale {
+                pixels.extend(&line);
+            }
+        }
+        pixels
+    }
+
+    /// An animated GIF with a frame for the cells of each tape, all as tall as the longest one.
+    /// Cell values are the colour indices, so the palette is the colour table.
+    pub fn gif(&self, tapes: &[Vec<u8>], delay: Duration) -> Vec<u8> {
+        let (width, scale) = (self.width.max(1), self.scale.max(1));
+        let rows = tapes.iter().map(|cells| cells.len().div_ceil(width)).max().unwrap_or(0).max(1);
+        let palette: Vec<[u8; 3]> = (0..128).map(|value| self.palette.pixel(value)).collect();
+        let frames: Vec<Vec<u8>> = tapes.iter().map(|cells| self.indices(cells, rows)).collect();
+        encode_gif(width * scale, rows * scale, &palette, &frames, (delay.as_millis() / 10) as u16)
+    }
+
+    /// The tape as a PNG, with cells past its end drawn as 0
+    pub fn png(&self, tape: &Tape) -> Vec<u8> {
+        let (width, scale) = (self.width.max(1), self.scale.max(1));
+        let rows = tape.data.len().div_ceil(width);
+        let mut pixels = Vec::with_capacity(width * scale * rows * scale * 3);
+        for row in tape.data.chunks(width) {
+            let mut line = Vec::with_capacity(width * scale * 3);
+            for column in 0..width {
+                let pixel = self.palette.pixel(row.get(column).copied().unwrap_or(0));
+                for _ in 0..scale {
+                    line.extend(pixel);
+                }
+            }
+            for _ in 0..scale {
+                pixels.extend(&line);
+            }
+        }
+        encode_png(width * scale, rows * scale, &pixels)
+    }
+
+    /// Write the tape to `path`
+    pub fn write(&self, tape: &Tape) -> io::Result<()> {
+        fs::write(&self.path, self.png(tape))
+    }
+
+    /// Write the tape as the `number`th snapshot, `tape.png` becoming `tape-1.png` and so on
+    pub fn write_snapshot(&self, tape: &Tape, number: usize) -> io::Result<()> {
+        fs::write(self.snapshot_path(number), self.png(tape))
+    }
+
+    fn snapshot_path(&self, number: usize) -> String {
+        let path = Path::new(&self.path);
+        let stem = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or("tape");
+        let name = match path.extension().and_then(|extension| extension.to_str()) {
+            Some(extension) => format!("{}-{}.{}", stem, number, extension),
+            None => format!("{}-{}", stem, number),
+        };
+        path.with_file_name(name).display().to_string()
+    }
+}
+
+
+/// An 8 bit RGB PNG of `pixels`, row by row. The image data is stored without compression, which keeps this short
+/// and is fine for pictures of a tape.
+pub fn encode_png(width: usize, height: usize, pixels: &[u8]) -> Vec<u8> {
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+
+    let mut header = Vec::new();
+    header.extend((width as u32).to_be_bytes());
+    header.extend((height as u32).to_be_bytes());
+    // 8 bit RGB, the standard compression and filters, no interlacing
+    header.extend([8, 2, 0, 0, 0]);
+    chunk(&mut png, b"IHDR", &header);
+
+    // Each row starts with its filter type, 0 for none
+    let mut raw = Vec::with_capacity(height * (width * 3 + 1));
+    for row in pixels.chunks(width * 3).take(height) {
+        raw.push(0);
+        raw.extend(row);
+    }
+    chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+    chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+
+/// A looping GIF of `frames` of colour indices into `palette`, which has 128 colours, each shown for `delay`
+/// hundredths of a second
+pub fn encode_gif(width: usize, height: usize, palette: &[[u8; 3]], frames: &[Vec<u8>], delay: u16) -> Vec<u8> {
+    let mut gif = b"GIF89a".to_vec();
+    gif.extend((width as u16).to_le_bytes());
+    gif.extend((height as u16).to_le_bytes());
+    // A global colour table of 2^7 colours with 8 bits each
+    gif.extend([0xf6, 0, 0]);
+    for index in 0..128 {
+        gif.extend(palette.get(index).copied().unwrap_or([0; 3]));
+    }
+    // Loop forever
+    gif.extend(b"\x21\xff\x0bNETSCAPE2.0\x03\x01\x00\x00\x00");
+
+    for frame in frames {
+        gif.extend([0x21, 0xf9, 4, 0]);
+        gif.extend(delay.to_le_bytes());
+        gif.extend([0, 0]);
+
+        gif.push(0x2c);
+        gif.extend([0, 0, 0, 0]);
+        gif.extend((width as u16).to_le_bytes());
+        gif.extend((height as u16).to_le_bytes());
+        gif.push(0);
+
+        gif.push(7);
+        for block in lzw(frame, 7).chunks(255) {
+            gif.push(block.len() as u8);
+            gif.extend(block);
+        }
+        gif.push(0);
+    }
+    gif.push(0x3b);
+    gif
+}
+
+
+/// GIF's LZW compression of `indices`, each under 2^`min_size`
+fn lzw(indices: &[u8], min_size: u32) -> Vec<u8> {
+    let clear = 1u16 << min_size;
+    let end = clear + 1;
+    let mut codes = Codes::default();
+    let mut size = min_size + 1;
+    let mut next = end + 1;
+    let mut table: HashMap<(u16, u8), u16> = HashMap::new();
+    codes.push(clear, size);
+    let Some((&first, rest)) = indices.split_first() else {
+        codes.push(end, size);
+        return codes.finish();
+    };
+    let mut prefix = first as u16;
+    for &index in rest {
+        if let Some(&code) = table.get(&(prefix, index)) {
+            prefix = code;
+            continue;
+        }
+        codes.push(prefix, size);
+        if next == 4096 {
+            // The table is full, so start again
+            codes.push(clear, size);
+            table.clear();
+            next = end + 1;
+            size = min_size + 1;
+        } else {
+            if next >= 1 << size {
+                size += 1;
+            }
+            table.insert((prefix, index), next);
+            next += 1;
+        }
+        prefix = index as u16;
+    }
+    codes.push(prefix, size);
+    codes.push(end, size);
+    codes.finish()
+}
+
+
+/// Codes of varying sizes packed into bytes from the lowest bit up
+#[derive(Default)]
+struct Codes {
+    packed: Vec<u8>,
+    bits: u32,
+    bit_count: u32,
+}
+
+
+impl Codes {
+    fn push(&mut self, code: u16, size: u32) {
+        self.bits |= (code as u32) << self.bit_count;
+        self.bit_count += size;
+        while self.bit_count >= 8 {
+            self.packed.push(self.bits as u8);
+            self.bits >>= 8;
+            self.bit_count -= 8;
+        }
+    }
+
+    /// The packed codes with whatever bits are left over
+    fn finish(mut self) -> Vec<u8> {
+        if self.bit_count > 0 {
+            self.packed.push(self.bits as u8);
+        }
+        self.packed
+    }
+}
+
+
+fn chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend((data.len() as u32).to_be_bytes());
+    png.extend(kind);
+    png.extend(data);
+    png.extend(crc32(kind.iter().chain(data)).to_be_bytes());
+}
+
+
+/// `data` in zlib format, as deflate blocks that aren't compressed
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut zlib = vec![0x78, 0x01];
+    let mut blocks = data.chunks(u16::MAX as usize).peekable();
+    if blocks.peek().is_none() {
+        zlib.extend([1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        zlib.push(last as u8);
+        zlib.extend((block.len() as u16).to_le_bytes());
+        zlib.extend((!(block.len() as u16)).to_le_bytes());
+        zlib.extend(block);
+    }
+    zlib.extend(adler32(data).to_be_bytes());
+    zlib
+}
+
+
+fn crc32<'a>(bytes: impl IntoIterator<Item = &'a u8>) -> u32 {
+    let mut crc = u32::MAX;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+
+fn adler32(bytes: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in bytes {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksums() {
+        assert_eq!(crc32(b"IEND"), 0xae42_6082);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+    }
+
+    #[test]
+    fn cells_become_squares() {
+        let image = TapeImage { path: "tape.png".to_string(), width: 2, scale: 2, palette: Palette::Gray };
+        let png = image.png(&Tape { data: vec![0, 127, 64], pointer: 0 });
+        // 4x4 pixels, the last cell of the second row being past the end of the tape
+        assert_eq!(&png[16..24], &[0, 0, 0, 4, 0, 0, 0, 4]);
+        let idat = png.windows(4).position(|kind| kind == b"IDAT").unwrap();
+        // After the zlib header and the block header, the first row, then the third
+        let rows = &png[idat + 4 + 2 + 5..];
+        assert_eq!(&rows[..13], &[0, 0, 0, 0, 0, 0, 0, 254, 254, 254, 254, 254, 254]);
+        assert_eq!(&rows[26..39], &[0, 128, 128, 128, 128, 128, 128, 0, 0, 0, 0, 0, 0]);
+        assert!(png.ends_with(b"IEND\xae\x42\x60\x82"));
+    }
+
+    #[test]
+    fn snapshots_are_numbered() {
+        let image = TapeImage { path: "out/tape.png".to_string(), width: 64, scale: 1, palette: Palette::Color };
+        assert_eq!(image.snapshot_path(3), "out/tape-3.png");
+        assert_eq!(Palette::Color.pixel(0), [0, 0, 0]);
+        assert_eq!(Palette::Color.pixel(64), [0, 255, 255]);
+    }
+
+    /// Undo `lzw`, to check it
+    fn unlzw(packed: &[u8], min_size: u32) -> Vec<u8> {
+        let clear = 1usize << min_size;
+        let mut table: Vec<Vec<u8>> = Vec::new();
+        let mut size = min_size + 1;
+        let mut previous: Option<usize> = None;
+        let mut indices = Vec::new();
+        let (mut bits, mut bit_count, mut bytes) = (0u32, 0, packed.iter());
+        loop {
+            while bit_count < size {
+                bits |= (*bytes.next().unwrap() as u32) << bit_count;
+                bit_count += 8;
+            }
+            let code = (bits & ((1 << size) - 1)) as usize;
+            bits >>= size;
+            bit_count -= size;
+            if code == clear {
+                table = (0..clear).map(|index| vec![index as u8]).chain([vec![], vec![]]).collect();
+                size = min_size + 1;
+                previous = None;
+                continue;
+            }
+            if code == clear + 1 {
+                return indices;
+            }
+            let entry = match (table.get(code), previous) {
+                (Some(entry), _) => entry.clone(),
+                (None, Some(previous)) => [&table[previous][..], &table[previous][..1]].concat(),
+                (None, None) => panic!("code {} before any other", code),
+            };
+            if let Some(previous) = previous {
+                table.push([&table[previous][..], &entry[..1]].concat());
+                if table.len() == 1 << size && size < 12 {
+                    size += 1;
+                }
+            }
+            indices.extend(&entry);
+            previous = Some(code);
+        }
+    }
+
+    #[test]
+    fn lzw_round_trips() {
+        // Long enough to fill the table and start again
+        let indices: Vec<u8> = (0..40_000u32).map(|i| ((i * i / 7 + i / 300) % 128) as u8).collect();
+        assert_eq!(unlzw(&lzw(&indices, 7), 7), indices);
+        assert_eq!(unlzw(&lzw(&[5; 1000], 7), 7), vec![5; 1000]);
+        assert_eq!(unlzw(&lzw(&[], 7), 7), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn gif_frames() {
+        let image = TapeImage { path: "tape.gif".to_string(), width: 2, scale: 1, palette: Palette::Gray };
+        let gif = image.gif(&[vec![1], vec![1, 2, 3]], Duration::from_millis(50));
+        assert!(gif.starts_with(b"GIF89a\x02\x00\x02\x00\xf6"));
+        // White for 127
+        assert_eq!(&gif[13 + 127 * 3..13 + 128 * 3], &[254; 3]);
+        let controls: Vec<usize> = (0..gif.len() - 4).filter(|&i| &gif[i..i + 4] == b"\x21\xf9\x04\x00").collect();
+        assert_eq!(controls.len(), 2);
+        assert!(controls.iter().all(|&i| gif[i + 4..i + 6] == [5, 0]));
+        assert_eq!(gif.last(), Some(&0x3b));
+    }
+}
@@ -0,0 +1,90 @@
+//! `#include <name>` lines, which link in a library of macros found on a search path, so libraries shared between
+//! projects can live in one place. The search path is the `--include-dir` directories, then those in the `BF_PATH`
+//! environment variable, then the including file's own directory.
+
+use std::{env, error::Error, path::{Path, PathBuf}};
+use crate::error::MacroError;
+
+
+/// The environment variable with more directories to search, separated like `PATH`
+pub const ENV_VAR: &str = "BF_PATH";
+
+
+/// The names `code` includes, and the code with the `#include` lines turned into spaces so their `<` and `>`
+/// aren't instructions and offsets stay the same
+pub fn directives(code: &str) -> Result<(String, Vec<String>), Box<dyn Error>> {
+    let mut names = Vec::new();
+    let mut blanked = String::with_capacity(code.len());
+    for line in code.split_inclusive('\n') {
+        let Some(rest) = line.trim_start().strip_prefix("#include") else {
+            blanked += line;
+            continue;
+        };
+        match rest.trim().strip_prefix('<').and_then(|rest| rest.strip_suffix('>')) {
+            Some(name) if !name.trim().is_empty() => names.push(name.trim().to_string()),
+            _ => return Err(MacroError(format!("expected #include <name>, not {}", line.trim())).into()),
+        }
+        blanked.extend(line.chars().map(|c| if c == '\n' { "\n".to_string() } else { " ".repeat(c.len_utf8()) }));
+    }
+    Ok((blanked, names))
+}
+
+
+/// Where to look for the files included by `including`
+pub fn search_path(include_dirs: &[String], including: impl AsRef<Path>) -> Vec<PathBuf> {
+    let mut path: Vec<PathBuf> = include_dirs.iter().map(PathBuf::from).collect();
+    if let Some(dirs) = env::var_os(ENV_VAR) {
+        path.extend(env::split_paths(&dirs).filter(|dir| !dir.as_os_str().is_empty()));
+    }
+    let dir = including.as_ref().parent().unwrap_or(Path::new("")).to_path_buf();
+    path.push(if dir.as_os_str().is_empty() { PathBuf::from(".") } else { dir });
+    path
+}
+
+
+/// The first file called `name`, or `name.bf` or `name.b`, in the search path
+pub fn resolve(name: &str, search_path: &[PathBuf]) -> Result<PathBuf, Box<dyn Error>> {
+    let candidates = [name.to_string(), format!("{}.bf", name), format!("{}.b", name)];
+    search_path.iter()
+        .flat_map(|dir| candidates.iter().map(move |candidate| dir.join(candidate)))
+        .find(|path| path.is_file())
+        .ok_or_else(|| {
+            let dirs: Vec<String> = search_path.iter().map(|dir| dir.display().to_string()).collect();
+            MacroError(format!("can't find <{}> in {} (add directories with --include-dir or {})", name, dirs.join(", "), ENV_VAR)).into()
+        })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn blanks_directives() {
+        let code = "#include <math>\n  #include <io.bf>\n+.";
+        let (blanked, names) = directives(code).unwrap();
+        assert_eq!(names, ["math", "io.bf"]);
+        assert_eq!(blanked.len(), code.len());
+        assert_eq!(blanked.trim(), "+.");
+        assert_eq!(directives("#include math").unwrap_err().to_string(), "expected #include <name>, not #include math");
+    }
+
+    #[test]
+    fn searches_in_order() {
+        let dir = env::temp_dir().join(format!("bf-rs-include-{}", std::process::id()));
+        let (first, second) = (dir.join("first"), dir.join("second"));
+        fs::create_dir_all(&first).unwrap();
+        fs::create_dir_all(&second).unwrap();
+        fs::write(first.join("util.b"), "").unwrap();
+        fs::write(second.join("util.bf"), "").unwrap();
+        fs::write(second.join("other.bf"), "").unwrap();
+
+        let path = search_path(&[first.display().to_string(), second.display().to_string()], "main.bf");
+        assert_eq!(path.last(), Some(&PathBuf::from(".")));
+        assert_eq!(resolve("util", &path).unwrap(), first.join("util.b"));
+        assert_eq!(resolve("other", &path).unwrap(), second.join("other.bf"));
+        assert!(resolve("missing", &path).unwrap_err().to_string().starts_with("can't find <missing> in "));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,339 @@
+//! Ways to feed `,` other than reading a stream until it has a byte
+
+use std::{collections::VecDeque, io::{self, Read, Write}, sync::mpsc::{self, Receiver, TryRecvError}, thread, time::Duration};
+
+
+/// Input that never blocks: `,` reads a sentinel value when no byte is ready yet.
+/// A thread reads the wrapped stream in the background, and the end of it still reads as 0.
+pub struct Polled {
+    receiver: Receiver<Vec<u8>>,
+    chunk: Vec<u8>,
+    position: usize,
+    sentinel: u8,
+}
+
+
+impl Polled {
+    pub fn new(mut reader: impl Read + Send + 'static, sentinel: u8) -> io::Result<Polled> {
+        let (sender, receiver) = mpsc::channel();
+        thread::Builder::new().name("polled input".to_string()).spawn(move || {
+            let mut buffer = [0; 1024];
+            // Stops at the end of input, on errors, or once nothing is polling any more
+            while let Ok(count @ 1..) = reader.read(&mut buffer) {
+                if sender.send(buffer[..count].to_vec()).is_err() {
+                    break;
+                }
+            }
+        })?;
+        Ok(Polled { receiver, chunk: Vec::new(), position: 0, sentinel })
+    }
+}
+
+
+impl Read for Polled {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        if buffer.is_empty() {
+            return Ok(0);
+        }
+        if self.position == self.chunk.len() {
+            match self.receiver.try_recv() {
+                Ok(chunk) => (self.chunk, self.position) = (chunk, 0),
+                Err(TryRecvError::Empty) => {
+                    buffer[0] = self.sentinel;
+                    return Ok(1);
+                },
+                Err(TryRecvError::Disconnected) => return Ok(0),
+            }
+        }
+        let count = buffer.len().min(self.chunk.len() - self.position);
+        buffer[..count].copy_from_slice(&self.chunk[self.position..self.position + count]);
+        self.position += count;
+        Ok(count)
+    }
+}
+
+
+/// Input that shows itself on stderr: a prompt before each line is read, and each byte read if `echo` is set,
+/// so a run with input from a file or raw keys reads like a terminal session. Stdout is left to the program.
+pub struct Shown<R> {
+    reader: R,
+    prompt: Option<String>,
+    echo: bool,
+    /// Whether the next byte starts a line, so the prompt is due
+    line_start: bool,
+}
+
+
+impl<R: Read> Shown<R> {
+    pub fn new(reader: R, prompt: Option<String>, echo: bool) -> Shown<R> {
+        Shown { reader, prompt, echo, line_start: true }
+    }
+}
+
+
+impl<R: Read> Read for Shown<R> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let mut stderr = io::stderr();
+        if let (Some(prompt), true) = (&self.prompt, self.line_start) {
+            stderr.write_all(prompt.as_bytes())?;
+            stderr.flush()?;
+        }
+        let count = self.reader.read(buffer)?;
+        if let Some(&last) = buffer[..count].last() {
+            self.line_start = last == b'\n';
+        }
+        if self.echo {
+            stderr.write_all(&buffer[..count])?;
+        }
+        Ok(count)
+    }
+}
+
+
+/// The terminal, even with stdin redirected, where there is one; otherwise stdin
+pub fn terminal() -> Box<dyn Read + Send> {
+    #[cfg(unix)]
+    if let Ok(tty) = std::fs::File::open("/dev/tty") {
+        return Box::new(tty);
+    }
+    Box::new(io::stdin())
+}
+
+
+/// A line of an input script
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step {
+    /// `send "text"`, with `\n`, `\t`, `\r`, `\0`, `\\`, `\"` and `\xNN` escapes
+    Send(Vec<u8>),
+    /// `wait 100ms` or `wait 2s`
+    Wait(Duration),
+    /// `send-eof`, ending the input
+    Eof,
+}
+
+
+/// The steps of an input script, a command a line, skipping blank lines and `#` comments
+pub fn parse_script(script: &str) -> Result<Vec<Step>, String> {
+    let mut steps = Vec::new();
+    for (i, line) in script.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if steps.last() == Some(&Step::Eof) {
+            return Err(format!("line {}: the input has ended, so nothing can come after send-eof", i + 1));
+        }
+        let (command, argument) = line.split_once(char::is_whitespace).map_or((line, ""), |(command, argument)| (command, argument.trim()));
+        let step = match command {
+            "send" => text(argument).map(Step::Send),
+            "wait" => duration(argument).map(Step::Wait),
+            "send-eof" if argument.is_empty() => Ok(Step::Eof),
+            _ => Err(format!("expected send \"text\", wait 100ms or send-eof, not {}", line)),
+        };
+        steps.push(step.map_err(|error| format!("line {}: {}", i + 1, error))?);
+    }
+    Ok(steps)
+}
+
+
+/// The bytes of a quoted string, with escapes
+fn text(quoted: &str) -> Result<Vec<u8>, String> {
+    let Some(inside) = quoted.strip_prefix('"').and_then(|rest| rest.strip_suffix('"')).filter(|_| quoted.len() >= 2) else {
+        return Err(format!("expected text in double quotes, not {}", quoted));
+    };
+    let mut bytes = Vec::new();
+    let mut chars = inside.chars();
+    while let Some(c) = chars.next() {
+        let c = match c {
+            '\\' => match chars.next() {
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some('r') => '\r',
+                Some('0') => '\0',
+                Some(c @ ('\\' | '"')) => c,
+                Some('x') => {
+                    // The byte itself, not a character, since from \x80 up that would be two bytes of UTF-8
+                    let hex: String = chars.by_ref().take(2).collect();
+                    let byte = u8::from_str_radix(&hex, 16).ok().filter(|_| hex.len() == 2)
+                        .ok_or_else(|| format!("\\x needs two hex digits, not {}", hex))?;
+                    bytes.push(byte);
+                    continue;
+                },
+                other => return Err(format!("unknown escape \\{}", other.map(String::from).unwrap_or_default())),
+            },
+            '"' => return Err("a \" in the text needs escaping as \\\"".to_string()),
+            c => c,
+        };
+        let mut buffer = [0; 4];
+        bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+    }
+    Ok(bytes)
+}
+
+
+/// `100ms` or `2s`, or a fraction like `0.5s`
+fn duration(text: &str) -> Result<Duration, String> {
+    let (number, unit) = match text.strip_suffix("ms") {
+        Some(number) => (number, 0.001),
+        None => (text.strip_suffix('s').unwrap_or("x"), 1.0),
+    };
+    number.trim().parse::<f64>().ok().filter(|seconds| seconds.is_finite() && *seconds >= 0.0)
+        .map(|number| Duration::from_secs_f64(number * unit))
+        .ok_or_else(|| format!("expected a time like 100ms or 2s, not {}", text))
+}
+
+
+/// Input from a script: what it sends, after its waits. A wait starts once the program reads past what was sent
+/// before it, or straight away with --poll-input, which reads ahead.
+pub struct Scripted {
+    steps: VecDeque<Step>,
+    sending: Vec<u8>,
+    position: usize,
+}
+
+
+impl Scripted {
+    pub fn new(steps: Vec<Step>) -> Scripted {
+        Scripted { steps: steps.into(), sending: Vec::new(), position: 0 }
+    }
+}
+
+
+impl Read for Scripted {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        if buffer.is_empty() {
+            return Ok(0);
+        }
+        while self.position == self.sending.len() {
+            match self.steps.pop_front() {
+                Some(Step::Send(bytes)) => (self.sending, self.position) = (bytes, 0),
+                Some(Step::Wait(duration)) => thread::sleep(duration),
+                Some(Step::Eof) | None => return Ok(0),
+            }
+        }
+        let count = buffer.len().min(self.sending.len() - self.position);
+        buffer[..count].copy_from_slice(&self.sending[self.position..self.position + count]);
+        self.position += count;
+        Ok(count)
+    }
+}
+
+
+/// Input from single keypresses, without waiting for Enter or echoing them. The terminal is only in raw mode
+/// while waiting for a key, so output looks the same as without it.
+/// Ctrl-D ends the input, and Ctrl-C stops the program, since raw mode keeps it from interrupting.
+#[cfg(not(target_family = "wasm"))]
+pub struct RawKeys;
+
+
+#[cfg(not(target_family = "wasm"))]
+impl RawKeys {
+    /// The byte a key gives, if it has one
+    fn byte(key: crossterm::event::KeyEvent) -> Option<u8> {
+        use crossterm::event::{KeyCode, KeyModifiers};
+        match key.code {
+            KeyCode::Char(c) if c.is_ascii() && key.modifiers.contains(KeyModifiers::CONTROL) => Some(c as u8 & 0x1f),
+            KeyCode::Char(c) if c.is_ascii() => Some(c as u8),
+            KeyCode::Enter => Some(b'\n'),
+            KeyCode::Tab => Some(b'\t'),
+            KeyCode::Backspace => Some(127),
+            KeyCode::Esc => Some(27),
+            _ => None,
+        }
+    }
+
+    fn key() -> io::Result<u8> {
+        use crossterm::event::{self, Event, KeyEventKind};
+        loop {
+            if let Event::Key(key) = event::read()? {
+                if key.kind != KeyEventKind::Release {
+                    if let Some(byte) = RawKeys::byte(key) {
+                        return Ok(byte);
+                    }
+                }
+            }
+        }
+    }
+}
+
+
+#[cfg(not(target_family = "wasm"))]
+impl Read for RawKeys {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        if buffer.is_empty() {
+            return Ok(0);
+        }
+        crossterm::terminal::enable_raw_mode()?;
+        let key = RawKeys::key();
+        crossterm::terminal::disable_raw_mode()?;
+
+        match key? {
+            // Ctrl-D
+            4 => Ok(0),
+            // Ctrl-C
+            3 => Err(io::Error::new(io::ErrorKind::Interrupted, "interrupted")),
+            byte => {
+                buffer[0] = byte;
+                Ok(1)
+            },
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn sentinel_until_ready() {
+        let (reader, mut writer) = io::pipe().unwrap();
+        let mut polled = Polled::new(reader, 127).unwrap();
+        let mut byte = [0];
+        assert_eq!((polled.read(&mut byte).unwrap(), byte[0]), (1, 127));
+
+        writer.write_all(b"a").unwrap();
+        drop(writer);
+        // However long the reader thread takes to pass the byte on
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while (polled.read(&mut byte).unwrap(), byte[0]) == (1, 127) {
+            assert!(Instant::now() < deadline, "the byte written never came");
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(byte[0], b'a');
+        assert_eq!(polled.read(&mut byte).unwrap(), 0);
+    }
+
+    #[test]
+    fn scripts() {
+        let script = "# a comment\nsend \"ab\\n\"\n\nwait 20ms\nsend \"\\x41\\\"\"\nsend-eof\n";
+        let steps = parse_script(script).unwrap();
+        assert_eq!(steps, [Step::Send(b"ab\n".to_vec()), Step::Wait(Duration::from_millis(20)), Step::Send(b"A\"".to_vec()), Step::Eof]);
+
+        let start = Instant::now();
+        let mut read = Vec::new();
+        Scripted::new(steps).read_to_end(&mut read).unwrap();
+        assert_eq!(read, b"ab\nA\"");
+        assert!(start.elapsed() >= Duration::from_millis(20));
+
+        assert_eq!(parse_script("wait 1.5s").unwrap(), [Step::Wait(Duration::from_millis(1500))]);
+        assert_eq!(parse_script("send \"\\x80\\xffé\"").unwrap(), [Step::Send(b"\x80\xff\xc3\xa9".to_vec())]);
+        assert_eq!(parse_script("send-eof\nsend \"a\"").unwrap_err(), "line 2: the input has ended, so nothing can come after send-eof");
+        assert!(parse_script("send abc").unwrap_err().starts_with("line 1: expected text in double quotes"));
+        assert!(parse_script("wait soon").is_err());
+        assert!(parse_script("send \"\\q\"").is_err());
+        assert!(parse_script("type \"a\"").is_err());
+    }
+
+    #[test]
+    fn prompt_at_line_starts() {
+        let mut shown = Shown::new(&b"a\nb"[..], Some("> ".to_string()), false);
+        let mut byte = [0];
+        let mut line_starts = Vec::new();
+        while shown.read(&mut byte).unwrap() == 1 {
+            line_starts.push(shown.line_start);
+        }
+        assert_eq!(line_starts, vec![false, true, false]);
+    }
+}
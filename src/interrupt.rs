@@ -0,0 +1,30 @@
+//! Stopping a running program at the next op when Ctrl-C is pressed, to see where it was
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+
+/// Make Ctrl-C stop `run_program` at the next op. A second Ctrl-C exits straight away,
+/// for programs waiting on input.
+#[cfg(not(target_family = "wasm"))]
+pub fn install() -> Result<(), ctrlc::Error> {
+    ctrlc::set_handler(|| {
+        if request() {
+            std::process::exit(crate::error::INTERRUPTED.into());
+        }
+    })
+}
+
+
+/// Ask the running program to stop, returning whether it had already been asked
+pub fn request() -> bool {
+    REQUESTED.swap(true, Ordering::Relaxed)
+}
+
+
+/// Whether the program was asked to stop, clearing the request
+pub fn take() -> bool {
+    REQUESTED.swap(false, Ordering::Relaxed)
+}
@@ -0,0 +1,324 @@
+use std::fmt;
+use crate::Instruction;
+
+
+/// An instruction in the compiled program. Runs of instructions and common loops are folded into single ops.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub enum Op {
+    /// Add to the current cell, wrapping at 128
+    Add(u8),
+    /// Move the pointer right
+    Right(usize),
+    /// Move the pointer left, stopping at the start of the tape
+    Left(usize),
+    /// Set the current cell to 0, from `[-]` and `[+]`
+    Clear,
+    /// Add the current cell times `factor` to the cell `offset` to the right, from loops like `[->++<]`.
+    /// Always followed by a `Clear` of the current cell.
+    MulAdd { offset: usize, factor: u8 },
+    /// `[`, jumps past the matching `JumpIfNonZero` at the given index if the current cell is 0
+    JumpIfZero(usize),
+    /// `]`, jumps back past the matching `JumpIfZero` at the given index if the current cell is not 0
+    JumpIfNonZero(usize),
+    Input,
+    Output,
+    Break,
+    /// pbrain `(`, with the index of the matching `ProcedureClose`
+    ProcedureOpen(usize),
+    ProcedureClose,
+    Call,
+    NextTape,
+    CopyToNextTape,
+    Push,
+    Pop,
+}
+
+
+impl fmt::Display for Op {
+    /// Ops that came from a single instruction are shown as that instruction
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Op::Add(1) => write!(f, "+"),
+            Op::Add(127) => write!(f, "-"),
+            Op::Add(n) => write!(f, "+{}", n),
+            Op::Right(1) => write!(f, ">"),
+            Op::Right(n) => write!(f, ">{}", n),
+            Op::Left(1) => write!(f, "<"),
+            Op::Left(n) => write!(f, "<{}", n),
+            Op::Clear => write!(f, "[-]"),
+            Op::MulAdd { offset, factor } => write!(f, "*>{}x{}", offset, factor),
+            Op::JumpIfZero(_) => write!(f, "["),
+            Op::JumpIfNonZero(_) => write!(f, "]"),
+            Op::Input => write!(f, ","),
+            Op::Output => write!(f, "."),
+            Op::Break => write!(f, "@"),
+            Op::ProcedureOpen(_) => write!(f, "("),
+            Op::ProcedureClose => write!(f, ")"),
+            Op::Call => write!(f, ":"),
+            Op::NextTape => write!(f, "^"),
+            Op::CopyToNextTape => write!(f, "&"),
+            Op::Push => write!(f, "$"),
+            Op::Pop => write!(f, "%"),
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub struct Program {
+    pub ops: Vec<Op>,
+    /// Source offset of the first instruction each op came from, empty if unknown
+    pub offsets: Vec<usize>,
+}
+
+
+impl Program {
+    /// Compile parsed instructions, folding runs and simple loops if `optimize` is set
+    pub fn compile(instructions: &[Instruction], optimize: bool) -> Result<Program, &'static str> {
+        let instructions: Vec<(Instruction, usize)> = instructions.iter().map(|&instruction| (instruction, 0)).collect();
+        let mut program = Program::compile_with_offsets(&instructions, optimize)?;
+        program.offsets.clear();
+        Ok(program)
+    }
+
+    /// Compile instructions along with their source offsets, from `parse_with_offsets`
+    pub fn compile_with_offsets(instructions: &[(Instruction, usize)], optimize: bool) -> Result<Program, &'static str> {
+        let (instructions, source_offsets): (Vec<Instruction>, Vec<usize>) = instructions.iter().copied().unzip();
+        let mut ops: Vec<Op> = Vec::new();
+        let mut offsets: Vec<usize> = Vec::new();
+        let mut folded_loops = 0;
+        let mut i = 0;
+
+        while i < instructions.len() {
+            let instruction = instructions[i];
+
+            if optimize {
+                if instruction == Instruction::Open {
+                    if let Some((loop_ops, length)) = fold_loop(&instructions[i..]) {
+                        offsets.extend(loop_ops.iter().map(|_| source_offsets[i]));
+                        ops.extend(loop_ops);
+                        folded_loops += 1;
+                        i += length;
+                        continue;
+                    }
+                }
+
+                let run_length = instructions[i..].iter().take_while(|x| **x == instruction).count();
+                let folded = match instruction {
+                    Instruction::Increment => Some(Op::Add((run_length % 128) as u8)),
+                    Instruction::Decrement => Some(Op::Add(((128 - run_length % 128) % 128) as u8)),
+                    Instruction::Right => Some(Op::Right(run_length)),
+                    Instruction::Left => Some(Op::Left(run_length)),
+                    _ => None,
+                };
+                if let Some(op) = folded {
+                    ops.push(op);
+                    offsets.push(source_offsets[i]);
+                    i += run_length;
+                    continue;
+                }
+            }
+
+            ops.push(match instruction {
+                Instruction::Increment => Op::Add(1),
+                Instruction::Decrement => Op::Add(127),
+                Instruction::Left => Op::Left(1),
+                Instruction::Right => Op::Right(1),
+                // Jump targets are filled in once the whole program is compiled
+                Instruction::Open => Op::JumpIfZero(0),
+                Instruction::Close => Op::JumpIfNonZero(0),
+                Instruction::Input => Op::Input,
+                Instruction::Output => Op::Output,
+                Instruction::Break => Op::Break,
+                Instruction::ProcedureOpen => Op::ProcedureOpen(0),
+                Instruction::ProcedureClose => Op::ProcedureClose,
+                Instruction::Call => Op::Call,
+                Instruction::NextTape => Op::NextTape,
+                Instruction::CopyToNextTape => Op::CopyToNextTape,
+                Instruction::Push => Op::Push,
+                Instruction::Pop => Op::Pop,
+            });
+            offsets.push(source_offsets[i]);
+            i += 1;
+        }
+
+        link_jumps(&mut ops)?;
+        if optimize {
+            log::info!("optimized {} instructions into {} ops, folding {} loops", instructions.len(), ops.len(), folded_loops);
+        }
+        Ok(Program { ops, offsets })
+    }
+
+    /// Source offset of the op at `index`, if the program has them
+    pub fn offset(&self, index: usize) -> Option<usize> {
+        self.offsets.get(index).copied()
+    }
+}
+
+
+/// Try to fold a loop starting at `instructions[0]` into straight-line ops.
+/// Returns the ops and the number of instructions they replace.
+pub(crate) fn fold_loop(instructions: &[Instruction]) -> Option<(Vec<Op>, usize)> {
+    // Only loops made of + - < > can be folded
+    let body_length = instructions[1..].iter()
+        .take_while(|x| matches!(x, Instruction::Increment | Instruction::Decrement | Instruction::Left | Instruction::Right))
+        .count();
+    if instructions.get(body_length + 1) != Some(&Instruction::Close) {
+        return None;
+    }
+    let body = &instructions[1..(body_length + 1)];
+
+    // Net change to each cell, relative to the loop cell
+    let mut offset: isize = 0;
+    let mut changes: Vec<(usize, u8)> = Vec::new();
+    for instruction in body {
+        match instruction {
+            Instruction::Right => offset += 1,
+            Instruction::Left => {
+                offset -= 1;
+                // Going left of the loop cell could hit the start of the tape,
+                // where the pointer stops and the loop does something else entirely
+                if offset < 0 {
+                    return None;
+                }
+            },
+            Instruction::Increment | Instruction::Decrement => {
+                let amount = if *instruction == Instruction::Increment { 1 } else { 127 };
+                let offset = offset as usize;
+                match changes.iter_mut().find(|(x, _)| *x == offset) {
+                    Some((_, change)) => *change = (*change + amount) % 128,
+                    None => changes.push((offset, amount)),
+                }
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    // The pointer has to end up back where it started
+    if offset != 0 {
+        return None;
+    }
+
+    // And the loop cell has to go down by exactly one each time
+    let loop_change = changes.iter().find(|(x, _)| *x == 0).map(|(_, change)| *change);
+    if loop_change != Some(127) {
+        // `[+]` also clears, since cells wrap
+        return if body == [Instruction::Increment] {
+            Some((vec![Op::Clear], 3))
+        } else {
+            None
+        };
+    }
+
+    let mut ops: Vec<Op> = changes.iter()
+        .filter(|(offset, change)| *offset != 0 && *change != 0)
+        .map(|&(offset, factor)| Op::MulAdd { offset, factor })
+        .collect();
+    ops.push(Op::Clear);
+    Some((ops, body_length + 2))
+}
+
+
+fn link_jumps(ops: &mut [Op]) -> Result<(), &'static str> {
+    let mut open_brackets: Vec<usize> = Vec::new();
+    let mut open_procedures: Vec<usize> = Vec::new();
+
+    for i in 0..ops.len() {
+        match ops[i] {
+            Op::JumpIfZero(_) => open_brackets.push(i),
+            Op::JumpIfNonZero(_) => {
+                let Some(open) = open_brackets.pop() else {
+                    return Err("all brackets must have matching brackets");
+                };
+                ops[open] = Op::JumpIfZero(i);
+                ops[i] = Op::JumpIfNonZero(open);
+            },
+            Op::ProcedureOpen(_) => open_procedures.push(i),
+            Op::ProcedureClose => {
+                if let Some(open) = open_procedures.pop() {
+                    ops[open] = Op::ProcedureOpen(i);
+                }
+            },
+            _ => (),
+        }
+    }
+
+    if !open_brackets.is_empty() {
+        return Err("all brackets must have matching brackets");
+    }
+    if !open_procedures.is_empty() {
+        return Err("all procedures must be closed");
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse, ParseOptions};
+
+    fn compile(code: &str) -> Vec<Op> {
+        Program::compile(&parse(code, &ParseOptions::default()).unwrap(), true).unwrap().ops
+    }
+
+    #[cfg(feature = "serialize")]
+    #[test]
+    fn program_round_trips() {
+        let program = Program::compile_with_offsets(&crate::parse_with_offsets("+[->++<]", &ParseOptions::default()).unwrap(), true).unwrap();
+        let json = serde_json::to_string(&program).unwrap();
+        assert_eq!(serde_json::from_str::<Program>(&json).unwrap(), program);
+    }
+
+    #[test]
+    fn fold_runs() {
+        assert_eq!(compile("+++>>--<"), vec![Op::Add(3), Op::Right(2), Op::Add(126), Op::Left(1)]);
+    }
+
+    #[test]
+    fn fold_clear_loops() {
+        assert_eq!(compile("+[-]>[+]"), vec![Op::Add(1), Op::Clear, Op::Right(1), Op::Clear]);
+    }
+
+    #[test]
+    fn fold_multiply_loops() {
+        assert_eq!(
+            compile("[->++>+<<]"),
+            vec![Op::MulAdd { offset: 1, factor: 2 }, Op::MulAdd { offset: 2, factor: 1 }, Op::Clear]
+        );
+    }
+
+    #[test]
+    fn keep_loops_left_of_start() {
+        assert_eq!(
+            compile("[-<+>]"),
+            vec![Op::JumpIfZero(5), Op::Add(127), Op::Left(1), Op::Add(1), Op::Right(1), Op::JumpIfNonZero(0)]
+        );
+    }
+
+    #[test]
+    fn link_nested_jumps() {
+        assert_eq!(
+            compile("[.[,]]"),
+            vec![Op::JumpIfZero(5), Op::Output, Op::JumpIfZero(4), Op::Input, Op::JumpIfNonZero(2), Op::JumpIfNonZero(0)]
+        );
+    }
+
+    #[test]
+    fn keep_source_offsets() {
+        let instructions = crate::parse_with_offsets("a++ [-]>.", &ParseOptions::default()).unwrap();
+        let program = Program::compile_with_offsets(&instructions, true).unwrap();
+
+        assert_eq!(program.ops, vec![Op::Add(2), Op::Clear, Op::Right(1), Op::Output]);
+        assert_eq!(program.offsets, vec![1, 4, 7, 8]);
+    }
+
+    #[test]
+    fn unmatched_brackets() {
+        let options = ParseOptions::default();
+        assert!(Program::compile(&parse("[[]", &options).unwrap(), true).is_err());
+        assert!(Program::compile(&parse("[]]", &options).unwrap(), false).is_err());
+    }
+}
@@ -1,39 +1,91 @@
-use std::{error::Error, io::{self, Read, Write}, collections::HashMap};
-use clap::{Parser, ValueEnum};
-
-
-#[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
-pub struct Args {
-    pub filepath: String,
-
-    #[arg(short, long, value_enum, default_value_t=DebugMode::None)]
-    pub debug_mode: DebugMode,
-    
-    /// Enable breakpoints
-    #[arg(short='b', long)]
-    pub breakpoints: bool,
-
-    /// Enable macros
-    #[arg(short='m', long)]
-    pub macros: bool,
+use std::{borrow::Cow, error::Error, io::{self, IsTerminal, Read, Write}, collections::{HashMap, VecDeque}, time::Instant};
+use clap::ValueEnum;
+use serde::Deserialize;
+
+pub mod cli;
+pub mod dialect;
+pub mod vm;
+pub mod ir;
+pub mod emit;
+pub mod compile;
+pub mod bytecode;
+pub mod disasm;
+pub mod format;
+pub mod generate;
+pub mod check;
+pub mod analyze;
+pub mod lint;
+pub mod bounds;
+pub mod cfg;
+pub mod config;
+pub mod debug;
+pub mod testing;
+pub mod token;
+pub mod profile;
+pub mod coverage;
+pub mod error;
+pub mod input;
+pub mod reference;
+pub mod equiv;
+pub mod symbolic;
+pub mod interrupt;
+pub mod hang;
+pub mod image;
+pub mod audio;
+pub mod trace;
+pub mod snapshot;
+pub mod dashboard;
+pub mod progress;
+pub mod expansion;
+pub mod literate;
+pub mod framebuffer;
+pub mod bfc;
+pub mod link;
+pub mod include;
+pub mod golf;
+pub mod mutate;
+pub mod random;
+#[cfg(not(target_family = "wasm"))]
+pub mod lsp;
+#[cfg(not(target_family = "wasm"))]
+pub mod pipe;
+#[cfg(not(target_family = "wasm"))]
+pub mod batch;
+#[cfg(not(target_family = "wasm"))]
+pub mod serve;
+#[cfg(feature = "wasm")]
+pub mod browser;
+#[cfg(feature = "capi")]
+pub mod capi;
+#[cfg(feature = "python")]
+pub mod python;
+#[cfg(feature = "arbitrary")]
+pub mod fuzz;
+#[cfg(feature = "node")]
+pub mod node;
+pub use dialect::{Dialect, detect_dialect};
+pub use vm::{Eof, Vm, VmOptions, Tape};
+pub use ir::{Op, Program};
+pub use debug::{DebugMode, DebugFormat, DebugOptions};
+use error::MacroError;
+
+
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize))]
+#[serde(rename_all = "lowercase")]
+pub enum Extension {
+    /// `^` switches to the next tape, `&` copies the current cell to the next tape
+    Tapes,
+
+    /// `$` pushes the current cell onto a stack, `%` pops it back into the current cell
+    Stack,
 }
 
 
-#[derive(ValueEnum, Clone, Debug, PartialEq)]
-pub enum DebugMode {
-    None,
-    
-    /// Print memory and instructions
-    Verbose,
-    
-    /// Verbose, but pause at every instruction
-    Step,
-}
-
 
-
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
 pub enum Instruction {
     Increment,
     Decrement,
@@ -44,63 +96,224 @@ pub enum Instruction {
     Input,
     Output,
     Break,
+    /// pbrain `(`, starts a procedure definition
+    ProcedureOpen,
+    /// pbrain `)`, ends a procedure definition
+    ProcedureClose,
+    /// pbrain `:`, calls the procedure numbered by the current cell
+    Call,
+    /// Tapes extension `^`
+    NextTape,
+    /// Tapes extension `&`
+    CopyToNextTape,
+    /// Stack extension `$`
+    Push,
+    /// Stack extension `%`
+    Pop,
+}
+
+
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub struct ParseOptions {
+    pub dialect: Dialect,
+    pub breakpoints: bool,
+    pub macros: bool,
+    pub extensions: Vec<Extension>,
 }
 
 
 const VALID_CHARS: [char; 8]= ['[', ']', '<', '>', '+', '-', '.', ','];
+const PBRAIN_CHARS: [char; 3]= ['(', ')', ':'];
+const TAPES_CHARS: [char; 2]= ['^', '&'];
+const STACK_CHARS: [char; 2]= ['$', '%'];
+
+
+/// All characters that are instructions with these options (except breakpoints)
+pub(crate) fn instruction_chars(options: &ParseOptions) -> impl Iterator<Item = char> + '_ {
+    let pbrain = options.dialect == Dialect::Pbrain;
+    let tapes = options.extensions.contains(&Extension::Tapes);
+    let stack = options.extensions.contains(&Extension::Stack);
+
+    VALID_CHARS.iter()
+        .chain(PBRAIN_CHARS.iter().filter(move |_| pbrain))
+        .chain(TAPES_CHARS.iter().filter(move |_| tapes))
+        .chain(STACK_CHARS.iter().filter(move |_| stack))
+        .copied()
+}
+
+
+/// The private use character `source_text` puts in place of a byte that isn't part of a UTF-8 character. Only
+/// bytes from 0x80 up can be, and each gets its own character, so macro names that differ in one still differ.
+pub fn stray_byte(byte: u8) -> char {
+    char::from_u32(0xf700 + byte as u32).unwrap_or(char::REPLACEMENT_CHARACTER)
+}
 
 
-fn parse_string(code: &str, breakpoints: bool) -> Vec<Instruction> {
-    // Split at '@' so I can see whether they are macros or breakpoints
-    code.chars().filter_map({
-        |x| match x {
-            '+' => Some(Instruction::Increment),
-            '-' => Some(Instruction::Decrement),
-            '<' => Some(Instruction::Left),
-            '>' => Some(Instruction::Right),
-            '[' => Some(Instruction::Open),
-            ']' => Some(Instruction::Close),
-            ',' => Some(Instruction::Input),
-            '.' => Some(Instruction::Output),
-            '@' => if breakpoints { Some(Instruction::Break) } else { None },
-            _ => None,
+/// Whether `c` is one of the characters `stray_byte` gives
+pub fn is_stray_byte(c: char) -> bool {
+    ('\u{f780}'..='\u{f7ff}').contains(&c)
+}
+
+
+/// Source code from a file's bytes. Each byte that isn't part of a UTF-8 character becomes its `stray_byte`, a
+/// comment like any other character that isn't an instruction, so a file with stray bytes still runs, and lines and
+/// columns are those of the file. Excerpts show them as U+FFFD.
+pub fn source_text(bytes: Vec<u8>) -> String {
+    String::from_utf8(bytes).unwrap_or_else(|error| {
+        log::debug!("the source isn't valid UTF-8, reading the bytes that aren't as comments");
+        let bytes = error.into_bytes();
+        let mut text = String::with_capacity(bytes.len());
+        for chunk in bytes.utf8_chunks() {
+            text += chunk.valid();
+            text.extend(chunk.invalid().iter().map(|&byte| stray_byte(byte)));
         }
-    }).collect()
+        text
+    })
+}
+
+
+/// Parse a file's bytes, which don't have to be UTF-8, with offsets into them
+pub fn parse_bytes(code: &[u8], options: &ParseOptions) -> Result<Vec<(Instruction, usize)>, Box<dyn Error>> {
+    let text = source_text(code.to_vec());
+    // Each stray byte is 3 bytes of text, so offsets after one are 2 further along than in the file
+    let strays: Vec<usize> = text.char_indices().filter(|&(_, c)| is_stray_byte(c)).map(|(i, _)| i).collect();
+    let instructions = parse_with_offsets(&text, options)?;
+    Ok(instructions.into_iter().map(|(instruction, offset)| (instruction, offset - 2 * strays.partition_point(|&i| i < offset))).collect())
+}
+
+
+/// How long a `#!` first line is, without its newline, or 0 if there isn't one
+fn shebang_length(code: &str) -> usize {
+    match code.starts_with("#!") {
+        true => code.find('\n').unwrap_or(code.len()),
+        false => 0,
+    }
+}
+
+
+/// `code` with a `#!` first line, like `#!/usr/bin/env bf-rs` in an executable script, turned into spaces, so its
+/// `-` and `!` aren't code and offsets stay the same
+pub fn blank_shebang(code: &str) -> Cow<'_, str> {
+    match shebang_length(code) {
+        0 => Cow::Borrowed(code),
+        length => Cow::Owned(" ".repeat(length) + &code[length..]),
+    }
+}
+
+
+pub fn parse(code: &str, options: &ParseOptions) -> Result<Vec<Instruction>, Box<dyn Error>> {
+    Ok(parse_with_offsets(code, options)?.into_iter().map(|(instruction, _)| instruction).collect())
+}
+
+
+/// Split code at its first `!` into the program and its input, for programs like self-interpreters that carry
+/// their input after the code. The `!` of a `#!` line doesn't count.
+pub fn split_input(code: &str) -> (&str, &str) {
+    let start = shebang_length(code);
+    match code[start..].find('!') {
+        Some(i) => (&code[..start + i], &code[start + i + 1..]),
+        None => (code, ""),
+    }
 }
 
-pub fn parse_string_macros(code: &str, breakpoints: bool) -> Result<Vec<Instruction>, Box<dyn Error>> {
+
+/// Parse, keeping the byte offset into `code` that each instruction came from.
+/// Instructions from a macro have the offset of the macro call.
+pub fn parse_with_offsets(code: &str, options: &ParseOptions) -> Result<Vec<(Instruction, usize)>, Box<dyn Error>> {
+    let code = &*blank_shebang(code);
+    if options.dialect == Dialect::Ook {
+        let translated = dialect::translate_ook(code)?;
+        return Ok(translated.into_iter()
+            .filter_map(|(c, offset)| parse_char(c, options).map(|instruction| (instruction, offset)))
+            .collect());
+    }
+
+    if options.macros {
+        parse_macros_with_offsets(code, options)
+    } else {
+        Ok(parse_chars(code, 0, options).collect())
+    }
+}
+
+
+pub(crate) fn parse_char(c: char, options: &ParseOptions) -> Option<Instruction> {
+    let procedures = options.dialect == Dialect::Pbrain;
+    let tapes = options.extensions.contains(&Extension::Tapes);
+    let stack = options.extensions.contains(&Extension::Stack);
+
+    match c {
+        '+' => Some(Instruction::Increment),
+        '-' => Some(Instruction::Decrement),
+        '<' => Some(Instruction::Left),
+        '>' => Some(Instruction::Right),
+        '[' => Some(Instruction::Open),
+        ']' => Some(Instruction::Close),
+        ',' => Some(Instruction::Input),
+        '.' => Some(Instruction::Output),
+        '@' => if options.breakpoints { Some(Instruction::Break) } else { None },
+        '(' if procedures => Some(Instruction::ProcedureOpen),
+        ')' if procedures => Some(Instruction::ProcedureClose),
+        ':' if procedures => Some(Instruction::Call),
+        '^' if tapes => Some(Instruction::NextTape),
+        '&' if tapes => Some(Instruction::CopyToNextTape),
+        '$' if stack => Some(Instruction::Push),
+        '%' if stack => Some(Instruction::Pop),
+        _ => None,
+    }
+}
+
+
+/// Parse plain code, where `base_offset` is the offset of `code` in the whole source
+pub(crate) fn parse_chars<'a>(code: &'a str, base_offset: usize, options: &'a ParseOptions) -> impl Iterator<Item = (Instruction, usize)> + 'a {
+    code.char_indices().filter_map(move |(i, c)| parse_char(c, options).map(|instruction| (instruction, base_offset + i)))
+}
+
+
+pub fn parse_string_macros(code: &str, options: &ParseOptions) -> Result<Vec<Instruction>, Box<dyn Error>> {
+    Ok(parse_macros_with_offsets(code, options)?.into_iter().map(|(instruction, _)| instruction).collect())
+}
+
+/// Parts of code outside of macro definitions, with where each one starts
+pub(crate) type Segments = Vec<(usize, String)>;
+
+/// Split code into the parts outside of macro definitions, and the macro definitions keyed by `@name@`
+pub(crate) fn split_macros(code: &str, options: &ParseOptions) -> Result<(Segments, HashMap<String, String>), Box<dyn Error>> {
+    let code = &*blank_shebang(code);
     // Process brackets first
 
-    let mut split_string: Vec<String> = Vec::new();
+    // Code outside of macro definitions, with where it starts in `code`
+    let mut split_string: Vec<(usize, String)> = Vec::new();
     let mut remaining_string = code;
     let mut macro_strings: HashMap<String, String> = HashMap::new();
     
     while !remaining_string.is_empty() {
+        let offset = code.len() - remaining_string.len();
         match remaining_string.find('{') {
             Some(i) => {
-                split_string.push(remaining_string[..i].to_string());
+                split_string.push((offset, remaining_string[..i].to_string()));
                 remaining_string = &remaining_string[(i + 1)..];
                 
                 // Find closing bracket
                 let Some(close_index) = remaining_string.find('}') else {
-                    return Err("all curly brackets must be matched".into());
+                    return Err(MacroError("all curly brackets must be matched".to_string()).into());
                 };
                 let macro_string = &remaining_string[..close_index];
                 if macro_string.contains('{') {
-                    return Err("macros in macros are not allowed".into());
+                    return Err(MacroError("macros in macros are not allowed".to_string()).into());
                 }
                 
                 // Find macro name
-                let Some(macro_name) = split_string.last() else {
-                    return Err("macros must have a name".into());
+                let Some((_, macro_name)) = split_string.last() else {
+                    return Err(MacroError("macros must have a name".to_string()).into());
                 };
                 let Some(macro_name) = macro_name.split_whitespace().last() else {
-                    return Err("macros must have a name".into());
+                    return Err(MacroError("macros must have a name".to_string()).into());
                 };
                 
                 // If macro_name contains any instruction, error
-                if VALID_CHARS.iter().any(|c| macro_name.contains(*c)) {
-                    return Err("macro name cannot contain instructions".into());
+                if instruction_chars(options).any(|c| macro_name.contains(c)) {
+                    return Err(MacroError("macro name cannot contain instructions".to_string()).into());
                 }
                 
                 // @macro_name<space> so I can easily find and replace
@@ -109,18 +322,47 @@ pub fn parse_string_macros(code: &str, breakpoints: bool) -> Result<Vec<Instruct
             },
             None => {
                 if remaining_string.contains('}') {
-                    return Err("all curly brackets must be matched".into());
+                    return Err(MacroError("all curly brackets must be matched".to_string()).into());
                 }
-                split_string.push(remaining_string.to_string());
+                split_string.push((offset, remaining_string.to_string()));
                 break;
             }
         }
     }
-    
 
+    Ok((split_string, macro_strings))
+}
+
+
+/// The calls of every macro defined in `code` and `libraries`, like `@name@`
+pub(crate) fn macro_calls(libraries: &[&str], code: &str, options: &ParseOptions) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut calls = Vec::new();
+    for code in libraries.iter().chain([&code]) {
+        calls.extend(split_macros(code, options)?.1.into_keys());
+    }
+    Ok(calls)
+}
+
+
+fn parse_macros_with_offsets(code: &str, options: &ParseOptions) -> Result<Vec<(Instruction, usize)>, Box<dyn Error>> {
+    parse_with_libraries(&[], code, options)
+}
+
+
+/// Parse code with macros, which can also call the macros exported by `libraries`, linking them the way
+/// [`link::link`] does with the libraries named by their place in the list
+pub fn parse_with_libraries(libraries: &[&str], code: &str, options: &ParseOptions) -> Result<Vec<(Instruction, usize)>, Box<dyn Error>> {
+    let names: Vec<String> = (1..=libraries.len()).map(|i| format!("library {}", i)).collect();
+    let libraries: Vec<(&str, &str)> = names.iter().map(String::as_str).zip(libraries.iter().copied()).collect();
+    link::link(&libraries, code, options)
+}
+
+
+/// Swap the macro calls in `split_string` for the code of `macro_strings`, keyed by `@name@`
+pub(crate) fn expand(split_string: &Segments, macro_strings: HashMap<String, String>, options: &ParseOptions) -> Result<Vec<(Instruction, usize)>, Box<dyn Error>> {
     // Replace all macro calls with the macro code
     // First do the macros
-    let mut remaining_macros = macro_strings.clone();
+    let mut remaining_macros = macro_strings;
     let mut processed_macros: HashMap<String, String> = HashMap::new();
     while !remaining_macros.is_empty() {
         let macro_names: Vec<&String> = remaining_macros.keys().collect();
@@ -137,12 +379,13 @@ pub fn parse_string_macros(code: &str, breakpoints: bool) -> Result<Vec<Instruct
                 new_code = new_code.replace(macro_name2, macro_code2);
             }
             
+            log::debug!("macro {} expands to {} instructions", macro_name, parse_chars(&new_code, 0, options).count());
             processed_macros.insert(macro_name.to_string(), new_code);
             to_remove.push(macro_name.to_string())
         }
         
         if to_remove.is_empty() {
-            return Err("recursive macros are not allowed".into());
+            return Err(MacroError("recursive macros are not allowed".to_string()).into());
         }
         
         for macro_name in to_remove {
@@ -150,16 +393,32 @@ pub fn parse_string_macros(code: &str, breakpoints: bool) -> Result<Vec<Instruct
         }
     }
 
-    // Then do the non-macro code
-    for (macro_name, macro_string) in processed_macros {
-        for code_string in &mut split_string {
-            *code_string = code_string.replace(&macro_name, &macro_string);
+    // Then do the non-macro code, swapping calls for the macro code
+    let mut instructions: Vec<(Instruction, usize)> = Vec::new();
+    for (segment_offset, code_string) in split_string {
+        let mut i = 0;
+        while i < code_string.len() {
+            let rest = &code_string[i..];
+            let call = processed_macros.iter()
+                .filter(|(macro_name, _)| rest.starts_with(macro_name.as_str()))
+                .max_by_key(|(macro_name, _)| macro_name.len());
+
+            if let Some((macro_name, macro_string)) = call {
+                let call_offset = segment_offset + i;
+                log::trace!("expanded {} at offset {}", macro_name, call_offset);
+                instructions.extend(parse_chars(macro_string, 0, options).map(|(instruction, _)| (instruction, call_offset)));
+                i += macro_name.len();
+            } else {
+                let c = rest.chars().next().unwrap();
+                if let Some(instruction) = parse_char(c, options) {
+                    instructions.push((instruction, segment_offset + i));
+                }
+                i += c.len_utf8();
+            }
         }
     }
-    
 
-    // Parse all strings, and join them into one vec
-    Ok(split_string.iter().flat_map(|s| parse_string(s, breakpoints)).collect())
+    Ok(instructions)
 }
 
 
@@ -174,111 +433,191 @@ pub fn instruction_to_char(instruction: &Instruction) -> char {
         Instruction::Input => ',',
         Instruction::Output => '.',
         Instruction::Break => '@',
+        Instruction::ProcedureOpen => '(',
+        Instruction::ProcedureClose => ')',
+        Instruction::Call => ':',
+        Instruction::NextTape => '^',
+        Instruction::CopyToNextTape => '&',
+        Instruction::Push => '$',
+        Instruction::Pop => '%',
     }
 }
 
-pub fn run(code: &str, breakpoints: bool, macros: bool, debug_mode: DebugMode) -> Result<(), Box<dyn Error>> {
-    let instructions = {
-        if macros {
-            parse_string_macros(code, breakpoints)?
-        } else {
-            parse_string(code, breakpoints)
-        }
+pub fn run(code: &str, options: &ParseOptions, vm_options: &VmOptions, debug_mode: DebugMode) -> Result<(), Box<dyn Error>> {
+    let instructions = parse_with_offsets(code, options)?;
+    // Keep one op per instruction when printing every step
+    let program = Program::compile_with_offsets(&instructions, debug_mode == DebugMode::None)?;
+
+    let debug_options = DebugOptions { mode: debug_mode, ..DebugOptions::default() };
+    run_program(program, vm_options, &debug_options, &mut io::stdin(), &mut io::stdout())?;
+    Ok(())
+}
+
+
+/// Run a compiled program, reading `,` from `input` and writing `.` to `output`.
+/// Only the program writes to `output`: debug output goes to stderr, and pauses for debugging always read stdin.
+/// Returns the finished vm, to look at its memory.
+pub fn run_program(
+    program: Program,
+    vm_options: &VmOptions,
+    debug_options: &DebugOptions,
+    input: &mut impl Read,
+    output: &mut impl Write,
+) -> Result<Vm, Box<dyn Error>> {
+    let uses_stack = program.ops.iter().any(|op| matches!(op, Op::Push | Op::Pop));
+    let mut vm = Vm::new(program, vm_options);
+    let mut printer = debug::StepPrinter::new(debug_options, uses_stack);
+    let debugging = matches!(debug_options.mode, DebugMode::Verbose | DebugMode::Step);
+    let mut visualizer = match debug_options.mode {
+        DebugMode::Visualize { steps_per_frame, delay } => Some(debug::Visualizer::new(steps_per_frame, delay)),
+        _ => None,
     };
+    let mut snapshots = 0;
+    let mut frames = Vec::new();
+    let mut steps: usize = 0;
+    let mut tracer = debug_options.trace.as_ref().map(trace::Tracer::create).transpose()?;
+    let mut commands = debug_options.commands.then(debug::Commands::default);
+    let mut sync = debug_options.framebuffer.as_ref().map(|framebuffer| framebuffer.sync_value(&vm.tapes()[0]));
+    #[cfg(not(target_family = "wasm"))]
+    let mut monitor = debug_options.dashboard.as_ref().map(dashboard::Dashboard::start).transpose()?;
+    #[cfg(target_family = "wasm")]
+    if debug_options.dashboard.is_some() {
+        return Err("the dashboard needs threads, which WASI doesn't have".into());
+    }
 
-    // Location of the instruction pointer
-    let mut i: usize = 0;
-    // Location of the data pointer
-    let mut pointer: usize = 0;
-    // Using u8, but max will be 127
-    let mut data: Vec<u8> = vec![0];
-    
-    while i < instructions.len() {
-        let instruction = &instructions[i];
-        let mut is_break = false;
-
-        match instruction {
-            Instruction::Increment => {
-                if data[pointer] == 127 {
-                    data[pointer] = 0;
-                } else {
-                    data[pointer] += 1;
-                }
-            },
-            Instruction::Decrement => {
-                if data[pointer] == 0 {
-                    data[pointer] = 127;
-                } else {
-                    data[pointer] -= 1;
-                }
-            },
-            Instruction::Left => {
-                pointer = pointer.saturating_sub(1);
-            },
-            Instruction::Right => {
-                pointer += 1;
-                if pointer >= data.len() {
-                    data.push(0);
-                }
-            },
-            Instruction::Open => {
-                if data[pointer] == 0 {
-                    let remaining_instructions = &instructions[(i + 1)..];
-                    i = find_matching_bracket(remaining_instructions, true)? + i + 1;
-                }
-            },
-            Instruction::Close => {
-                if data[pointer] != 0 {
-                    let remaining_instructions = &instructions[..i];
-                    i = find_matching_bracket(remaining_instructions, false)?;
-                }
-            },
-            Instruction::Input => {
-                io::stdout().flush()?;
+    let mut reporter = debug_options.progress.as_ref().map(progress::Progress::start);
+    let mut timer = debug_options.op_times.then(profile::Timer::start);
+    let mut trail = VecDeque::with_capacity(error::TRAIL_LENGTH);
 
-                let mut input: [u8; 1] = [0];
-                io::stdin().read(&mut input).map_err(|_| "failed to read input")?;
-                if input[0] > 127 {
-                    return Err("input must be an ASCII character".into());
-                }
-                data[pointer] = input[0];
-            },
-            Instruction::Output => {
-                let output = data[pointer] as char;
-                print!("{}", output);
-                if debug_mode == DebugMode::Step || debug_mode == DebugMode::Verbose {
-                    println!();
-                }
-            },
-            Instruction::Break => {
-                is_break = true;
+    while let Some(op) = vm.current_op() {
+        let ip = vm.ip();
+        if interrupt::take() {
+            output.flush()?;
+            let offset = vm.program().offset(ip);
+            return Err(Box::new(error::Interrupted { ip, offset, location: None, tape: vm.tape().clone(), excerpt: None }));
+        }
+        let before = tracer.as_ref().map(|_| trace::Before::new(&vm));
+        let started = timer.as_ref().map(|_| Instant::now());
+        if let Err(error) = vm.step(input, output) {
+            // Keep what the program wrote before the error
+            output.flush()?;
+            return Err(failed(error, vm.program(), ip, &trail));
+        }
+        if let Some(mut hang) = vm.take_hang() {
+            output.flush()?;
+            match &debug_options.hang_warnings {
+                // Only pause where someone can see the warning and answer it
+                Some(warnings) if io::stderr().is_terminal() && log::log_enabled!(log::Level::Warn) => {
+                    warnings.locate(&mut hang);
+                    log::warn!("{}", hang);
+                    eprintln!("press a key to keep going, or Ctrl-C to stop");
+                    debug::pause()?;
+                },
+                _ => return Err(Box::new(hang)),
+            }
+        }
+        if let (Some(timer), Some(started)) = (&mut timer, started) {
+            timer.record(op, started);
+        }
+        if trail.len() == error::TRAIL_LENGTH {
+            trail.pop_front();
+        }
+        trail.push_back(ip);
+        if let (Some(tracer), Some(before)) = (&mut tracer, before) {
+            tracer.step(&vm, ip, op, before)?;
+        }
+        let is_break = op == Op::Break;
+        steps += 1;
+        #[cfg(not(target_family = "wasm"))]
+        if let Some(monitor) = &mut monitor {
+            monitor.step(&vm, steps);
+        }
+        if let Some(reporter) = &mut reporter {
+            reporter.step(&vm, steps);
+        }
+        if let (Some(framebuffer), Some(sync)) = (&debug_options.framebuffer, &mut sync) {
+            let value = framebuffer.sync_value(&vm.tapes()[0]);
+            if value != *sync {
+                *sync = value;
+                framebuffer.draw(&vm.tapes()[0])?;
+            }
+        }
+        if let Some(recording) = &debug_options.recording {
+            if steps.is_multiple_of(recording.every.max(1)) {
+                frames.push(vm.tape().data.clone());
+            }
+        }
+        if let Some(visualizer) = &mut visualizer {
+            visualizer.step(&vm, op)?;
+            if is_break {
+                visualizer.draw(&vm)?;
             }
         }
-        
 
-        if (debug_mode == DebugMode::Step || debug_mode == DebugMode::Verbose) && !is_break {
-            let mut output = instruction_to_char(instruction).to_string() + ":";
-            let mut pointer_position: usize = 0;
-            for (i, x) in data.iter().enumerate() {
-                output += " ";
-                if i == pointer {
-                    pointer_position = output.len();
-                }
-                output += &x.to_string();
+        if op == Op::Output && debugging && debug_options.format == DebugFormat::Text {
+            // Show the output before the debug line that follows it
+            output.flush()?;
+            eprintln!();
+        }
+
+        if debugging {
+            if is_break {
+                eprintln!("{}", printer.breakpoint(ip));
+            } else {
+                eprintln!("{}", printer.step(&vm, ip, op));
             }
-            println!("{}", output);
-            println!("{}^", " ".repeat(pointer_position));
         }
         
-        if debug_mode == DebugMode::Step || is_break {
-            io::stdin().read_line(&mut String::new())?;
+        if let (true, Some(image)) = (is_break, &debug_options.snapshots) {
+            snapshots += 1;
+            image.write_snapshot(vm.tape(), snapshots)?;
         }
+        if debug_options.mode == DebugMode::Step || is_break {
+            output.flush()?;
+            match &mut commands {
+                Some(commands) => commands.prompt(&vm)?,
+                None => debug::pause()?,
+            }
+        }
+    }
+    // Everything the program wrote comes out before what's drawn and reported on stderr
+    output.flush()?;
+    if let Some(visualizer) = &visualizer {
+        visualizer.draw(&vm)?;
+    }
+    if let Some(tracer) = &mut tracer {
+        tracer.finish()?;
+    }
+    if let Some(framebuffer) = &debug_options.framebuffer {
+        framebuffer.draw(&vm.tapes()[0])?;
+    }
+    #[cfg(not(target_family = "wasm"))]
+    if let Some(monitor) = &mut monitor {
+        monitor.finish(&vm, steps);
+    }
+    if let Some(reporter) = &mut reporter {
+        reporter.finish(&vm, steps);
+    }
+    if let Some(timer) = &timer {
+        eprint!("{}", profile::times_table(&timer.times()));
+    }
+    if let Some(recording) = &debug_options.recording {
+        // End on the finished tape
+        frames.push(vm.tape().data.clone());
+        recording.write(&frames)?;
+    }
+    Ok(vm)
+}
 
-        i += 1;
+
+/// Add the op at `ip` and the ones in `trail` before it to an error from running it, except I/O errors and hangs,
+/// which say where they are themselves
+fn failed(error: Box<dyn Error>, program: &Program, ip: usize, trail: &VecDeque<usize>) -> Box<dyn Error> {
+    if error.is::<io::Error>() || error.is::<error::Hang>() {
+        return error;
     }
-    
-    println!();
-    Ok(())
+    let ran = |ip: usize| error::Ran { ip, op: program.ops[ip], offset: program.offset(ip), location: None };
+    Box::new(error::Failed { error, at: ran(ip), trail: trail.iter().rev().map(|&ip| ran(ip)).collect(), excerpt: None })
 }
 
 
@@ -326,19 +665,42 @@ pub fn find_matching_bracket(instructions: &[Instruction], forwards: bool) -> Re
 mod tests {
     use super::*;
 
+    fn options(breakpoints: bool) -> ParseOptions {
+        ParseOptions { breakpoints, ..Default::default() }
+    }
+
     #[test]
     fn parse_string_test() {
         assert_eq!(
-            parse_string("a<+<c<]", true),
+            parse("a<+<c<]", &options(true)).unwrap(),
             vec![Instruction::Left, Instruction::Increment, Instruction::Left, Instruction::Left, Instruction::Close]
         );
     }
     
+    #[test]
+    fn input_after_bang() {
+        assert_eq!(split_input(">,[.,]!hi!\n"), (">,[.,]", "hi!\n"));
+        assert_eq!(split_input("+."), ("+.", ""));
+        assert_eq!(split_input("#!/usr/bin/env bf-rs --bang\n,[.,]!hi"), ("#!/usr/bin/env bf-rs --bang\n,[.,]", "hi"));
+    }
+
+    #[test]
+    fn skips_shebang() {
+        let script = "#!/usr/bin/env bf-rs -e+\n+[-].";
+        let expected = vec![(Instruction::Increment, 25), (Instruction::Open, 26), (Instruction::Decrement, 27), (Instruction::Close, 28), (Instruction::Output, 29)];
+        assert_eq!(parse_with_offsets(script, &options(false)).unwrap(), expected);
+        // Even where the line would have been a macro's name
+        let with_macro = "#!/usr/bin/env bf-rs\nplus { + }\n@plus@";
+        assert_eq!(parse(with_macro, &ParseOptions { macros: true, ..options(false) }).unwrap(), [Instruction::Increment]);
+        assert_eq!(blank_shebang(" #!+"), " #!+");
+        assert_eq!(blank_shebang("#!-"), "   ");
+    }
+
     #[test]
     fn forward_match() {
         assert_eq!(
             Ok(5),
-            find_matching_bracket(&parse_string("-[-]-]]--", true), true)
+            find_matching_bracket(&parse("-[-]-]]--", &options(true)).unwrap(), true)
         );
     }
     
@@ -346,7 +708,7 @@ mod tests {
     fn backward_match() {
         assert_eq!(
             Ok(3),
-            find_matching_bracket(&parse_string("--[[-[-]-", true), false)
+            find_matching_bracket(&parse("--[[-[-]-", &options(true)).unwrap(), false)
         );
     }
     
@@ -373,7 +735,7 @@ test {
             Input
         ];
         
-        assert_eq!(parse_string_macros(code, true).unwrap(), instructions)
+        assert_eq!(parse_string_macros(code, &options(true)).unwrap(), instructions)
     }
 
     #[test]
@@ -383,7 +745,7 @@ test {
         let code = "<>@+@abc++@ -";
         let instructions = vec![Left, Right, Break, Increment, Break, Increment, Increment, Break, Decrement];
 
-        assert_eq!(parse_string_macros(code, true).unwrap(), instructions);
+        assert_eq!(parse_string_macros(code, &options(true)).unwrap(), instructions);
     }
 
     #[test]
@@ -393,7 +755,7 @@ test {
         let code = "<>@+@abc++@ -";
         let instructions = vec![Left, Right, Increment, Increment, Increment, Decrement];
 
-        assert_eq!(parse_string_macros(code, false).unwrap(), instructions);
+        assert_eq!(parse_string_macros(code, &options(false)).unwrap(), instructions);
     }
 
     #[test]
@@ -415,8 +777,73 @@ b {
             Decrement,
         ];
 
-        // dbg!(parse_string_macros(code, true).unwrap());
+        // dbg!(parse_string_macros(code, &options(true)).unwrap());
 
-        assert_eq!(parse_string_macros(code, true).unwrap(), instructions)
+        assert_eq!(parse_string_macros(code, &options(true)).unwrap(), instructions)
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn library_macros() {
+        use Instruction::*;
+
+        let library = "inc { + } twice { @inc@@inc@ } comments are fine";
+
+        assert_eq!(
+            parse_with_libraries(&[library], "@twice@.", &options(false)).unwrap(),
+            vec![(Increment, 0), (Increment, 0), (Output, 7)]
+        );
+        assert_eq!(parse_with_libraries(&[library], "@inc@ inc { - }", &options(false)).unwrap(), vec![(Decrement, 0)]);
+        assert!(parse_with_libraries(&["+ inc { + }"], "@inc@", &options(false)).is_err());
+    }
+
+    #[test]
+    fn pbrain_parse() {
+        use Instruction::*;
+
+        let code = "+(-):";
+        let pbrain = ParseOptions { dialect: Dialect::Pbrain, ..Default::default() };
+
+        assert_eq!(parse(code, &pbrain).unwrap(), vec![Increment, ProcedureOpen, Decrement, ProcedureClose, Call]);
+        assert_eq!(parse(code, &options(false)).unwrap(), vec![Increment, Decrement]);
+    }
+
+    #[test]
+    fn ook_parse() {
+        use Instruction::*;
+
+        let ook = ParseOptions { dialect: Dialect::Ook, ..Default::default() };
+
+        assert_eq!(parse("Ook. Ook. Ook! Ook.", &ook).unwrap(), vec![Increment, Output]);
+    }
+
+    #[test]
+    fn output_flushed_on_error() {
+        let pbrain = ParseOptions { dialect: Dialect::Pbrain, ..Default::default() };
+        // Calling a procedure that was never defined
+        let program = Program::compile(&parse("+.:", &pbrain).unwrap(), true).unwrap();
+        let mut output = io::BufWriter::new(Vec::new());
+        let result = run_program(program, &VmOptions::default(), &DebugOptions::default(), &mut io::empty(), &mut output);
+        let error = result.unwrap_err();
+        assert_eq!(output.get_ref(), &[1]);
+
+        let failed = error.downcast_ref::<error::Failed>().unwrap();
+        assert_eq!((failed.at.ip, failed.at.op), (2, Op::Call));
+        assert_eq!(failed.trail.iter().map(|ran| ran.ip).collect::<Vec<_>>(), [1, 0]);
+    }
+
+    #[test]
+    fn stray_bytes_are_comments() {
+        let source = source_text(b"+\xff\xfe+.".to_vec());
+        assert_eq!(parse(&source, &options(false)).unwrap(), vec![Instruction::Increment, Instruction::Increment, Instruction::Output]);
+        // Offsets stay those of the bytes, whatever the bytes are
+        let code = b"\xe9t\xe9 \xf0\x9f\x98\x80 +\xff.\xc3";
+        assert_eq!(source_text(code.to_vec()).chars().count(), code.len() - 3);
+        assert_eq!(parse_bytes(code, &options(false)).unwrap(), vec![(Instruction::Increment, 9), (Instruction::Output, 11)]);
+        // Names of macros can have them too, and they're told apart from each other and from a real DEL
+        let macros = ParseOptions { macros: true, ..options(false) };
+        assert_eq!(parse_bytes(b"a\xffb { + }\n@a\xffb@.", &macros).unwrap(), vec![(Instruction::Increment, 10), (Instruction::Output, 15)]);
+        let code = b"a\xffb { + }\na\xfeb { ++ }\nx\x7fy { +++ }\n@a\xffb@.";
+        assert_eq!(parse_bytes(code, &macros).unwrap(), vec![(Instruction::Increment, 33), (Instruction::Output, 38)]);
+        assert!(parse_bytes(b"x\x7fy { + }\n@x\xffy@", &macros).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,124 @@
+//! Linking libraries of macros into a program.
+//!
+//! A library marks the macros other files can call with `export` before their name, as in `export twice { ... }`,
+//! and the rest are its own, for its exported macros to call. A library without any `export` exports all of its
+//! macros. Exported names must be different across libraries, and the program's own macros replace theirs.
+
+use std::{collections::{HashMap, HashSet}, error::Error};
+use crate::{Instruction, ParseOptions, error::MacroError, expand, parse_chars, split_macros};
+
+
+/// The names a library exports, from the words before each macro's `{`
+fn exported(segments: &[(usize, String)], macros: &HashMap<String, String>) -> HashSet<String> {
+    let marked: HashSet<String> = segments.iter()
+        .filter_map(|(_, segment)| match segment.split_whitespace().rev().collect::<Vec<_>>()[..] {
+            [name, "export", ..] => Some(format!("@{}@", name)),
+            _ => None,
+        })
+        .collect();
+    match marked.is_empty() {
+        true => macros.keys().cloned().collect(),
+        false => marked,
+    }
+}
+
+
+/// Parse `code` with macros, linking in the macros exported by `libraries`, given as `(name, code)` for errors
+pub fn link(libraries: &[(&str, &str)], code: &str, options: &ParseOptions) -> Result<Vec<(Instruction, usize)>, Box<dyn Error>> {
+    // Every macro under the name it's called by: exported ones as `@name@`, the others as `@library/name@` so
+    // libraries can each have their own
+    let mut macro_strings: HashMap<String, String> = HashMap::new();
+    // The library each macro is in, by the name it's called by
+    let mut owners: HashMap<String, &str> = HashMap::new();
+    // Macros that aren't exported, and which library they're in
+    let mut private: Vec<(String, &str)> = Vec::new();
+    for (i, &(library, library_code)) in libraries.iter().enumerate() {
+        let (segments, macros) = split_macros(library_code, options)?;
+        if segments.iter().any(|(_, segment)| parse_chars(segment, 0, options).next().is_some()) {
+            return Err(MacroError(format!("{} can only define macros, code outside of them would never run", library)).into());
+        }
+        let exports = exported(&segments, &macros);
+        let own: Vec<String> = macros.keys().filter(|name| !exports.contains(*name)).cloned().collect();
+        let mangle = |name: &str| format!("@{}/{}", i, &name[1..]);
+        log::info!("linked {} of the {} macros in {}", exports.len(), macros.len(), library);
+
+        for (name, mut macro_code) in macros {
+            for call in &own {
+                macro_code = macro_code.replace(call.as_str(), &mangle(call));
+            }
+            if exports.contains(&name) {
+                if let Some(other) = owners.insert(name.clone(), library) {
+                    return Err(MacroError(format!("{} is exported by both {} and {}", name, other, library)).into());
+                }
+                macro_strings.insert(name, macro_code);
+            } else {
+                owners.insert(mangle(&name), library);
+                macro_strings.insert(mangle(&name), macro_code);
+                private.push((name, library));
+            }
+        }
+    }
+
+    let (split_string, macros) = split_macros(code, options)?;
+    macro_strings.extend(macros);
+
+    // Calls to another library's own macros would silently do nothing
+    for (name, library) in &private {
+        if macro_strings.contains_key(name) {
+            continue;
+        }
+        let caller = macro_strings.iter().find(|(_, macro_code)| macro_code.contains(name.as_str()));
+        if let Some(caller) = caller.and_then(|(key, _)| owners.get(key)) {
+            return Err(MacroError(format!("{} calls {}, which {} doesn't export", caller, name, library)).into());
+        }
+        if code.contains(name.as_str()) {
+            return Err(MacroError(format!("the program calls {}, which {} doesn't export", name, library)).into());
+        }
+    }
+
+    expand(&split_string, macro_strings, options)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Instruction::*;
+
+    fn options() -> ParseOptions {
+        ParseOptions { macros: true, ..Default::default() }
+    }
+
+    fn linked(libraries: &[(&str, &str)], code: &str) -> Result<String, String> {
+        link(libraries, code, &options())
+            .map(|instructions| instructions.iter().map(|(instruction, _)| crate::instruction_to_char(instruction)).collect())
+            .map_err(|error| error.to_string())
+    }
+
+    #[test]
+    fn exports() {
+        let math = "helper { + } export twice { @helper@@helper@ } export thrice { @twice@@helper@ }";
+        let io = "helper { . } export show { @helper@ }";
+        assert_eq!(linked(&[("math.bf", math), ("io.bf", io)], "@thrice@@show@"), Ok("+++.".to_string()));
+        assert_eq!(
+            linked(&[("math.bf", math), ("io.bf", io)], "@helper@"),
+            Err("the program calls @helper@, which math.bf doesn't export".to_string())
+        );
+        assert_eq!(
+            linked(&[("math.bf", math), ("io.bf", "export show { @helper@ }")], ""),
+            Err("io.bf calls @helper@, which math.bf doesn't export".to_string())
+        );
+        // The program can have a helper of its own
+        assert_eq!(linked(&[("math.bf", math)], "helper { - } @helper@@twice@"), Ok("-++".to_string()));
+    }
+
+    #[test]
+    fn collisions() {
+        assert_eq!(
+            linked(&[("a.bf", "inc { + }"), ("b.bf", "inc { ++ }")], "@inc@"),
+            Err("@inc@ is exported by both a.bf and b.bf".to_string())
+        );
+        assert_eq!(linked(&[("a.bf", "inc { + }"), ("b.bf", "export dec { - } inc { ++ }")], "@inc@@dec@"), Ok("+-".to_string()));
+        assert_eq!(link(&[("a.bf", "inc { + }")], "@inc@ inc { - }", &options()).unwrap(), vec![(Decrement, 0)]);
+    }
+}
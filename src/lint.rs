@@ -0,0 +1,149 @@
+//! Warnings about code that is allowed but probably not what was meant: empty loops, instructions that undo the one
+//! before them, and `@` characters that do nothing because breakpoints or macros are off.
+//!
+//! `check` prints these along with its errors, and `run --lint` before running. They never stop a program running.
+
+use std::ops::Range;
+use crate::{Dialect, Instruction, ParseOptions, analyze::{Finding, Severity}, blank_shebang, instruction_chars, instruction_to_char, macro_calls, parse_with_libraries, parse_with_offsets};
+
+
+/// Every warning for a program and the libraries whose macros it calls, in source order.
+/// Code that doesn't parse gets none, since `check` reports why.
+pub fn lint(libraries: &[&str], source: &str, options: &ParseOptions) -> Vec<Finding> {
+    let source = &*blank_shebang(source);
+    let parsed = match libraries.is_empty() {
+        true => parse_with_offsets(source, options),
+        false => parse_with_libraries(libraries, source, options),
+    };
+    let Ok(instructions) = parsed else {
+        return Vec::new();
+    };
+
+    let mut findings = pairs(source, &instructions);
+    if options.dialect != Dialect::Ook {
+        findings.extend(at_signs(libraries, source, options));
+    }
+    findings.sort_by_key(|finding| finding.span.start);
+    findings
+}
+
+
+fn warning(code: &'static str, message: String, span: Range<usize>) -> Finding {
+    Finding { severity: Severity::Warning, code, message, span }
+}
+
+
+/// Empty loops and instructions straight after the one they undo. Only instructions written out in the source count,
+/// not ones from macros, which are often combined in ways like `@inc@@dec@` that make sense on their own.
+fn pairs(source: &str, instructions: &[(Instruction, usize)]) -> Vec<Finding> {
+    let written = |(instruction, offset): (Instruction, usize)| {
+        let c = source[offset..].chars().next()?;
+        (instruction_to_char(&instruction) == c).then_some((instruction, offset))
+    };
+    let mut findings = Vec::new();
+    let mut i = 0;
+    while i + 1 < instructions.len() {
+        let (Some((first, start)), Some((second, end))) = (written(instructions[i]), written(instructions[i + 1])) else {
+            i += 1;
+            continue;
+        };
+        let span = start..end + 1;
+        let text = &source[start..start + 1];
+        let next = &source[end..end + 1];
+        let finding = match (first, second) {
+            (Instruction::Open, Instruction::Close) => Some(warning(
+                "empty-loop",
+                "this loop is empty, so it either does nothing or never ends".to_string(),
+                span,
+            )),
+            (Instruction::Increment, Instruction::Decrement) | (Instruction::Decrement, Instruction::Increment)
+            | (Instruction::Left, Instruction::Right) | (Instruction::Right, Instruction::Left) => Some(warning(
+                "cancelling",
+                format!("`{}` undoes the `{}` before it", next, text),
+                span,
+            )),
+            _ => None,
+        };
+        // A pair's second instruction can't start another pair
+        i += if finding.is_some() { 2 } else { 1 };
+        findings.extend(finding);
+    }
+    findings
+}
+
+
+/// `@name@` that isn't a call of a macro, and `@` that isn't a breakpoint
+fn at_signs(libraries: &[&str], source: &str, options: &ParseOptions) -> Vec<Finding> {
+    let calls = match options.macros {
+        true => macro_calls(libraries, source, options).unwrap_or_default(),
+        false => Vec::new(),
+    };
+    let name_char = |c: char| !c.is_whitespace() && c != '@' && !instruction_chars(options).any(|i| i == c);
+
+    let mut findings = Vec::new();
+    let mut rest = source;
+    while let Some(i) = rest.find('@') {
+        let start = source.len() - rest.len() + i;
+        let after = &rest[i + 1..];
+        let name_length = after.find(|c: char| !name_char(c)).unwrap_or(after.len());
+        if name_length > 0 && after[name_length..].starts_with('@') {
+            let call = &rest[i..i + name_length + 2];
+            let span = start..start + call.len();
+            if !options.macros {
+                let message = format!("`{}` looks like a macro call, but macros are off (-m)", call);
+                findings.push(warning("macros-off", message, span));
+            } else if !calls.iter().any(|defined| defined == call) {
+                let message = format!("`{}` looks like a macro call, but no macro is called `{}`", call, &call[1..call.len() - 1]);
+                findings.push(warning("undefined-macro", message, span));
+            }
+            rest = &rest[i + call.len()..];
+            continue;
+        }
+        if !options.breakpoints {
+            let message = "`@` is a breakpoint, but breakpoints are off (-b), so it does nothing".to_string();
+            findings.push(warning("breakpoints-off", message, start..start + 1));
+        }
+        rest = after;
+    }
+    findings
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn codes(code: &str, options: &ParseOptions) -> Vec<(&'static str, String)> {
+        lint(&[], code, options).into_iter().map(|finding| (finding.code, code[finding.span].to_string())).collect()
+    }
+
+    #[test]
+    fn empty_loops_and_cancelling_pairs() {
+        let options = ParseOptions::default();
+        assert_eq!(codes("+[]>+-<\n>\n<+-+", &options), vec![
+            ("empty-loop", "[]".to_string()),
+            ("cancelling", "+-".to_string()),
+            ("cancelling", "<\n>".to_string()),
+            ("cancelling", "+-".to_string()),
+        ]);
+        assert!(codes("+[-]>+<", &options).is_empty());
+    }
+
+    #[test]
+    fn macros_only_warn_where_written() {
+        let options = ParseOptions { macros: true, ..ParseOptions::default() };
+        assert!(codes("inc { + } dec { - } @inc@@dec@", &options).is_empty());
+        assert_eq!(codes("inc { + } @inc@ @nope@", &options), vec![("undefined-macro", "@nope@".to_string())]);
+    }
+
+    #[test]
+    fn at_signs_with_features_off() {
+        let code = "+@add@. mail me@example.com";
+        assert_eq!(codes(code, &ParseOptions::default()), vec![
+            ("macros-off", "@add@".to_string()),
+            ("breakpoints-off", "@".to_string()),
+        ]);
+        let options = ParseOptions { breakpoints: true, ..ParseOptions::default() };
+        assert_eq!(codes(code, &options), vec![("macros-off", "@add@".to_string())]);
+    }
+}
@@ -0,0 +1,115 @@
+//! Literate programs: Markdown files whose fenced ```` ```bf ```` blocks are the program, in order, and everything
+//! else is prose. The prose is blanked out rather than removed, so offsets, lines and columns in the code stay
+//! the same as in the Markdown file.
+
+use std::{error::Error, path::Path};
+
+
+/// Languages of the fenced blocks that are code
+const LANGUAGES: [&str; 3] = ["bf", "brainfuck", "b"];
+
+
+/// Whether a file is Markdown, going by its extension
+pub fn is_markdown(path: impl AsRef<Path>) -> bool {
+    path.as_ref().extension().is_some_and(|extension| extension == "md" || extension == "markdown")
+}
+
+
+/// The fence a line opens or closes a block with: its character and how many there are, and the info after it
+fn fence(line: &str) -> Option<(char, usize, &str)> {
+    let line = line.trim_start();
+    let c = line.chars().next().filter(|&c| c == '`' || c == '~')?;
+    let length = line.chars().take_while(|&x| x == c).count();
+    (length >= 3).then(|| (c, length, line[length..].trim()))
+}
+
+
+/// The markdown with everything but the code in its brainfuck blocks turned into spaces, keeping each line.
+/// A block's name is the word after its language, as in ```` ```bf hello ````. With `names`, only blocks with
+/// one of them are code, and each name must have a block.
+pub fn code(markdown: &str, names: &[String]) -> Result<String, Box<dyn Error>> {
+    let mut code = String::with_capacity(markdown.len());
+    let mut found = vec![false; names.len()];
+    // The fence of the block the line is in, and whether the block is code
+    let mut block: Option<(char, usize, bool)> = None;
+    for line in markdown.split_inclusive('\n') {
+        let keep = match (block, fence(line)) {
+            (None, Some((c, length, info))) => {
+                let mut words = info.split_whitespace();
+                let language = words.next().unwrap_or_default();
+                let name = words.next();
+                let named = match names.iter().position(|wanted| Some(wanted.as_str()) == name) {
+                    Some(i) => {
+                        found[i] = true;
+                        true
+                    },
+                    None => names.is_empty(),
+                };
+                block = Some((c, length, LANGUAGES.contains(&language) && named));
+                false
+            },
+            (Some((c, length, _)), Some((closing, closing_length, ""))) if closing == c && closing_length >= length => {
+                block = None;
+                false
+            },
+            (Some((_, _, keep)), _) => keep,
+            (None, None) => false,
+        };
+        if keep {
+            code += line;
+        } else {
+            // The same number of bytes, so offsets don't move
+            code.extend(line.chars().map(|c| if c == '\n' { "\n".to_string() } else { " ".repeat(c.len_utf8()) }));
+        }
+    }
+
+    if let Some(i) = found.iter().position(|&found| !found) {
+        return Err(format!("there's no ```bf block called {}", names[i]).into());
+    }
+    Ok(code)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TUTORIAL: &str = "\
+# Adding, with a [link]
+
+```bf
+++
+```
+
+Then a block in another language:
+~~~python
+print('+')
+~~~
+
+````brainfuck hello
+>+.
+```
+still code
+````
+";
+
+    #[test]
+    fn only_code_is_kept() {
+        let code = code(TUTORIAL, &[]).unwrap();
+        assert_eq!(code.len(), TUTORIAL.len());
+        assert_eq!(code.lines().filter(|line| !line.trim().is_empty()).collect::<Vec<_>>(), ["++", ">+.", "```", "still code"]);
+        assert_eq!(code.find('+'), TUTORIAL.find("++"));
+    }
+
+    #[test]
+    fn named_blocks() {
+        let code = code(TUTORIAL, &["hello".to_string()]).unwrap();
+        assert_eq!(code.split_whitespace().collect::<Vec<_>>(), [">+.", "```", "still", "code"]);
+        assert_eq!(
+            super::code(TUTORIAL, &["goodbye".to_string()]).unwrap_err().to_string(),
+            "there's no ```bf block called goodbye"
+        );
+        assert!(is_markdown("docs/tutorial.md"));
+        assert!(!is_markdown("hello.bf"));
+    }
+}
@@ -0,0 +1,325 @@
+//! A language server for `bf-rs lsp`, talking over stdin and stdout.
+//!
+//! It reports the same problems as `check`, shows where brackets match and what macros expand to on hover,
+//! goes to macro definitions and formats documents like `fmt`.
+
+use std::{collections::HashMap, error::Error, ops::Range};
+use lsp_server::{Connection, Message, Notification, Request, RequestId, Response};
+use lsp_types::{
+    notification::{DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, Notification as _, PublishDiagnostics},
+    request::{Formatting, GotoDefinition, HoverRequest, Request as _},
+    Diagnostic, DiagnosticSeverity, DocumentFormattingParams, GotoDefinitionParams, GotoDefinitionResponse, Hover,
+    HoverContents, HoverParams, HoverProviderCapability, Location, MarkupContent, MarkupKind, NumberOrString, OneOf, Position,
+    PublishDiagnosticsParams, ServerCapabilities, TextDocumentSyncCapability, TextDocumentSyncKind, TextEdit, Url,
+};
+use crate::{Instruction, ParseOptions, check, config::Config, detect_dialect, format::{self, Node}, instruction_to_char, parse_with_offsets};
+
+
+/// LSP positions count lines from 0 and columns in UTF-16 code units
+pub fn position(source: &str, offset: usize) -> Position {
+    let offset = offset.min(source.len());
+    let line_start = source[..offset].rfind('\n').map_or(0, |i| i + 1);
+    Position {
+        line: source[..line_start].matches('\n').count() as u32,
+        character: source[line_start..offset].encode_utf16().count() as u32,
+    }
+}
+
+
+/// The byte offset of a position, clamped to the end of its line
+pub fn offset(source: &str, position: Position) -> usize {
+    let line_start = if position.line == 0 {
+        0
+    } else {
+        source.match_indices('\n').nth(position.line as usize - 1).map_or(source.len(), |(i, _)| i + 1)
+    };
+
+    let mut units = 0;
+    for (i, c) in source[line_start..].char_indices() {
+        if units >= position.character as usize || c == '\n' {
+            return line_start + i;
+        }
+        units += c.len_utf16();
+    }
+    source.len()
+}
+
+
+fn range(source: &str, span: Range<usize>) -> lsp_types::Range {
+    lsp_types::Range { start: position(source, span.start), end: position(source, span.end) }
+}
+
+
+/// Problems with the program, the same ones `check` finds
+pub fn diagnostics(source: &str, options: &ParseOptions) -> Vec<Diagnostic> {
+    check::check(&[], source, options).into_iter().map(|diagnostic| {
+        // Errors without a place, like recursive macros, go at the start
+        let start = diagnostic.offset.unwrap_or(0);
+        let end = diagnostic.offset.map_or(0, |offset| offset + 1);
+        Diagnostic {
+            range: range(source, start..end),
+            severity: Some(DiagnosticSeverity::ERROR),
+            code: Some(NumberOrString::String(diagnostic.code.to_string())),
+            source: Some("bf-rs".to_string()),
+            message: diagnostic.message,
+            ..Diagnostic::default()
+        }
+    }).collect()
+}
+
+
+/// Every node in the tree, depth first
+fn walk<'a>(nodes: &'a [Node], found: &mut Vec<&'a Node>) {
+    for node in nodes {
+        found.push(node);
+        if let Node::Block { body, .. } | Node::MacroDefinition { body, .. } = node {
+            walk(body, found);
+        }
+    }
+}
+
+
+/// The name of a macro call, without its `@`s
+fn call_name(source: &str, span: &Range<usize>) -> String {
+    source[span.clone()].trim_matches('@').to_string()
+}
+
+
+/// Where a macro's name is, for each macro defined in the source
+fn definitions(source: &str, nodes: &[Node]) -> HashMap<String, Range<usize>> {
+    nodes.iter().filter_map(|node| match node {
+        Node::MacroDefinition { name, .. } => Some((source[name.clone()].to_string(), name.clone())),
+        _ => None,
+    }).collect()
+}
+
+
+/// Markdown about the bracket or macro call at `offset`
+pub fn hover(source: &str, options: &ParseOptions, at: usize) -> Option<String> {
+    let nodes = format::parse_tree(source, options).ok()?;
+    let mut found = Vec::new();
+    walk(&nodes, &mut found);
+
+    for node in found {
+        match node {
+            Node::Block { instruction, span, .. } if at == span.start || at + 1 == span.end => {
+                let (other, other_offset) = if at == span.start {
+                    (if *instruction == Instruction::Open { ']' } else { ')' }, span.end - 1)
+                } else {
+                    (instruction_to_char(instruction), span.start)
+                };
+                let place = position(source, other_offset);
+                return Some(format!("Matches `{}` on line {}, column {}", other, place.line + 1, place.character + 1));
+            },
+            Node::MacroCall(span) if span.contains(&at) => {
+                // Instructions from a macro have the offset of its call
+                let instructions = parse_with_offsets(source, options).ok()?;
+                let expansion: String = instructions.iter()
+                    .filter(|(_, offset)| *offset == span.start)
+                    .map(|(instruction, _)| instruction_to_char(instruction))
+                    .collect();
+                return Some(format!("Macro `{}` expands to:\n```brainfuck\n{}\n```", call_name(source, span), expansion));
+            },
+            _ => (),
+        }
+    }
+    None
+}
+
+
+/// Where the macro called at `offset` is defined
+pub fn definition(source: &str, options: &ParseOptions, at: usize) -> Option<Range<usize>> {
+    let nodes = format::parse_tree(source, options).ok()?;
+    let mut found = Vec::new();
+    walk(&nodes, &mut found);
+
+    let call = found.into_iter().find_map(|node| match node {
+        Node::MacroCall(span) if span.contains(&at) => Some(span),
+        _ => None,
+    })?;
+    definitions(source, &nodes).remove(&call_name(source, call))
+}
+
+
+/// Parse options for a document, from its `bf.toml` and file extension. Macros are always on,
+/// since editors have no flags to turn them on.
+fn options(uri: &Url) -> ParseOptions {
+    let path = uri.to_file_path().map(|path| path.display().to_string()).unwrap_or_else(|_| uri.path().to_string());
+    let config = Config::load(&path).unwrap_or_default();
+    ParseOptions {
+        dialect: config.dialect.unwrap_or_else(|| detect_dialect(&path)),
+        breakpoints: config.breakpoints.unwrap_or(true),
+        macros: config.macros.unwrap_or(true),
+        extensions: config.extensions.unwrap_or_default(),
+    }
+}
+
+
+struct Server {
+    connection: Connection,
+    /// Open documents by URI
+    documents: HashMap<Url, String>,
+}
+
+
+impl Server {
+    fn publish(&self, uri: &Url) -> Result<(), Box<dyn Error>> {
+        let diagnostics = match self.documents.get(uri) {
+            Some(source) => diagnostics(source, &options(uri)),
+            None => Vec::new(),
+        };
+        let params = PublishDiagnosticsParams { uri: uri.clone(), diagnostics, version: None };
+        self.connection.sender.send(Message::Notification(Notification::new(PublishDiagnostics::METHOD.to_string(), params)))?;
+        Ok(())
+    }
+
+    fn notification(&mut self, notification: Notification) -> Result<(), Box<dyn Error>> {
+        match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let params: lsp_types::DidOpenTextDocumentParams = serde_json::from_value(notification.params)?;
+                let uri = params.text_document.uri;
+                self.documents.insert(uri.clone(), params.text_document.text);
+                self.publish(&uri)
+            },
+            DidChangeTextDocument::METHOD => {
+                let params: lsp_types::DidChangeTextDocumentParams = serde_json::from_value(notification.params)?;
+                let uri = params.text_document.uri;
+                // Changes are always the whole document, since that's the only sync the server offers
+                if let Some(change) = params.content_changes.into_iter().last() {
+                    self.documents.insert(uri.clone(), change.text);
+                }
+                self.publish(&uri)
+            },
+            DidCloseTextDocument::METHOD => {
+                let params: lsp_types::DidCloseTextDocumentParams = serde_json::from_value(notification.params)?;
+                self.documents.remove(&params.text_document.uri);
+                self.publish(&params.text_document.uri)
+            },
+            _ => Ok(()),
+        }
+    }
+
+    /// The result of a request, null when there's nothing to show
+    fn request(&self, request: Request) -> Result<serde_json::Value, Box<dyn Error>> {
+        Ok(match request.method.as_str() {
+            HoverRequest::METHOD => {
+                let params: HoverParams = serde_json::from_value(request.params)?;
+                let document = params.text_document_position_params;
+                let Some(source) = self.documents.get(&document.text_document.uri) else {
+                    return Ok(serde_json::Value::Null);
+                };
+                let at = offset(source, document.position);
+                let hover = hover(source, &options(&document.text_document.uri), at).map(|value| Hover {
+                    contents: HoverContents::Markup(MarkupContent { kind: MarkupKind::Markdown, value }),
+                    range: None,
+                });
+                serde_json::to_value(hover)?
+            },
+            GotoDefinition::METHOD => {
+                let params: GotoDefinitionParams = serde_json::from_value(request.params)?;
+                let document = params.text_document_position_params;
+                let uri = document.text_document.uri;
+                let Some(source) = self.documents.get(&uri) else {
+                    return Ok(serde_json::Value::Null);
+                };
+                let span = definition(source, &options(&uri), offset(source, document.position));
+                let response = span.map(|span| GotoDefinitionResponse::Scalar(Location { uri: uri.clone(), range: range(source, span) }));
+                serde_json::to_value(response)?
+            },
+            Formatting::METHOD => {
+                let params: DocumentFormattingParams = serde_json::from_value(request.params)?;
+                let uri = params.text_document.uri;
+                let Some(source) = self.documents.get(&uri) else {
+                    return Ok(serde_json::Value::Null);
+                };
+                // Code that doesn't parse is left alone, the diagnostics already say why
+                let edits = format::format_source(source, &options(&uri)).ok().map(|formatted| {
+                    vec![TextEdit { range: range(source, 0..source.len()), new_text: formatted }]
+                });
+                serde_json::to_value(edits)?
+            },
+            _ => return Err(format!("unknown request {}", request.method).into()),
+        })
+    }
+
+    fn respond(&self, id: RequestId, result: Result<serde_json::Value, Box<dyn Error>>) -> Result<(), Box<dyn Error>> {
+        let response = match result {
+            Ok(value) => Response::new_ok(id, value),
+            Err(error) => Response::new_err(id, lsp_server::ErrorCode::InvalidRequest as i32, error.to_string()),
+        };
+        self.connection.sender.send(Message::Response(response))?;
+        Ok(())
+    }
+}
+
+
+/// Serve one client over stdin and stdout until it shuts the server down
+pub fn serve() -> Result<(), Box<dyn Error>> {
+    let (connection, io_threads) = Connection::stdio();
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        definition_provider: Some(OneOf::Left(true)),
+        document_formatting_provider: Some(OneOf::Left(true)),
+        ..ServerCapabilities::default()
+    };
+    connection.initialize(serde_json::to_value(capabilities)?)?;
+
+    let mut server = Server { connection, documents: HashMap::new() };
+    while let Ok(message) = server.connection.receiver.recv() {
+        match message {
+            Message::Request(request) => {
+                if server.connection.handle_shutdown(&request)? {
+                    break;
+                }
+                let id = request.id.clone();
+                let result = server.request(request);
+                server.respond(id, result)?;
+            },
+            Message::Notification(notification) => {
+                if let Err(error) = server.notification(notification) {
+                    log::warn!("{}", error);
+                }
+            },
+            Message::Response(_) => (),
+        }
+    }
+
+    drop(server);
+    io_threads.join()?;
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn macro_options() -> ParseOptions {
+        ParseOptions { macros: true, ..ParseOptions::default() }
+    }
+
+    #[test]
+    fn positions() {
+        let source = "ab\né+\n+";
+        assert_eq!(position(source, 6), Position { line: 1, character: 2 });
+        assert_eq!(offset(source, Position { line: 1, character: 1 }), 5);
+        assert_eq!(offset(source, Position { line: 2, character: 9 }), source.len());
+    }
+
+    #[test]
+    fn bracket_diagnostics() {
+        let diagnostics = diagnostics("+\n]", &ParseOptions::default());
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].range.start, Position { line: 1, character: 0 });
+    }
+
+    #[test]
+    fn hover_brackets_and_macros() {
+        let source = "two { ++ }\n[-@two@]";
+        assert_eq!(hover(source, &macro_options(), 11).unwrap(), "Matches `]` on line 2, column 8");
+        assert_eq!(hover(source, &macro_options(), 15).unwrap(), "Macro `two` expands to:\n```brainfuck\n++\n```");
+        assert_eq!(definition(source, &macro_options(), 15), Some(0..3));
+        assert_eq!(hover(source, &macro_options(), 12), None);
+    }
+}
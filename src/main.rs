@@ -1,17 +1,1031 @@
-use std::{fs, error::Error};
-use bf_rs::*;
-use clap::Parser;
+use std::{fs, error::Error, io::{self, BufReader, BufWriter, Cursor, Read, Write}, net::{TcpListener, TcpStream}, path::{Path, PathBuf}, process::{self, ExitCode}, thread, time::{Duration, Instant, SystemTime}};
+use bf_rs::{*, cli::*, config::Config, emit::source_map::LineIndex};
+use clap::{CommandFactory, Parser};
 
 
 
 
-fn main() -> Result<(), Box<dyn Error>> {
+fn main() -> ExitCode {
     let args = Args::parse();
-    
-    let filepath = args.filepath;
-    let code_string = fs::read_to_string(filepath)?;
-    
-    run(&code_string, args.breakpoints, args.macros, args.debug_mode)?;
-    
+    env_logger::Builder::new()
+        .filter_level(args.log_level())
+        .format_timestamp(None)
+        .format_target(false)
+        .init();
+
+    let result = match args.into_command() {
+        Command::Run(args) => run(args),
+        Command::Debug(args) => debug(args),
+        Command::Check(args) => check(args),
+        Command::Analyze(args) => analyze(args),
+        Command::Fmt(args) => fmt(args),
+        Command::Compile(args) => compile(args),
+        Command::Bench(args) => bench(args),
+        Command::Disasm(args) => disasm(args),
+        Command::Cfg(args) => cfg(args),
+        Command::Minify(args) => minify(args),
+        Command::Golf(args) => golf(args),
+        Command::GenPrint(args) => gen_print(args),
+        Command::Const(args) => {
+            println!("{}", generate::shortest_constant(args.value));
+            Ok(())
+        },
+        Command::GenRandom(args) => gen_random(args),
+        Command::Bfc(args) => bfc(args),
+        Command::Equiv(args) => equiv(args),
+        Command::Explore(args) => explore(args),
+        Command::Test(args) => test(args),
+        Command::Mutate(args) => mutate(args),
+        #[cfg(not(target_family = "wasm"))]
+        Command::Pipe(args) => pipe(args),
+        #[cfg(not(target_family = "wasm"))]
+        Command::Batch(args) => batch(args),
+        Command::Completions(args) => {
+            clap_complete::generate(args.shell, &mut Args::command(), "bf-rs", &mut io::stdout());
+            Ok(())
+        },
+        #[cfg(not(target_family = "wasm"))]
+        Command::Lsp => bf_rs::lsp::serve(),
+        #[cfg(not(target_family = "wasm"))]
+        Command::Serve(args) => bf_rs::serve::serve(&args.address),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("error: {}", error);
+            ExitCode::from(error::exit_code(&*error))
+        },
+    }
+}
+
+
+/// A program from a file, stdin or --eval
+struct Loaded {
+    /// Where the program came from, `<eval>` for --eval
+    filepath: String,
+    /// Source code, if the file isn't bytecode
+    source: Option<String>,
+    program: Program,
+    config: Config,
+    /// What came after the `!` with --bang, to read before the rest of the input
+    input: Vec<u8>,
+    /// For each op, where it is in a macro if it came from one; empty if that isn't known
+    expansions: Vec<Option<expansion::Expanded>>,
+}
+
+
+/// A program's files, before parsing
+struct Files {
+    filepath: String,
+    bytes: Vec<u8>,
+    /// Each library's path and code
+    libraries: Vec<(String, String)>,
+}
+
+
+fn read(args: &SourceArgs) -> Result<Files, Box<dyn Error>> {
+    // Code from --eval is always brainfuck unless --dialect says otherwise
+    let (filepath, bytes) = match (args.filepaths.last().cloned(), args.eval.clone()) {
+        (_, Some(code)) => ("<eval>".to_string(), code.into_bytes()),
+        (Some(filepath), None) if filepath == "-" => {
+            let mut bytes = Vec::new();
+            io::stdin().read_to_end(&mut bytes)?;
+            (filepath, bytes)
+        },
+        (Some(filepath), None) => {
+            let bytes = fs::read(&filepath)?;
+            (filepath, bytes)
+        },
+        (None, None) => return Err("a program file or --eval is required".into()),
+    };
+    let libraries = args.filepaths[..args.filepaths.len().saturating_sub(1)].iter()
+        .map(|path| {
+            let source = source_text(fs::read(path)?);
+            match literate::is_markdown(path) {
+                true => Ok((path.clone(), literate::code(&source, &[])?)),
+                false => Ok((path.clone(), source)),
+            }
+        })
+        .collect::<Result<Vec<(String, String)>, Box<dyn Error>>>()?;
+    let mut files = Files { filepath, bytes, libraries };
+    if !bytecode::is_bytecode(&files.bytes) {
+        include(&mut files, &args.include_dirs)?;
+    }
+    Ok(files)
+}
+
+
+/// Swap the program's and libraries' `#include` lines for the libraries they name, each included once
+fn include(files: &mut Files, include_dirs: &[String]) -> Result<(), Box<dyn Error>> {
+    let (code, names) = include::directives(&source_text(files.bytes.clone()))?;
+    if names.is_empty() && files.libraries.iter().all(|(_, library)| !library.contains("#include")) {
+        return Ok(());
+    }
+    files.bytes = code.into_bytes();
+    let mut pending: Vec<(String, PathBuf)> = names.into_iter().map(|name| (name, PathBuf::from(&files.filepath))).collect();
+    for (path, library) in &mut files.libraries {
+        let (code, names) = include::directives(library)?;
+        *library = code;
+        pending.extend(names.into_iter().map(|name| (name, PathBuf::from(&*path))));
+    }
+
+    let mut included: Vec<PathBuf> = files.libraries.iter().filter_map(|(path, _)| fs::canonicalize(path).ok()).collect();
+    while let Some((name, including)) = pending.pop() {
+        let path = include::resolve(&name, &include::search_path(include_dirs, &including))?;
+        let canonical = fs::canonicalize(&path)?;
+        if included.contains(&canonical) {
+            continue;
+        }
+        included.push(canonical);
+        let (code, names) = include::directives(&source_text(fs::read(&path)?))?;
+        log::info!("including <{}> from {}", name, path.display());
+        pending.extend(names.into_iter().map(|name| (name, path.clone())));
+        files.libraries.push((path.display().to_string(), code));
+    }
+    Ok(())
+}
+
+
+fn load(args: &SourceArgs, optimize: bool) -> Result<Loaded, Box<dyn Error>> {
+    load_linted(args, optimize, false)
+}
+
+
+/// Load a program, first printing its lint warnings to stderr if `lint` is set
+fn load_linted(args: &SourceArgs, optimize: bool, lint: bool) -> Result<Loaded, Box<dyn Error>> {
+    let Files { filepath, bytes, libraries } = read(args)?;
+    let config = Config::load(&filepath)?;
+
+    if bytecode::is_bytecode(&bytes) {
+        if !libraries.is_empty() {
+            return Err("libraries can only be used with source code, not bytecode".into());
+        }
+        let program = bytecode::load(&bytes).map_err(error::parse)?;
+        return Ok(Loaded { filepath, source: None, program, config, input: Vec::new(), expansions: Vec::new() });
+    }
+
+    let mut source = source_text(bytes);
+    if literate::is_markdown(&filepath) {
+        source = literate::code(&source, &args.blocks)?;
+    }
+    if bfc::is_bfc(&filepath) {
+        source = bfc::compile(&source).map_err(|error| error::ParseError(format!("{}:{}", filepath, error.0)))?;
+    }
+    let mut options = args.parse.options(&filepath, &config);
+    let mut input = Vec::new();
+    if args.bang {
+        if options.dialect == Dialect::Ook {
+            return Err("--bang can't be used with Ook!, which has ! in its instructions".into());
+        }
+        let (code, after) = split_input(&source);
+        input = after.as_bytes().to_vec();
+        source = code.to_string();
+    }
+    // Libraries are linked through macros
+    options.macros |= !libraries.is_empty();
+
+    let instructions = if libraries.is_empty() {
+        parse_with_offsets(&source, &options).map_err(error::parse)?
+    } else if options.dialect == Dialect::Ook {
+        return Err("Ook! doesn't have macros, so it can't use libraries".into());
+    } else {
+        let libraries: Vec<(&str, &str)> = libraries.iter().map(|(path, code)| (path.as_str(), code.as_str())).collect();
+        link::link(&libraries, &source, &options).map_err(error::parse)?
+    };
+
+    check::validate(&instructions, &filepath, &source)?;
+    if lint {
+        let libraries: Vec<&str> = libraries.iter().map(|(_, code)| code.as_str()).collect();
+        for finding in lint::lint(&libraries, &source, &options) {
+            eprintln!("{}", finding.render(&filepath, &source));
+        }
+    }
+    let program = Program::compile_with_offsets(&instructions, optimize).map_err(|error| error::parse(error.into()))?;
+    // Errors in macro code point into the macro; that isn't worked out for code linked from libraries
+    let expansions = match libraries.is_empty() && options.macros && options.dialect != Dialect::Ook {
+        true => expansion::expansions(&source, &options).ok()
+            .filter(|expansions| expansions.len() == instructions.len())
+            .and_then(|expansions| expansion::by_op(&instructions, &expansions, optimize).ok())
+            .unwrap_or_default(),
+        false => Vec::new(),
+    };
+    Ok(Loaded { filepath, source: Some(source), program, config, input, expansions })
+}
+
+
+/// Only programs that use the tapes extension get more than one tape, 2 unless the flag or config says otherwise.
+/// The flag or config also picks what the end of input reads as.
+fn vm_options(program: &Program, vm: &VmArgs, config: &Config) -> VmOptions {
+    let uses_tapes = bytecode::flags(program) & bytecode::FLAG_TAPES != 0;
+    if vm.tapes.is_some() && !uses_tapes {
+        log::warn!("--tapes does nothing, the program doesn't use the tapes extension");
+    }
+    VmOptions {
+        tapes: if uses_tapes { vm.tapes.or(config.tapes).unwrap_or(2) } else { 1 },
+        eof: vm.eof.or(config.eof).unwrap_or_default(),
+        detect_hangs: false,
+        stop_on_hang: false,
+        max_output: None,
+        truncate_output: false,
+    }
+}
+
+
+/// The program's input, starting with `embedded` from after a `!`
+fn open_input(args: &ExecArgs, embedded: Vec<u8>) -> Result<Box<dyn Read>, Box<dyn Error>> {
+    let input: Box<dyn Read + Send> = if !args.args.is_empty() {
+        Box::new(Cursor::new(args.args.join(" ") + "\n"))
+    } else {
+        // With the program from stdin, there is nothing left there for `,` without --input
+        match (&args.input, &args.input_script) {
+            (Some(path), _) => {
+                let file = BufReader::new(fs::File::open(path)?);
+                match args.then_terminal {
+                    true => Box::new(file.chain(input::terminal())),
+                    false => Box::new(file),
+                }
+            },
+            (None, Some(path)) => {
+                let steps = input::parse_script(&fs::read_to_string(path)?).map_err(|error| format!("{}: {}", path, error))?;
+                Box::new(input::Scripted::new(steps))
+            },
+            #[cfg(not(target_family = "wasm"))]
+            (None, None) if args.raw_input && io::IsTerminal::is_terminal(&io::stdin()) => Box::new(input::RawKeys),
+            (None, None) => Box::new(io::stdin()),
+        }
+    };
+    let input: Box<dyn Read + Send> = match embedded.is_empty() {
+        true => input,
+        false => Box::new(Cursor::new(embedded).chain(input)),
+    };
+    let input: Box<dyn Read + Send> = match args.poll_input {
+        Some(sentinel) => Box::new(input::Polled::new(input, sentinel)?),
+        None => input,
+    };
+    Ok(match args.prompt.is_some() || args.echo {
+        true => Box::new(input::Shown::new(input, args.prompt.clone(), args.echo)),
+        false => input,
+    })
+}
+
+
+/// The stream for `--io`, once connected
+fn connect(address: &Io) -> Result<TcpStream, Box<dyn Error>> {
+    Ok(match address {
+        Io::Connect(address) => TcpStream::connect(address)?,
+        Io::Listen(address) => {
+            let listener = TcpListener::bind(address)?;
+            eprintln!("listening on {}", listener.local_addr()?);
+            let (stream, client) = listener.accept()?;
+            log::info!("connection from {}", client);
+            stream
+        },
+    })
+}
+
+
+fn run(args: RunArgs) -> Result<(), Box<dyn Error>> {
+    if args.watch {
+        watch(&args)
+    } else {
+        stop_on_interrupt()?;
+        run_once(&args)
+    }
+}
+
+
+/// Make Ctrl-C stop the program and show where it was, instead of only killing it
+fn stop_on_interrupt() -> Result<(), Box<dyn Error>> {
+    #[cfg(not(target_family = "wasm"))]
+    interrupt::install()?;
+    Ok(())
+}
+
+
+/// Add where the program was in its source to an interruption, runtime error or hang, going into the macro
+/// an op came from if it's in `expansions`
+fn locate(mut error: Box<dyn Error>, filepath: &str, source: Option<&str>, expansions: &[Option<expansion::Expanded>]) -> Box<dyn Error> {
+    let Some(source) = source else {
+        return error;
+    };
+    let index = LineIndex::new(source);
+    let position = |offset| {
+        let (line, column) = index.line_column(source, offset);
+        format!("{}:{}", line + 1, column + 1)
+    };
+    // The instruction at an offset, which is one character however many bytes it is
+    let instruction = |offset: usize| offset..offset + source[offset..].chars().next().map_or(1, char::len_utf8);
+    let expanded = |ip: usize| expansions.get(ip).and_then(Option::as_ref);
+    // Like ` in macro `copy`, called at prog.b:3:9`, innermost first
+    let called = |calls: &[expansion::Call]| -> String {
+        calls.iter().map(|call| format!(" in macro `{}`, called at {}:{}", call.name, filepath, position(call.offset))).collect()
+    };
+    // Where the op at `ip`, parsed at `offset`, really is, and its offset
+    let at = |ip: usize, offset: usize| match expanded(ip) {
+        Some(expanded) => (format!("{}:{}{}", filepath, position(expanded.offset), called(&expanded.calls)), expanded.offset),
+        None => (format!("{}:{}", filepath, position(offset)), offset),
+    };
+    if let Some(interrupted) = error.downcast_mut::<error::Interrupted>() {
+        if let Some((location, offset)) = interrupted.offset.map(|offset| at(interrupted.ip, offset)) {
+            interrupted.location = Some(location);
+            interrupted.excerpt = Some(error::excerpt(source, instruction(offset)));
+        }
+    } else if let Some(failed) = error.downcast_mut::<error::Failed>() {
+        for ran in std::iter::once(&mut failed.at).chain(&mut failed.trail) {
+            ran.location = ran.offset.map(|offset| at(ran.ip, offset).0);
+        }
+        failed.excerpt = failed.at.offset.map(|offset| error::excerpt(source, instruction(at(failed.at.ip, offset).1)));
+    } else if let Some(hang) = error.downcast_mut::<error::Hang>() {
+        let (open, close) = match (expanded(hang.open), expanded(hang.close)) {
+            // Both brackets from the same call of a macro
+            (Some(open), Some(close)) if open.calls == close.calls => (Some(open.offset), Some((close.offset, called(&open.calls)))),
+            _ => (hang.offsets.map(|(open, _)| open), hang.offsets.map(|(_, close)| (close, String::new()))),
+        };
+        if let (Some(open), Some((close, called))) = (open, close) {
+            hang.location = Some(format!("{}:{} to {}{}", filepath, position(open), position(close), called));
+            hang.excerpt = Some(error::excerpt(source, open..close + 1));
+        }
+    }
+    error
+}
+
+
+/// Run the program again whenever one of its files changes, until interrupted
+fn watch(args: &RunArgs) -> Result<(), Box<dyn Error>> {
+    if args.source.eval.is_some() || args.source.filepaths.iter().any(|path| path == "-") {
+        return Err("--watch needs the program to be in a file".into());
+    }
+    let modified = || -> Vec<Option<SystemTime>> {
+        args.source.filepaths.iter()
+            .map(|path| fs::metadata(path).and_then(|metadata| metadata.modified()).ok())
+            .collect()
+    };
+
+    loop {
+        let last_modified = modified();
+        // Clear the screen and move to the top left
+        print!("\x1b[2J\x1b[H");
+        io::stdout().flush()?;
+        if let Err(error) = run_once(args) {
+            eprintln!("error: {}", error);
+        }
+
+        while modified() == last_modified {
+            thread::sleep(Duration::from_millis(200));
+        }
+    }
+}
+
+
+fn run_once(args: &RunArgs) -> Result<(), Box<dyn Error>> {
+    let start = Instant::now();
+    // Coverage needs an op for every instruction
+    let Loaded { filepath, source, program, config, input, expansions } = load_linted(&args.source, args.coverage.is_none() && args.exec.trace.is_none(), args.lint)?;
+    let load_time = start.elapsed();
+
+    if args.dump_ir {
+        print!("{}", disasm::disassemble(&program));
+        return Ok(());
+    }
+
+    if let Some(target) = args.emit {
+        let Some(map_path) = &args.source_map else {
+            io::stdout().write_all(&emit::emit(&program, target, args.exec.vm.eof.or(config.eof).unwrap_or_default())?)?;
+            return Ok(());
+        };
+        let Some(source) = source else {
+            return Err("source maps need the program's source, not bytecode".into());
+        };
+
+        let (mut code, source_map) = emit::emit_with_source_map(&program, target, args.exec.vm.eof.or(config.eof).unwrap_or_default())?;
+        if target == emit::Target::Js {
+            let map_name = Path::new(&map_path).file_name().and_then(|name| name.to_str()).unwrap_or(map_path);
+            code.extend(format!("//# sourceMappingURL={}\n", map_name).into_bytes());
+        }
+        fs::write(map_path, source_map.to_json(&filepath, &source))?;
+        io::stdout().write_all(&code)?;
+        return Ok(());
+    }
+
+    let vm_options = VmOptions {
+        detect_hangs: args.exec.detect_hangs || args.exec.stop_on_hang,
+        stop_on_hang: args.exec.stop_on_hang,
+        max_output: args.exec.max_output,
+        truncate_output: args.exec.truncate_output,
+        ..vm_options(&program, &args.exec.vm, &config)
+    };
+    let mut tones = args.wav.as_ref().map(|path| audio::Tones::new(path, Duration::from_millis(args.note_length)));
+    let (mut input, mut output): (Box<dyn Read>, Box<dyn Write + '_>) = match &args.io {
+        Some(address) => {
+            let stream = connect(address)?;
+            (Box::new(Cursor::new(input).chain(BufReader::new(stream.try_clone()?))), Box::new(BufWriter::new(stream)))
+        },
+        None => {
+            let output: Box<dyn Write> = match (&args.output_file, &mut tones) {
+                (Some(path), _) => Box::new(BufWriter::new(fs::File::create(path)?)),
+                (_, Some(tones)) => Box::new(tones),
+                (None, None) => Box::new(io::stdout()),
+            };
+            (open_input(&args.exec, input)?, output)
+        },
+    };
+    let start = Instant::now();
+    let vm = match (&args.profile, &args.coverage) {
+        (None, None) => {
+            let debug_options = DebugOptions {
+                snapshots: args.exec.image.snapshots(),
+                recording: args.exec.image.recording(),
+                trace: args.exec.trace(source.as_deref()),
+                dashboard: args.exec.dashboard(source.as_deref()),
+                progress: args.exec.progress(source.as_deref()),
+                framebuffer: args.exec.framebuffer.framebuffer(),
+                op_times: args.op_times,
+                hang_warnings: args.exec.hang_warnings(&filepath, source.as_deref()),
+                ..DebugOptions::default()
+            };
+            run_program(program, &vm_options, &debug_options, &mut input, &mut output)
+                .map_err(|error| locate(error::runtime(error), &filepath, source.as_deref(), &expansions))?
+        },
+        (profile_path, coverage_path) => {
+            let profiled = program.clone();
+            let (vm, counts) = profile::run_profiled(program, &vm_options, &mut input, &mut output).map_err(error::runtime)?;
+            if let Some(profile_path) = profile_path {
+                fs::write(profile_path, profile::folded(&profiled, &counts, &filepath, source.as_deref()))?;
+            }
+            if let Some(coverage_path) = coverage_path {
+                let Some(source) = &source else {
+                    return Err("coverage needs the program's source, not bytecode".into());
+                };
+                let counts = coverage::counts(&profiled, &counts);
+                let report = match Path::new(coverage_path).extension().and_then(|extension| extension.to_str()) {
+                    Some("info" | "lcov") => coverage::lcov(&filepath, source, &counts),
+                    _ => coverage::annotate(source, &counts),
+                };
+                fs::write(coverage_path, report)?;
+            }
+            vm
+        },
+    };
+    if args.newline {
+        eprintln!();
+    }
+    if args.time {
+        eprintln!("parse and compile: {:?}, run: {:?}", load_time, start.elapsed());
+    }
+    drop(output);
+    if let Some(tones) = &mut tones {
+        tones.finish()?;
+    }
+    finish(&vm, &args.exec)
+}
+
+
+fn debug(args: DebugArgs) -> Result<(), Box<dyn Error>> {
+    // Keep one op per instruction, so every step can be shown
+    let Loaded { filepath, source, program, config, input, expansions } = load(&args.source, false)?;
+
+    let vm_options = VmOptions {
+        detect_hangs: args.exec.detect_hangs || args.exec.stop_on_hang,
+        stop_on_hang: args.exec.stop_on_hang,
+        max_output: args.exec.max_output,
+        truncate_output: args.exec.truncate_output,
+        ..vm_options(&program, &args.exec.vm, &config)
+    };
+    let debug_options = DebugOptions {
+        mode: match (args.step, args.visualize) {
+            (true, _) => DebugMode::Step,
+            (_, true) => DebugMode::Visualize { steps_per_frame: args.steps_per_frame, delay: Duration::from_millis(args.frame_delay) },
+            _ => DebugMode::Verbose,
+        },
+        format: args.debug_format,
+        color: args.color.enabled(),
+        snapshots: args.exec.image.snapshots(),
+        recording: args.exec.image.recording(),
+        trace: args.exec.trace(source.as_deref()),
+        commands: args.commands,
+        dashboard: args.exec.dashboard(source.as_deref()),
+        progress: args.exec.progress(source.as_deref()),
+        framebuffer: args.exec.framebuffer.framebuffer(),
+        op_times: false,
+        hang_warnings: args.exec.hang_warnings(&filepath, source.as_deref()),
+    };
+    let mut input = open_input(&args.exec, input)?;
+    stop_on_interrupt()?;
+    let vm = run_program(program, &vm_options, &debug_options, &mut input, &mut io::stdout())
+        .map_err(|error| locate(error::runtime(error), &filepath, source.as_deref(), &expansions))?;
+    finish(&vm, &args.exec)
+}
+
+
+/// Warn if the output was cut off, write the picture of the tape if there is one, and exit with a cell if asked to
+fn finish(vm: &Vm, args: &ExecArgs) -> Result<(), Box<dyn Error>> {
+    if let (true, Some(limit)) = (vm.is_truncated(), args.max_output) {
+        log::warn!("the output was cut off at --max-output, {} bytes", limit);
+    }
+    if let Some(image) = args.image.image() {
+        image.write(vm.tape())?;
+    }
+    if let Some(exit_cell) = args.exit_cell {
+        process::exit(exit_cell.value(vm).into());
+    }
+    Ok(())
+}
+
+
+fn check(args: CheckArgs) -> Result<(), Box<dyn Error>> {
+    let Files { filepath, bytes, libraries } = read(&args.source)?;
+    if bytecode::is_bytecode(&bytes) {
+        bytecode::load(&bytes).map_err(error::parse)?;
+        return Ok(());
+    }
+
+    let source = source_text(bytes);
+    let mut options = args.source.parse.options(&filepath, &Config::load(&filepath)?);
+    options.macros |= !libraries.is_empty();
+    let libraries: Vec<&str> = libraries.iter().map(|(_, code)| code.as_str()).collect();
+
+    let diagnostics = check::check(&libraries, &source, &options);
+    let warnings = lint::lint(&libraries, &source, &options);
+    match args.format {
+        CheckFormat::Text => {
+            for diagnostic in &diagnostics {
+                eprintln!("{}", diagnostic.render(&filepath, &source));
+            }
+            for warning in &warnings {
+                eprintln!("{}", warning.render(&filepath, &source));
+            }
+            let plural = |count: usize| if count == 1 { "" } else { "s" };
+            let totals: Vec<String> = [(diagnostics.len(), "error"), (warnings.len(), "warning")].into_iter()
+                .filter(|&(count, _)| count > 0)
+                .map(|(count, kind)| format!("{} {}{}", count, kind, plural(count)))
+                .collect();
+            if !totals.is_empty() {
+                eprintln!("{}", totals.join(", "));
+            }
+        },
+        CheckFormat::Json => {
+            let json: Vec<serde_json::Value> = diagnostics.iter().map(|diagnostic| diagnostic.json(&filepath, &source))
+                .chain(warnings.iter().map(|warning| warning.json(&filepath, &source)))
+                .collect();
+            println!("{}", serde_json::Value::Array(json));
+        },
+    }
+    if diagnostics.is_empty() {
+        return Ok(());
+    }
+    process::exit(error::PARSE.into());
+}
+
+
+fn analyze(args: AnalyzeArgs) -> Result<(), Box<dyn Error>> {
+    let Files { filepath, bytes, libraries } = read(&args.source)?;
+    if bytecode::is_bytecode(&bytes) {
+        return Err("analyze needs the program's source, not bytecode".into());
+    }
+
+    let source = source_text(bytes);
+    let mut options = args.source.parse.options(&filepath, &Config::load(&filepath)?);
+    options.macros |= !libraries.is_empty();
+    let instructions = if libraries.is_empty() {
+        parse_with_offsets(&source, &options).map_err(error::parse)?
+    } else {
+        let libraries: Vec<(&str, &str)> = libraries.iter().map(|(path, code)| (path.as_str(), code.as_str())).collect();
+        link::link(&libraries, &source, &options).map_err(error::parse)?
+    };
+    check::validate(&instructions, &filepath, &source)?;
+
+    if args.loops {
+        let loops = analyze::classify_loops(&instructions);
+        println!("{} loop{}", loops.len(), if loops.len() == 1 { "" } else { "s" });
+        for kind in analyze::LoopKind::ALL {
+            println!("  {:<18}{}", kind, loops.iter().filter(|(other, _)| *other == kind).count());
+        }
+        let index = LineIndex::new(&source);
+        let slow: Vec<_> = loops.iter().filter(|(kind, _)| !kind.is_optimized()).collect();
+        if !slow.is_empty() {
+            println!("not optimized:");
+        }
+        for (kind, span) in slow {
+            let (line, column) = index.line_column(&source, span.start);
+            // On one line, and cut short if long
+            let code = source[span.clone()].split_whitespace().collect::<Vec<_>>().join(" ");
+            let code = match code.char_indices().nth(40) {
+                Some((end, _)) => format!("{}...", &code[..end]),
+                None => code,
+            };
+            println!("  {}:{}:{}: {} {}", filepath, line + 1, column + 1, kind, code);
+        }
+        return Ok(());
+    }
+
+    let findings = analyze::analyze(&instructions);
+    match args.format {
+        CheckFormat::Text => {
+            for finding in &findings {
+                eprintln!("{}", finding.render(&filepath, &source));
+            }
+        },
+        CheckFormat::Json => {
+            let json: Vec<serde_json::Value> = findings.iter().map(|finding| finding.json(&filepath, &source)).collect();
+            println!("{}", serde_json::Value::Array(json));
+        },
+    }
+    Ok(())
+}
+
+
+fn bench(args: BenchArgs) -> Result<(), Box<dyn Error>> {
+    let Loaded { program, config, .. } = load(&args.source, true)?;
+    let input = match &args.input {
+        Some(path) => fs::read(path)?,
+        None => Vec::new(),
+    };
+    let vm_options = vm_options(&program, &args.vm, &config);
+
+    let mut times: Vec<Duration> = Vec::new();
+    let mut vm = Vm::new(program, &vm_options);
+    for _ in 0..args.runs.max(1) {
+        vm.reset();
+        let start = Instant::now();
+        vm.run(&mut Cursor::new(&input), &mut io::sink()).map_err(error::runtime)?;
+        times.push(start.elapsed());
+    }
+
+    let total: Duration = times.iter().sum();
+    println!(
+        "{} runs, mean {:?}, min {:?}, max {:?}",
+        times.len(),
+        total / times.len() as u32,
+        times.iter().min().unwrap(),
+        times.iter().max().unwrap(),
+    );
+    Ok(())
+}
+
+
+fn equiv(args: EquivArgs) -> Result<(), Box<dyn Error>> {
+    let load_program = |filepath: &String| -> Result<(Program, VmOptions), Box<dyn Error>> {
+        let source = SourceArgs { filepaths: vec![filepath.clone()], eval: None, blocks: Vec::new(), include_dirs: Vec::new(), bang: false, parse: args.parse.clone() };
+        let Loaded { program, config, .. } = load(&source, true)?;
+        let vm_options = vm_options(&program, &args.vm, &config);
+        Ok((program, vm_options))
+    };
+    let (first, second) = (load_program(&args.first)?, load_program(&args.second)?);
+    let inputs = match args.inputs.is_empty() {
+        true => equiv::default_inputs(),
+        false => args.inputs.iter().map(fs::read).collect::<Result<_, _>>()?,
+    };
+
+    let finished = equiv::compare(&first, &second, &inputs, args.max_steps)
+        .map_err(|difference| format!("{} and {} are different: {}", args.first, args.second, difference))?;
+    println!("{} and {} are the same on {} input{}", args.first, args.second, inputs.len(), if inputs.len() == 1 { "" } else { "s" });
+    if finished < inputs.len() {
+        log::warn!("neither program finished in {} steps on {} of the inputs", args.max_steps, inputs.len() - finished);
+    }
+    Ok(())
+}
+
+
+fn explore(args: ExploreArgs) -> Result<(), Box<dyn Error>> {
+    let Loaded { filepath, source, program, config, .. } = load(&args.source, true)?;
+    let limits = symbolic::Limits { inputs: args.max_input, steps: args.max_steps, paths: args.max_paths };
+    let exploration = symbolic::explore(&program, config.eof.unwrap_or_default(), &limits)?;
+
+    let index = source.as_deref().map(LineIndex::new);
+    for found in &exploration.found {
+        let location = match (&source, &index, program.offset(found.op)) {
+            (Some(source), Some(index), Some(offset)) => {
+                let (line, column) = index.line_column(source, offset);
+                format!("{}:{}:{}", filepath, line + 1, column + 1)
+            },
+            _ => filepath.clone(),
+        };
+        println!("{}: {}", location, found);
+    }
+    if exploration.found.is_empty() {
+        println!("no input of up to {} bytes reaches a breakpoint or moves the pointer left of cell 0", args.max_input);
+    }
+    if exploration.cut_off > 0 {
+        log::warn!("{} paths were cut off after {} steps", exploration.cut_off, args.max_steps);
+    }
+    if exploration.incomplete {
+        log::warn!("stopped after {} paths, some weren't explored", args.max_paths);
+    }
+    Ok(())
+}
+
+
+fn test(args: TestArgs) -> Result<(), Box<dyn Error>> {
+    let paths: Vec<PathBuf> = args.paths.iter().map(PathBuf::from).collect();
+    let text = args.format == TestFormat::Text;
+    let (mut passed, mut failed) = (0, 0);
+    let mut suites = Vec::new();
+
+    for path in testing::discover(&paths)? {
+        let mut suite = testing::Suite { name: path.display().to_string(), cases: Vec::new() };
+        let source_args = SourceArgs {
+            filepaths: vec![path.display().to_string()],
+            eval: None,
+            blocks: Vec::new(),
+            include_dirs: Vec::new(),
+            bang: false,
+            parse: args.parse.clone(),
+        };
+        let cases = testing::cases(&path)?;
+        let Loaded { program, config, .. } = match load(&source_args, true) {
+            Ok(loaded) => loaded,
+            Err(error) => {
+                if text {
+                    println!("{}: FAILED to load: {}", path.display(), error);
+                }
+                failed += cases.len().max(1);
+                let failure = Some(testing::Failure::Error(format!("failed to load: {}", error)));
+                suite.cases.extend(cases.iter().map(|case| testing::CaseResult { name: case.name.clone(), duration: Duration::ZERO, failure: failure.clone() }));
+                if cases.is_empty() {
+                    suite.cases.push(testing::CaseResult { name: "load".to_string(), duration: Duration::ZERO, failure });
+                }
+                suites.push(suite);
+                continue;
+            },
+        };
+        let vm_options = vm_options(&program, &args.vm, &config);
+
+        for case in &cases {
+            let start = Instant::now();
+            let result = testing::run_case(&program, &vm_options, case);
+            let duration = start.elapsed();
+            let failure = match result {
+                Ok(output) if case.expected.as_ref().is_none_or(|expected| *expected == output) => {
+                    if text {
+                        println!("{} {}: ok", path.display(), case.name);
+                    }
+                    passed += 1;
+                    None
+                },
+                Ok(output) => {
+                    let diff = testing::diff(case.expected.as_deref().unwrap_or_default(), &output);
+                    if text {
+                        println!("{} {}: FAILED", path.display(), case.name);
+                        print!("{}", diff);
+                    }
+                    failed += 1;
+                    Some(testing::Failure::Output(diff))
+                },
+                Err(error) => {
+                    if text {
+                        println!("{} {}: FAILED: {}", path.display(), case.name, error);
+                    }
+                    failed += 1;
+                    Some(testing::Failure::Error(error.to_string()))
+                },
+            };
+            suite.cases.push(testing::CaseResult { name: case.name.clone(), duration, failure });
+        }
+        suites.push(suite);
+    }
+
+    match text {
+        true => println!("{} passed, {} failed", passed, failed),
+        false => print!("{}", testing::junit(&suites)),
+    }
+    if failed > 0 {
+        process::exit(1);
+    }
+    Ok(())
+}
+
+
+fn mutate(args: MutateArgs) -> Result<(), Box<dyn Error>> {
+    let paths: Vec<PathBuf> = args.paths.iter().map(PathBuf::from).collect();
+    let (mut killed, mut survived) = (0, 0);
+
+    for path in testing::discover(&paths)? {
+        let filepath = path.display().to_string();
+        let cases = testing::cases(&path)?;
+        let mut source = source_text(fs::read(&path)?);
+        if literate::is_markdown(&path) {
+            source = literate::code(&source, &[])?;
+        }
+        let config = Config::load(&filepath)?;
+        let options = args.parse.options(&filepath, &config);
+        let instructions = parse_with_offsets(&source, &options).map_err(error::parse)?;
+        let program = Program::compile_with_offsets(&instructions, true).map_err(|error| error::parse(error.into()))?;
+        let vm_options = vm_options(&program, &args.vm, &config);
+        if mutate::judge(&program, &vm_options, &cases, args.max_steps) != mutate::Verdict::Survived {
+            return Err(format!("{} fails its own fixtures, run `bf-rs test` on it first", filepath).into());
+        }
+
+        let index = LineIndex::new(&source);
+        for mutant in mutate::mutants(&instructions) {
+            match mutate::judge(&mutant.program, &vm_options, &cases, args.max_steps) {
+                mutate::Verdict::Survived => {
+                    let (line, column) = index.line_column(&source, mutant.offset);
+                    println!("{}:{}:{}: {} survived", filepath, line + 1, column + 1, mutant.change);
+                    survived += 1;
+                },
+                verdict => {
+                    log::debug!("{} at offset {}: {:?}", mutant.change, mutant.offset, verdict);
+                    killed += 1;
+                },
+            }
+        }
+    }
+
+    let total = killed + survived;
+    let score = if total == 0 { 100.0 } else { killed as f64 * 100.0 / total as f64 };
+    println!("{} mutants, {} caught and {} survived ({:.1}% caught)", total, killed, survived, score);
+    Ok(())
+}
+
+
+#[cfg(not(target_family = "wasm"))]
+fn pipe(args: PipeArgs) -> Result<(), Box<dyn Error>> {
+    let mut stages = Vec::new();
+    for filepath in &args.programs {
+        let source = SourceArgs { filepaths: vec![filepath.clone()], eval: None, blocks: Vec::new(), include_dirs: Vec::new(), bang: false, parse: args.parse.clone() };
+        let Loaded { program, config, .. } = load(&source, true)?;
+        let vm_options = vm_options(&program, &args.vm, &config);
+        stages.push((program, vm_options));
+    }
+
+    let mut input: Box<dyn Read + Send> = match &args.input {
+        Some(path) => Box::new(BufReader::new(fs::File::open(path)?)),
+        None => Box::new(io::stdin()),
+    };
+    pipe::run_pipeline(stages, &mut input, &mut io::stdout()).map_err(|error| error as Box<dyn Error>)?;
+    Ok(())
+}
+
+
+#[cfg(not(target_family = "wasm"))]
+fn batch(args: BatchArgs) -> Result<(), Box<dyn Error>> {
+    let source = SourceArgs { filepaths: vec![args.filepath.clone()], eval: None, blocks: Vec::new(), include_dirs: Vec::new(), bang: false, parse: args.parse.clone() };
+    let Loaded { program, config, .. } = load(&source, true)?;
+    let vm_options = vm_options(&program, &args.vm, &config);
+    let inputs: Vec<PathBuf> = args.inputs.iter().map(PathBuf::from).collect();
+    let directory = args.out_dir.as_ref().map(PathBuf::from);
+    if let Some(directory) = &directory {
+        fs::create_dir_all(directory)?;
+    }
+    let outputs: Vec<PathBuf> = inputs.iter().map(|input| batch::output_path(input, directory.as_deref())).collect();
+    if let Some(input) = inputs.iter().find(|input| outputs.contains(input)) {
+        return Err(format!("the output for one input would overwrite {}, pass --out-dir", input.display()).into());
+    }
+    for (i, output) in outputs.iter().enumerate() {
+        if let Some(j) = outputs[..i].iter().position(|earlier| earlier == output) {
+            return Err(format!("{} and {} would both write {}", inputs[j].display(), inputs[i].display(), output.display()).into());
+        }
+    }
+
+    let runs = batch::run(&program, &vm_options, &inputs, directory.as_deref(), args.jobs)?;
+    print!("{}", batch::summary(&runs));
+    if runs.iter().any(|run| run.error.is_some()) {
+        process::exit(1);
+    }
+    Ok(())
+}
+
+
+fn compile(args: CompileArgs) -> Result<(), Box<dyn Error>> {
+    let code_string = source_text(fs::read(&args.filepath)?);
+
+    let config = Config::load(&args.filepath)?;
+    let options = args.parse.options(&args.filepath, &config);
+    let instructions = parse_with_offsets(&code_string, &options).map_err(error::parse)?;
+    check::validate(&instructions, &args.filepath, &code_string)?;
+    let program = Program::compile_with_offsets(&instructions, true).map_err(|error| error::parse(error.into()))?;
+
+    let output = match args.output {
+        Some(output) => output.into(),
+        None if args.emit == CompileFormat::Bfc => Path::new(&args.filepath).with_extension("bfc"),
+        None => Path::new(&args.filepath).with_extension(""),
+    };
+    if output == Path::new(&args.filepath) {
+        return Err("the output would overwrite the program, pass a different path with -o".into());
+    }
+
+    match args.emit {
+        CompileFormat::Native => {
+            let debug_source = args.debug_info.then_some((args.filepath.as_str(), code_string.as_str()));
+            compile::compile_native(&program, &output, &args.cc, args.eof.or(config.eof).unwrap_or_default(), debug_source)
+        },
+        CompileFormat::Bfc => Ok(fs::write(output, bytecode::save(&program))?),
+    }
+}
+
+
+fn disasm(args: DisasmArgs) -> Result<(), Box<dyn Error>> {
+    let bytes = fs::read(&args.filepath)?;
+    if !bytecode::is_bytecode(&bytes) {
+        return Err("not a bytecode file, use --dump-ir to list the ops of a source file".into());
+    }
+
+    print!("{}", disasm::disassemble(&bytecode::load(&bytes).map_err(error::parse)?));
+    Ok(())
+}
+
+
+fn fmt(args: FmtArgs) -> Result<(), Box<dyn Error>> {
+    // Stray bytes would be written back as U+FFFD, so only UTF-8 files are formatted
+    let code_string = fs::read_to_string(&args.filepath)?;
+
+    let options = args.parse.options(&args.filepath, &Config::load(&args.filepath)?);
+    let formatted = format::format_source(&code_string, &options).map_err(error::parse)?;
+
+    if args.write {
+        fs::write(&args.filepath, formatted)?;
+    } else {
+        print!("{}", formatted);
+    }
+    Ok(())
+}
+
+
+fn cfg(args: CfgArgs) -> Result<(), Box<dyn Error>> {
+    let Loaded { program, source, config, .. } = load(&args.source, true)?;
+    let counts = if args.counts {
+        let vm_options = vm_options(&program, &args.vm, &config);
+        let input = match &args.input {
+            Some(path) => fs::read(path)?,
+            None => Vec::new(),
+        };
+        let (_, counts) = profile::run_profiled(program.clone(), &vm_options, &mut Cursor::new(input), &mut io::sink())
+            .map_err(error::runtime)?;
+        Some(counts)
+    } else {
+        None
+    };
+
+    let dot = cfg::dot(&program, source.as_deref(), counts.as_deref());
+    match &args.output {
+        Some(path) => fs::write(path, dot)?,
+        None => print!("{}", dot),
+    }
+    Ok(())
+}
+
+
+fn minify(args: MinifyArgs) -> Result<(), Box<dyn Error>> {
+    let code_string = source_text(fs::read(&args.filepath)?);
+
+    let options = args.parse.options(&args.filepath, &Config::load(&args.filepath)?);
+    let instructions = parse(&code_string, &options).map_err(error::parse)?;
+    // Check the brackets match, so what's printed is a program that runs
+    Program::compile(&instructions, false).map_err(|error| error::parse(error.into()))?;
+    print!("{}", format::minify(&instructions, args.width));
+    Ok(())
+}
+
+
+fn golf(args: GolfArgs) -> Result<(), Box<dyn Error>> {
+    let code_string = source_text(fs::read(&args.filepath)?);
+    let options = args.parse.options(&args.filepath, &Config::load(&args.filepath)?);
+    let instructions = parse(&code_string, &options).map_err(error::parse)?;
+    let inputs = match args.inputs.is_empty() {
+        true => equiv::default_inputs(),
+        false => args.inputs.iter().map(fs::read).collect::<Result<_, _>>()?,
+    };
+
+    let golfed = golf::golf(&instructions, &inputs, args.max_steps)?;
+    if golfed.rewrites.is_empty() {
+        eprintln!("no shorter program found than the {} instructions of {}", instructions.len(), args.filepath);
+        return Ok(());
+    }
+    for rewrite in &golfed.rewrites {
+        eprintln!("- {}", rewrite);
+    }
+    eprintln!("{} instructions down to {}, the same on {} inputs", instructions.len(), golfed.code.len(), inputs.len());
+    let golfed = parse(&golfed.code, &ParseOptions::default())?;
+    print!("{}", format::minify(&golfed, args.width));
+    Ok(())
+}
+
+
+fn gen_print(args: GenPrintArgs) -> Result<(), Box<dyn Error>> {
+    println!("{}", generate::print_program(&args.text)?);
+    Ok(())
+}
+
+
+fn gen_random(args: GenRandomArgs) -> Result<(), Box<dyn Error>> {
+    let seed = args.seed.unwrap_or_else(|| SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map_or(0, |time| time.as_nanos() as u64));
+    eprintln!("seed {}", seed);
+    let options = random::Options { max_length: args.max_length, max_depth: args.max_depth, weights: args.weights };
+    let mut rng = random::Rng::new(seed);
+    for _ in 0..args.count {
+        println!("{}", format::minify(&random::generate(&mut rng, &options), 0).trim_end());
+    }
+    Ok(())
+}
+
+
+fn bfc(args: BfcArgs) -> Result<(), Box<dyn Error>> {
+    let source = fs::read_to_string(&args.filepath)?;
+    let code = bfc::compile(&source).map_err(|error| error::ParseError(format!("{}:{}", args.filepath, error.0)))?;
+    match &args.output {
+        Some(path) => fs::write(path, code)?,
+        None => print!("{}", code),
+    }
     Ok(())
 }
@@ -0,0 +1,116 @@
+//! Mutation testing, for the `mutate` subcommand: small changes to a program that its tests should notice.
+//! A mutant no test fails on points at code the tests don't check.
+
+use crate::{Instruction, Program, VmOptions, instruction_to_char, reference, testing::{self, Case}};
+
+
+/// A program with one change
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mutant {
+    /// Where the changed instruction is in the source
+    pub offset: usize,
+    /// What was changed, like `+ to -`
+    pub change: String,
+    pub program: Program,
+}
+
+
+/// How a mutant did against the tests
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    /// A case's output was wrong, or the run errored
+    Killed(String),
+    /// A case ran for too long
+    TimedOut(String),
+    Survived,
+}
+
+
+/// Every mutant of a program: each `+` and `-` flipped, each `<` and `>` swapped, and each instruction other than
+/// a bracket deleted
+pub fn mutants(instructions: &[(Instruction, usize)]) -> Vec<Mutant> {
+    let mut mutants = Vec::new();
+    for (i, &(instruction, offset)) in instructions.iter().enumerate() {
+        let flipped = match instruction {
+            Instruction::Increment => Some(Instruction::Decrement),
+            Instruction::Decrement => Some(Instruction::Increment),
+            Instruction::Left => Some(Instruction::Right),
+            Instruction::Right => Some(Instruction::Left),
+            _ => None,
+        };
+        let mut changes = Vec::new();
+        if let Some(flipped) = flipped {
+            let mut mutated = instructions.to_vec();
+            mutated[i].0 = flipped;
+            changes.push((format!("{} to {}", instruction_to_char(&instruction), instruction_to_char(&flipped)), mutated));
+        }
+        if !matches!(instruction, Instruction::Open | Instruction::Close) {
+            let mut mutated = instructions.to_vec();
+            mutated.remove(i);
+            changes.push((format!("deleting {}", instruction_to_char(&instruction)), mutated));
+        }
+        for (change, mutated) in changes {
+            if let Ok(program) = Program::compile_with_offsets(&mutated, true) {
+                mutants.push(Mutant { offset, change, program });
+            }
+        }
+    }
+    mutants
+}
+
+
+/// Run the cases on a program until one fails, letting each take up to `max_steps` ops
+pub fn judge(program: &Program, vm_options: &VmOptions, cases: &[Case], max_steps: usize) -> Verdict {
+    for case in cases {
+        // What the program wrote, none if it errored, or none at all if it ran too long
+        let output = match case.steps.is_empty() {
+            true => reference::run_vm_with(program.clone(), vm_options, &case.input, max_steps)
+                .map(|outcome| outcome.error.is_none().then_some(outcome.output)),
+            false => match testing::converse(program, vm_options, case, max_steps) {
+                Ok(output) => output.map(Some),
+                Err(_) => Some(None),
+            },
+        };
+        match output {
+            None => return Verdict::TimedOut(case.name.clone()),
+            Some(None) => return Verdict::Killed(case.name.clone()),
+            Some(Some(output)) if case.expected.as_ref().is_some_and(|expected| *expected != output) => {
+                return Verdict::Killed(case.name.clone())
+            },
+            Some(Some(_)) => (),
+        }
+    }
+    Verdict::Survived
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ParseOptions, parse_with_offsets};
+
+    fn case(input: &str, output: &str) -> Case {
+        Case { name: format!("{:?}", input), input: input.into(), expected: Some(output.into()), steps: Vec::new() }
+    }
+
+    #[test]
+    fn finds_untested_code() {
+        // The `+` is overwritten by the input straight away
+        let instructions = parse_with_offsets("+,.", &ParseOptions::default()).unwrap();
+        let mutants = mutants(&instructions);
+        let changes: Vec<(usize, &str)> = mutants.iter().map(|mutant| (mutant.offset, mutant.change.as_str())).collect();
+        assert_eq!(changes, [(0, "+ to -"), (0, "deleting +"), (1, "deleting ,"), (2, "deleting .")]);
+
+        let cases = [case("a", "a")];
+        let verdicts: Vec<Verdict> = mutants.iter().map(|mutant| judge(&mutant.program, &VmOptions::default(), &cases, 100)).collect();
+        assert_eq!(verdicts, [Verdict::Survived, Verdict::Survived, Verdict::Killed("\"a\"".to_string()), Verdict::Killed("\"a\"".to_string())]);
+    }
+
+    #[test]
+    fn timeouts_kill() {
+        let instructions = parse_with_offsets("+[-.]", &ParseOptions::default()).unwrap();
+        let mutants = mutants(&instructions);
+        let flipped = mutants.iter().find(|mutant| mutant.offset == 2 && mutant.change == "- to +").unwrap();
+        assert_eq!(judge(&flipped.program, &VmOptions::default(), &[case("", "\0")], 100), Verdict::TimedOut("\"\"".to_string()));
+    }
+}
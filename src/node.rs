@@ -0,0 +1,155 @@
+//! napi-rs bindings, built as a Node.js addon with `napi build --release --features node`.
+//! Parse and macro errors throw with the `InvalidArg` status, errors while running with `GenericFailure`.
+
+use std::{collections::VecDeque, error::Error};
+use napi::{Status, bindgen_prelude::Buffer};
+use napi_derive::napi;
+use crate::{ParseOptions, Program, Vm, VmOptions, disasm, parse_with_offsets};
+
+
+fn invalid(error: Box<dyn Error>) -> napi::Error {
+    napi::Error::new(Status::InvalidArg, error.to_string())
+}
+
+
+fn failure(error: Box<dyn Error>) -> napi::Error {
+    napi::Error::from_reason(error.to_string())
+}
+
+
+/// How to compile a program, macros are off and optimizing is on unless set
+#[napi(object)]
+#[derive(Default)]
+pub struct CompileOptions {
+    pub macros: Option<bool>,
+    /// Without it, every op is one instruction, which is easier to step through
+    pub optimize: Option<bool>,
+}
+
+
+fn compile_program(code: &str, options: Option<CompileOptions>) -> Result<Program, Box<dyn Error>> {
+    let options = options.unwrap_or_default();
+    let parse_options = ParseOptions { macros: options.macros.unwrap_or(false), ..ParseOptions::default() };
+    Ok(Program::compile_with_offsets(&parse_with_offsets(code, &parse_options)?, options.optimize.unwrap_or(true))?)
+}
+
+
+/// A compiled program
+#[napi(js_name = "Program")]
+pub struct NodeProgram {
+    program: Program,
+}
+
+
+#[napi]
+impl NodeProgram {
+    #[napi(constructor)]
+    pub fn new(code: String, options: Option<CompileOptions>) -> napi::Result<NodeProgram> {
+        Ok(NodeProgram { program: compile_program(&code, options).map_err(invalid)? })
+    }
+
+    /// The ops, as `--dump-ir` shows them
+    #[napi]
+    pub fn ops(&self) -> Vec<String> {
+        self.program.ops.iter().map(|op| op.to_string()).collect()
+    }
+
+    #[napi]
+    pub fn disassemble(&self) -> String {
+        disasm::disassemble(&self.program)
+    }
+}
+
+
+/// Compile a program, the same as `new Program(code, options)`
+#[napi]
+pub fn compile(code: String, options: Option<CompileOptions>) -> napi::Result<NodeProgram> {
+    NodeProgram::new(code, options)
+}
+
+
+/// Run a program to the end with `input`, returning its output
+#[napi]
+pub fn run(code: String, input: Option<String>, options: Option<CompileOptions>) -> napi::Result<String> {
+    let program = compile_program(&code, options).map_err(invalid)?;
+    let mut output = Vec::new();
+    let input = input.unwrap_or_default();
+    Vm::new(program, &VmOptions::default()).run(&mut input.as_bytes(), &mut output).map_err(failure)?;
+    Ok(String::from_utf8_lossy(&output).into_owned())
+}
+
+
+/// A program being run one op at a time, with its input and output as strings
+#[napi(js_name = "Vm")]
+pub struct NodeVm {
+    vm: Vm,
+    input: VecDeque<u8>,
+    output: Vec<u8>,
+}
+
+
+#[napi]
+impl NodeVm {
+    #[napi(constructor)]
+    pub fn new(program: &NodeProgram, input: Option<String>) -> NodeVm {
+        NodeVm {
+            vm: Vm::new(program.program.clone(), &VmOptions::default()),
+            input: input.unwrap_or_default().bytes().collect(),
+            output: Vec::new(),
+        }
+    }
+
+    /// Run one op, returning whether there are more
+    #[napi]
+    pub fn step(&mut self) -> napi::Result<bool> {
+        self.vm.step(&mut self.input, &mut self.output).map_err(failure)?;
+        Ok(!self.vm.is_finished())
+    }
+
+    /// Run up to `max_steps` ops, or to the end without it, returning whether there are more
+    #[napi]
+    pub fn run(&mut self, max_steps: Option<u32>) -> napi::Result<bool> {
+        let mut steps = 0;
+        while !self.vm.is_finished() && max_steps.is_none_or(|max_steps| steps < max_steps) {
+            self.vm.step(&mut self.input, &mut self.output).map_err(failure)?;
+            steps += 1;
+        }
+        Ok(!self.vm.is_finished())
+    }
+
+    /// Add to the input that `,` reads, the end of it reads as 0
+    #[napi]
+    pub fn push_input(&mut self, input: String) {
+        self.input.extend(input.bytes());
+    }
+
+    /// The output since the last call
+    #[napi]
+    pub fn take_output(&mut self) -> String {
+        let output = String::from_utf8_lossy(&self.output).into_owned();
+        self.output.clear();
+        output
+    }
+
+    /// The cells of the tape
+    #[napi(getter)]
+    pub fn tape(&self) -> Buffer {
+        self.vm.tape().data.clone().into()
+    }
+
+    #[napi(getter)]
+    pub fn pointer(&self) -> u32 {
+        self.vm.tape().pointer as u32
+    }
+
+    /// Index of the next op
+    #[napi(getter)]
+    pub fn ip(&self) -> u32 {
+        self.vm.ip() as u32
+    }
+
+    #[napi(getter)]
+    pub fn finished(&self) -> bool {
+        self.vm.is_finished()
+    }
+}
@@ -0,0 +1,161 @@
+//! Running programs as a pipeline, each one's output being the next one's input, like shell filters.
+//! Every program runs on its own thread, connected by bounded channels.
+
+use std::{error::Error, io::{self, Read, Write}, sync::mpsc::{self, Receiver, SyncSender}, thread};
+use crate::{DebugOptions, Program, Vm, VmOptions, error::RuntimeError, run_program};
+
+
+/// Output is sent in chunks of up to this many bytes, and programs flush it before reading input
+const CHUNK_SIZE: usize = 4096;
+/// How many chunks can wait for the next program before the one writing them blocks
+const CHANNEL_CHUNKS: usize = 16;
+
+
+/// Sends what's written to the next program
+pub struct ChannelWriter {
+    sender: SyncSender<Vec<u8>>,
+    buffer: Vec<u8>,
+}
+
+
+impl Write for ChannelWriter {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(bytes);
+        if self.buffer.len() >= CHUNK_SIZE {
+            self.flush()?;
+        }
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let chunk = std::mem::take(&mut self.buffer);
+        // The next program stopped reading, like a closed pipe
+        self.sender.send(chunk).map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))
+    }
+}
+
+
+/// Reads what the previous program wrote, ending when it finishes
+pub struct ChannelReader {
+    receiver: Receiver<Vec<u8>>,
+    chunk: Vec<u8>,
+    position: usize,
+}
+
+
+impl Read for ChannelReader {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        if self.position == self.chunk.len() {
+            match self.receiver.recv() {
+                Ok(chunk) => (self.chunk, self.position) = (chunk, 0),
+                Err(_) => return Ok(0),
+            }
+        }
+        let count = buffer.len().min(self.chunk.len() - self.position);
+        buffer[..count].copy_from_slice(&self.chunk[self.position..self.position + count]);
+        self.position += count;
+        Ok(count)
+    }
+}
+
+
+/// A connected writer and reader
+pub fn channel() -> (ChannelWriter, ChannelReader) {
+    let (sender, receiver) = mpsc::sync_channel(CHANNEL_CHUNKS);
+    (ChannelWriter { sender, buffer: Vec::new() }, ChannelReader { receiver, chunk: Vec::new(), position: 0 })
+}
+
+
+/// Errors have to cross threads, so like `error::runtime` everything but I/O errors becomes a `RuntimeError`
+fn sendable(error: Box<dyn Error>) -> Box<dyn Error + Send + Sync> {
+    match error.downcast::<io::Error>() {
+        Ok(error) => error,
+        Err(error) => Box::new(RuntimeError(error.to_string())),
+    }
+}
+
+
+/// Run programs concurrently, the first reading `input` and the last writing `output`.
+/// A program writing after the next one has finished stops there, without an error.
+/// Returns the last program's finished vm, or the first error.
+pub fn run_pipeline(
+    stages: Vec<(Program, VmOptions)>,
+    input: &mut (dyn Read + Send),
+    output: &mut (dyn Write + Send),
+) -> Result<Vm, Box<dyn Error + Send + Sync>> {
+    let count = stages.len();
+    if count == 0 {
+        return Err("a pipeline needs at least one program".into());
+    }
+    let (mut writers, mut readers): (Vec<_>, Vec<_>) = (1..count).map(|_| channel()).unzip();
+    writers.reverse();
+    readers.reverse();
+    let mut first_input = Some(input);
+    let mut last_output = Some(output);
+
+    thread::scope(|scope| {
+        let handles: Vec<_> = stages.into_iter().enumerate().map(|(i, (program, vm_options))| {
+            let mut input: Box<dyn Read + Send + '_> = match first_input.take() {
+                Some(input) => Box::new(input),
+                None => Box::new(readers.pop().expect("a reader for every program after the first")),
+            };
+            let mut output: Box<dyn Write + Send + '_> = if i + 1 == count {
+                Box::new(last_output.take().expect("one output for the last program"))
+            } else {
+                Box::new(writers.pop().expect("a writer for every program before the last"))
+            };
+
+            scope.spawn(move || {
+                let result = run_program(program, &vm_options, &DebugOptions::default(), &mut input, &mut output).map_err(sendable);
+                // Dropping the ends now lets the programs next to this one see it finished
+                drop((input, output));
+                result
+            })
+        }).collect();
+
+        let mut last = None;
+        let mut first_error = None;
+        for (i, handle) in handles.into_iter().enumerate() {
+            match handle.join().expect("programs don't panic") {
+                Ok(vm) => last = Some(vm),
+                // The next program finished first, so the rest of this one's output isn't needed
+                Err(error) if i + 1 < count && error.downcast_ref::<io::Error>()
+                    .is_some_and(|error| error.kind() == io::ErrorKind::BrokenPipe) => (),
+                Err(error) => { first_error.get_or_insert(error); },
+            }
+        }
+        match first_error {
+            Some(error) => Err(error),
+            None => Ok(last.expect("the last program finished")),
+        }
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ParseOptions, parse};
+
+    fn stage(code: &str) -> (Program, VmOptions) {
+        (Program::compile(&parse(code, &ParseOptions::default()).unwrap(), true).unwrap(), VmOptions::default())
+    }
+
+    #[test]
+    fn programs_feed_each_other() {
+        let mut output = Vec::new();
+        run_pipeline(vec![stage(",[+.,]"), stage(",[+.,]"), stage(",[.,]")], &mut &b"abc"[..], &mut output).unwrap();
+        assert_eq!(output, b"cde");
+    }
+
+    #[test]
+    fn reader_finishing_early() {
+        // The first program never stops writing, the second reads one byte
+        let mut output = Vec::new();
+        run_pipeline(vec![stage("+[.]"), stage(",.")], &mut io::empty(), &mut output).unwrap();
+        assert_eq!(output, [1]);
+    }
+}
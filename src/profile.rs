@@ -0,0 +1,237 @@
+//! Counting the ops a program runs, as folded stacks for flamegraph tools, and timing each kind of op
+
+use std::{collections::BTreeMap, error::Error, io::{Read, Write}, time::{Duration, Instant}};
+use crate::{Op, Program, Vm, VmOptions};
+use crate::emit::source_map::LineIndex;
+
+
+/// Run a program to the end, counting how many times each op ran
+pub fn run_profiled(
+    program: Program,
+    vm_options: &VmOptions,
+    input: &mut impl Read,
+    output: &mut impl Write,
+) -> Result<(Vm, Vec<u64>), Box<dyn Error>> {
+    let mut counts = vec![0; program.ops.len()];
+    let mut vm = Vm::new(program, vm_options);
+    while !vm.is_finished() {
+        counts[vm.ip()] += 1;
+        if let Err(error) = vm.step(input, output) {
+            output.flush()?;
+            return Err(error);
+        }
+        if let Some(hang) = vm.take_hang() {
+            output.flush()?;
+            return Err(Box::new(hang));
+        }
+    }
+    output.flush()?;
+    Ok((vm, counts))
+}
+
+
+/// The kinds of op timed by `run_timed`: ops from one instruction as that instruction, then the ones the optimizer
+/// makes from several
+pub const KINDS: [&str; 21] = [
+    "+", "-", ">", "<", "[", "]", ",", ".", "@", "(", ")", ":", "^", "&", "$", "%",
+    "+n", ">n", "<n", "[-]", "*>nxm",
+];
+
+
+/// The index in `KINDS` of the kind of `op`
+pub fn kind(op: Op) -> usize {
+    match op {
+        Op::Add(1) => 0,
+        Op::Add(127) => 1,
+        Op::Right(1) => 2,
+        Op::Left(1) => 3,
+        Op::JumpIfZero(_) => 4,
+        Op::JumpIfNonZero(_) => 5,
+        Op::Input => 6,
+        Op::Output => 7,
+        Op::Break => 8,
+        Op::ProcedureOpen(_) => 9,
+        Op::ProcedureClose => 10,
+        Op::Call => 11,
+        Op::NextTape => 12,
+        Op::CopyToNextTape => 13,
+        Op::Push => 14,
+        Op::Pop => 15,
+        Op::Add(_) => 16,
+        Op::Right(_) => 17,
+        Op::Left(_) => 18,
+        Op::Clear => 19,
+        Op::MulAdd { .. } => 20,
+    }
+}
+
+
+/// How many times one kind of op ran, and how long that took altogether
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Timed {
+    pub count: u64,
+    pub time: Duration,
+}
+
+
+/// What `run_timed` measured
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Times {
+    /// By the index of their kind in `KINDS`
+    pub kinds: [Timed; KINDS.len()],
+    /// The whole run, including the time between ops, in timing them and in anything else watching the run
+    pub total: Duration,
+}
+
+
+/// Adds up how long each kind of op takes as a program runs
+pub struct Timer {
+    kinds: [Timed; KINDS.len()],
+    start: Instant,
+}
+
+
+impl Timer {
+    pub fn start() -> Timer {
+        Timer { kinds: [Timed::default(); KINDS.len()], start: Instant::now() }
+    }
+
+    /// Count `op` as having run from `before` until now
+    pub fn record(&mut self, op: Op, before: Instant) {
+        let timed = &mut self.kinds[kind(op)];
+        timed.time += before.elapsed();
+        timed.count += 1;
+    }
+
+    /// The times so far, with the total since `start`
+    pub fn times(&self) -> Times {
+        Times { kinds: self.kinds, total: self.start.elapsed() }
+    }
+}
+
+
+/// A row for each kind of op that ran, slowest first, and one for the time spent between ops
+pub fn times_table(times: &Times) -> String {
+    let total = times.total.as_secs_f64().max(f64::EPSILON);
+    let mut kinds: Vec<(&str, Timed)> = KINDS.iter().copied().zip(times.kinds).filter(|(_, timed)| timed.count > 0).collect();
+    kinds.sort_by_key(|(_, timed)| std::cmp::Reverse(timed.time));
+    let in_ops: Duration = kinds.iter().map(|(_, timed)| timed.time).sum();
+
+    let mut rows: Vec<[String; 5]> = kinds.iter().map(|(kind, timed)| [
+        kind.to_string(),
+        timed.count.to_string(),
+        format!("{:.3}ms", timed.time.as_secs_f64() * 1e3),
+        format!("{:.1}", timed.time.as_nanos() as f64 / timed.count as f64),
+        format!("{:.1}%", timed.time.as_secs_f64() / total * 100.0),
+    ]).collect();
+    let between = times.total.saturating_sub(in_ops);
+    rows.push([
+        "between ops".to_string(),
+        String::new(),
+        format!("{:.3}ms", between.as_secs_f64() * 1e3),
+        String::new(),
+        format!("{:.1}%", between.as_secs_f64() / total * 100.0),
+    ]);
+    let headings = ["op", "count", "time", "ns/op", "share"].map(str::to_string);
+    let widths: Vec<usize> = (0..5).map(|column| {
+        rows.iter().chain([&headings]).map(|row| row[column].chars().count()).max().unwrap_or(0)
+    }).collect();
+
+    let mut table = String::new();
+    for row in [&headings].into_iter().chain(&rows) {
+        let line = format!(
+            "{:<a$}  {:>b$}  {:>c$}  {:>d$}  {:>e$}", row[0], row[1], row[2], row[3], row[4],
+            a = widths[0], b = widths[1], c = widths[2], d = widths[3], e = widths[4],
+        );
+        table += line.trim_end();
+        table += "\n";
+    }
+    table
+}
+
+
+/// The counts as folded stacks, one `program;loop@1:3;loop@2:5 count` line per loop.
+/// Loops are named by where they start in `source`, or by op index without it.
+/// A loop's frame is its body, so the instructions in it count towards it and its parents.
+pub fn folded(program: &Program, counts: &[u64], root: &str, source: Option<&str>) -> String {
+    let index = source.map(LineIndex::new);
+    let name = |op: usize| {
+        let kind = if matches!(program.ops[op], Op::ProcedureOpen(_)) { "procedure" } else { "loop" };
+        match (source, &index, program.offset(op)) {
+            (Some(source), Some(index), Some(offset)) => {
+                let (line, column) = index.line_column(source, offset);
+                format!("{}@{}:{}", kind, line + 1, column + 1)
+            },
+            _ => format!("{}@op{}", kind, op),
+        }
+    };
+
+    // Stacks are keyed by the opening ops of the loops around them, which keeps them in program order
+    let mut stacks: BTreeMap<Vec<usize>, u64> = BTreeMap::new();
+    let mut open: Vec<usize> = Vec::new();
+    for (i, op) in program.ops.iter().enumerate() {
+        let closes = matches!(op, Op::JumpIfNonZero(_) | Op::ProcedureClose);
+        // The checks at both ends of a loop belong to it
+        if matches!(op, Op::JumpIfZero(_) | Op::ProcedureOpen(_)) {
+            open.push(i);
+        }
+        if counts[i] > 0 {
+            *stacks.entry(open.clone()).or_default() += counts[i];
+        }
+        if closes {
+            open.pop();
+        }
+    }
+
+    let mut folded = String::new();
+    for (stack, count) in stacks {
+        folded += root;
+        for op in stack {
+            folded += ";";
+            folded += &name(op);
+        }
+        folded += &format!(" {}\n", count);
+    }
+    folded
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_with_offsets, ParseOptions};
+
+    #[test]
+    fn nested_loops() {
+        let source = "++[>++\n[-.]<-]";
+        let program = Program::compile_with_offsets(&parse_with_offsets(source, &ParseOptions::default()).unwrap(), true).unwrap();
+        let (_, counts) = run_profiled(program.clone(), &VmOptions::default(), &mut std::io::empty(), &mut std::io::sink()).unwrap();
+
+        assert_eq!(
+            folded(&program, &counts, "a.b", Some(source)),
+            "a.b 1\na.b;loop@1:3 11\na.b;loop@1:3;loop@2:1 14\n"
+        );
+        assert!(folded(&program, &counts, "a.b", None).contains("a.b;loop@op1;loop@op4 14\n"));
+    }
+
+    #[test]
+    fn times_by_kind() {
+        let program = Program::compile(&crate::parse("++[>+++<-]>[-].", &ParseOptions::default()).unwrap(), true).unwrap();
+        let mut vm = Vm::new(program, &VmOptions::default());
+        let mut timer = Timer::start();
+        while let Some(op) = vm.current_op() {
+            let before = Instant::now();
+            vm.step(&mut std::io::empty(), &mut std::io::sink()).unwrap();
+            timer.record(op, before);
+        }
+        let times = timer.times();
+        let count = |kind: &str| times.kinds[KINDS.iter().position(|&k| k == kind).unwrap()].count;
+        // `++` is folded, the loop becomes a multiply and a clear
+        assert_eq!([count("+n"), count("*>nxm"), count("[-]"), count(">"), count("."), count("[")], [1, 1, 2, 1, 1, 0]);
+
+        let table = times_table(&times);
+        assert!(table.starts_with("op "));
+        assert_eq!(table.lines().count(), 1 + 5 + 1);
+        assert!(table.lines().last().unwrap().starts_with("between ops"));
+    }
+}
@@ -0,0 +1,111 @@
+//! A line on stderr showing how far a long run has got, so a slow program can be told apart from a hang.
+
+use std::{io::{self, IsTerminal, Write}, time::{Duration, Instant}};
+use crate::{Vm, emit::source_map::LineIndex};
+
+
+/// How often the line is redrawn on a terminal
+const INTERVAL: Duration = Duration::from_millis(250);
+
+/// How often a new line is written when stderr isn't a terminal, like a log file
+const LOGGED_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Steps between looking at the clock
+const CHECK_EVERY: usize = 4096;
+
+
+/// The source to show positions in, if it isn't bytecode
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub struct Progress {
+    pub source: Option<String>,
+}
+
+
+/// The running side of a progress line
+pub struct Reporter {
+    source: Option<(String, LineIndex)>,
+    terminal: bool,
+    start: Instant,
+    last: Instant,
+    last_steps: usize,
+}
+
+
+impl Progress {
+    pub fn start(&self) -> Reporter {
+        let source = self.source.as_ref().map(|source| (source.clone(), LineIndex::new(source)));
+        let now = Instant::now();
+        Reporter { source, terminal: io::stderr().is_terminal(), start: now, last: now, last_steps: 0 }
+    }
+}
+
+
+impl Reporter {
+    /// Show the vm's state if it's been long enough since the last time, after `steps` steps
+    pub fn step(&mut self, vm: &Vm, steps: usize) {
+        let interval = if self.terminal { INTERVAL } else { LOGGED_INTERVAL };
+        if steps.is_multiple_of(CHECK_EVERY) && self.last.elapsed() >= interval {
+            self.show(vm, steps);
+        }
+    }
+
+    /// Show the finished vm, leaving the line there
+    pub fn finish(&mut self, vm: &Vm, steps: usize) {
+        self.show(vm, steps);
+        if self.terminal {
+            eprintln!();
+        }
+    }
+
+    fn show(&mut self, vm: &Vm, steps: usize) {
+        let rate = (steps - self.last_steps) as f64 / self.last.elapsed().as_secs_f64().max(f64::EPSILON);
+        self.last = Instant::now();
+        self.last_steps = steps;
+        let at = match (&self.source, vm.program().offset(vm.ip())) {
+            _ if vm.is_finished() => "the end".to_string(),
+            (Some((source, index)), Some(offset)) => {
+                let (line, column) = index.line_column(source, offset);
+                format!("line {} column {}", line + 1, column + 1)
+            },
+            _ => format!("op {}", vm.ip()),
+        };
+        let line = line(steps, rate, self.start.elapsed(), &at);
+        let mut stderr = io::stderr().lock();
+        // Clearing the rest of the old line, in case it was longer
+        let _ = match self.terminal {
+            true => write!(stderr, "\r{}\x1b[K", line),
+            false => writeln!(stderr, "{}", line),
+        };
+        let _ = stderr.flush();
+    }
+}
+
+
+/// `12.3M steps, 45.6M/s, 1.2s, at line 3 column 7`
+fn line(steps: usize, rate: f64, elapsed: Duration, at: &str) -> String {
+    format!("{} steps, {}/s, {:.1}s, at {}", short(steps as f64), short(rate), elapsed.as_secs_f64(), at)
+}
+
+
+/// A number with a K, M or G suffix once it's that big
+fn short(n: f64) -> String {
+    match [(1e9, "G"), (1e6, "M"), (1e3, "K")].iter().find(|(size, _)| n >= *size) {
+        Some((size, suffix)) => format!("{:.1}{}", n / size, suffix),
+        None => format!("{}", n.round()),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines() {
+        assert_eq!(line(12_345_678, 4_560_000.0, Duration::from_millis(2710), "line 3 column 7"), "12.3M steps, 4.6M/s, 2.7s, at line 3 column 7");
+        assert_eq!(line(999, 0.4, Duration::ZERO, "op 5"), "999 steps, 0/s, 0.0s, at op 5");
+        assert_eq!(short(1500.0), "1.5K");
+        assert_eq!(short(2e9), "2.0G");
+    }
+}
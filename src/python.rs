@@ -0,0 +1,140 @@
+//! pyo3 bindings, built as the `bf_rs` Python module with `maturin develop --features python`.
+//! Parse and macro errors raise `ValueError`, errors while running raise `RuntimeError`.
+
+use std::{collections::VecDeque, error::Error};
+use pyo3::{prelude::*, exceptions::{PyRuntimeError, PyValueError}, types::PyBytes};
+use crate::{ParseOptions, Program, Vm, VmOptions, disasm, parse_with_offsets};
+
+
+fn value_error(error: Box<dyn Error>) -> PyErr {
+    PyValueError::new_err(error.to_string())
+}
+
+
+fn runtime_error(error: Box<dyn Error>) -> PyErr {
+    PyRuntimeError::new_err(error.to_string())
+}
+
+
+fn compile(code: &str, macros: bool, optimize: bool) -> Result<Program, Box<dyn Error>> {
+    let options = ParseOptions { macros, ..ParseOptions::default() };
+    Ok(Program::compile_with_offsets(&parse_with_offsets(code, &options)?, optimize)?)
+}
+
+
+/// A compiled program
+#[pyclass(name = "Program", frozen)]
+pub struct PyProgram {
+    program: Program,
+}
+
+
+#[pymethods]
+impl PyProgram {
+    /// Without `optimize`, every op is one instruction, which is easier to step through
+    #[new]
+    #[pyo3(signature = (code, macros = false, optimize = true))]
+    fn new(code: &str, macros: bool, optimize: bool) -> PyResult<PyProgram> {
+        Ok(PyProgram { program: compile(code, macros, optimize).map_err(value_error)? })
+    }
+
+    /// The ops, as `--dump-ir` shows them
+    fn ops(&self) -> Vec<String> {
+        self.program.ops.iter().map(|op| op.to_string()).collect()
+    }
+
+    fn __len__(&self) -> usize {
+        self.program.ops.len()
+    }
+
+    fn __str__(&self) -> String {
+        disasm::disassemble(&self.program)
+    }
+}
+
+
+/// A program being run, with its input and output as strings
+#[pyclass(name = "Vm")]
+pub struct PyVm {
+    vm: Vm,
+    input: VecDeque<u8>,
+    output: Vec<u8>,
+}
+
+
+#[pymethods]
+impl PyVm {
+    #[new]
+    #[pyo3(signature = (program, input = ""))]
+    fn new(program: &PyProgram, input: &str) -> PyVm {
+        PyVm {
+            vm: Vm::new(program.program.clone(), &VmOptions::default()),
+            input: input.bytes().collect(),
+            output: Vec::new(),
+        }
+    }
+
+    /// Run one op, returning whether there are more
+    fn step(&mut self) -> PyResult<bool> {
+        self.vm.step(&mut self.input, &mut self.output).map_err(runtime_error)?;
+        Ok(!self.vm.is_finished())
+    }
+
+    /// Run until the program ends
+    fn run(&mut self) -> PyResult<()> {
+        self.vm.run(&mut self.input, &mut self.output).map_err(runtime_error)
+    }
+
+    /// Add to the input that `,` reads, the end of it reads as 0
+    fn push_input(&mut self, input: &str) {
+        self.input.extend(input.bytes());
+    }
+
+    /// Everything written so far
+    #[getter]
+    fn output(&self) -> String {
+        String::from_utf8_lossy(&self.output).into_owned()
+    }
+
+    /// The cells of the tape, as bytes so `memoryview` and `numpy.frombuffer` can use it
+    #[getter]
+    fn tape<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, &self.vm.tape().data)
+    }
+
+    #[getter]
+    fn pointer(&self) -> usize {
+        self.vm.tape().pointer
+    }
+
+    /// Index of the next op
+    #[getter]
+    fn ip(&self) -> usize {
+        self.vm.ip()
+    }
+
+    #[getter]
+    fn finished(&self) -> bool {
+        self.vm.is_finished()
+    }
+}
+
+
+/// Run a program to the end with `input`, returning its output
+#[pyfunction]
+#[pyo3(signature = (code, input = "", macros = false))]
+fn run(code: &str, input: &str, macros: bool) -> PyResult<String> {
+    let program = compile(code, macros, true).map_err(value_error)?;
+    let mut output = Vec::new();
+    Vm::new(program, &VmOptions::default()).run(&mut input.as_bytes(), &mut output).map_err(runtime_error)?;
+    Ok(String::from_utf8_lossy(&output).into_owned())
+}
+
+
+#[pymodule]
+fn bf_rs(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<PyProgram>()?;
+    module.add_class::<PyVm>()?;
+    module.add_function(wrap_pyfunction!(run, module)?)?;
+    Ok(())
+}
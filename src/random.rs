@@ -0,0 +1,186 @@
+//! Random plain brainfuck programs with matched brackets, for property tests, the fuzz targets and the
+//! `gen-random` subcommand.
+
+use std::str::FromStr;
+use crate::Instruction;
+
+
+/// How often each kind of instruction is picked, relative to the others. `loops` is for opening a loop, and
+/// closing one is as likely as opening one while any are open.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Weights {
+    pub plus: u32,
+    pub minus: u32,
+    pub left: u32,
+    pub right: u32,
+    pub loops: u32,
+    pub input: u32,
+    pub output: u32,
+}
+
+
+impl Default for Weights {
+    fn default() -> Weights {
+        Weights { plus: 4, minus: 4, left: 3, right: 3, loops: 2, input: 1, output: 1 }
+    }
+}
+
+
+impl FromStr for Weights {
+    type Err = String;
+
+    /// Some of `plus=4,minus=4,left=3,right=3,loops=2,input=1,output=1`, the rest keeping their defaults
+    fn from_str(weights: &str) -> Result<Weights, String> {
+        let mut parsed = Weights::default();
+        for weight in weights.split(',').filter(|weight| !weight.is_empty()) {
+            let (name, value) = weight.split_once('=').ok_or_else(|| format!("expected NAME=WEIGHT, not {}", weight))?;
+            let value = value.parse().map_err(|_| format!("{} isn't a weight, which is a whole number", value))?;
+            *match name {
+                "plus" => &mut parsed.plus,
+                "minus" => &mut parsed.minus,
+                "left" => &mut parsed.left,
+                "right" => &mut parsed.right,
+                "loops" => &mut parsed.loops,
+                "input" => &mut parsed.input,
+                "output" => &mut parsed.output,
+                _ => return Err(format!("there's no weight called {}, the names are plus, minus, left, right, loops, input and output", name)),
+            } = value;
+        }
+        Ok(parsed)
+    }
+}
+
+
+/// The shape of the programs generated
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Options {
+    /// Most instructions in a program, brackets included
+    pub max_length: usize,
+    /// How deep loops can nest
+    pub max_depth: usize,
+    pub weights: Weights,
+}
+
+
+impl Default for Options {
+    fn default() -> Options {
+        Options { max_length: 100, max_depth: 5, weights: Weights::default() }
+    }
+}
+
+
+/// Where the random choices come from: a seeded generator, or the fuzzer's bytes
+pub trait Choices {
+    /// A number from 0 up to but not including `n`
+    fn below(&mut self, n: usize) -> usize;
+}
+
+
+/// A small seeded generator (SplitMix64), so the same seed gives the same programs on every platform
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+}
+
+
+impl Choices for Rng {
+    fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n.max(1) as u64) as usize
+    }
+}
+
+
+/// A random program of up to `options.max_length` instructions, with every bracket matched
+pub fn generate(choices: &mut impl Choices, options: &Options) -> Vec<Instruction> {
+    let Weights { plus, minus, left, right, loops, input, output } = options.weights;
+    let length = choices.below(options.max_length + 1);
+    let mut instructions = Vec::with_capacity(length);
+    let mut depth = 0;
+    while instructions.len() + depth < length {
+        // A loop can only open with room to close it, and as deep as allowed
+        let open = if depth < options.max_depth && instructions.len() + depth + 2 <= length { loops } else { 0 };
+        let close = if depth > 0 { loops } else { 0 };
+        let kinds = [
+            (plus, Instruction::Increment), (minus, Instruction::Decrement), (left, Instruction::Left),
+            (right, Instruction::Right), (open, Instruction::Open), (close, Instruction::Close),
+            (input, Instruction::Input), (output, Instruction::Output),
+        ];
+        let total: u32 = kinds.iter().map(|(weight, _)| weight).sum();
+        if total == 0 {
+            break;
+        }
+        let mut pick = choices.below(total as usize) as u32;
+        let &(_, instruction) = kinds.iter().find(|(weight, _)| {
+            let found = pick < *weight;
+            pick = pick.saturating_sub(*weight);
+            found
+        }).unwrap();
+        match instruction {
+            Instruction::Open => depth += 1,
+            Instruction::Close => depth -= 1,
+            _ => (),
+        }
+        instructions.push(instruction);
+    }
+    instructions.extend(std::iter::repeat_n(Instruction::Close, depth));
+    instructions
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Program, reference};
+
+    #[test]
+    fn shapes() {
+        let options = Options { max_length: 40, max_depth: 2, ..Options::default() };
+        let mut rng = Rng::new(7);
+        for _ in 0..200 {
+            let program = generate(&mut rng, &options);
+            assert!(program.len() <= 40);
+            let mut depth = 0i32;
+            for instruction in &program {
+                depth += (*instruction == Instruction::Open) as i32 - (*instruction == Instruction::Close) as i32;
+                assert!((0..=2).contains(&depth));
+            }
+            assert_eq!(depth, 0);
+        }
+        assert_eq!(generate(&mut Rng::new(1), &options), generate(&mut Rng::new(1), &options));
+
+        let only_plus = Options { weights: "minus=0,left=0,right=0,loops=0,input=0,output=0".parse().unwrap(), ..options };
+        assert!(generate(&mut rng, &only_plus).iter().all(|&instruction| instruction == Instruction::Increment));
+        assert!("plus=x".parse::<Weights>().is_err());
+        assert!("jump=1".parse::<Weights>().is_err());
+    }
+
+    #[test]
+    fn optimizer_matches_reference() {
+        let mut rng = Rng::new(2024);
+        let mut compared = 0;
+        for _ in 0..300 {
+            let instructions = generate(&mut rng, &Options::default());
+            let input = b"some input";
+            let Some(expected) = reference::run(&instructions, input, 10_000).unwrap() else {
+                continue;
+            };
+            let program = Program::compile(&instructions, true).unwrap();
+            assert_eq!(reference::run_vm(program, input, 20_000), Some(expected), "{:?}", instructions);
+            compared += 1;
+        }
+        assert!(compared > 100, "only {} programs finished", compared);
+    }
+}
@@ -0,0 +1,179 @@
+//! A deliberately simple interpreter for plain brainfuck, to check the compiler and vm against.
+//!
+//! It runs instructions as they were parsed, one at a time, searching for matching brackets every time it jumps,
+//! so it is slow but easy to see it is right.
+
+use std::error::Error;
+use crate::{Instruction, Program, Tape, Vm, VmOptions};
+
+
+/// Steps `verify` lets the reference interpreter take before giving up on a program
+pub const MAX_STEPS: usize = 1_000_000;
+
+
+/// How a run ended. Trailing zeros are removed from the tape, since optimized ops can grow it without writing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Outcome {
+    pub error: Option<String>,
+    pub output: Vec<u8>,
+    pub cells: Vec<u8>,
+    pub pointer: usize,
+}
+
+
+impl Outcome {
+    fn new(error: Option<String>, output: Vec<u8>, mut cells: Vec<u8>, pointer: usize) -> Outcome {
+        let length = cells.iter().rposition(|&cell| cell != 0).map_or(0, |last| last + 1);
+        cells.truncate(length);
+        Outcome { error, output, cells, pointer }
+    }
+}
+
+
+/// Run plain brainfuck for up to `max_steps` instructions, `None` if it didn't finish by then.
+/// Errors if the program has brackets that don't match or instructions from pbrain or the extensions.
+pub fn run(instructions: &[Instruction], input: &[u8], max_steps: usize) -> Result<Option<Outcome>, Box<dyn Error>> {
+    let mut cells = vec![0u8];
+    let mut pointer = 0;
+    let mut input = input.iter();
+    let mut output = Vec::new();
+    let mut ip = 0;
+
+    for _ in 0..max_steps {
+        let Some(instruction) = instructions.get(ip) else {
+            return Ok(Some(Outcome::new(None, output, cells, pointer)));
+        };
+        match instruction {
+            Instruction::Increment => cells[pointer] = (cells[pointer] + 1) % 128,
+            Instruction::Decrement => cells[pointer] = (cells[pointer] + 127) % 128,
+            Instruction::Right => {
+                pointer += 1;
+                if pointer == cells.len() {
+                    cells.push(0);
+                }
+            },
+            Instruction::Left => pointer = pointer.saturating_sub(1),
+            Instruction::Output => output.push(cells[pointer]),
+            Instruction::Input => {
+                let byte = input.next().copied().unwrap_or(0);
+                if byte > 127 {
+                    let error = Some("input must be an ASCII character".to_string());
+                    return Ok(Some(Outcome::new(error, output, cells, pointer)));
+                }
+                cells[pointer] = byte;
+            },
+            Instruction::Open if cells[pointer] == 0 => ip = matching(instructions, ip)?,
+            Instruction::Close if cells[pointer] != 0 => ip = matching(instructions, ip)?,
+            Instruction::Open | Instruction::Close | Instruction::Break => (),
+            _ => return Err("the reference interpreter only runs plain brainfuck".into()),
+        }
+        ip += 1;
+    }
+    Ok(instructions.get(ip).is_none().then(|| Outcome::new(None, output, cells, pointer)))
+}
+
+
+/// Index of the bracket matching the one at `ip`
+fn matching(instructions: &[Instruction], ip: usize) -> Result<usize, Box<dyn Error>> {
+    let forwards = instructions[ip] == Instruction::Open;
+    let mut depth = 0;
+    let mut i = ip;
+    loop {
+        match instructions[i] {
+            Instruction::Open => depth += 1,
+            Instruction::Close => depth -= 1,
+            _ => (),
+        }
+        if depth == 0 {
+            return Ok(i);
+        }
+        i = match forwards {
+            true if i + 1 < instructions.len() => i + 1,
+            false if i > 0 => i - 1,
+            _ => return Err("all brackets must have matching brackets".into()),
+        };
+    }
+}
+
+
+/// Run a compiled program on the vm for up to `max_steps` ops, `None` if it didn't finish by then
+pub fn run_vm(program: Program, input: &[u8], max_steps: usize) -> Option<Outcome> {
+    run_vm_with(program, &VmOptions::default(), input, max_steps)
+}
+
+
+/// The same as `run_vm`, with a vm made with `options`. Only the active tape ends up in the outcome.
+pub fn run_vm_with(program: Program, options: &VmOptions, mut input: &[u8], max_steps: usize) -> Option<Outcome> {
+    let mut vm = Vm::new(program, options);
+    let mut output = Vec::new();
+    let mut error = None;
+    for _ in 0..max_steps {
+        if vm.is_finished() {
+            break;
+        }
+        if let Err(step_error) = vm.step(&mut input, &mut output) {
+            error = Some(step_error.to_string());
+            break;
+        }
+    }
+    if error.is_none() && !vm.is_finished() {
+        return None;
+    }
+    let Tape { data, pointer } = vm.tape().clone();
+    Some(Outcome::new(error, output, data, pointer))
+}
+
+
+/// Run a program with the reference interpreter and optimized on the vm, and check they end the same way:
+/// with the same error, output, tape and pointer.
+pub fn verify(instructions: &[Instruction], input: &[u8]) -> Result<(), Box<dyn Error>> {
+    let Some(expected) = run(instructions, input, MAX_STEPS)? else {
+        return Err(format!("the program didn't finish in {} steps", MAX_STEPS).into());
+    };
+    let program = Program::compile(instructions, true)?;
+    // Folding a loop can add an op when it doesn't run, so give the vm some leeway
+    let Some(actual) = run_vm(program, input, MAX_STEPS * 2) else {
+        return Err("the optimized program didn't finish, but the reference one did".into());
+    };
+
+    if actual.error != expected.error {
+        return Err(format!("the reference interpreter ended with {:?}, the vm with {:?}", expected.error, actual.error).into());
+    }
+    if actual.output != expected.output {
+        return Err(format!("the reference interpreter wrote {:?}, the vm {:?}", expected.output, actual.output).into());
+    }
+    if (&actual.cells, actual.pointer) != (&expected.cells, expected.pointer) {
+        return Err(format!(
+            "the reference interpreter ended with tape {:?} at {}, the vm with {:?} at {}",
+            expected.cells, expected.pointer, actual.cells, actual.pointer,
+        ).into());
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ParseOptions, parse};
+
+    fn instructions(code: &str) -> Vec<Instruction> {
+        parse(code, &ParseOptions::default()).unwrap()
+    }
+
+    #[test]
+    fn reference_runs() {
+        let outcome = run(&instructions(",[->++<]>."), b"\x03", 100).unwrap().unwrap();
+        assert_eq!(outcome, Outcome { error: None, output: vec![6], cells: vec![0, 6], pointer: 1 });
+        assert_eq!(run(&instructions("+[]"), b"", 100).unwrap(), None);
+        assert!(run(&instructions("+]"), b"", 100).is_err());
+    }
+
+    #[test]
+    fn optimized_matches_reference() {
+        for code in ["++++++++[>++++[>++>+++<<-]>+<<-]>>.>.", "<<-[+>-<]>[-]", ",[.,]", "+[->>+++<<]>>[-<+>]"] {
+            verify(&instructions(code), b"hello").unwrap();
+        }
+        assert!(verify(&instructions(","), &[200]).is_ok());
+    }
+}
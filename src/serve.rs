@@ -0,0 +1,254 @@
+//! A playground in the browser: a small HTTP server with a page to write programs on and run them.
+//! Programs run natively in the server, one request at a time, and stop after a limit so a loop that never ends
+//! can't hold it up.
+
+use std::{error::Error, fmt, io::{self, BufRead, BufReader, Read, Write}, net::{TcpListener, TcpStream}, time::Duration};
+use serde::Deserialize;
+use serde_json::json;
+use crate::{Extension, ParseOptions, Program, Vm, VmOptions, parse_with_offsets};
+
+
+const PAGE: &str = include_str!("serve.html");
+
+/// Ops a program can run before it's stopped
+const STEP_LIMIT: u64 = 10_000_000;
+
+/// Bytes of output kept, beyond which the program is stopped
+const OUTPUT_LIMIT: usize = 1 << 20;
+
+/// Most bytes of a request body, bigger ones get a 413
+const BODY_LIMIT: usize = 1 << 20;
+
+/// Most bytes of a request line and headers together
+const HEAD_LIMIT: u64 = 64 << 10;
+
+/// How long a client has for each read and write, so one that goes quiet doesn't hold up the others
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+
+/// An HTTP request, with only what the server looks at
+#[derive(Debug)]
+pub(crate) struct Request {
+    pub method: String,
+    pub path: String,
+    pub body: Vec<u8>,
+}
+
+
+/// A request with a `Content-Length` over `BODY_LIMIT`, as the error inside an `io::Error`
+#[derive(Debug)]
+struct TooLarge(usize);
+
+impl fmt::Display for TooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "a body of {} bytes is more than the {} allowed", self.0, BODY_LIMIT)
+    }
+}
+
+impl Error for TooLarge {}
+
+
+/// Read a request's line, headers and body, reading no more than `HEAD_LIMIT` and `BODY_LIMIT` allow
+pub(crate) fn read_request(stream: &mut impl Read) -> io::Result<Request> {
+    let mut reader = BufReader::new(stream.take(HEAD_LIMIT));
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let path = parts.next().unwrap_or_default().to_string();
+
+    let mut length = 0;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse().unwrap_or(0);
+            }
+        }
+    }
+    if length > BODY_LIMIT {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, TooLarge(length)));
+    }
+    // The head's limit is spent on what's been read, some of the body may already be in the buffer
+    let buffered = reader.buffer().len().min(length);
+    reader.get_mut().set_limit((length - buffered) as u64);
+    let mut body = Vec::with_capacity(length);
+    reader.take(length as u64).read_to_end(&mut body)?;
+    if body.len() < length {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(Request { method, path, body })
+}
+
+
+/// Read a request from a client with `TIMEOUT` for each read and write, answering one that's too large with a 413
+/// and giving `None` for it
+pub(crate) fn receive(stream: &mut TcpStream) -> io::Result<Option<Request>> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    match read_request(stream) {
+        Ok(request) => Ok(Some(request)),
+        Err(error) if error.get_ref().is_some_and(|inner| inner.is::<TooLarge>()) => {
+            respond(stream, "413 Content Too Large", "text/plain", error.to_string().as_bytes())?;
+            Ok(None)
+        },
+        Err(error) => Err(error),
+    }
+}
+
+
+/// Write a whole response, closing the connection after
+pub(crate) fn respond(stream: &mut impl Write, status: &str, content_type: &str, body: &[u8]) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status, content_type, body.len()
+    )?;
+    stream.write_all(body)?;
+    stream.flush()
+}
+
+
+/// What the page sends to run a program
+#[derive(Deserialize)]
+struct RunRequest {
+    code: String,
+    #[serde(default)]
+    input: String,
+    #[serde(default)]
+    macros: bool,
+    #[serde(default)]
+    extensions: Vec<Extension>,
+}
+
+
+fn compile(request: &RunRequest) -> Result<Program, Box<dyn Error>> {
+    let options = ParseOptions { macros: request.macros, extensions: request.extensions.clone(), ..ParseOptions::default() };
+    Ok(Program::compile_with_offsets(&parse_with_offsets(&request.code, &options)?, true)?)
+}
+
+
+/// Run a program for the page, as far as it gets, and what happened as JSON:
+/// `{"output":..,"tape":[..],"pointer":..,"steps":..,"error":..}`, the error being null if it finished
+fn run(request: &RunRequest, step_limit: u64) -> serde_json::Value {
+    let program = match compile(request) {
+        Ok(program) => program,
+        Err(error) => return json!({ "output": "", "tape": [0], "pointer": 0, "steps": 0, "error": error.to_string() }),
+    };
+
+    let tapes = if request.extensions.contains(&Extension::Tapes) { 2 } else { 1 };
+    let mut vm = Vm::new(program, &VmOptions { tapes, detect_hangs: true, stop_on_hang: true, ..VmOptions::default() });
+    let (mut input, mut output) = (request.input.as_bytes(), Vec::new());
+    let mut steps = 0;
+    let mut error = None;
+    while !vm.is_finished() {
+        if steps == step_limit {
+            error = Some(format!("stopped after {} steps", step_limit));
+            break;
+        }
+        if output.len() > OUTPUT_LIMIT {
+            error = Some(format!("stopped after {} bytes of output", OUTPUT_LIMIT));
+            break;
+        }
+        if let Err(step_error) = vm.step(&mut input, &mut output) {
+            error = Some(step_error.to_string());
+            break;
+        }
+        steps += 1;
+    }
+    json!({
+        "output": String::from_utf8_lossy(&output),
+        "tape": vm.tape().data,
+        "pointer": vm.tape().pointer,
+        "steps": steps,
+        "error": error,
+    })
+}
+
+
+/// The status, content type and body answering `request`
+fn answer(request: &Request) -> (&'static str, &'static str, Vec<u8>) {
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/") => ("200 OK", "text/html; charset=utf-8", PAGE.as_bytes().to_vec()),
+        ("POST", "/run") => match serde_json::from_slice::<RunRequest>(&request.body) {
+            Ok(run_request) => ("200 OK", "application/json", run(&run_request, STEP_LIMIT).to_string().into_bytes()),
+            Err(error) => ("400 Bad Request", "text/plain", error.to_string().into_bytes()),
+        },
+        _ => ("404 Not Found", "text/plain", b"not found".to_vec()),
+    }
+}
+
+
+fn handle(stream: &mut TcpStream) -> io::Result<()> {
+    let Some(request) = receive(stream)? else {
+        return Ok(());
+    };
+    log::info!("{} {}", request.method, request.path);
+    let (status, content_type, body) = answer(&request);
+    respond(stream, status, content_type, &body)
+}
+
+
+/// Serve the playground at `address` until killed
+pub fn serve(address: &str) -> Result<(), Box<dyn Error>> {
+    let listener = TcpListener::bind(address)?;
+    eprintln!("playground at http://{}/", listener.local_addr()?);
+    for stream in listener.incoming() {
+        if let Err(error) = handle(&mut stream?) {
+            log::warn!("request failed: {}", error);
+        }
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn post(body: &str) -> serde_json::Value {
+        let request = read_request(&mut format!(
+            "POST /run HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{}", body.len(), body
+        ).as_bytes()).unwrap();
+        let (status, _, body) = answer(&request);
+        assert_eq!(status, "200 OK");
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[test]
+    fn runs_programs() {
+        let result = post(r#"{"code": ",+.>++", "input": "a"}"#);
+        assert_eq!(result, json!({ "output": "b", "tape": [98, 2], "pointer": 1, "steps": 5, "error": null }));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(post(r#"{"code": "+]"}"#)["error"], "all brackets must have matching brackets");
+        assert!(post(r#"{"code": "+[]"}"#)["error"].as_str().unwrap().contains("never end"));
+        let endless = RunRequest { code: "+[>+<]".to_string(), input: String::new(), macros: false, extensions: Vec::new() };
+        assert_eq!(run(&endless, 1000)["error"], "stopped after 1000 steps");
+    }
+
+    #[test]
+    fn limits_requests() {
+        let huge = read_request(&mut &b"POST /run HTTP/1.1\r\nContent-Length: 99999999999999\r\n\r\n{}"[..]).unwrap_err();
+        assert!(huge.get_ref().unwrap().is::<TooLarge>());
+        let short = read_request(&mut &b"POST /run HTTP/1.1\r\nContent-Length: 10\r\n\r\n{}"[..]).unwrap_err();
+        assert_eq!(short.kind(), io::ErrorKind::UnexpectedEof);
+        // Bytes past the body are left alone
+        let request = read_request(&mut &b"POST /run HTTP/1.1\r\nContent-Length: 2\r\n\r\n{}extra"[..]).unwrap();
+        assert_eq!(request.body, b"{}");
+        let endless_header = format!("GET / HTTP/1.1\r\nX: {}", "a".repeat(1 << 20));
+        assert!(read_request(&mut endless_header.as_bytes()).is_ok_and(|request| request.body.is_empty()));
+    }
+
+    #[test]
+    fn pages() {
+        let get = |path: &str| answer(&Request { method: "GET".to_string(), path: path.to_string(), body: Vec::new() });
+        assert!(String::from_utf8(get("/").2).unwrap().contains("<textarea"));
+        assert_eq!(get("/nothing").0, "404 Not Found");
+    }
+}
@@ -0,0 +1,110 @@
+//! Named copies of a vm's tapes, and which cells changed between two of them
+
+use std::{collections::BTreeMap, fmt};
+use crate::{Tape, Vm};
+
+
+/// A cell that has a different value in one snapshot than in another
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Change {
+    pub tape: usize,
+    pub cell: usize,
+    pub old: u8,
+    pub new: u8,
+}
+
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.tape > 0 {
+            write!(f, "tape {} ", self.tape)?;
+        }
+        write!(f, "cell {}: {} -> {}", self.cell, self.old, self.new)
+    }
+}
+
+
+/// The cells that changed going from `before` to `after`. Tapes only grow, and cells past the end of one are 0.
+pub fn diff(before: &[Tape], after: &[Tape]) -> Vec<Change> {
+    let mut changes = Vec::new();
+    for tape in 0..before.len().max(after.len()) {
+        let cells = |tapes: &[Tape]| tapes.get(tape).map_or(Vec::new(), |tape| tape.data.clone());
+        let (old_cells, new_cells) = (cells(before), cells(after));
+        for cell in 0..old_cells.len().max(new_cells.len()) {
+            let old = old_cells.get(cell).copied().unwrap_or(0);
+            let new = new_cells.get(cell).copied().unwrap_or(0);
+            if old != new {
+                changes.push(Change { tape, cell, old, new });
+            }
+        }
+    }
+    changes
+}
+
+
+#[derive(Clone, Debug, Default)]
+pub struct Snapshots {
+    taken: BTreeMap<String, Vec<Tape>>,
+}
+
+
+impl Snapshots {
+    /// Save the vm's tapes as `name`, replacing any snapshot already called that
+    pub fn take(&mut self, name: &str, vm: &Vm) {
+        self.taken.insert(name.to_string(), vm.tapes().to_vec());
+    }
+
+    pub fn get(&self, name: &str) -> Option<&[Tape]> {
+        self.taken.get(name).map(Vec::as_slice)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.taken.keys().map(String::as_str)
+    }
+
+    /// The cells that changed from the snapshot `from` to the snapshot `to`, or to the vm now without one
+    pub fn diff(&self, from: &str, to: Option<&str>, vm: &Vm) -> Result<Vec<Change>, String> {
+        let snapshot = |name: &str| self.get(name).ok_or_else(|| format!("there's no snapshot called {}", name));
+        let after = match to {
+            Some(name) => snapshot(name)?,
+            None => vm.tapes(),
+        };
+        Ok(diff(snapshot(from)?, after))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Program, VmOptions, parse};
+
+    fn ran(code: &str) -> Vm {
+        let mut vm = Vm::new(Program::compile(&parse(code, &Default::default()).unwrap(), false).unwrap(), &VmOptions::default());
+        vm.run(&mut std::io::empty(), &mut std::io::sink()).unwrap();
+        vm
+    }
+
+    #[test]
+    fn changed_cells() {
+        let before = [Tape { data: vec![1, 2], pointer: 0 }];
+        let after = [Tape { data: vec![1, 5, 7], pointer: 2 }];
+        assert_eq!(diff(&before, &after), vec![
+            Change { tape: 0, cell: 1, old: 2, new: 5 },
+            Change { tape: 0, cell: 2, old: 0, new: 7 },
+        ]);
+        assert_eq!(diff(&after, &after), vec![]);
+        assert_eq!(Change { tape: 1, cell: 3, old: 0, new: 65 }.to_string(), "tape 1 cell 3: 0 -> 65");
+    }
+
+    #[test]
+    fn named() {
+        let mut snapshots = Snapshots::default();
+        snapshots.take("start", &ran(""));
+        snapshots.take("end", &ran("+>++"));
+        assert_eq!(snapshots.names().collect::<Vec<_>>(), ["end", "start"]);
+        assert_eq!(snapshots.diff("start", Some("end"), &ran("")).unwrap().len(), 2);
+        assert_eq!(snapshots.diff("start", None, &ran("+")).unwrap(), vec![Change { tape: 0, cell: 0, old: 0, new: 1 }]);
+        assert_eq!(snapshots.diff("middle", None, &ran("")), Err("there's no snapshot called middle".to_string()));
+    }
+}
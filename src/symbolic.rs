@@ -0,0 +1,376 @@
+//! Experimental: running a program on every input at once, to find inputs that reach a breakpoint or move
+//! the pointer left of cell 0.
+//!
+//! Each byte read by `,` is an unknown, with the set of values it can still have. A loop on a cell holding an
+//! unknown splits the run in two, one where the cell is 0 and one where it isn't, and ops that can't keep an unknown
+//! as it is split the run once for each value it can have. Every run that reaches what's being looked for gives an
+//! input that really does, since values are only ever ruled out when they would have taken another path.
+
+use std::fmt;
+use crate::{Eof, Op, Program};
+
+
+/// How far exploring goes before giving up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Input bytes to try, with `,` after that acting as at the end of input
+    pub inputs: usize,
+    /// Ops each path can run before it's cut off
+    pub steps: usize,
+    /// Paths to explore in all
+    pub paths: usize,
+}
+
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits { inputs: 8, steps: 100_000, paths: 10_000 }
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Goal {
+    /// A breakpoint op ran
+    Breakpoint,
+    /// A move left from closer to the start of the tape than it goes, which stops at cell 0
+    Underflow,
+}
+
+
+/// An input that makes the program reach `goal` at the op `op`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Found {
+    pub goal: Goal,
+    pub op: usize,
+    pub input: Vec<u8>,
+}
+
+
+impl fmt::Display for Found {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let input = String::from_utf8_lossy(&self.input);
+        match self.goal {
+            Goal::Breakpoint => write!(f, "input {:?} reaches the breakpoint at op {}", input, self.op),
+            Goal::Underflow => write!(f, "input {:?} moves the pointer left of cell 0 at op {}", input, self.op),
+        }
+    }
+}
+
+
+/// What exploring found, with the first input found for each op that reaches a goal
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Exploration {
+    pub found: Vec<Found>,
+    /// Paths that ran to the end of the program, or to an error
+    pub finished: usize,
+    /// Paths cut off at `Limits::steps`
+    pub cut_off: usize,
+    /// Whether `Limits::paths` ran out before every path was explored
+    pub incomplete: bool,
+}
+
+
+/// A cell's value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Value {
+    Known(u8),
+    /// The `input`th byte read, plus `plus`
+    Unknown { input: usize, plus: u8 },
+}
+
+
+/// One way through the program
+#[derive(Debug, Clone)]
+struct Path {
+    ip: usize,
+    cells: Vec<Value>,
+    pointer: usize,
+    stack: Vec<Value>,
+    /// The values each input byte read so far can still have, bit `n` being set if it could be `n`
+    inputs: Vec<u128>,
+    steps: usize,
+}
+
+
+impl Path {
+    fn current(&self) -> Value {
+        self.resolve(self.cells[self.pointer])
+    }
+
+    /// A value, known if its input byte can only be one thing
+    fn resolve(&self, value: Value) -> Value {
+        match value {
+            Value::Unknown { input, plus } if self.inputs[input].count_ones() == 1 => {
+                Value::Known((self.inputs[input].trailing_zeros() as u8 + plus) % 128)
+            },
+            value => value,
+        }
+    }
+
+    /// A copy where input byte `input` can only be the values in `values`
+    fn with(&self, input: usize, values: u128) -> Path {
+        let mut path = self.clone();
+        path.inputs[input] = values;
+        path
+    }
+
+    /// The current cell split by whether it's 0, as the paths where it is and where it isn't
+    fn split_zero(&self) -> (Option<Path>, Option<Path>) {
+        match self.current() {
+            Value::Known(0) => (Some(self.clone()), None),
+            Value::Known(_) => (None, Some(self.clone())),
+            Value::Unknown { input, plus } => {
+                // The input value that makes the cell 0
+                let zero = 1 << ((128 - plus as u32) % 128);
+                let values = self.inputs[input];
+                let is_zero = (values & zero != 0).then(|| self.with(input, zero));
+                let non_zero = (values & !zero != 0).then(|| self.with(input, values & !zero));
+                (is_zero, non_zero)
+            },
+        }
+    }
+
+    /// A path for each value the current cell can have, most preferred first, so the next op can treat it as known
+    fn split_values(&self) -> Vec<Path> {
+        match self.current() {
+            Value::Known(_) => vec![self.clone()],
+            Value::Unknown { input, .. } => {
+                let values = self.inputs[input];
+                preferred().into_iter().filter(|n| values & (1 << n) != 0).map(|n| self.with(input, 1 << n)).collect()
+            },
+        }
+    }
+
+    /// An input that takes this path, made of the most preferred bytes it can be
+    fn example(&self) -> Vec<u8> {
+        let preferred = preferred();
+        self.inputs.iter().map(|&values| {
+            preferred.iter().copied().find(|&byte| values & (1 << byte) != 0).unwrap_or(0)
+        }).collect()
+    }
+}
+
+
+/// Every input byte, letters first, then digits, then other printable characters, so found inputs are easy to type
+fn preferred() -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(128);
+    for byte in (b'a'..=b'z').chain(b'A'..=b'Z').chain(b'0'..=b'9').chain(b' '..=b'~').chain([b'\n']).chain(0..128) {
+        if !bytes.contains(&byte) {
+            bytes.push(byte);
+        }
+    }
+    bytes
+}
+
+
+/// What running one op on a path led to
+enum Step {
+    Continue(Path),
+    Split(Vec<Path>),
+    /// The path ended with an error, like popping an empty stack
+    Failed,
+}
+
+
+/// Explore the paths through a program until `limits` runs out, reading with `eof` once there are no input bytes left.
+/// Programs that use pbrain procedures or more than one tape can't be explored.
+pub fn explore(program: &Program, eof: Eof, limits: &Limits) -> Result<Exploration, &'static str> {
+    let unsupported = program.ops.iter().any(|op| matches!(op, Op::ProcedureOpen(_) | Op::Call | Op::NextTape | Op::CopyToNextTape));
+    if unsupported {
+        return Err("programs with pbrain procedures or the tapes extension can't be explored");
+    }
+
+    let mut exploration = Exploration::default();
+    let start = Path { ip: 0, cells: vec![Value::Known(0)], pointer: 0, stack: Vec::new(), inputs: Vec::new(), steps: 0 };
+    let mut pending = vec![start];
+    let mut explored = 1;
+    while let Some(mut path) = pending.pop() {
+        loop {
+            if path.ip >= program.ops.len() {
+                exploration.finished += 1;
+                break;
+            }
+            if path.steps >= limits.steps {
+                exploration.cut_off += 1;
+                break;
+            }
+            if let Some(goal) = goal(program.ops[path.ip], &path) {
+                if !exploration.found.iter().any(|found| found.op == path.ip && found.goal == goal) {
+                    exploration.found.push(Found { goal, op: path.ip, input: path.example() });
+                }
+            }
+            match step(program, path, eof, limits) {
+                Step::Continue(next) => path = next,
+                Step::Split(paths) => {
+                    // The first path carries on, so each split adds one less than its size
+                    let room = limits.paths.saturating_sub(explored);
+                    if paths.len() - 1 > room {
+                        exploration.incomplete = true;
+                    }
+                    let mut paths = paths.into_iter().take(room + 1);
+                    let Some(next) = paths.next() else {
+                        break;
+                    };
+                    explored += paths.len();
+                    // Taken from the end, so the paths are explored in order
+                    pending.extend(paths.rev());
+                    path = next;
+                },
+                Step::Failed => {
+                    exploration.finished += 1;
+                    break;
+                },
+            }
+        }
+    }
+    exploration.found.sort_by_key(|found| found.op);
+    Ok(exploration)
+}
+
+
+/// What an op reaches, if it's being looked for
+fn goal(op: Op, path: &Path) -> Option<Goal> {
+    match op {
+        Op::Break => Some(Goal::Breakpoint),
+        Op::Left(n) if n > path.pointer => Some(Goal::Underflow),
+        _ => None,
+    }
+}
+
+
+/// Run the op at `path.ip`, which isn't past the end of the program
+fn step(program: &Program, mut path: Path, eof: Eof, limits: &Limits) -> Step {
+    let i = path.ip;
+    path.steps += 1;
+    match program.ops[i] {
+        Op::Add(n) => {
+            let cell = &mut path.cells[path.pointer];
+            *cell = match *cell {
+                Value::Known(value) => Value::Known((value + n) % 128),
+                Value::Unknown { input, plus } => Value::Unknown { input, plus: (plus + n) % 128 },
+            };
+        },
+        Op::Right(n) => {
+            path.pointer += n;
+            if path.pointer >= path.cells.len() {
+                path.cells.resize(path.pointer + 1, Value::Known(0));
+            }
+        },
+        Op::Left(n) => path.pointer = path.pointer.saturating_sub(n),
+        Op::Clear => path.cells[path.pointer] = Value::Known(0),
+        Op::MulAdd { offset, factor } => {
+            let target = path.pointer + offset;
+            if target >= path.cells.len() {
+                path.cells.resize(target + 1, Value::Known(0));
+            }
+            let (Value::Known(value), added) = (path.current(), path.resolve(path.cells[target])) else {
+                // Each value runs this op again, now known
+                return Step::Split(path.split_values());
+            };
+            let product = (value as usize * factor as usize % 128) as u8;
+            path.cells[target] = match added {
+                Value::Known(added) => Value::Known((added + product) % 128),
+                Value::Unknown { input, plus } => Value::Unknown { input, plus: (plus + product) % 128 },
+            };
+        },
+        Op::JumpIfZero(close) => {
+            let (zero, non_zero) = path.split_zero();
+            let zero = zero.map(|mut path| {
+                path.ip = close;
+                path
+            });
+            return advance(zero.into_iter().chain(non_zero).collect());
+        },
+        Op::JumpIfNonZero(open) => {
+            let (zero, non_zero) = path.split_zero();
+            let non_zero = non_zero.map(|mut path| {
+                path.ip = open;
+                path
+            });
+            return advance(zero.into_iter().chain(non_zero).collect());
+        },
+        Op::Input if path.inputs.len() < limits.inputs => {
+            path.cells[path.pointer] = Value::Unknown { input: path.inputs.len(), plus: 0 };
+            path.inputs.push(u128::MAX);
+        },
+        Op::Input => match eof {
+            Eof::Zero => path.cells[path.pointer] = Value::Known(0),
+            Eof::Max => path.cells[path.pointer] = Value::Known(127),
+            Eof::Unchanged => (),
+        },
+        Op::Output | Op::Break | Op::ProcedureClose => (),
+        Op::Push => path.stack.push(path.cells[path.pointer]),
+        Op::Pop => {
+            let Some(value) = path.stack.pop() else {
+                return Step::Failed;
+            };
+            path.cells[path.pointer] = value;
+        },
+        Op::ProcedureOpen(_) | Op::Call | Op::NextTape | Op::CopyToNextTape => unreachable!("checked by explore"),
+    }
+    path.ip += 1;
+    Step::Continue(path)
+}
+
+
+/// Move split paths on to their next ops
+fn advance(paths: Vec<Path>) -> Step {
+    let mut paths: Vec<Path> = paths.into_iter().map(|mut path| {
+        path.ip += 1;
+        path
+    }).collect();
+    match paths.len() {
+        1 => Step::Continue(paths.remove(0)),
+        _ => Step::Split(paths),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Dialect, ParseOptions, parse};
+
+    fn explore_code(code: &str, eof: Eof, limits: &Limits) -> Result<Exploration, &'static str> {
+        let options = ParseOptions { breakpoints: true, dialect: Dialect::Pbrain, ..ParseOptions::default() };
+        let program = Program::compile(&parse(code, &options).unwrap(), true).unwrap();
+        explore(&program, eof, limits)
+    }
+
+    fn found(code: &str) -> Vec<(Goal, Vec<u8>)> {
+        let exploration = explore_code(code, Eof::Zero, &Limits::default()).unwrap();
+        assert!(!exploration.incomplete);
+        exploration.found.into_iter().map(|found| (found.goal, found.input)).collect()
+    }
+
+    #[test]
+    fn finds_the_input_a_breakpoint_needs() {
+        // Only reaches the breakpoint when the input is "Q"
+        let code = format!(",{}[[-]>-<]>+[@-]", "-".repeat(b'Q' as usize));
+        assert_eq!(found(&code), vec![(Goal::Breakpoint, b"Q".to_vec())]);
+        assert_eq!(found(",[-]>[@]"), vec![]);
+    }
+
+    #[test]
+    fn finds_underflows() {
+        assert_eq!(found(",[<[-]]"), vec![(Goal::Underflow, b"a".to_vec())]);
+        // Multiplying splits on every value of the input
+        assert_eq!(found(",[->++<]>[<<[-]]"), vec![(Goal::Underflow, b"a".to_vec())]);
+    }
+
+    #[test]
+    fn reads_past_the_limit_are_the_end_of_input() {
+        let limits = Limits { inputs: 1, ..Limits::default() };
+        assert!(explore_code(",>,[@]", Eof::Zero, &limits).unwrap().found.is_empty());
+        let found = explore_code(",>,[@]", Eof::Max, &limits).unwrap().found;
+        assert_eq!(found, vec![Found { goal: Goal::Breakpoint, op: 4, input: b"a".to_vec() }]);
+    }
+
+    #[test]
+    fn loops_on_known_cells_are_cut_off() {
+        let exploration = explore_code("+[]", Eof::Zero, &Limits { steps: 100, ..Limits::default() }).unwrap();
+        assert_eq!((exploration.finished, exploration.cut_off), (0, 1));
+        assert!(explore_code("(:)", Eof::Zero, &Limits::default()).is_err());
+    }
+}
@@ -0,0 +1,359 @@
+//! Input and output fixtures for `bf-rs test`
+//!
+//! A program `prog.b` is tested by `prog.out`, with `prog.in` as its input if there is one,
+//! and by the cases in `prog.tests.toml`:
+//! ```toml
+//! [[case]]
+//! name = "empty"
+//! input = ""
+//! output = "\n"
+//! ```
+//! A case can also be a conversation, checking the output so far before sending more input:
+//! ```toml
+//! [[case]]
+//! name = "greets"
+//! steps = [{ expect = "Name?" }, { send = "Ada\n" }, { expect = "Hello Ada" }]
+//! ```
+//!
+//! As well as the text `bf-rs test` prints, the results can be a JUnit XML report for CI and IDEs.
+
+use std::{collections::VecDeque, error::Error, fs, io, path::{Path, PathBuf}, time::Duration};
+use serde::Deserialize;
+use crate::{Dialect, Op, Program, Vm, VmOptions};
+
+
+/// Most steps a conversation can take, so a program stuck in a loop fails instead of hanging `bf-rs test`
+pub const CONVERSATION_STEPS: usize = 1_000_000_000;
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Case {
+    pub name: String,
+    pub input: Vec<u8>,
+    /// The whole output, if the case checks it
+    pub expected: Option<Vec<u8>>,
+    /// What to expect and send, in order, after `input`
+    pub steps: Vec<Step>,
+}
+
+
+/// Part of a conversation with a program
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Step {
+    /// Wait for the program to write this, after what earlier steps expected
+    Expect(String),
+    /// Give the program this input
+    Send(String),
+}
+
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    #[serde(default)]
+    case: Vec<ManifestCase>,
+}
+
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ManifestCase {
+    name: String,
+    #[serde(default)]
+    input: String,
+    output: Option<String>,
+    #[serde(default)]
+    steps: Vec<Step>,
+}
+
+
+/// The cases for the program at `path`, empty if it has no fixtures
+pub fn cases(path: &Path) -> Result<Vec<Case>, Box<dyn Error>> {
+    let mut cases = Vec::new();
+
+    let output_path = path.with_extension("out");
+    if output_path.is_file() {
+        let input_path = path.with_extension("in");
+        let input = if input_path.is_file() { fs::read(input_path)? } else { Vec::new() };
+        let name = output_path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        cases.push(Case { name, input, expected: Some(fs::read(&output_path)?), steps: Vec::new() });
+    }
+
+    let manifest_path = path.with_extension("tests.toml");
+    if manifest_path.is_file() {
+        let manifest: Manifest = toml::from_str(&fs::read_to_string(&manifest_path)?)
+            .map_err(|error| format!("{}: {}", manifest_path.display(), error))?;
+        for case in manifest.case {
+            if case.output.is_none() && case.steps.is_empty() {
+                return Err(format!("{}: case {} needs an output or steps to check", manifest_path.display(), case.name).into());
+            }
+            cases.push(Case {
+                name: case.name,
+                input: case.input.into_bytes(),
+                expected: case.output.map(String::into_bytes),
+                steps: case.steps,
+            });
+        }
+    }
+    Ok(cases)
+}
+
+
+/// Programs with fixtures in `paths`, searching directories recursively, in path order
+pub fn discover(paths: &[PathBuf]) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut programs = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            let mut entries: Vec<PathBuf> = fs::read_dir(path)?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<Result<_, io::Error>>()?;
+            entries.sort();
+            // Files given directly are always tested, found ones only if they are programs
+            let found = discover(&entries)?;
+            programs.extend(found.into_iter().filter(|program| Dialect::from_path(program).is_some()));
+        } else if path.with_extension("out").is_file() || path.with_extension("tests.toml").is_file() {
+            programs.push(path.clone());
+        }
+    }
+    Ok(programs)
+}
+
+
+/// Run a program with the case's input, and its steps if it has any, returning what it wrote
+pub fn run_case(program: &Program, vm_options: &VmOptions, case: &Case) -> Result<Vec<u8>, Box<dyn Error>> {
+    if !case.steps.is_empty() {
+        return converse(program, vm_options, case, CONVERSATION_STEPS)?
+            .ok_or_else(|| format!("the program didn't finish in {} steps", CONVERSATION_STEPS).into());
+    }
+    let mut output = Vec::new();
+    Vm::new(program.clone(), vm_options).run(&mut io::Cursor::new(&case.input), &mut output)?;
+    Ok(output)
+}
+
+
+/// Run a case's steps, sending input once the output expected before it has been written. Fails if the program
+/// finishes, or waits for input there isn't yet, before writing what's expected. After the last step, reading
+/// input gets the end of it. Returns what the program wrote, or none if it took more than `max_steps` steps.
+pub fn converse(program: &Program, vm_options: &VmOptions, case: &Case, max_steps: usize) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+    let mut vm = Vm::new(program.clone(), vm_options);
+    let mut input: VecDeque<u8> = case.input.iter().copied().collect();
+    let mut output = Vec::new();
+    // Where the output not yet matched by an expect starts
+    let mut matched = 0;
+    let mut steps = case.steps.iter().peekable();
+    let mut taken = 0;
+    loop {
+        while let Some(step) = steps.peek() {
+            match step {
+                Step::Send(text) => input.extend(text.as_bytes()),
+                Step::Expect(text) => match find(&output[matched..], text.as_bytes()) {
+                    Some(end) => matched += end,
+                    None => break,
+                },
+            }
+            steps.next();
+        }
+        let waiting = vm.current_op() == Some(Op::Input) && input.is_empty() && steps.peek().is_some();
+        if vm.is_finished() || waiting {
+            break;
+        }
+        if taken == max_steps {
+            return Ok(None);
+        }
+        vm.step(&mut input, &mut output)?;
+        taken += 1;
+    }
+    if let Some(Step::Expect(text)) = steps.next() {
+        let stopped = if vm.is_finished() { "finished" } else { "waited for input" };
+        let written = String::from_utf8_lossy(&output[matched..]);
+        return Err(format!("expected {:?}, but the program {} after writing {:?}", text, stopped, written).into());
+    }
+    Ok(Some(output))
+}
+
+
+/// Where the first `needle` in `haystack` ends
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    (needle.len()..=haystack.len()).find(|&end| haystack[..end].ends_with(needle))
+}
+
+
+/// The lines that differ, as `-expected` and `+actual`
+pub fn diff(expected: &[u8], actual: &[u8]) -> String {
+    let expected = String::from_utf8_lossy(expected);
+    let actual = String::from_utf8_lossy(actual);
+    let expected_lines: Vec<&str> = expected.split_inclusive('\n').collect();
+    let actual_lines: Vec<&str> = actual.split_inclusive('\n').collect();
+
+    let mut shown = String::new();
+    for i in 0..expected_lines.len().max(actual_lines.len()) {
+        let (expected_line, actual_line) = (expected_lines.get(i), actual_lines.get(i));
+        if expected_line == actual_line {
+            continue;
+        }
+        // Debug formatting shows missing newlines and control characters
+        if let Some(line) = expected_line {
+            shown += &format!("-{:?}\n", line);
+        }
+        if let Some(line) = actual_line {
+            shown += &format!("+{:?}\n", line);
+        }
+    }
+    shown
+}
+
+
+/// How a case went wrong
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Failure {
+    /// The output was wrong, with the diff
+    Output(String),
+    /// The program didn't load or errored while running
+    Error(String),
+}
+
+
+/// A case that was run, for reports
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaseResult {
+    pub name: String,
+    pub duration: Duration,
+    pub failure: Option<Failure>,
+}
+
+
+/// The cases run for one program
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Suite {
+    pub name: String,
+    pub cases: Vec<CaseResult>,
+}
+
+
+/// A JUnit XML report, with a `<testsuite>` for each program and a `<testcase>` for each case
+pub fn junit(suites: &[Suite]) -> String {
+    let count = |suite: &Suite, output: bool| suite.cases.iter()
+        .filter(|case| matches!((&case.failure, output), (Some(Failure::Output(_)), true) | (Some(Failure::Error(_)), false)))
+        .count();
+    let time = |suite: &Suite| suite.cases.iter().map(|case| case.duration).sum::<Duration>().as_secs_f64();
+
+    let mut xml = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n".to_string();
+    xml += &format!(
+        "<testsuites name=\"bf-rs test\" tests=\"{}\" failures=\"{}\" errors=\"{}\" time=\"{:.6}\">\n",
+        suites.iter().map(|suite| suite.cases.len()).sum::<usize>(),
+        suites.iter().map(|suite| count(suite, true)).sum::<usize>(),
+        suites.iter().map(|suite| count(suite, false)).sum::<usize>(),
+        suites.iter().map(time).sum::<f64>(),
+    );
+    for suite in suites {
+        xml += &format!(
+            "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"{}\" time=\"{:.6}\">\n",
+            escape(&suite.name), suite.cases.len(), count(suite, true), count(suite, false), time(suite),
+        );
+        for case in &suite.cases {
+            let start = format!(
+                "    <testcase name=\"{}\" classname=\"{}\" time=\"{:.6}\"",
+                escape(&case.name), escape(&suite.name), case.duration.as_secs_f64(),
+            );
+            xml += &match &case.failure {
+                None => format!("{}/>\n", start),
+                Some(Failure::Output(diff)) => format!(
+                    "{}>\n      <failure message=\"wrong output\" type=\"output\">{}</failure>\n    </testcase>\n", start, escape(diff),
+                ),
+                Some(Failure::Error(error)) => format!(
+                    "{}>\n      <error message=\"{}\" type=\"error\"/>\n    </testcase>\n", start, escape(error),
+                ),
+            };
+        }
+        xml += "  </testsuite>\n";
+    }
+    xml + "</testsuites>\n"
+}
+
+
+/// Text for XML attributes and elements, replacing the control characters XML can't have
+fn escape(text: &str) -> String {
+    text.chars().map(|c| match c {
+        '&' => "&amp;".to_string(),
+        '<' => "&lt;".to_string(),
+        '>' => "&gt;".to_string(),
+        '"' => "&quot;".to_string(),
+        '\n' | '\t' | '\r' => c.to_string(),
+        c if c.is_control() => char::REPLACEMENT_CHARACTER.to_string(),
+        c => c.to_string(),
+    }).collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diff_lines() {
+        assert_eq!(diff(b"a\nb\nc\n", b"a\nx\nc"), "-\"b\\n\"\n+\"x\\n\"\n-\"c\\n\"\n+\"c\"\n");
+        assert_eq!(diff(b"same", b"same"), "");
+    }
+
+    #[test]
+    fn conversations() {
+        // Asks for a name with `?`, then greets whoever it reads up to the end of the line
+        let code = "+++++++[>+++++++++<-]>.<++++++++++[>>++++++++++<<-]>>++++++.>,----------[++++++++++.,----------]";
+        let program = Program::compile(&crate::parse(code, &crate::ParseOptions::default()).unwrap(), true).unwrap();
+        let case = |steps: Vec<Step>| Case { name: "greets".to_string(), input: Vec::new(), expected: None, steps };
+        let expect = |text: &str| Step::Expect(text.to_string());
+        let send = |text: &str| Step::Send(text.to_string());
+
+        let good = case(vec![expect("?"), send("Ada\n"), expect("Ada")]);
+        assert_eq!(converse(&program, &VmOptions::default(), &good, 10_000).unwrap().unwrap(), b"?jAda");
+        assert_eq!(run_case(&program, &VmOptions::default(), &good).unwrap(), b"?jAda");
+
+        // Nothing was sent, so the program waits for input after writing `?j`
+        let waits = case(vec![expect("Ada"), send("Ada\n")]);
+        assert_eq!(converse(&program, &VmOptions::default(), &waits, 10_000).unwrap_err().to_string(), "expected \"Ada\", but the program waited for input after writing \"?j\"");
+        let finishes = case(vec![send("Ada\n"), expect("Bob")]);
+        assert_eq!(converse(&program, &VmOptions::default(), &finishes, 10_000).unwrap_err().to_string(), "expected \"Bob\", but the program finished after writing \"?jAda\"");
+        assert_eq!(converse(&program, &VmOptions::default(), &good, 10).unwrap(), None);
+    }
+
+    #[test]
+    fn junit_report() {
+        let case = |name: &str, failure| CaseResult { name: name.to_string(), duration: Duration::from_millis(2), failure };
+        let suites = [Suite {
+            name: "dir/a<b>.b".to_string(),
+            cases: vec![
+                case("passes", None),
+                case("wrong", Some(Failure::Output(diff(b"x\n", b"y\n")))),
+                case("\"quoted\"", Some(Failure::Error("wrapped \u{1} & stopped".to_string()))),
+            ],
+        }];
+        let report = junit(&suites);
+        assert!(report.contains("<testsuites name=\"bf-rs test\" tests=\"3\" failures=\"1\" errors=\"1\" time=\"0.006000\">"));
+        assert!(report.contains("<testsuite name=\"dir/a&lt;b&gt;.b\" tests=\"3\""));
+        assert!(report.contains("<testcase name=\"passes\" classname=\"dir/a&lt;b&gt;.b\" time=\"0.002000\"/>"));
+        assert!(report.contains("<failure message=\"wrong output\" type=\"output\">-&quot;x\\n&quot;\n+&quot;y\\n&quot;\n</failure>"));
+        assert!(report.contains("<testcase name=\"&quot;quoted&quot;\""));
+        assert!(report.contains("<error message=\"wrapped \u{fffd} &amp; stopped\" type=\"error\"/>"));
+        assert!(report.ends_with("</testsuites>\n"));
+    }
+
+    #[test]
+    fn fixtures() {
+        let directory = std::env::temp_dir().join(format!("bf-rs-fixtures-{}", std::process::id()));
+        fs::create_dir_all(directory.join("nested")).unwrap();
+        fs::write(directory.join("cat.b"), ",[.,]").unwrap();
+        fs::write(directory.join("cat.in"), "hi").unwrap();
+        fs::write(directory.join("cat.out"), "hi").unwrap();
+        fs::write(directory.join("nested/a.bf"), "+.").unwrap();
+        fs::write(directory.join("nested/a.tests.toml"), "[[case]]\nname = \"one\"\noutput = \"\\u0001\"\n").unwrap();
+        fs::write(directory.join("untested.b"), "").unwrap();
+
+        let programs = discover(std::slice::from_ref(&directory)).unwrap();
+        assert_eq!(programs, vec![directory.join("cat.b"), directory.join("nested/a.bf")]);
+
+        let cases = cases(&programs[1]).unwrap();
+        assert_eq!(cases, vec![Case { name: "one".to_string(), input: Vec::new(), expected: Some(vec![1]), steps: Vec::new() }]);
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
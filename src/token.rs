@@ -0,0 +1,174 @@
+//! Classifying every character of a program, for syntax highlighting.
+//!
+//! Tokenizing never fails: anything that wouldn't parse, like a `}` with no `{`, is a comment.
+
+use std::{cmp::Reverse, ops::Range};
+use crate::{Dialect, Instruction, ParseOptions, dialect, format, parse_char};
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    Instruction(Instruction),
+    /// `@` with breakpoints enabled
+    Breakpoint,
+    /// The name before a macro's `{`
+    MacroName,
+    /// The `{` or `}` around a macro's body. Tokens between them are the body.
+    MacroBody,
+    /// `@name@`
+    MacroCall,
+    /// Anything else, including whitespace
+    Comment,
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub kind: TokenKind,
+    /// Byte range in the source
+    pub span: Range<usize>,
+}
+
+
+/// Split code into tokens, in order and covering all of it. Runs of comment characters are one token.
+pub fn tokenize(code: &str, options: &ParseOptions) -> Vec<Token> {
+    if options.dialect == Dialect::Ook {
+        return tokenize_ook(code, options);
+    }
+
+    let mut names = if options.macros { format::macro_names(code) } else { Vec::new() };
+    // Longest first, so calls match the same macro as in `parse`
+    names.sort_by_key(|name| Reverse(name.len()));
+
+    let mut tokens = Vec::new();
+    let mut in_macro = false;
+    let mut position = 0;
+    while let Some(c) = code[position..].chars().next() {
+        let start = position;
+        let call = code[start..].strip_prefix('@').and_then(|rest| {
+            names.iter().find(|name| rest.strip_prefix(**name).is_some_and(|rest| rest.starts_with('@')))
+        });
+
+        let (kind, length) = match (call, c) {
+            (Some(name), _) => (TokenKind::MacroCall, name.len() + 2),
+            (None, '{') if options.macros && !in_macro => {
+                split_macro_name(&mut tokens, code);
+                in_macro = true;
+                (TokenKind::MacroBody, 1)
+            },
+            (None, '}') if options.macros && in_macro => {
+                in_macro = false;
+                (TokenKind::MacroBody, 1)
+            },
+            _ => match parse_char(c, options) {
+                Some(Instruction::Break) => (TokenKind::Breakpoint, 1),
+                Some(instruction) => (TokenKind::Instruction(instruction), 1),
+                None => (TokenKind::Comment, c.len_utf8()),
+            },
+        };
+        push(&mut tokens, kind, start..start + length);
+        position += length;
+    }
+    tokens
+}
+
+
+/// Add a token, joining it to the one before if both are comments
+fn push(tokens: &mut Vec<Token>, kind: TokenKind, span: Range<usize>) {
+    match tokens.last_mut() {
+        Some(last) if kind == TokenKind::Comment && last.kind == TokenKind::Comment => last.span.end = span.end,
+        _ => tokens.push(Token { kind, span }),
+    }
+}
+
+
+/// A macro's name is the last word of the comment before its `{`, the same way `parse` finds it
+fn split_macro_name(tokens: &mut Vec<Token>, code: &str) {
+    let Some(comment) = tokens.pop_if(|token| token.kind == TokenKind::Comment) else {
+        return;
+    };
+    let text = &code[comment.span.clone()];
+    let Some(name) = text.split_whitespace().last() else {
+        tokens.push(comment);
+        return;
+    };
+    let name_start = comment.span.start + text.rfind(name).unwrap_or(0);
+    let name_end = name_start + name.len();
+
+    if comment.span.start < name_start {
+        tokens.push(Token { kind: TokenKind::Comment, span: comment.span.start..name_start });
+    }
+    tokens.push(Token { kind: TokenKind::MacroName, span: name_start..name_end });
+    if name_end < comment.span.end {
+        tokens.push(Token { kind: TokenKind::Comment, span: name_end..comment.span.end });
+    }
+}
+
+
+/// Each pair of Ook! words is one instruction, anything between them is a comment
+fn tokenize_ook(code: &str, options: &ParseOptions) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let Ok(instructions) = dialect::translate_ook(code) else {
+        if !code.is_empty() {
+            tokens.push(Token { kind: TokenKind::Comment, span: 0..code.len() });
+        }
+        return tokens;
+    };
+
+    let mut position = 0;
+    for (c, offset) in instructions {
+        // The second word starts with the next "Ook" after the first
+        let second = code[offset + 3..].find("Ook").map_or(code.len(), |i| offset + 3 + i);
+        let end = (second + 4).min(code.len());
+        if position < offset {
+            push(&mut tokens, TokenKind::Comment, position..offset);
+        }
+        let kind = parse_char(c, options).map_or(TokenKind::Comment, TokenKind::Instruction);
+        push(&mut tokens, kind, offset..end);
+        position = end;
+    }
+    if position < code.len() {
+        push(&mut tokens, TokenKind::Comment, position..code.len());
+    }
+    tokens
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds<'a>(code: &'a str, options: &ParseOptions) -> Vec<(TokenKind, &'a str)> {
+        tokenize(code, options).into_iter().map(|token| (token.kind, &code[token.span])).collect()
+    }
+
+    #[test]
+    fn macros_and_comments() {
+        let options = ParseOptions { macros: true, breakpoints: true, ..ParseOptions::default() };
+        assert_eq!(kinds("add two { ++ }\n@two@@ }", &options), vec![
+            (TokenKind::Comment, "add "),
+            (TokenKind::MacroName, "two"),
+            (TokenKind::Comment, " "),
+            (TokenKind::MacroBody, "{"),
+            (TokenKind::Comment, " "),
+            (TokenKind::Instruction(Instruction::Increment), "+"),
+            (TokenKind::Instruction(Instruction::Increment), "+"),
+            (TokenKind::Comment, " "),
+            (TokenKind::MacroBody, "}"),
+            (TokenKind::Comment, "\n"),
+            (TokenKind::MacroCall, "@two@"),
+            (TokenKind::Breakpoint, "@"),
+            (TokenKind::Comment, " }"),
+        ]);
+    }
+
+    #[test]
+    fn ook_pairs() {
+        let options = ParseOptions { dialect: Dialect::Ook, ..ParseOptions::default() };
+        assert_eq!(kinds("Ook. Ook. hi Ook! Ook.", &options), vec![
+            (TokenKind::Instruction(Instruction::Increment), "Ook. Ook."),
+            (TokenKind::Comment, " hi "),
+            (TokenKind::Instruction(Instruction::Output), "Ook! Ook."),
+        ]);
+    }
+}
@@ -0,0 +1,178 @@
+//! A record of every step a program takes, as JSON lines or CSV, for looking at a run with other tools.
+//!
+//! Each step has the same fields, in this order:
+//!
+//! - `step`: how many ops had run, counting this one, from 1
+//! - `ip`: the op's index in the program
+//! - `line`, `column`: where the op's instruction is in the source, from 1, or null (empty in CSV) without one
+//! - `op`: the op, as the instruction it came from or as `disasm` shows it
+//! - `pointer`: the pointer after the op
+//! - `cell`: the cell the pointer was at before the op, on the tape that was active
+//! - `old`, `new`: that cell's value before and after
+//! - `io`: the byte `,` read or `.` wrote, or null (empty in CSV)
+
+use std::{fs::File, io::{self, BufWriter, Write}};
+use clap::ValueEnum;
+use crate::{Op, Vm, emit::source_map::{LineIndex, json_string}};
+
+
+const CSV_HEADER: &str = "step,ip,line,column,op,pointer,cell,old,new,io";
+
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub enum TraceFormat {
+    /// A JSON object per line
+    #[default]
+    Json,
+
+    /// A header, then a line per step
+    Csv,
+}
+
+
+/// Where to write a trace, and the source to find each op's line in
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub struct Trace {
+    pub path: String,
+    pub format: TraceFormat,
+    pub source: Option<String>,
+}
+
+
+/// The cell an op is about to change: the active tape, the pointer and the cell's value
+#[derive(Clone, Copy, Debug)]
+pub struct Before {
+    tape: usize,
+    cell: usize,
+    old: u8,
+}
+
+
+impl Before {
+    pub fn new(vm: &Vm) -> Before {
+        Before { tape: vm.active_tape(), cell: vm.tape().pointer, old: vm.tape().current() }
+    }
+}
+
+
+pub struct Tracer<W: Write> {
+    output: W,
+    format: TraceFormat,
+    source: Option<(String, LineIndex)>,
+    steps: u64,
+}
+
+
+impl Tracer<BufWriter<File>> {
+    pub fn create(trace: &Trace) -> io::Result<Tracer<BufWriter<File>>> {
+        Tracer::new(BufWriter::new(File::create(&trace.path)?), trace.format, trace.source.as_deref())
+    }
+}
+
+
+impl<W: Write> Tracer<W> {
+    pub fn new(mut output: W, format: TraceFormat, source: Option<&str>) -> io::Result<Tracer<W>> {
+        if format == TraceFormat::Csv {
+            writeln!(output, "{}", CSV_HEADER)?;
+        }
+        let source = source.map(|source| (source.to_string(), LineIndex::new(source)));
+        Ok(Tracer { output, format, source, steps: 0 })
+    }
+
+    /// Record `op`, which was at `ip`, now that it has run
+    pub fn step(&mut self, vm: &Vm, ip: usize, op: Op, before: Before) -> io::Result<()> {
+        self.steps += 1;
+        let position = match (&self.source, vm.program().offset(ip)) {
+            (Some((source, index)), Some(offset)) => Some(index.line_column(source, offset)),
+            _ => None,
+        };
+        let new = vm.tapes()[before.tape].data.get(before.cell).copied().unwrap_or(0);
+        let io = matches!(op, Op::Input | Op::Output).then(|| vm.tape().current());
+        let pointer = vm.tape().pointer;
+
+        match self.format {
+            TraceFormat::Json => {
+                let (line, column) = match position {
+                    Some((line, column)) => ((line + 1).to_string(), (column + 1).to_string()),
+                    None => ("null".to_string(), "null".to_string()),
+                };
+                writeln!(
+                    self.output,
+                    "{{\"step\":{},\"ip\":{},\"line\":{},\"column\":{},\"op\":{},\"pointer\":{},\"cell\":{},\"old\":{},\"new\":{},\"io\":{}}}",
+                    self.steps, ip, line, column, json_string(&op.to_string()), pointer, before.cell, before.old, new,
+                    io.map_or("null".to_string(), |byte| byte.to_string()),
+                )
+            },
+            TraceFormat::Csv => {
+                let (line, column) = match position {
+                    Some((line, column)) => ((line + 1).to_string(), (column + 1).to_string()),
+                    None => (String::new(), String::new()),
+                };
+                writeln!(
+                    self.output,
+                    "{},{},{},{},{},{},{},{},{},{}",
+                    self.steps, ip, line, column, csv_field(&op.to_string()), pointer, before.cell, before.old, new,
+                    io.map_or(String::new(), |byte| byte.to_string()),
+                )
+            },
+        }
+    }
+
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+
+/// Quote a field if it has a comma or a quote, doubling the quotes
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ParseOptions, Program, VmOptions, parse_with_offsets};
+
+    fn trace(source: &str, input: &[u8], format: TraceFormat) -> String {
+        let instructions = parse_with_offsets(source, &ParseOptions::default()).unwrap();
+        let program = Program::compile_with_offsets(&instructions, false).unwrap();
+        let mut vm = Vm::new(program, &VmOptions::default());
+        let mut tracer = Tracer::new(Vec::new(), format, Some(source)).unwrap();
+        let (mut input, mut output) = (input, Vec::new());
+        while let Some(op) = vm.current_op() {
+            let (ip, before) = (vm.ip(), Before::new(&vm));
+            vm.step(&mut input, &mut output).unwrap();
+            tracer.step(&vm, ip, op, before).unwrap();
+        }
+        String::from_utf8(tracer.output).unwrap()
+    }
+
+    #[test]
+    fn json_lines() {
+        assert_eq!(trace("+\n>,.", b"A", TraceFormat::Json), [
+            r#"{"step":1,"ip":0,"line":1,"column":1,"op":"+","pointer":0,"cell":0,"old":0,"new":1,"io":null}"#,
+            r#"{"step":2,"ip":1,"line":2,"column":1,"op":">","pointer":1,"cell":0,"old":1,"new":1,"io":null}"#,
+            r#"{"step":3,"ip":2,"line":2,"column":2,"op":",","pointer":1,"cell":1,"old":0,"new":65,"io":65}"#,
+            r#"{"step":4,"ip":3,"line":2,"column":3,"op":".","pointer":1,"cell":1,"old":65,"new":65,"io":65}"#,
+            "",
+        ].join("\n"));
+    }
+
+    #[test]
+    fn csv() {
+        assert_eq!(trace(",-", b"", TraceFormat::Csv), [
+            CSV_HEADER,
+            "1,0,1,1,\",\",0,0,0,0,0",
+            "2,1,1,2,-,0,0,0,127,",
+            "",
+        ].join("\n"));
+    }
+}
@@ -0,0 +1,594 @@
+use std::{error::Error, fmt, io::{Read, Write}, collections::HashMap, hash::{DefaultHasher, Hash, Hasher}};
+use clap::ValueEnum;
+use serde::Deserialize;
+use crate::{error, hang, ir::{Op, Program}};
+
+
+/// Cells shown on each side of the pointer when a tape is displayed
+const SHOWN_CELLS: usize = 8;
+
+/// Ops shown on each side of the next one when a vm is displayed
+const SHOWN_OPS: usize = 6;
+
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub struct Tape {
+    // Using u8, but max will be 127
+    pub data: Vec<u8>,
+    // Location of the data pointer
+    pub pointer: usize,
+}
+
+impl Tape {
+    pub fn new() -> Tape {
+        Tape { data: vec![0], pointer: 0 }
+    }
+
+    pub fn current(&self) -> u8 {
+        self.data[self.pointer]
+    }
+
+    pub fn current_mut(&mut self) -> &mut u8 {
+        &mut self.data[self.pointer]
+    }
+}
+
+impl Default for Tape {
+    fn default() -> Self {
+        Tape::new()
+    }
+}
+
+impl fmt::Display for Tape {
+    /// Where the pointer is and the cells around it, like `cell 10 of 20: ... 9 [10] 11 ...`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let start = self.pointer.saturating_sub(SHOWN_CELLS);
+        let end = (self.pointer + SHOWN_CELLS + 1).min(self.data.len());
+        write!(f, "cell {} of {}:", self.pointer, self.data.len())?;
+        if start > 0 {
+            write!(f, " ...")?;
+        }
+        for (i, cell) in self.data[start..end].iter().enumerate() {
+            match start + i == self.pointer {
+                true => write!(f, " [{}]", cell)?,
+                false => write!(f, " {}", cell)?,
+            }
+        }
+        if end < self.data.len() {
+            write!(f, " ...")?;
+        }
+        Ok(())
+    }
+}
+
+
+/// What `,` does once there is no input left
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize))]
+#[serde(rename_all = "lowercase")]
+pub enum Eof {
+    /// Set the cell to 0
+    #[default]
+    Zero,
+    /// Set the cell to 127, the -1 of 7 bit cells
+    Max,
+    /// Leave the cell as it is
+    Unchanged,
+}
+
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+pub struct VmOptions {
+    /// Number of independent tapes, only more than one is useful with the tapes extension
+    pub tapes: usize,
+    pub eof: Eof,
+    /// Note an `error::Hang` for `Vm::take_hang` when a loop comes back to the same state without any I/O
+    pub detect_hangs: bool,
+    /// With `detect_hangs`, stop with the `error::Hang` instead of noting it and going on
+    pub stop_on_hang: bool,
+    /// Stop with `error::TooMuchOutput` when the program goes to write more than this many bytes
+    pub max_output: Option<u64>,
+    /// At `max_output`, end the program as if it had finished instead, leaving `Vm::is_truncated` set
+    pub truncate_output: bool,
+}
+
+impl Default for VmOptions {
+    fn default() -> Self {
+        VmOptions { tapes: 1, eof: Eof::Zero, detect_hangs: false, stop_on_hang: false, max_output: None, truncate_output: false }
+    }
+}
+
+
+#[derive(Clone)]
+pub struct Vm {
+    program: Program,
+    // Location of the instruction pointer
+    ip: usize,
+    tapes: Vec<Tape>,
+    // Index of the tape the program is currently using
+    active_tape: usize,
+    // pbrain procedure start locations, keyed by procedure number
+    procedures: HashMap<u8, usize>,
+    // Return locations of the procedures currently being called
+    call_stack: Vec<usize>,
+    // Values pushed by the stack extension
+    stack: Vec<u8>,
+    eof: Eof,
+    hangs: Option<hang::Detector>,
+    stop_on_hang: bool,
+    // A hang noticed without stopping, until it's taken
+    hang: Option<error::Hang>,
+    // Bytes written so far, counted when there's a limit
+    written: u64,
+    max_output: Option<u64>,
+    truncate_output: bool,
+    truncated: bool,
+}
+
+impl Vm {
+    pub fn new(program: Program, options: &VmOptions) -> Vm {
+        Vm {
+            program,
+            ip: 0,
+            tapes: vec![Tape::new(); options.tapes.max(1)],
+            active_tape: 0,
+            procedures: HashMap::new(),
+            call_stack: Vec::new(),
+            stack: Vec::new(),
+            eof: options.eof,
+            hangs: options.detect_hangs.then(hang::Detector::default),
+            stop_on_hang: options.stop_on_hang,
+            hang: None,
+            written: 0,
+            max_output: options.max_output,
+            truncate_output: options.truncate_output,
+            truncated: false,
+        }
+    }
+
+    /// Go back to how `new` left the vm, keeping the memory the tapes and stacks already have,
+    /// to run the program again without allocating
+    pub fn reset(&mut self) {
+        self.ip = 0;
+        for tape in &mut self.tapes {
+            tape.data.clear();
+            tape.data.push(0);
+            tape.pointer = 0;
+        }
+        self.active_tape = 0;
+        self.procedures.clear();
+        self.call_stack.clear();
+        self.stack.clear();
+        if let Some(hangs) = &mut self.hangs {
+            *hangs = hang::Detector::default();
+        }
+        self.hang = None;
+        self.written = 0;
+        self.truncated = false;
+    }
+
+    /// Reset the vm to run another program, with the same options
+    pub fn load(&mut self, program: Program) {
+        self.program = program;
+        self.reset();
+    }
+
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+
+    pub fn ip(&self) -> usize {
+        self.ip
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.ip >= self.program.ops.len()
+    }
+
+    /// Whether the program was ended early at `VmOptions::max_output` with `truncate_output`
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// The hang noticed since this was last called, when `VmOptions::detect_hangs` is on without `stop_on_hang`
+    pub fn take_hang(&mut self) -> Option<error::Hang> {
+        self.hang.take()
+    }
+
+    /// The op that will run on the next step
+    pub fn current_op(&self) -> Option<Op> {
+        self.program.ops.get(self.ip).copied()
+    }
+
+    pub fn tapes(&self) -> &[Tape] {
+        &self.tapes
+    }
+
+    pub fn active_tape(&self) -> usize {
+        self.active_tape
+    }
+
+    pub fn tape(&self) -> &Tape {
+        &self.tapes[self.active_tape]
+    }
+
+    /// The stack extension's stack, with the top value last
+    pub fn stack(&self) -> &[u8] {
+        &self.stack
+    }
+
+    fn tape_mut(&mut self) -> &mut Tape {
+        &mut self.tapes[self.active_tape]
+    }
+
+    /// Whether the loop from `open` to `close` is going round in the same state as last time, with hang detection on.
+    /// Only the cells in the loop's read set are hashed when it has one, or everything when it doesn't.
+    fn repeats(&mut self, open: usize, close: usize) -> bool {
+        let Some(mut hangs) = self.hangs.take() else {
+            return false;
+        };
+        let repeated = hangs.repeat(&self.program.ops, open, close, |read_set| {
+            let mut hasher = DefaultHasher::new();
+            let tape = self.tape();
+            match read_set {
+                // The pointer can't stop at the start of the tape partway round, so the cells are where they look
+                Some(offsets) if offsets.first().is_none_or(|&lowest| tape.pointer as isize + lowest >= 0) => {
+                    (tape.pointer, self.active_tape).hash(&mut hasher);
+                    for &offset in offsets {
+                        tape.data.get((tape.pointer as isize + offset) as usize).copied().unwrap_or(0).hash(&mut hasher);
+                    }
+                },
+                _ => (&self.tapes, self.active_tape, &self.stack, &self.call_stack).hash(&mut hasher),
+            }
+            hasher.finish()
+        });
+        self.hangs = Some(hangs);
+        repeated
+    }
+
+    /// Run until the end of the program, stopping at a hang even without `stop_on_hang`
+    pub fn run(&mut self, input: &mut impl Read, output: &mut impl Write) -> Result<(), Box<dyn Error>> {
+        while !self.is_finished() {
+            self.step(input, output)?;
+            if let Some(hang) = self.take_hang() {
+                return Err(Box::new(hang));
+            }
+        }
+        Ok(())
+    }
+
+    /// Run a single op, reading `,` from `input` and writing `.` to `output`
+    pub fn step(&mut self, input: &mut impl Read, output: &mut impl Write) -> Result<(), Box<dyn Error>> {
+        let Some(op) = self.current_op() else {
+            return Ok(());
+        };
+        let i = self.ip;
+
+        match op {
+            Op::Add(n) => {
+                let cell = self.tape_mut().current_mut();
+                *cell = (*cell + n) % 128;
+            },
+            Op::Right(n) => {
+                let tape = self.tape_mut();
+                tape.pointer += n;
+                if tape.pointer >= tape.data.len() {
+                    tape.data.resize(tape.pointer + 1, 0);
+                }
+            },
+            Op::Left(n) => {
+                let tape = self.tape_mut();
+                tape.pointer = tape.pointer.saturating_sub(n);
+            },
+            Op::Clear => {
+                *self.tape_mut().current_mut() = 0;
+            },
+            Op::MulAdd { offset, factor } => {
+                let tape = self.tape_mut();
+                let value = tape.current();
+                let target = tape.pointer + offset;
+                if target >= tape.data.len() {
+                    tape.data.resize(target + 1, 0);
+                }
+                tape.data[target] = ((tape.data[target] as usize + value as usize * factor as usize) % 128) as u8;
+            },
+            Op::JumpIfZero(close) => {
+                if self.tape().current() == 0 {
+                    self.ip = close;
+                }
+            },
+            Op::JumpIfNonZero(open) => {
+                if self.tape().current() != 0 {
+                    if self.repeats(open, i) {
+                        let offsets = self.program.offset(open).zip(self.program.offset(i));
+                        let hang = error::Hang { open, close: i, offsets, location: None, excerpt: None };
+                        if self.stop_on_hang {
+                            return Err(Box::new(hang));
+                        }
+                        self.hang = Some(hang);
+                    }
+                    self.ip = open;
+                } else if let Some(hangs) = &mut self.hangs {
+                    hangs.exit(i);
+                }
+            },
+            Op::Input => {
+                output.flush()?;
+                if let Some(hangs) = &mut self.hangs {
+                    hangs.io();
+                }
+
+                let mut byte: [u8; 1] = [0];
+                let value = match input.read(&mut byte)? {
+                    0 => match self.eof {
+                        Eof::Zero => 0,
+                        Eof::Max => 127,
+                        Eof::Unchanged => self.tape().current(),
+                    },
+                    _ if byte[0] > 127 => return Err("input must be an ASCII character".into()),
+                    _ => byte[0],
+                };
+                *self.tape_mut().current_mut() = value;
+            },
+            Op::Output => {
+                if let Some(limit) = self.max_output {
+                    if self.written == limit {
+                        if !self.truncate_output {
+                            return Err(Box::new(error::TooMuchOutput { limit }));
+                        }
+                        self.truncated = true;
+                        self.ip = self.program.ops.len();
+                        return Ok(());
+                    }
+                    self.written += 1;
+                }
+                output.write_all(&[self.tape().current()])?;
+                if let Some(hangs) = &mut self.hangs {
+                    hangs.io();
+                }
+            },
+            Op::Break => (),
+            Op::ProcedureOpen(close) => {
+                // Only record the procedure, it runs when called
+                self.procedures.insert(self.tape().current(), i);
+                self.ip = close;
+            },
+            Op::ProcedureClose => {
+                // Outside of a call this does nothing
+                if let Some(return_location) = self.call_stack.pop() {
+                    self.ip = return_location;
+                }
+            },
+            Op::Call => {
+                let number = self.tape().current();
+                let Some(&start) = self.procedures.get(&number) else {
+                    return Err(format!("procedure {} is not defined", number).into());
+                };
+                self.call_stack.push(i);
+                self.ip = start;
+            },
+            Op::NextTape => {
+                self.active_tape = (self.active_tape + 1) % self.tapes.len();
+            },
+            Op::CopyToNextTape => {
+                let value = self.tape().current();
+                let next = (self.active_tape + 1) % self.tapes.len();
+                *self.tapes[next].current_mut() = value;
+            },
+            Op::Push => {
+                let value = self.tape().current();
+                self.stack.push(value);
+            },
+            Op::Pop => {
+                let Some(value) = self.stack.pop() else {
+                    return Err("cannot pop from an empty stack".into());
+                };
+                *self.tape_mut().current_mut() = value;
+            },
+        }
+
+        self.ip += 1;
+        Ok(())
+    }
+
+    /// Display the vm with the line of `source` the next op came from, and a caret under it
+    pub fn in_source<'a>(&'a self, source: &'a str) -> InSource<'a> {
+        InSource { vm: self, source }
+    }
+}
+
+impl fmt::Display for Vm {
+    /// A few lines: the ops around the next one, which is in braces, the active tape, and the stack if it's used
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ops = &self.program.ops;
+        match self.is_finished() {
+            true => write!(f, "finished after {} ops:", ops.len())?,
+            false => write!(f, "op {} of {}:", self.ip, ops.len())?,
+        }
+        let start = self.ip.saturating_sub(SHOWN_OPS);
+        let end = (self.ip + SHOWN_OPS + 1).min(ops.len());
+        if start > 0 {
+            write!(f, " ...")?;
+        }
+        for (i, op) in ops.iter().enumerate().take(end).skip(start) {
+            match i == self.ip {
+                true => write!(f, " {{{}}}", op)?,
+                false => write!(f, " {}", op)?,
+            }
+        }
+        if end < ops.len() {
+            write!(f, " ...")?;
+        }
+        match self.tapes.len() {
+            1 => write!(f, "\npointer at {}", self.tape())?,
+            tapes => write!(f, "\ntape {} of {}, pointer at {}", self.active_tape, tapes, self.tape())?,
+        }
+        if !self.stack.is_empty() {
+            write!(f, "\nstack: {:?}", self.stack)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Vm {
+    /// The same as Display rather than every op and cell, which can be millions
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Vm")
+            .field("ip", &self.ip)
+            .field("op", &self.current_op())
+            .field("tape", &format_args!("{}", self.tape()))
+            .field("active_tape", &self.active_tape)
+            .field("tapes", &self.tapes.len())
+            .field("stack", &self.stack)
+            .field("call_stack", &self.call_stack)
+            .finish_non_exhaustive()
+    }
+}
+
+
+/// A vm displayed with its source, from `Vm::in_source`
+pub struct InSource<'a> {
+    vm: &'a Vm,
+    source: &'a str,
+}
+
+impl fmt::Display for InSource<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.vm)?;
+        let Some(offset) = self.vm.program.offset(self.vm.ip).filter(|&offset| offset < self.source.len()) else {
+            return Ok(());
+        };
+        let start = self.source[..offset].rfind('\n').map_or(0, |newline| newline + 1);
+        let end = self.source[offset..].find('\n').map_or(self.source.len(), |newline| offset + newline);
+        let line = self.source[..start].matches('\n').count() + 1;
+        let column = self.source[start..offset].chars().count();
+        write!(f, "\nline {} column {}:\n    {}\n    {}^", line, column + 1, self.source[start..end].trim_end(), " ".repeat(column))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use crate::{parse, ParseOptions, Extension};
+
+    fn run_vm(code: &str, options: &ParseOptions, vm_options: &VmOptions) -> (Vm, Vec<u8>) {
+        let program = Program::compile(&parse(code, options).unwrap(), true).unwrap();
+        let mut vm = Vm::new(program, vm_options);
+        let mut output = Vec::new();
+        vm.run(&mut io::empty(), &mut output).unwrap();
+        (vm, output)
+    }
+
+    #[test]
+    fn display() {
+        let program = Program::compile_with_offsets(&crate::parse_with_offsets("++\n>,[>+<-]$", &ParseOptions { extensions: vec![Extension::Stack], ..Default::default() }).unwrap(), true).unwrap();
+        let mut vm = Vm::new(program, &VmOptions::default());
+        for _ in 0..3 {
+            vm.step(&mut &b"a"[..], &mut io::sink()).unwrap();
+        }
+        assert_eq!(vm.to_string(), "op 3 of 6: +2 > , {*>1x1} [-] $\npointer at cell 1 of 2: 2 [97]");
+        assert_eq!(vm.in_source("++\n>,[>+<-]$").to_string(), format!("{}\nline 2 column 3:\n    >,[>+<-]$\n      ^", vm));
+        vm.run(&mut io::empty(), &mut io::sink()).unwrap();
+        assert_eq!(vm.to_string(), "finished after 6 ops: +2 > , *>1x1 [-] $\npointer at cell 1 of 3: 2 [0] 97\nstack: [0]");
+        assert!(format!("{:?}", vm).starts_with("Vm { ip: 6, op: None, tape: cell 1 of 3: 2 [0] 97, "));
+    }
+
+    #[test]
+    fn wrapping_cells() {
+        let (vm, _) = run_vm("->+>-+", &ParseOptions::default(), &VmOptions::default());
+        assert_eq!(vm.tape().data, vec![127, 1, 0]);
+    }
+
+    #[test]
+    fn reset_runs_again() {
+        let program = Program::compile(&parse(",[.>,]", &ParseOptions::default()).unwrap(), true).unwrap();
+        let mut vm = Vm::new(program, &VmOptions::default());
+        let mut output = Vec::new();
+        vm.run(&mut &b"abc"[..], &mut output).unwrap();
+        let capacity = vm.tape().data.capacity();
+
+        vm.reset();
+        assert_eq!((vm.ip(), vm.tape()), (0, &Tape::new()));
+        assert_eq!(vm.tape().data.capacity(), capacity);
+        vm.run(&mut &b"de"[..], &mut output).unwrap();
+        assert_eq!(output, b"abcde");
+
+        vm.load(Program::compile(&parse("+++", &ParseOptions::default()).unwrap(), true).unwrap());
+        vm.run(&mut io::empty(), &mut output).unwrap();
+        assert_eq!(vm.tape().data, vec![3]);
+    }
+
+    #[test]
+    fn eof_policies() {
+        for (eof, cell) in [(Eof::Zero, 0), (Eof::Max, 127), (Eof::Unchanged, 3)] {
+            let (vm, _) = run_vm("+++,", &ParseOptions::default(), &VmOptions { eof, ..VmOptions::default() });
+            assert_eq!(vm.tape().current(), cell);
+        }
+    }
+
+    #[test]
+    fn output_limits() {
+        let code = "+++[.]";
+        let limited = VmOptions { max_output: Some(5), ..VmOptions::default() };
+        let program = Program::compile(&parse(code, &ParseOptions::default()).unwrap(), true).unwrap();
+        let mut output = Vec::new();
+        let error = Vm::new(program.clone(), &limited).run(&mut io::empty(), &mut output).unwrap_err();
+        assert_eq!(error.downcast_ref::<error::TooMuchOutput>(), Some(&error::TooMuchOutput { limit: 5 }));
+        assert_eq!(output, [3; 5]);
+
+        let mut vm = Vm::new(program, &VmOptions { truncate_output: true, ..limited });
+        let mut output = Vec::new();
+        vm.run(&mut io::empty(), &mut output).unwrap();
+        assert!(vm.is_finished() && vm.is_truncated());
+        assert_eq!(output, [3; 5]);
+
+        let (vm, output) = run_vm("+.....", &ParseOptions::default(), &limited);
+        assert!(!vm.is_truncated());
+        assert_eq!(output, [1; 5]);
+    }
+
+    #[test]
+    fn separate_tapes() {
+        let options = ParseOptions { extensions: vec![Extension::Tapes], ..Default::default() };
+        let (vm, output) = run_vm("+++&^>++.^.", &options, &VmOptions { tapes: 2, ..VmOptions::default() });
+
+        assert_eq!(vm.active_tape(), 0);
+        assert_eq!(vm.tapes()[0].data, vec![3]);
+        assert_eq!(vm.tapes()[1].data, vec![3, 2]);
+        assert_eq!(output, vec![2, 3]);
+    }
+
+    #[test]
+    fn tape_instructions_need_extension() {
+        let (vm, _) = run_vm("+^+", &ParseOptions::default(), &VmOptions { tapes: 2, ..VmOptions::default() });
+        assert_eq!(vm.tapes()[0].data, vec![2]);
+    }
+
+    #[test]
+    fn multiply_loop_grows_tape() {
+        let (vm, _) = run_vm("+++[->>++<<]", &ParseOptions::default(), &VmOptions::default());
+        assert_eq!(vm.tape().data, vec![0, 0, 6]);
+    }
+
+    #[test]
+    fn stack_push_pop() {
+        let options = ParseOptions { extensions: vec![Extension::Stack], ..Default::default() };
+        let (vm, _) = run_vm("+$+$>%>%>$", &options, &VmOptions::default());
+
+        assert_eq!(vm.tape().data, vec![2, 2, 1, 0]);
+        assert_eq!(vm.stack(), &[0]);
+    }
+
+    #[test]
+    fn stack_underflow() {
+        let options = ParseOptions { extensions: vec![Extension::Stack], ..Default::default() };
+        let program = Program::compile(&parse("%", &options).unwrap(), true).unwrap();
+        let mut vm = Vm::new(program, &VmOptions::default());
+
+        assert!(vm.step(&mut io::empty(), &mut Vec::new()).is_err());
+    }
+}